@@ -0,0 +1,199 @@
+//! Micro-benchmarks for the hot paths: parsing readings, scanning for
+//! delimiters, updating the per-station map and merging stats, and whole
+//! runs on each `--executor` for comparing them. Run with
+//! `cargo bench`, or `cargo bench -- parse` for the ones whose names
+//! contain `parse`.
+//!
+//! criterion is not among the dependencies, so this is a small harness of
+//! its own: each benchmark runs for about a second after a warm-up, and
+//! the median of its samples is reported per input handled.
+
+#[path = "../tests/common/mod.rs"]
+mod common;
+
+use common::Rng;
+use onebrc::cli::Executor;
+use onebrc::processor::ProcessorBuilder;
+use onebrc::stat::Stat;
+use onebrc::{parse, simd};
+use std::collections::HashMap;
+use std::fs;
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+const WARM_UP: Duration = Duration::from_millis(200);
+const MEASURE: Duration = Duration::from_secs(1);
+const SAMPLES: usize = 50;
+
+const NAMES: &[&str] = &[
+    "Abha",
+    "Abidjan",
+    "Abéché",
+    "Zürich",
+    "São Paulo",
+    "東京",
+    "St. John's",
+    "Las Palmas de Gran Canaria",
+];
+
+/// Readings like the 1BRC ones, from -99.9 to 99.9.
+fn readings(n: usize) -> Vec<String> {
+    let mut rng = Rng::new(153);
+    (0..n)
+        .map(|_| format!("{:.1}", (rng.below(1999) as i64 - 999) as f64 / 10.0))
+        .collect()
+}
+
+/// `station;reading` lines over `stations` distinct names.
+fn lines(n: usize, stations: usize) -> Vec<String> {
+    let mut rng = Rng::new(1153);
+    let values = readings(n);
+    (0..n)
+        .map(|i| {
+            let station = rng.below(stations as u64) as usize;
+            format!("{}{};{}", NAMES[station % NAMES.len()], station, values[i])
+        })
+        .collect()
+}
+
+/// Times `routine`, which handles `items` inputs per call, and prints the
+/// median time per item.
+fn bench(filter: &Option<String>, name: &str, items: usize, mut routine: impl FnMut()) {
+    if filter
+        .as_ref()
+        .is_some_and(|filter| !name.contains(filter.as_str()))
+    {
+        return;
+    }
+    let started = Instant::now();
+    let mut calls_per_sample = 0u64;
+    while started.elapsed() < WARM_UP {
+        routine();
+        calls_per_sample += 1;
+    }
+    // Enough calls per sample for all of them to fill the measuring time
+    let calls_per_sample =
+        (calls_per_sample * MEASURE.as_nanos() as u64 / WARM_UP.as_nanos() as u64 / SAMPLES as u64)
+            .max(1);
+    let mut samples: Vec<f64> = (0..SAMPLES)
+        .map(|_| {
+            let started = Instant::now();
+            for _ in 0..calls_per_sample {
+                routine();
+            }
+            started.elapsed().as_nanos() as f64 / (calls_per_sample * items as u64) as f64
+        })
+        .collect();
+    samples.sort_by(f64::total_cmp);
+    println!(
+        "{:<32} {:>9.2} ns/item  (min {:.2}, max {:.2})",
+        name,
+        samples[SAMPLES / 2],
+        samples[0],
+        samples[SAMPLES - 1]
+    );
+}
+
+fn main() {
+    // `cargo bench` passes `--bench`, anything else narrows the run
+    let filter = std::env::args().skip(1).find(|arg| !arg.starts_with("--"));
+    simd::init(false);
+
+    let values = readings(10_000);
+    bench(&filter, "parse_value", values.len(), || {
+        for value in &values {
+            black_box(parse::parse_value(black_box(value), false).unwrap());
+        }
+    });
+    bench(&filter, "parse_1brc", values.len(), || {
+        for value in &values {
+            black_box(parse::parse_1brc(black_box(value)).unwrap());
+        }
+    });
+    bench(&filter, "parse_std", values.len(), || {
+        for value in &values {
+            black_box(black_box(value).parse::<f64>().unwrap());
+        }
+    });
+
+    let records = lines(10_000, 400);
+    bench(&filter, "find_delimiter", records.len(), || {
+        for line in &records {
+            black_box(simd::find(black_box(line.as_bytes()), b';'));
+        }
+    });
+    bench(&filter, "find_delimiter_std", records.len(), || {
+        for line in &records {
+            black_box(black_box(line.as_str()).find(';'));
+        }
+    });
+    bench(&filter, "split", records.len(), || {
+        for line in &records {
+            black_box(simd::split(black_box(line), b';').count());
+        }
+    });
+
+    for stations in [400, 10_000] {
+        let records = lines(100_000, stations);
+        let parsed: Vec<(&str, f64)> = records
+            .iter()
+            .map(|line| {
+                let (name, value) = line.split_once(';').unwrap();
+                (name, parse::parse_1brc(value).unwrap())
+            })
+            .collect();
+        let name = format!("map_update_{}_stations", stations);
+        bench(&filter, &name, parsed.len(), || {
+            let mut map: HashMap<&str, Stat> = HashMap::new();
+            for &(name, value) in &parsed {
+                match map.get_mut(name) {
+                    Some(stat) => stat.add(value),
+                    None => {
+                        map.insert(name, Stat::new(value));
+                    }
+                }
+            }
+            black_box(map);
+        });
+    }
+
+    let mut rng = Rng::new(2153);
+    let stats: Vec<Stat> = values
+        .iter()
+        .map(|value| {
+            let mut stat = Stat::new(value.parse().unwrap());
+            stat.add(rng.below(1999) as f64 / 10.0 - 99.9);
+            stat
+        })
+        .collect();
+    bench(&filter, "stat_merge", stats.len(), || {
+        let mut total = stats[0].clone();
+        for stat in &stats[1..] {
+            total.merge(black_box(stat));
+        }
+        black_box(total);
+    });
+
+    // A file small enough for many runs, on as many threads as a run
+    // would have by default
+    let records = lines(200_000, 400);
+    let path = std::env::temp_dir().join(format!("1brc-bench-{}.csv", std::process::id()));
+    fs::write(&path, records.join("\n") + "\n").unwrap();
+    let threads = std::thread::available_parallelism().map_or(1, |n| n.get());
+    for (name, executor) in [
+        ("executor_threads", Executor::Threads),
+        ("executor_pipeline", Executor::Pipeline),
+        #[cfg(feature = "runtime-tokio")]
+        ("executor_tokio", Executor::Tokio),
+    ] {
+        let processor = ProcessorBuilder::new(path.to_string_lossy())
+            .threads(threads)
+            .executor(executor)
+            .build()
+            .unwrap();
+        bench(&filter, name, records.len(), || {
+            black_box(processor.run_blocking().unwrap());
+        });
+    }
+    fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,64 @@
+//! Records what `--version` reports about the build that only Cargo and git
+//! know: the commit, the profile and the target's CPU features.
+
+use std::env;
+use std::path::Path;
+use std::process::Command;
+
+fn main() {
+    println!("cargo:rustc-env=ONEBRC_COMMIT={}", commit());
+    println!(
+        "cargo:rustc-env=ONEBRC_PROFILE={}, opt-level {}",
+        env::var("PROFILE").unwrap_or_default(),
+        env::var("OPT_LEVEL").unwrap_or_default()
+    );
+    println!(
+        "cargo:rustc-env=ONEBRC_TARGET={}",
+        env::var("TARGET").unwrap_or_default()
+    );
+    // Those enabled at compile time, by the target or `-C target-cpu`
+    println!(
+        "cargo:rustc-env=ONEBRC_TARGET_FEATURES={}",
+        env::var("CARGO_CFG_TARGET_FEATURE").unwrap_or_default()
+    );
+
+    // Checking out or committing moves HEAD or the branch it is on, and
+    // staging changes the index
+    println!("cargo:rerun-if-changed=build.rs");
+    for path in [".git/HEAD", ".git/index"] {
+        if Path::new(path).exists() {
+            println!("cargo:rerun-if-changed={}", path);
+        }
+    }
+    if let Ok(head) = std::fs::read_to_string(".git/HEAD")
+        && let Some(branch) = head.trim().strip_prefix("ref: ")
+        && Path::new(".git").join(branch).exists()
+    {
+        println!("cargo:rerun-if-changed=.git/{}", branch);
+    }
+}
+
+/// The abbreviated commit, with `-dirty` if the tree has changes of its
+/// own, or `unknown` outside a git checkout.
+fn commit() -> String {
+    let git = |args: &[&str]| {
+        Command::new("git")
+            .args(args)
+            .output()
+            .ok()
+            .filter(|output| output.status.success())
+            .map(|output| String::from_utf8_lossy(&output.stdout).trim().to_string())
+    };
+    match git(&["rev-parse", "--short=12", "HEAD"]) {
+        Some(commit) if !commit.is_empty() => {
+            let dirty = git(&["status", "--porcelain", "--untracked-files=no"])
+                .is_some_and(|status| !status.is_empty());
+            if dirty {
+                format!("{}-dirty", commit)
+            } else {
+                commit
+            }
+        }
+        _ => "unknown".to_string(),
+    }
+}
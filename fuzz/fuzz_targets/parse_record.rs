@@ -0,0 +1,46 @@
+//! Arbitrary bytes through everything a record goes through. None of it
+//! may panic: a record either parses or is rejected with an error.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use onebrc::jsonl::{Fields, Parser};
+use onebrc::{csv, parse, simd, time};
+
+fuzz_target!(|data: &[u8]| {
+    let Ok(line) = std::str::from_utf8(data) else {
+        return;
+    };
+
+    // The default CSV path, whose splitting must agree with `str::split`
+    let parts: Vec<&str> = simd::split(line, csv::DELIMITER as u8).collect();
+    assert_eq!(parts, line.split(csv::DELIMITER).collect::<Vec<_>>());
+    for part in &parts {
+        let _ = parse::parse_value(part.trim(), false);
+        let _ = parse::parse_value(part.trim(), true);
+        let _ = time::parse_timestamp(part.trim());
+    }
+
+    // The word-at-a-time parser trusts its input, but must agree with the
+    // general one on every reading it is meant for
+    let strict = parse::parse_1brc(line);
+    if is_1brc_reading(line) {
+        let general = parse::parse_value(line, false).expect("a plain decimal");
+        assert_eq!(strict.expect("a 1brc reading").to_bits(), general.to_bits());
+    }
+
+    if !csv::is_open(line) {
+        let _ = csv::split_record(line);
+    }
+    let _ = Parser::default().parse(line, &Fields::default());
+});
+
+/// Whether `s` matches `-?\d?\d\.\d`.
+fn is_1brc_reading(s: &str) -> bool {
+    let digits = s.strip_prefix('-').unwrap_or(s).as_bytes();
+    match digits {
+        [a, b'.', c] => a.is_ascii_digit() && c.is_ascii_digit(),
+        [a, b, b'.', c] => a.is_ascii_digit() && b.is_ascii_digit() && c.is_ascii_digit(),
+        _ => false,
+    }
+}
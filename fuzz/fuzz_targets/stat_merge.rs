@@ -0,0 +1,56 @@
+//! Merging the aggregates of any split of the readings must give what
+//! aggregating them all at once does.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use onebrc::stat::Stat;
+
+fuzz_target!(|data: &[u8]| {
+    // Each reading is two bytes of tenths, and a zero byte before one
+    // starts a new part
+    let mut parts: Vec<Vec<f64>> = vec![vec![]];
+    let mut bytes = data;
+    while let [first, rest @ ..] = bytes {
+        if *first == 0 {
+            parts.push(vec![]);
+            bytes = rest;
+            continue;
+        }
+        let [low, high, rest @ ..] = bytes else {
+            break;
+        };
+        let tenths = i16::from_le_bytes([*low, *high]);
+        parts.last_mut().unwrap().push(f64::from(tenths) / 10.0);
+        bytes = rest;
+    }
+
+    let all: Vec<f64> = parts.iter().flatten().copied().collect();
+    let Some(whole) = aggregate(&all) else {
+        return;
+    };
+    let mut merged: Option<Stat> = None;
+    for part in parts.iter().filter_map(|part| aggregate(part)) {
+        match &mut merged {
+            Some(merged) => merged.merge(&part),
+            None => merged = Some(part),
+        }
+    }
+    let merged = merged.expect("some part has readings");
+
+    assert_eq!(merged.count, whole.count);
+    assert_eq!(merged.min.to_bits(), whole.min.to_bits());
+    assert_eq!(merged.max.to_bits(), whole.max.to_bits());
+    // Only the order of the additions differs
+    let tolerance = 1e-9 * whole.count as f64 * 3276.8;
+    assert!((merged.sum - whole.sum).abs() <= tolerance);
+});
+
+fn aggregate(readings: &[f64]) -> Option<Stat> {
+    let (first, rest) = readings.split_first()?;
+    let mut stat = Stat::new(*first);
+    for &value in rest {
+        stat.add(value);
+    }
+    Some(stat)
+}
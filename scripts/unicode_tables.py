@@ -0,0 +1,82 @@
+#!/usr/bin/env python3
+"""Generates src/unicode_tables.rs from Python's unicodedata module.
+
+Usage: python3 scripts/unicode_tables.py > src/unicode_tables.rs
+"""
+
+import sys
+import unicodedata
+
+MAX = 0x110000
+HANGUL = range(0xAC00, 0xD7A4)
+
+
+def chars():
+    for cp in range(MAX):
+        if 0xD800 <= cp <= 0xDFFF or cp in HANGUL:
+            continue
+        yield cp, chr(cp)
+
+
+def table(name, rows, per_line):
+    out = [f"pub static {name}: &[{rows[0]}] = &["]
+    entries = rows[1]
+    for i in range(0, len(entries), per_line):
+        out.append("    " + " ".join(entries[i:i + per_line]))
+    out.append("];")
+    return "\n".join(out)
+
+
+def hexes(s):
+    return ", ".join(f"0x{ord(c):X}" for c in s)
+
+
+def main():
+    ccc = []
+    canonical = []
+    compat = []
+    compose = []
+    for cp, c in chars():
+        klass = unicodedata.combining(c)
+        if klass:
+            ccc.append(f"(0x{cp:X}, {klass}),")
+
+        nfd = unicodedata.normalize("NFD", c)
+        if nfd != c:
+            canonical.append(f"(0x{cp:X}, &[{hexes(nfd)}]),")
+        nfkd = unicodedata.normalize("NFKD", c)
+        if nfkd != nfd:
+            compat.append(f"(0x{cp:X}, &[{hexes(nfkd)}]),")
+
+        # Primary composites: two-character canonical decompositions that
+        # survive NFC, which rules out exclusions and non-starters.
+        raw = unicodedata.decomposition(c)
+        if raw and not raw.startswith("<"):
+            parts = [int(p, 16) for p in raw.split()]
+            if len(parts) == 2 and unicodedata.normalize("NFC", c) == c:
+                compose.append((parts[0], parts[1], cp))
+
+    compose.sort()
+    print("// Generated by scripts/unicode_tables.py from Unicode "
+          f"{unicodedata.unidata_version}. Do not edit.")
+    print()
+    print("/// Canonical combining classes of all non-starters.")
+    print("#[rustfmt::skip]")
+    print(table("COMBINING_CLASS", ("(u32, u8)", ccc), 6))
+    print()
+    print("/// Full canonical decompositions, excluding Hangul syllables.")
+    print("#[rustfmt::skip]")
+    print(table("CANONICAL_DECOMPOSITION", ("(u32, &[u32])", canonical), 3))
+    print()
+    print("/// Full compatibility decompositions that differ from the canonical one.")
+    print("#[rustfmt::skip]")
+    print(table("COMPATIBILITY_DECOMPOSITION", ("(u32, &[u32])", compat), 3))
+    print()
+    print("/// Primary composites, sorted by their (starter, combining) pair.")
+    print("#[rustfmt::skip]")
+    print(table("COMPOSITION", ("((u32, u32), u32)",
+                                [f"((0x{a:X}, 0x{b:X}), 0x{c:X})," for a, b, c in compose]), 3))
+
+
+if __name__ == "__main__":
+    sys.exit(main())
@@ -0,0 +1,96 @@
+//! Pinning threads to CPU cores for `--pin-cores` and `--pin-reader`.
+
+use crate::warning::Warning;
+use anyhow::bail;
+use std::io;
+
+/// Parses a core list such as `0-15` or `0,2,4-7`.
+pub fn parse_core_list(v: &str) -> anyhow::Result<Vec<usize>> {
+    let mut cores = Vec::new();
+    for part in v.split(',') {
+        let part = part.trim();
+        match part.split_once('-') {
+            Some((start, end)) => {
+                let start: usize = parse_core(start)?;
+                let end: usize = parse_core(end)?;
+                if start > end {
+                    bail!("core range `{}` is reversed", part);
+                }
+                cores.extend(start..=end);
+            }
+            None => cores.push(parse_core(part)?),
+        }
+    }
+    Ok(cores)
+}
+
+pub fn parse_core(v: &str) -> anyhow::Result<usize> {
+    let core: usize = v
+        .trim()
+        .parse()
+        .map_err(|_| anyhow::anyhow!("expected a core number, got `{}`", v))?;
+    if core >= MAX_CORES {
+        bail!("core {} is out of range", core);
+    }
+    Ok(core)
+}
+
+/// Cores representable in the kernel's default `cpu_set_t`.
+const MAX_CORES: usize = 1024;
+
+/// Restricts the calling thread to run on `cores` only.
+#[cfg(target_os = "linux")]
+pub fn pin_current_thread(cores: &[usize]) -> io::Result<()> {
+    use std::ffi::c_int;
+
+    unsafe extern "C" {
+        fn sched_setaffinity(pid: c_int, cpusetsize: usize, mask: *const u8) -> c_int;
+    }
+
+    let mut set = [0u8; MAX_CORES / 8];
+    for &core in cores {
+        set[core / 8] |= 1 << (core % 8);
+    }
+    // SAFETY: `set` is a valid cpu_set_t, and pid 0 is the calling thread
+    if unsafe { sched_setaffinity(0, set.len(), set.as_ptr()) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn pin_current_thread(_cores: &[usize]) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "pinning threads is only supported on Linux",
+    ))
+}
+
+/// Pins the calling thread, only warning if that fails since pinning is an
+/// optimization.
+pub fn pin_or_warn(role: &str, cores: &[usize]) {
+    if let Err(e) = pin_current_thread(cores) {
+        crate::warn(Warning::Unpinned {
+            thread: role.to_string(),
+            cores: cores.to_vec(),
+            reason: e.to_string(),
+        });
+    }
+}
+
+/// A runtime with one worker thread per core in `cores`, each pinned to its
+/// core as it starts.
+#[cfg(feature = "runtime-tokio")]
+pub fn pinned_runtime(cores: Vec<usize>) -> io::Result<tokio::runtime::Runtime> {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    let next = AtomicUsize::new(0);
+    tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .worker_threads(cores.len())
+        .on_thread_start(move || {
+            let core = cores[next.fetch_add(1, Ordering::Relaxed) % cores.len()];
+            pin_or_warn("worker", &[core]);
+        })
+        .build()
+}
@@ -0,0 +1,274 @@
+//! `--agg`: the aggregates reported per key, and the state kept for the
+//! ones a [`Stat`](crate::Stat) does not have. Minimum, maximum, sum and
+//! count are kept for every key anyway; a standard deviation needs the sum
+//! of squares as well, and percentiles a histogram of the readings, so
+//! those are only kept when asked for. So are the rows the minimum and
+//! maximum came from, for `--track-extremes`.
+
+use crate::arena::Arena;
+use crate::{Key, StatResult};
+use anyhow::bail;
+use std::collections::{BTreeMap, HashMap};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Agg {
+    Min,
+    Mean,
+    Max,
+    Count,
+    Sum,
+    /// Of the population, so 0 for a single reading
+    Stddev,
+    /// The `p`th percentile, by nearest rank
+    Percentile(u8),
+}
+
+impl Agg {
+    /// A comma-separated list, such as `min,p50,max`.
+    pub fn parse_list(v: &str) -> anyhow::Result<Vec<Agg>> {
+        let mut aggs = vec![];
+        for name in v.split(',') {
+            let agg = Agg::parse(name.trim())?;
+            if aggs.contains(&agg) {
+                bail!("`{}` is listed twice", name.trim());
+            }
+            aggs.push(agg);
+        }
+        Ok(aggs)
+    }
+
+    fn parse(v: &str) -> anyhow::Result<Self> {
+        Ok(match v {
+            "min" => Agg::Min,
+            "mean" => Agg::Mean,
+            "max" => Agg::Max,
+            "count" => Agg::Count,
+            "sum" => Agg::Sum,
+            "stddev" => Agg::Stddev,
+            _ => match v.strip_prefix('p').map(str::parse::<u8>) {
+                Some(Ok(p)) if p <= 100 => Agg::Percentile(p),
+                _ => bail!(
+                    "expected one of min, mean, max, count, sum, stddev or p0 to p100, got `{}`",
+                    v
+                ),
+            },
+        })
+    }
+
+    pub fn name(self) -> String {
+        match self {
+            Agg::Min => "min".to_string(),
+            Agg::Mean => "mean".to_string(),
+            Agg::Max => "max".to_string(),
+            Agg::Count => "count".to_string(),
+            Agg::Sum => "sum".to_string(),
+            Agg::Stddev => "stddev".to_string(),
+            Agg::Percentile(p) => format!("p{}", p),
+        }
+    }
+
+    /// What the Prometheus output calls the gauge, and its help text.
+    pub fn metric(self) -> (String, String) {
+        match self {
+            Agg::Count => (
+                "station_readings".to_string(),
+                "Number of readings".to_string(),
+            ),
+            Agg::Min => ("station_temp_min".to_string(), "Lowest reading".to_string()),
+            Agg::Mean => ("station_temp_mean".to_string(), "Mean reading".to_string()),
+            Agg::Max => (
+                "station_temp_max".to_string(),
+                "Highest reading".to_string(),
+            ),
+            Agg::Sum => (
+                "station_temp_sum".to_string(),
+                "Sum of the readings".to_string(),
+            ),
+            Agg::Stddev => (
+                "station_temp_stddev".to_string(),
+                "Standard deviation of the readings".to_string(),
+            ),
+            Agg::Percentile(p) => (
+                format!("station_temp_p{}", p),
+                format!("{}th percentile of the readings", p),
+            ),
+        }
+    }
+
+    /// The aggregate of `stat`, rounded to `precision` decimals unless it
+    /// is a count.
+    pub(crate) fn format(self, stat: &StatResult, precision: usize) -> String {
+        match self {
+            Agg::Count => stat.count.to_string(),
+            Agg::Min => format!("{:.*}", precision, stat.min),
+            Agg::Mean => format!("{:.*}", precision, stat.mean),
+            Agg::Max => format!("{:.*}", precision, stat.max),
+            Agg::Sum => format!("{:.*}", precision, stat.sum),
+            Agg::Stddev => format!("{:.*}", precision, stat.stddev()),
+            Agg::Percentile(p) => format!("{:.*}", precision, stat.percentile(p)),
+        }
+    }
+}
+
+/// What the requested aggregates need beyond a [`Stat`](crate::Stat).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Tracking {
+    squares: bool,
+    histogram: bool,
+    extremes: bool,
+}
+
+impl Tracking {
+    pub fn of(aggs: &[Agg], extremes: bool) -> Self {
+        Tracking {
+            squares: aggs.contains(&Agg::Stddev),
+            histogram: aggs.iter().any(|agg| matches!(agg, Agg::Percentile(_))),
+            extremes,
+        }
+    }
+
+    pub fn any(self) -> bool {
+        self.squares || self.histogram || self.extremes
+    }
+
+    /// Whether the rows of the extremes are needed, and so where each row
+    /// starts.
+    pub fn extremes(self) -> bool {
+        self.extremes
+    }
+}
+
+/// The lowest and highest readings of a key, each with the byte offset of
+/// the row it came from. Of equal readings the first row is kept, so that
+/// the rows do not depend on how the input was split.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Extremes {
+    pub min: (f64, u64),
+    pub max: (f64, u64),
+}
+
+impl Extremes {
+    fn add(&mut self, min: (f64, u64), max: (f64, u64)) {
+        if min.0 < self.min.0 || (min.0 == self.min.0 && min.1 < self.min.1) {
+            self.min = min;
+        }
+        if max.0 > self.max.0 || (max.0 == self.max.0 && max.1 < self.max.1) {
+            self.max = max;
+        }
+    }
+}
+
+/// The state behind the aggregates a [`Stat`](crate::Stat) does not have.
+#[derive(Clone, Debug, Default)]
+pub struct Extra {
+    sum_of_squares: f64,
+    /// Each distinct reading, as its [`order_key`], and how often it was
+    /// seen
+    histogram: BTreeMap<i64, u64>,
+    extremes: Option<Extremes>,
+}
+
+impl Extra {
+    /// Adds `value`, read from the row at byte `at`.
+    fn add(&mut self, tracking: Tracking, value: f64, at: u64) {
+        if tracking.squares {
+            self.sum_of_squares += value * value;
+        }
+        if tracking.histogram {
+            *self.histogram.entry(order_key(value)).or_default() += 1;
+        }
+        if tracking.extremes {
+            match &mut self.extremes {
+                Some(extremes) => extremes.add((value, at), (value, at)),
+                None => {
+                    self.extremes = Some(Extremes {
+                        min: (value, at),
+                        max: (value, at),
+                    })
+                }
+            }
+        }
+    }
+
+    pub fn merge(&mut self, other: &Extra) {
+        self.sum_of_squares += other.sum_of_squares;
+        for (&key, &count) in &other.histogram {
+            *self.histogram.entry(key).or_default() += count;
+        }
+        match (&mut self.extremes, other.extremes) {
+            (Some(extremes), Some(other)) => extremes.add(other.min, other.max),
+            (None, other) => self.extremes = other,
+            (_, None) => {}
+        }
+    }
+
+    pub fn extremes(&self) -> Option<Extremes> {
+        self.extremes
+    }
+
+    pub fn stddev(&self, mean: f64, count: i64) -> f64 {
+        // Rounding can take the variance of equal readings just below 0
+        (self.sum_of_squares / count as f64 - mean * mean)
+            .max(0.0)
+            .sqrt()
+    }
+
+    /// One of the readings, so that p0 is the minimum and p100 the
+    /// maximum.
+    pub fn percentile(&self, p: u8, count: i64) -> f64 {
+        let rank = ((p as f64 / 100.0 * count as f64).ceil() as u64).max(1);
+        let mut seen = 0;
+        for (&key, &n) in &self.histogram {
+            seen += n;
+            if seen >= rank {
+                return from_order_key(key);
+            }
+        }
+        f64::NAN
+    }
+}
+
+/// The bits of `value` as an integer that orders as the readings do, with
+/// -0.0 taken for 0.0.
+fn order_key(value: f64) -> i64 {
+    let bits = (value + 0.0).to_bits() as i64;
+    // Negative readings order backwards by their bits
+    bits ^ (((bits >> 63) as u64) >> 1) as i64
+}
+
+fn from_order_key(key: i64) -> f64 {
+    f64::from_bits((key ^ (((key >> 63) as u64) >> 1) as i64) as u64)
+}
+
+/// Adds `value`, from the row at byte `at`, to the extra state of
+/// `lookup`, storing a copy of the key if it is new.
+pub fn track(
+    extras: &mut HashMap<Key, Extra>,
+    tracking: Tracking,
+    lookup: Key,
+    value: f64,
+    at: u64,
+    arena: &mut Arena,
+) {
+    if let Some(extra) = extras.get_mut(&lookup) {
+        extra.add(tracking, value, at);
+        return;
+    }
+    extras
+        .entry(lookup.stored(arena))
+        .or_default()
+        .add(tracking, value, at);
+}
+
+/// Merges the extra state of `from` into `into`, keeping the keys of
+/// `from` where they are new.
+pub fn merge(into: &mut HashMap<Key, Extra>, from: HashMap<Key, Extra>) {
+    for (key, extra) in from {
+        match into.get_mut(&key) {
+            Some(total) => total.merge(&extra),
+            None => {
+                into.insert(key, extra);
+            }
+        }
+    }
+}
@@ -0,0 +1,164 @@
+//! The global allocator this binary was built with, reported by
+//! `--version`.
+//!
+//! That is always the system allocator: there are no `alloc-mimalloc` or
+//! `alloc-jemalloc` features, as neither crate is a dependency of this
+//! tree, and an optional dependency has to resolve for every build. One
+//! would register its allocator here with `#[global_allocator]` and set
+//! `NAME` under the same `cfg`.
+//!
+//! With the `alloc-stats` feature the system allocator is wrapped to count
+//! allocations per phase of the run, for `--stats --alloc-stats`.
+
+#[cfg(not(feature = "alloc-stats"))]
+pub const NAME: &str = "system";
+#[cfg(feature = "alloc-stats")]
+pub const NAME: &str = "system, counted";
+
+use crate::phase::Phase;
+
+/// Attributes the allocations from here on, on every thread, to `phase`.
+/// Called by [`crate::phase::enter`].
+pub fn enter(phase: Phase) {
+    #[cfg(feature = "alloc-stats")]
+    counting::PHASE.store(phase as usize, std::sync::atomic::Ordering::Relaxed);
+    #[cfg(not(feature = "alloc-stats"))]
+    let _ = phase;
+}
+
+/// The allocations so far per phase, or `None` in builds that do not count
+/// them.
+pub fn report() -> Option<String> {
+    #[cfg(feature = "alloc-stats")]
+    return Some(counting::report());
+    #[cfg(not(feature = "alloc-stats"))]
+    None
+}
+
+#[cfg(feature = "alloc-stats")]
+mod counting {
+    use crate::memory::format_bytes;
+    use crate::phase::Phase;
+    use std::alloc::{GlobalAlloc, Layout, System};
+    use std::fmt::Write;
+    use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+    /// Allocations by the power of two their size rounds down to, the last
+    /// one taking everything from 32 KiB up
+    const SIZE_CLASSES: usize = 17;
+
+    pub static PHASE: AtomicUsize = AtomicUsize::new(0);
+    static COUNTERS: [Counters; 4] = [const { Counters::new() }; 4];
+
+    struct Counters {
+        allocations: AtomicU64,
+        reallocations: AtomicU64,
+        bytes: AtomicU64,
+        by_size: [AtomicU64; SIZE_CLASSES],
+    }
+
+    impl Counters {
+        const fn new() -> Self {
+            Counters {
+                allocations: AtomicU64::new(0),
+                reallocations: AtomicU64::new(0),
+                bytes: AtomicU64::new(0),
+                by_size: [const { AtomicU64::new(0) }; SIZE_CLASSES],
+            }
+        }
+
+        /// Counts `bytes` more in use, for an allocation of `size`.
+        fn count(&self, bytes: usize, size: usize) {
+            self.bytes.fetch_add(bytes as u64, Ordering::Relaxed);
+            let class = (usize::BITS - size.leading_zeros()) as usize;
+            self.by_size[class.min(SIZE_CLASSES - 1)].fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    fn current() -> &'static Counters {
+        &COUNTERS[PHASE.load(Ordering::Relaxed)]
+    }
+
+    struct Counting;
+
+    // Only counts, leaving the allocating to `System`
+    unsafe impl GlobalAlloc for Counting {
+        unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+            let counters = current();
+            counters.allocations.fetch_add(1, Ordering::Relaxed);
+            counters.count(layout.size(), layout.size());
+            unsafe { System.alloc(layout) }
+        }
+
+        unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+            let counters = current();
+            counters.allocations.fetch_add(1, Ordering::Relaxed);
+            counters.count(layout.size(), layout.size());
+            unsafe { System.alloc_zeroed(layout) }
+        }
+
+        unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+            let counters = current();
+            counters.reallocations.fetch_add(1, Ordering::Relaxed);
+            counters.count(new_size.saturating_sub(layout.size()), new_size);
+            unsafe { System.realloc(ptr, layout, new_size) }
+        }
+
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+            unsafe { System.dealloc(ptr, layout) }
+        }
+    }
+
+    #[global_allocator]
+    static GLOBAL: Counting = Counting;
+
+    pub fn report() -> String {
+        let mut out = String::from("Allocations:");
+        for phase in Phase::ALL {
+            let counters = &COUNTERS[phase as usize];
+            let allocations = counters.allocations.load(Ordering::Relaxed);
+            let reallocations = counters.reallocations.load(Ordering::Relaxed);
+            let _ = write!(
+                out,
+                "\n  {}: {} allocations, {} reallocations, {}",
+                phase.name(),
+                allocations,
+                reallocations,
+                format_bytes(counters.bytes.load(Ordering::Relaxed) as usize)
+            );
+            // The sizes most allocations come in, where a regression in
+            // the hot path would show first
+            let (class, count) = counters
+                .by_size
+                .iter()
+                .map(|count| count.load(Ordering::Relaxed))
+                .enumerate()
+                .max_by_key(|&(_, count)| count)
+                .unwrap_or_default();
+            let total = allocations + reallocations;
+            if count > 0 {
+                let _ = write!(
+                    out,
+                    ", mostly {} ({}%)",
+                    describe(class),
+                    count * 100 / total.max(1)
+                );
+            }
+        }
+        out
+    }
+
+    fn describe(class: usize) -> String {
+        match class {
+            0 => "empty".to_string(),
+            _ if class == SIZE_CLASSES - 1 => {
+                format!("{} and up", format_bytes(1 << (class - 1)))
+            }
+            _ => format!(
+                "{} to {}",
+                format_bytes(1 << (class - 1)),
+                format_bytes((1 << class) - 1)
+            ),
+        }
+    }
+}
@@ -0,0 +1,83 @@
+//! A bump arena for station names. Each worker copies the names of new keys
+//! into large blocks instead of allocating a `String` per key, and the
+//! whole arena is freed at once after the reduce phase.
+
+use std::ptr::NonNull;
+
+const BLOCK_SIZE: usize = 64 * 1024;
+
+/// Strings handed out by [`Arena::alloc`] are typed `&'static str` so that
+/// keys holding them can be moved between tasks and collected into the
+/// results, but they are only valid until the arena is dropped. Every arena
+/// must therefore outlive all keys built from it.
+#[derive(Debug, Default)]
+pub struct Arena {
+    blocks: Vec<NonNull<[u8]>>,
+    /// Bytes used in the last block
+    used: usize,
+}
+
+// The blocks are owned exclusively by the arena and never written again
+// once handed out
+unsafe impl Send for Arena {}
+
+impl Arena {
+    pub fn alloc(&mut self, s: &str) -> &'static str {
+        let len = s.len();
+        let ptr = if len > BLOCK_SIZE / 4 {
+            // Large names get a block of their own, which is put before the
+            // current one so that keeps being filled
+            let block = self.new_block(len);
+            let last = self.blocks.len() - 1;
+            if last > 0 {
+                self.blocks.swap(last - 1, last);
+            } else {
+                self.used = len;
+            }
+            block
+        } else {
+            if self.blocks.is_empty() || self.used + len > self.blocks[self.blocks.len() - 1].len()
+            {
+                self.new_block(BLOCK_SIZE);
+                self.used = 0;
+            }
+            let block = self.blocks[self.blocks.len() - 1];
+            // SAFETY: `used + len` is within the block
+            let ptr = unsafe { block.cast::<u8>().as_ptr().add(self.used) };
+            self.used += len;
+            ptr
+        };
+        // SAFETY: the destination is unused arena memory of `len` bytes, and
+        // the copy is valid UTF-8 for as long as the arena lives
+        unsafe {
+            std::ptr::copy_nonoverlapping(s.as_ptr(), ptr, len);
+            std::str::from_utf8_unchecked(std::slice::from_raw_parts(ptr, len))
+        }
+    }
+
+    fn new_block(&mut self, size: usize) -> *mut u8 {
+        let block: Box<[u8]> = vec![0; size].into_boxed_slice();
+        let block = NonNull::from(Box::leak(block));
+        self.blocks.push(block);
+        block.cast::<u8>().as_ptr()
+    }
+}
+
+impl Drop for Arena {
+    fn drop(&mut self) {
+        for block in self.blocks.drain(..) {
+            // SAFETY: every block came from `Box::leak` in `new_block`
+            drop(unsafe { Box::from_raw(block.as_ptr()) });
+        }
+    }
+}
+
+/// Extends `s` to `'static` for looking up a key without copying its name.
+///
+/// # Safety
+///
+/// The result must not be stored anywhere that outlives `s`.
+pub unsafe fn transient(s: &str) -> &'static str {
+    // SAFETY: upheld by the caller
+    unsafe { std::mem::transmute::<&str, &'static str>(s) }
+}
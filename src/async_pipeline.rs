@@ -0,0 +1,294 @@
+//! The channel pipeline on a tokio runtime, with async workers, for
+//! `--executor tokio` in builds with the `runtime-tokio` feature.
+
+use crate::input::{Block, Blocks, Source};
+use crate::numa::{Node, Placement};
+use crate::worker::{self, ReadError, WorkerError};
+use crate::{Context, ThreadData, affinity, chunk_cost, manifest, new_thread_data, process_chunk};
+use std::io;
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
+use tokio::runtime::Handle;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore, mpsc};
+use tokio::task::JoinHandle;
+
+/// Blocks read ahead of the dispatch, per reader.
+const HANDOFF: usize = 2;
+
+/// Runs the pipeline to completion on a new runtime.
+pub fn run(
+    ctx: &Arc<Context>,
+    source: &Source,
+    nodes: &[Node],
+    num_threads: usize,
+    spill_limit: Option<usize>,
+) -> anyhow::Result<(u64, Vec<ThreadData>)> {
+    let runtime = build_runtime(&ctx.config.pin_cores, num_threads)?;
+    runtime.block_on(run_pipeline(ctx, source, nodes, num_threads, spill_limit))
+}
+
+/// One reader per NUMA node, or just one, splits the file into blocks of
+/// whole lines and deals them out to async workers.
+pub async fn run_pipeline(
+    ctx: &Arc<Context>,
+    source: &Source,
+    nodes: &[Node],
+    num_threads: usize,
+    spill_limit: Option<usize>,
+) -> anyhow::Result<(u64, Vec<ThreadData>)> {
+    let placement = if nodes.len() > 1 {
+        Some(Placement::new(nodes)?)
+    } else {
+        None
+    };
+    let partitions = placement.as_ref().map_or(1, Placement::len);
+    // Every node needs at least one worker for its part of the file
+    let num_threads = num_threads.max(partitions);
+    let config = &ctx.config;
+    ctx.memory
+        .buffers
+        .add(source.buffer_size() * partitions * config.readers);
+
+    // Under a memory limit, half of it goes to the maps (which spill past
+    // their share) and a quarter to queued chunks, counted in KiB permits
+    let queue_permits = config
+        .max_memory
+        .map(|limit| Arc::new(Semaphore::new((limit / 4 / 1024).max(1))));
+
+    // Spawn worker threads, each with its own channel so that every chunk
+    // is processed by exactly one worker
+    let mut senders: Vec<Vec<_>> = (0..partitions).map(|_| Vec::new()).collect();
+    let mut handles = vec![];
+    for i in 0..num_threads {
+        let (tx, mut rx) = mpsc::channel::<Chunk>(16);
+        let node = i * partitions / num_threads;
+        senders[node].push(tx);
+        let ctx = ctx.clone();
+        let worker = async move {
+            // Worker thread loop
+            let mut data = new_thread_data(&ctx.config, i, spill_limit);
+            while let Some(chunk) = rx.recv().await {
+                if ctx.cancelled.load(Ordering::Relaxed) {
+                    break;
+                }
+                let result = worker::guard(i, |offset| {
+                    *offset = chunk.offset;
+                    process_chunk(&mut data, &chunk.text, chunk.offset, &ctx, i)?;
+                    drop(chunk);
+                    ctx.memory.check()
+                });
+                if result.is_err() && !ctx.config.best_effort {
+                    ctx.cancelled.store(true, Ordering::Relaxed);
+                }
+                // Chunks still queued on failure are dropped with `rx`
+                result?;
+            }
+            Ok(data)
+        };
+        let handle: tokio::task::JoinHandle<Result<_, WorkerError>> = match &placement {
+            Some(placement) => placement.handle(node).spawn(worker),
+            None => tokio::spawn(worker),
+        };
+        handles.push(handle);
+    }
+
+    // Read file and send chunks to workers
+    let len = source.len();
+    let readers = config.readers;
+    let mut tasks = vec![];
+    for (node, senders) in senders.into_iter().enumerate() {
+        let range =
+            len * node as u64 / partitions as u64..len * (node as u64 + 1) / partitions as u64;
+        // A node's runtime pins the blocking threads along with the workers
+        let (handle, pin_reader) = match &placement {
+            Some(placement) => (placement.handle(node).clone(), None),
+            None => (Handle::current(), config.pin_reader),
+        };
+        // The node's readers share its workers
+        for reader in 0..readers {
+            ctx.assign(manifest::reader(partitions, node, reader), &range);
+            let blocks = if readers > 1 {
+                source.stripes(
+                    range.clone(),
+                    reader,
+                    readers,
+                    config.chunk_bytes,
+                    config.csv_strict,
+                )
+            } else {
+                source
+                    .blocks(range.clone(), config.chunk_bytes, config.csv_strict)
+                    .map_err(|e| ReadError::new(config, e))?
+            };
+            let blocks = spawn_reader(&handle, blocks, pin_reader);
+            let dispatch = read_blocks(ctx.clone(), blocks, senders.clone(), queue_permits.clone());
+            tasks.push(handle.spawn(dispatch));
+        }
+    }
+    let mut read = Ok(());
+    for task in tasks {
+        let result = task.await?;
+        read = read.and(result);
+    }
+
+    // Wait for all worker threads to complete. A failed worker is the
+    // cause of anything the reader ran into sending to it
+    let mut results = vec![];
+    for handle in handles {
+        results.push(handle.await?);
+    }
+    let thread_datas: Vec<ThreadData> = worker::collect(results, config.best_effort)?;
+    read?;
+    Ok((
+        thread_datas.iter().map(|data| data.lines).sum(),
+        thread_datas,
+    ))
+}
+
+/// A block of lines starting at byte `offset`, counted against the queue
+/// budget until it is dropped, whether processed or not.
+struct Chunk {
+    offset: u64,
+    text: Block,
+    cost: usize,
+    ctx: Arc<Context>,
+    _permit: Option<OwnedSemaphorePermit>,
+}
+
+impl Drop for Chunk {
+    fn drop(&mut self) {
+        self.ctx.memory.queues.sub(self.cost);
+    }
+}
+
+/// Blocks read on a blocking thread, with the task reading them.
+struct Reader {
+    blocks: mpsc::Receiver<io::Result<(u64, Block)>>,
+    task: JoinHandle<()>,
+}
+
+/// Reads `blocks` on a blocking thread of the runtime behind `handle`,
+/// pinned to `pin_reader` if given, so that waiting for the disk holds up
+/// none of the threads the workers and the dispatch run on. Up to
+/// `HANDOFF` blocks wait to be dispatched.
+fn spawn_reader(handle: &Handle, blocks: Blocks, pin_reader: Option<usize>) -> Reader {
+    let (tx, rx) = mpsc::channel(HANDOFF);
+    let task = handle.spawn_blocking(move || {
+        if let Some(core) = pin_reader {
+            affinity::pin_or_warn("reader", &[core]);
+        }
+        for block in blocks {
+            let failed = block.is_err();
+            // The dispatch has stopped early
+            if tx.blocking_send(block).is_err() || failed {
+                break;
+            }
+        }
+    });
+    Reader { blocks: rx, task }
+}
+
+/// Sends the blocks of `reader` round-robin to `senders`. Dropping the
+/// senders at the end signals the workers to finish.
+async fn read_blocks(
+    ctx: Arc<Context>,
+    mut reader: Reader,
+    mut senders: Vec<mpsc::Sender<Chunk>>,
+    queue_permits: Option<Arc<Semaphore>>,
+) -> anyhow::Result<()> {
+    let mut next_worker = 0;
+    let result = async {
+        while let Some(block) = reader.blocks.recv().await {
+            let (offset, text) = block.map_err(|e| ReadError::new(&ctx.config, e))?;
+            let chunk = reserve_queue_space(&ctx, queue_permits.as_ref(), offset, text).await?;
+            if !send(&ctx, &mut senders, &mut next_worker, chunk).await {
+                break;
+            }
+        }
+        anyhow::Ok(())
+    }
+    .await;
+    // Closing the channel stops the reading thread after its current block
+    drop(reader.blocks);
+    reader.task.await?;
+    result
+}
+
+/// Sends `chunk` to the next worker, returning whether there are still
+/// workers to send to. A worker that failed has dropped its receiver, so
+/// its share goes to the others.
+async fn send(
+    ctx: &Context,
+    senders: &mut Vec<mpsc::Sender<Chunk>>,
+    next_worker: &mut usize,
+    mut chunk: Chunk,
+) -> bool {
+    if let Some(export) = &ctx.sinks.export {
+        export.dispatch(chunk.offset);
+    }
+    while !senders.is_empty() && !ctx.cancelled.load(Ordering::Relaxed) {
+        match senders[*next_worker].send(chunk).await {
+            Ok(()) => {
+                *next_worker = (*next_worker + 1) % senders.len();
+                return true;
+            }
+            Err(mpsc::error::SendError(unsent)) => {
+                chunk = unsent;
+                senders.remove(*next_worker);
+                if *next_worker >= senders.len() {
+                    *next_worker = 0;
+                }
+            }
+        }
+    }
+    false
+}
+
+/// Waits until a chunk fits into the queue budget, if there is one, and
+/// accounts for it.
+async fn reserve_queue_space(
+    ctx: &Arc<Context>,
+    permits: Option<&Arc<Semaphore>>,
+    offset: u64,
+    text: Block,
+) -> anyhow::Result<Chunk> {
+    let cost = chunk_cost(&text);
+    let permit = match permits {
+        Some(permits) => {
+            // A chunk larger than the whole budget only waits for an empty
+            // queue
+            let n = cost
+                .div_ceil(1024)
+                .min(ctx.config.max_memory.unwrap_or(0) / 4 / 1024)
+                .max(1);
+            Some(permits.clone().acquire_many_owned(n as u32).await?)
+        }
+        None => None,
+    };
+    ctx.memory.queues.add(cost);
+    let chunk = Chunk {
+        offset,
+        text,
+        cost,
+        ctx: ctx.clone(),
+        _permit: permit,
+    };
+    ctx.memory.check()?;
+    Ok(chunk)
+}
+
+/// Builds the runtime the workers run on. With `--pin-cores` it gets one
+/// thread per listed core, each pinned to its core as it starts, and
+/// otherwise one per worker.
+fn build_runtime(
+    pin_cores: &Option<Vec<usize>>,
+    num_threads: usize,
+) -> std::io::Result<tokio::runtime::Runtime> {
+    match pin_cores.clone() {
+        Some(cores) => affinity::pinned_runtime(cores),
+        None => tokio::runtime::Builder::new_multi_thread()
+            .enable_all()
+            .worker_threads(num_threads)
+            .build(),
+    }
+}
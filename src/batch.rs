@@ -0,0 +1,93 @@
+//! `--strategy vectorized`: a block is first parsed into columns, one of
+//! keys, one of windows and one of readings, and only then aggregated in a
+//! loop that does nothing but look up and update stats. Each loop stays
+//! small and its branches predictable, and a loop over plain columns is
+//! where SIMD aggregation can come in later.
+//!
+//! The keys are slices of the block rather than hashes with offsets: std's
+//! `HashMap` cannot be handed a hash computed elsewhere, so carrying one
+//! would only mean hashing twice.
+
+use crate::{
+    Context, Key, ThreadData, add_reading, check_length, is_repeat, reading, report_progress,
+    row_error, window_of, write_row,
+};
+use crate::{csv, simd};
+use std::borrow::Cow;
+
+/// The lines of one block that got past parsing and filtering.
+#[derive(Default)]
+struct Batch<'a> {
+    names: Vec<Cow<'a, str>>,
+    /// Left empty without `--window`
+    windows: Vec<Option<i64>>,
+    values: Vec<f64>,
+    /// Where each row starts, left empty without `--track-extremes`
+    offsets: Vec<u64>,
+}
+
+/// Parses `lines`, the complete ones of a block with the byte each starts
+/// at, into a batch and then aggregates it.
+pub fn process<'a>(
+    data: &mut ThreadData,
+    lines: impl Iterator<Item = (u64, &'a str)>,
+    ctx: &'a Context,
+    thread_id: usize,
+) -> anyhow::Result<()> {
+    let config = &ctx.config;
+    let mut batch = Batch::default();
+    for (at, line) in lines {
+        data.lines += 1;
+        report_progress(data, line, ctx, thread_id);
+        check_length(ctx, line, at)?;
+        if is_repeat(data, ctx, line) {
+            continue;
+        }
+        let mut parts = simd::split(line, csv::DELIMITER as u8);
+        let (timestamp, station, value) = match (
+            config.window,
+            parts.next(),
+            parts.next(),
+            parts.next(),
+            parts.next(),
+        ) {
+            (None, Some(station), Some(value), None, _) => (None, station, value),
+            (Some(_), Some(timestamp), Some(station), Some(value), None) => {
+                (Some(timestamp), station, value)
+            }
+            _ => continue,
+        };
+        let row = |e| row_error(ctx, at, line, e);
+        let window = window_of(config, timestamp).map_err(row)?;
+        let name = ctx.keys.apply(station);
+        if !config.stations.is_empty() && !config.stations.iter().any(|s| *s == name) {
+            continue;
+        }
+        let Some(value) = reading(data, config, &name, Some(value)).map_err(row)? else {
+            continue;
+        };
+        write_row(data, ctx, line, &name)?;
+        if config.window.is_some() {
+            batch.windows.push(window);
+        }
+        batch.names.push(name);
+        batch.values.push(value);
+        if ctx.tracking.extremes() {
+            batch.offsets.push(at);
+        }
+    }
+
+    for (i, (name, &value)) in batch.names.iter().zip(&batch.values).enumerate() {
+        let window = batch.windows.get(i).copied().flatten();
+        let at = batch.offsets.get(i).copied().unwrap_or(0);
+        // SAFETY: the lookup key is dropped before the batch
+        add_reading(
+            data,
+            ctx,
+            unsafe { Key::transient(name, window, None) },
+            value,
+            at,
+        )?;
+    }
+    Ok(())
+}
@@ -0,0 +1,154 @@
+//! A Bloom filter that threads insert into at once, for skipping rows seen
+//! before without keeping them.
+//!
+//! All of an item's bits are in one 64-bit word, set with a single atomic
+//! `or`, so that of two threads inserting the same item exactly one finds
+//! it new. That costs more bits per item than bits spread over the whole
+//! filter would for the same false-positive rate.
+
+use crate::xxhash::xxh64;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// The lowest false-positive rate a filter can be sized for, at some 80
+/// bits per item.
+pub const MIN_FALSE_POSITIVES: f64 = 1e-5;
+
+pub struct Bloom {
+    words: Box<[AtomicU64]>,
+    /// Bits set per item
+    bits: u32,
+}
+
+impl Bloom {
+    /// A filter that, with `items` in it, takes an item not in it for one
+    /// that is at about the rate `false_positives`, which must be from
+    /// [`MIN_FALSE_POSITIVES`] to 1.
+    pub fn new(items: u64, false_positives: f64) -> Bloom {
+        let (bits_per_item, bits) = sizing(false_positives);
+        let words = (items as f64 * bits_per_item / 64.0).ceil().max(1.0) as usize;
+        Bloom {
+            words: (0..words).map(|_| AtomicU64::new(0)).collect(),
+            bits,
+        }
+    }
+
+    /// Adds `item`, returning whether it was new, which it always is the
+    /// first time and wrongly is not at the false-positive rate.
+    pub fn insert(&self, item: &[u8]) -> bool {
+        let (word, mask) = self.locate(item);
+        let before = self.words[word].fetch_or(mask, Ordering::Relaxed);
+        before & mask != mask
+    }
+
+    /// Whether `item` looks to have been inserted, leaving it out if not.
+    pub fn contains(&self, item: &[u8]) -> bool {
+        let (word, mask) = self.locate(item);
+        self.words[word].load(Ordering::Relaxed) & mask == mask
+    }
+
+    /// The word `item` goes in and its bits there.
+    fn locate(&self, item: &[u8]) -> (usize, u64) {
+        let hash = xxh64(item, 0);
+        let word = ((u128::from(hash) * self.words.len() as u128) >> 64) as usize;
+        (word, mask(mix(hash), self.bits))
+    }
+
+    pub fn bytes(&self) -> usize {
+        self.words.len() * std::mem::size_of::<AtomicU64>()
+    }
+}
+
+/// The bits of the word an item goes in, six bits of `hash` picking each.
+fn mask(mut hash: u64, bits: u32) -> u64 {
+    let mut mask = 0;
+    for i in 0..bits {
+        // Ten picks use up a hash
+        if i > 0 && i % 10 == 0 {
+            hash = mix(hash);
+        }
+        mask |= 1 << (hash & 63);
+        hash >>= 6;
+    }
+    mask
+}
+
+/// The splitmix64 finalizer, so that the bits within a word do not follow
+/// from where the word is.
+fn mix(mut x: u64) -> u64 {
+    x = (x ^ (x >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    x ^ (x >> 31)
+}
+
+/// The fewest bits per item, in halves, and the bits set per item that
+/// keep false positives under `target`.
+fn sizing(target: f64) -> (f64, u32) {
+    let target = target.max(MIN_FALSE_POSITIVES);
+    for halves in 2..=256 {
+        let bits_per_item = f64::from(halves) / 2.0;
+        for bits in 1..=16 {
+            if false_positive_rate(bits_per_item, bits) <= target {
+                return (bits_per_item, bits);
+            }
+        }
+    }
+    unreachable!("128 bits per item are below MIN_FALSE_POSITIVES")
+}
+
+/// The chance that an item not in a filter with `bits_per_item` finds all
+/// of its bits set. The items in a word are Poisson distributed.
+fn false_positive_rate(bits_per_item: f64, bits: u32) -> f64 {
+    let per_word = 64.0 / bits_per_item;
+    let unset: f64 = 1.0 - 1.0 / 64.0;
+    let mut p = (-per_word).exp();
+    let mut rate = 0.0;
+    let terms = (per_word + 10.0 * per_word.sqrt() + 20.0) as u32;
+    for items in 0..terms {
+        let set = 1.0 - unset.powi((bits * items) as i32);
+        rate += p * set.powi(bits as i32);
+        p *= per_word / f64::from(items + 1);
+    }
+    rate
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn items_are_new_only_the_first_time() {
+        let bloom = Bloom::new(1000, 1e-3);
+        for i in 0..1000 {
+            assert!(bloom.insert(format!("Station {};{}", i, i % 7).as_bytes()));
+        }
+        for i in 0..1000 {
+            assert!(!bloom.insert(format!("Station {};{}", i, i % 7).as_bytes()));
+        }
+    }
+
+    #[test]
+    fn false_positives_stay_near_the_target() {
+        for target in [1e-2, 1e-3] {
+            let items = 100_000;
+            let bloom = Bloom::new(items, target);
+            for i in 0..items {
+                bloom.insert(format!("in {}", i).as_bytes());
+            }
+            let trials = 200_000;
+            let wrong = (0..trials)
+                .filter(|i| bloom.contains(format!("out {}", i).as_bytes()))
+                .count();
+            let rate = wrong as f64 / trials as f64;
+            assert!(rate < target * 1.3, "{} for {}", rate, target);
+        }
+    }
+
+    #[test]
+    fn sizing_matches_the_rate_it_promises() {
+        assert_eq!(sizing(1e-2), (12.0, 5));
+        assert_eq!(sizing(1e-4), (44.0, 9));
+        let (bits_per_item, bits) = sizing(MIN_FALSE_POSITIVES);
+        assert!(bits_per_item < 128.0);
+        assert!(false_positive_rate(bits_per_item, bits) <= MIN_FALSE_POSITIVES);
+    }
+}
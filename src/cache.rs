@@ -0,0 +1,200 @@
+//! `--cache-dir`: the results of earlier runs, reused while neither the
+//! input nor any option that changes what is aggregated has changed.
+//! Output options are applied afresh, so a cached run only skips the scan.
+
+use crate::Summary;
+use crate::arena::Arena;
+use crate::cli::Config;
+use crate::format::json_string;
+use crate::jsonl::Fields;
+use crate::results::Results;
+use crate::warning::Warning;
+use anyhow::Context;
+use std::fmt::Debug;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+/// Inputs larger than this are fingerprinted from evenly spaced samples
+/// rather than read in full.
+const SAMPLES: u64 = 16;
+const SAMPLE_SIZE: u64 = 64 * 1024;
+
+/// The cache file for one input and configuration.
+pub struct Cache {
+    path: PathBuf,
+}
+
+impl Cache {
+    pub fn new(dir: &Path, config: &Config) -> anyhow::Result<Self> {
+        let mut hash = Fnv::default();
+        hash.write(env!("CARGO_PKG_VERSION").as_bytes());
+        for (option, value) in aggregation_options(config) {
+            hash.write(format!("{}={}\n", option, value).as_bytes());
+        }
+        // Files the keys are derived from count as much as the input, each
+        // under its option, as the same file means another thing to each
+        for (option, path) in [
+            ("input", Some(&config.input)),
+            ("aliases", config.aliases.as_ref()),
+            ("key-map", config.key_map.as_ref()),
+            ("metadata", config.metadata.as_ref()),
+        ] {
+            let Some(path) = path else {
+                continue;
+            };
+            hash.write(option.as_bytes());
+            fingerprint(&mut hash, Path::new(path))
+                .with_context(|| format!("failed to fingerprint {}", path))?;
+        }
+        Ok(Cache {
+            path: dir.join(format!("{:016x}.bin", hash.0)),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The cached results, with their names allocated in `arena`. A file
+    /// that cannot be read is warned about and treated as missing.
+    pub fn load(&self, arena: &mut Arena) -> Option<Summary> {
+        let file = match File::open(&self.path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return None,
+            Err(e) => {
+                crate::warn(Warning::CacheIgnored {
+                    path: self.path.clone(),
+                    reason: e.to_string(),
+                });
+                return None;
+            }
+        };
+        match Results::deserialize(&mut BufReader::new(file)) {
+            Ok(results) => Some(Summary::from_results(results, arena)),
+            Err(e) => {
+                crate::warn(Warning::CacheIgnored {
+                    path: self.path.clone(),
+                    reason: e.to_string(),
+                });
+                None
+            }
+        }
+    }
+
+    /// Writes `summary` to a temporary file that then replaces the cache
+    /// file, so that a concurrent run never reads half of it. Failing to
+    /// is only warned about.
+    pub fn store(&self, summary: &Summary) {
+        let tmp = self
+            .path
+            .with_extension(format!("tmp-{}", std::process::id()));
+        let result = self
+            .path
+            .parent()
+            .map_or(Ok(()), fs::create_dir_all)
+            .and_then(|()| {
+                let mut out = BufWriter::new(File::create(&tmp)?);
+                summary.to_results().serialize(&mut out)?;
+                out.into_inner()?.sync_all()
+            })
+            .and_then(|()| fs::rename(&tmp, &self.path));
+        if let Err(e) = result {
+            let _ = fs::remove_file(&tmp);
+            crate::warn(Warning::CacheNotWritten {
+                path: self.path.clone(),
+                reason: e.to_string(),
+            });
+        }
+    }
+}
+
+/// The options that change the results, as opposed to how they are
+/// computed or shown, each by name with its value in JSON.
+pub fn aggregation_options(config: &Config) -> Vec<(&'static str, String)> {
+    let name = |value: &dyn Debug| json_string(&format!("{:?}", value));
+    let number = |value: Option<f64>| value.map_or_else(|| "null".to_string(), |v| v.to_string());
+    let strings = |values: &[String]| {
+        let values: Vec<String> = values.iter().map(|v| json_string(v)).collect();
+        format!("[{}]", values.join(", "))
+    };
+    let Fields {
+        station,
+        value,
+        timestamp,
+    } = &config.json_fields;
+    vec![
+        ("min_value", number(config.range.min)),
+        ("max_value", number(config.range.max)),
+        ("stations", strings(&config.stations)),
+        ("input_unit", name(&config.input_unit)),
+        ("unit", name(&config.output_unit())),
+        (
+            "group_by_prefix",
+            number(config.group_by_prefix.map(|n| n as f64)),
+        ),
+        ("normalize_keys", name(&config.normalize_keys)),
+        ("group_by", name(&config.group_by)),
+        ("mode", name(&config.mode)),
+        ("window", number(config.window.map(|w| w as f64))),
+        ("input_format", name(&config.input_format)),
+        ("encoding", name(&config.encoding)),
+        (
+            "json_fields",
+            strings(&[station.clone(), value.clone(), timestamp.clone()]),
+        ),
+        ("csv_strict", config.csv_strict.to_string()),
+        ("lenient_numbers", config.lenient_numbers.to_string()),
+        ("strict_1brc", config.strict_1brc.to_string()),
+        // A line over the limit fails the run
+        ("max_line_bytes", config.max_line_bytes.to_string()),
+        ("null_values", strings(&config.null_values)),
+        ("null_policy", name(&config.null_policy)),
+    ]
+}
+
+/// Hashes the size, modification time and contents of `path`.
+fn fingerprint(hash: &mut Fnv, path: &Path) -> io::Result<()> {
+    let mut file = File::open(path)?;
+    let metadata = file.metadata()?;
+    let modified = metadata
+        .modified()?
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    hash.write(&metadata.len().to_le_bytes());
+    hash.write(&modified.as_nanos().to_le_bytes());
+
+    let len = metadata.len();
+    let mut buf = Vec::new();
+    if len <= SAMPLES * SAMPLE_SIZE {
+        file.read_to_end(&mut buf)?;
+        hash.write(&buf);
+        return Ok(());
+    }
+    buf.resize(SAMPLE_SIZE as usize, 0);
+    for i in 0..SAMPLES {
+        file.seek(SeekFrom::Start((len - SAMPLE_SIZE) * i / (SAMPLES - 1)))?;
+        file.read_exact(&mut buf)?;
+        hash.write(&buf);
+    }
+    Ok(())
+}
+
+/// 64-bit FNV-1a, which unlike `DefaultHasher` is the same in every build.
+pub struct Fnv(pub u64);
+
+impl Default for Fnv {
+    fn default() -> Self {
+        Fnv(0xcbf2_9ce4_8422_2325)
+    }
+}
+
+impl Fnv {
+    pub fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 ^= byte as u64;
+            self.0 = self.0.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+}
@@ -0,0 +1,202 @@
+//! CPU and memory limits imposed through cgroups (v1 or v2), which
+//! containers use and `available_parallelism` may not fully reflect.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const ROOT: &str = "/sys/fs/cgroup";
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Limits {
+    /// CPUs worth of quota, possibly fractional
+    pub cpus: Option<f64>,
+    pub memory: Option<usize>,
+}
+
+impl Limits {
+    pub fn detect() -> Limits {
+        let Ok(membership) = fs::read_to_string("/proc/self/cgroup") else {
+            return Limits::default();
+        };
+        let mut limits = Limits::default();
+        for line in membership.lines() {
+            // `hierarchy-id:controllers:path`, with v2 as `0::path`
+            let mut parts = line.splitn(3, ':');
+            let (Some(id), Some(controllers), Some(path)) =
+                (parts.next(), parts.next(), parts.next())
+            else {
+                continue;
+            };
+            if id == "0" && controllers.is_empty() {
+                let dir = controller_dir(Path::new(ROOT), path);
+                limits.cpus = limits.cpus.or_else(|| cpu_max(&dir));
+                limits.memory = limits.memory.or_else(|| memory_max(&dir));
+            }
+            for controller in controllers.split(',') {
+                match controller {
+                    "cpu" => {
+                        // Usually co-mounted as `cpu,cpuacct`, with `cpu` a link
+                        let mut mount = Path::new(ROOT).join(controllers);
+                        if !mount.is_dir() {
+                            mount = Path::new(ROOT).join("cpu");
+                        }
+                        let dir = controller_dir(&mount, path);
+                        limits.cpus = limits.cpus.or_else(|| cfs_quota(&dir));
+                    }
+                    "memory" => {
+                        let dir = controller_dir(&Path::new(ROOT).join("memory"), path);
+                        limits.memory = limits.memory.or_else(|| memory_limit_v1(&dir));
+                    }
+                    _ => {}
+                }
+            }
+        }
+        limits
+    }
+
+    /// Threads worth running: the available CPUs, capped by the quota.
+    pub fn parallelism(&self) -> usize {
+        let available = std::thread::available_parallelism().map_or(1, |n| n.get());
+        match self.cpus {
+            Some(cpus) => available.min(cpus.ceil() as usize).max(1),
+            None => available,
+        }
+    }
+}
+
+/// The directory of the cgroup at `path` under `mount`. Inside a container
+/// the cgroup namespace usually makes the mount itself the process's group,
+/// so fall back to it.
+fn controller_dir(mount: &Path, path: &str) -> PathBuf {
+    let dir = mount.join(path.trim_start_matches('/'));
+    if dir.is_dir() {
+        dir
+    } else {
+        mount.to_path_buf()
+    }
+}
+
+fn read(dir: &Path, file: &str) -> Option<String> {
+    fs::read_to_string(dir.join(file))
+        .ok()
+        .map(|s| s.trim().to_string())
+}
+
+/// v2 `cpu.max`: `quota period`, or `max period` without a limit.
+fn cpu_max(dir: &Path) -> Option<f64> {
+    let value = read(dir, "cpu.max")?;
+    let (quota, period) = value.split_once(' ')?;
+    let quota: f64 = quota.parse().ok()?;
+    let period: f64 = period.parse().ok()?;
+    (period > 0.0).then(|| quota / period)
+}
+
+/// v2 `memory.max`, which is `max` without a limit.
+fn memory_max(dir: &Path) -> Option<usize> {
+    read(dir, "memory.max")?.parse().ok()
+}
+
+/// v1 quota and period, where a quota of -1 means no limit.
+fn cfs_quota(dir: &Path) -> Option<f64> {
+    let quota: i64 = read(dir, "cpu.cfs_quota_us")?.parse().ok()?;
+    let period: i64 = read(dir, "cpu.cfs_period_us")?.parse().ok()?;
+    (quota > 0 && period > 0).then(|| quota as f64 / period as f64)
+}
+
+/// v1 limit, which is a huge page-aligned number without a limit.
+fn memory_limit_v1(dir: &Path) -> Option<usize> {
+    let limit: u64 = read(dir, "memory.limit_in_bytes")?.parse().ok()?;
+    (limit < 1 << 60).then_some(limit as usize)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A cgroup directory holding the given control files.
+    fn group(name: &str, files: &[(&str, &str)]) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("1brc-cgroup-{}-{}", std::process::id(), name));
+        fs::create_dir_all(&dir).unwrap();
+        for (file, contents) in files {
+            fs::write(dir.join(file), contents).unwrap();
+        }
+        dir
+    }
+
+    #[test]
+    fn v2_limits() {
+        let dir = group(
+            "v2",
+            &[
+                ("cpu.max", "150000 100000\n"),
+                ("memory.max", "536870912\n"),
+            ],
+        );
+        assert_eq!(cpu_max(&dir), Some(1.5));
+        assert_eq!(memory_max(&dir), Some(512 << 20));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn v2_max_is_no_limit() {
+        let dir = group(
+            "v2-max",
+            &[("cpu.max", "max 100000\n"), ("memory.max", "max\n")],
+        );
+        assert_eq!(cpu_max(&dir), None);
+        assert_eq!(memory_max(&dir), None);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn v1_limits() {
+        let dir = group(
+            "v1",
+            &[
+                ("cpu.cfs_quota_us", "50000\n"),
+                ("cpu.cfs_period_us", "100000\n"),
+                ("memory.limit_in_bytes", "1073741824\n"),
+            ],
+        );
+        assert_eq!(cfs_quota(&dir), Some(0.5));
+        assert_eq!(memory_limit_v1(&dir), Some(1 << 30));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn v1_unlimited() {
+        let dir = group(
+            "v1-unlimited",
+            &[
+                ("cpu.cfs_quota_us", "-1\n"),
+                ("cpu.cfs_period_us", "100000\n"),
+                ("memory.limit_in_bytes", "9223372036854771712\n"),
+            ],
+        );
+        assert_eq!(cfs_quota(&dir), None);
+        assert_eq!(memory_limit_v1(&dir), None);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn missing_or_malformed_files_are_no_limit() {
+        let dir = group(
+            "malformed",
+            &[("cpu.max", "100000\n"), ("cpu.cfs_period_us", "0\n")],
+        );
+        assert_eq!(cpu_max(&dir), None);
+        assert_eq!(cfs_quota(&dir), None);
+        assert_eq!(memory_max(&dir), None);
+        assert_eq!(memory_limit_v1(&dir), None);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn the_quota_caps_parallelism_to_at_least_one_thread() {
+        let available = std::thread::available_parallelism().map_or(1, |n| n.get());
+        let limits = |cpus| Limits { cpus, memory: None };
+        assert_eq!(limits(None).parallelism(), available);
+        assert_eq!(limits(Some(0.1)).parallelism(), 1);
+        assert_eq!(limits(Some(1000.0)).parallelism(), available);
+    }
+}
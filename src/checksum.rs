@@ -0,0 +1,59 @@
+//! `--expect-sha256`: the input is checked against the digest it was
+//! published with. SHA-256 takes the bytes in order, which the workers'
+//! ranges are not, so a thread of its own reads the input again from the
+//! start while it is aggregated, mostly out of the page cache the scan
+//! fills, and the run only waits for it at the end.
+
+use crate::Context;
+use crate::sha256::{self, Digest, Sha256};
+use anyhow::bail;
+use std::fs::File;
+use std::io::{self, Read};
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
+use std::thread::{self, JoinHandle};
+
+pub struct Check {
+    expected: Digest,
+    /// `None` when the run was cancelled first
+    hasher: JoinHandle<io::Result<Option<Digest>>>,
+}
+
+impl Check {
+    /// Starts hashing the input of `ctx`, stopping early if the run is
+    /// cancelled.
+    pub fn spawn(ctx: &Arc<Context>, expected: Digest) -> Self {
+        let ctx = ctx.clone();
+        let hasher = thread::spawn(move || {
+            let mut file = File::open(&ctx.config.input)?;
+            let mut hasher = Sha256::default();
+            let mut buf = vec![0; 1 << 20];
+            loop {
+                if ctx.cancelled.load(Ordering::Relaxed) {
+                    return Ok(None);
+                }
+                match file.read(&mut buf) {
+                    Ok(0) => return Ok(Some(hasher.finish())),
+                    Ok(n) => hasher.update(&buf[..n]),
+                    Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                    Err(e) => return Err(e),
+                }
+            }
+        });
+        Check { expected, hasher }
+    }
+
+    /// Waits for the digest and fails unless it is the expected one.
+    pub fn finish(self, input: &str) -> anyhow::Result<()> {
+        match self.hasher.join().unwrap() {
+            Ok(Some(digest)) if digest != self.expected => bail!(
+                "{} has SHA-256 {}, expected {}",
+                input,
+                sha256::to_hex(&digest),
+                sha256::to_hex(&self.expected)
+            ),
+            Ok(_) => Ok(()),
+            Err(e) => bail!("failed to hash {}: {}", input, e),
+        }
+    }
+}
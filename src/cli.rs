@@ -0,0 +1,2456 @@
+use crate::affinity;
+use crate::agg::{Agg, Tracking};
+use crate::bloom;
+use crate::collate;
+use crate::diff::DiffArgs;
+use crate::generate::{Compress, Distribution, GenerateArgs, Skew};
+use crate::jsonl::Fields;
+use crate::mmap::Advice;
+use crate::output;
+use crate::profile;
+use crate::query::Query;
+use crate::sha256::{self, Digest};
+use anyhow::{anyhow, bail};
+use std::path::PathBuf;
+use std::time::Duration;
+
+const DEFAULT_INPUT: &str = "data/weather_stations.csv";
+
+/// Inclusive bounds a reading must fall within to be aggregated.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ValueRange {
+    pub min: Option<f64>,
+    pub max: Option<f64>,
+}
+
+impl ValueRange {
+    pub fn is_set(&self) -> bool {
+        self.min.is_some() || self.max.is_some()
+    }
+
+    pub fn contains(&self, value: f64) -> bool {
+        self.min.is_none_or(|min| value >= min) && self.max.is_none_or(|max| value <= max)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Unit {
+    #[default]
+    Celsius,
+    Fahrenheit,
+}
+
+impl Unit {
+    fn parse(v: &str) -> anyhow::Result<Self> {
+        match v.to_ascii_lowercase().as_str() {
+            "c" | "celsius" => Ok(Unit::Celsius),
+            "f" | "fahrenheit" => Ok(Unit::Fahrenheit),
+            _ => bail!("expected `c` or `f`, got `{}`", v),
+        }
+    }
+
+    /// Converts a reading in this unit to `to`.
+    pub fn convert(self, value: f64, to: Unit) -> f64 {
+        match (self, to) {
+            (Unit::Celsius, Unit::Fahrenheit) => value * 9.0 / 5.0 + 32.0,
+            (Unit::Fahrenheit, Unit::Celsius) => (value - 32.0) * 5.0 / 9.0,
+            _ => value,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum KeyNormalization {
+    #[default]
+    None,
+    Lower,
+    Nfc,
+    NfkcLower,
+}
+
+impl KeyNormalization {
+    fn parse(v: &str) -> anyhow::Result<Self> {
+        match v {
+            "none" => Ok(KeyNormalization::None),
+            "lower" => Ok(KeyNormalization::Lower),
+            "nfc" => Ok(KeyNormalization::Nfc),
+            "nfkc-lower" => Ok(KeyNormalization::NfkcLower),
+            _ => bail!("expected one of none, lower, nfc, nfkc-lower, got `{}`", v),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OutputFormat {
+    #[default]
+    Text,
+    Csv,
+    Json,
+    /// Aligned columns for reading in a terminal
+    Table,
+    Markdown,
+    /// Prometheus text exposition format, one gauge sample per aggregate
+    Prom,
+}
+
+impl OutputFormat {
+    pub fn parse(v: &str) -> anyhow::Result<Self> {
+        match v {
+            "text" => Ok(OutputFormat::Text),
+            "csv" => Ok(OutputFormat::Csv),
+            "json" => Ok(OutputFormat::Json),
+            "table" => Ok(OutputFormat::Table),
+            "markdown" => Ok(OutputFormat::Markdown),
+            "prom" => Ok(OutputFormat::Prom),
+            _ => bail!(
+                "expected one of text, csv, json, table, markdown, prom, got `{}`",
+                v
+            ),
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            OutputFormat::Text => "text",
+            OutputFormat::Csv => "csv",
+            OutputFormat::Json => "json",
+            OutputFormat::Table => "table",
+            OutputFormat::Markdown => "markdown",
+            OutputFormat::Prom => "prom",
+        }
+    }
+}
+
+/// What the rows of the output are ordered by.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SortField {
+    /// The key's name, then its window
+    #[default]
+    Name,
+    Min,
+    Mean,
+    Max,
+    Count,
+}
+
+impl SortField {
+    pub fn parse(v: &str) -> anyhow::Result<Self> {
+        match v {
+            "name" => Ok(SortField::Name),
+            "min" => Ok(SortField::Min),
+            "mean" => Ok(SortField::Mean),
+            "max" => Ok(SortField::Max),
+            "count" => Ok(SortField::Count),
+            _ => bail!("expected one of name, min, mean, max, count, got `{}`", v),
+        }
+    }
+}
+
+/// Order of the rows in the output.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Sort {
+    pub field: SortField,
+    pub descending: bool,
+}
+
+/// How station names compare when sorting by name.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Collation {
+    /// UTF-8 byte order, as the challenge specifies
+    #[default]
+    Bytes,
+    /// Letters before accents before case
+    Unicode,
+    /// `Unicode` with the given language's letters of its own
+    Locale(&'static [(char, char)]),
+}
+
+impl Collation {
+    fn parse(v: &str) -> anyhow::Result<Self> {
+        match v {
+            "bytes" => Ok(Collation::Bytes),
+            "unicode" => Ok(Collation::Unicode),
+            _ => match v.strip_prefix("locale:") {
+                Some(language) => match collate::tailoring(&language.to_ascii_lowercase()) {
+                    Some(letters) => Ok(Collation::Locale(letters)),
+                    None => bail!(
+                        "no collation rules for `{}`, expected one of {}",
+                        language,
+                        collate::languages().collect::<Vec<_>>().join(", ")
+                    ),
+                },
+                None => bail!("expected `bytes`, `unicode` or `locale:xx`, got `{}`", v),
+            },
+        }
+    }
+}
+
+/// Whether table output is colored.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Color {
+    /// When writing to a terminal and `NO_COLOR` is not set
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+impl Color {
+    fn parse(v: &str) -> anyhow::Result<Self> {
+        match v {
+            "auto" => Ok(Color::Auto),
+            "always" => Ok(Color::Always),
+            "never" => Ok(Color::Never),
+            _ => bail!("expected one of auto, always, never, got `{}`", v),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum InputFormat {
+    /// `station;value` lines
+    #[default]
+    Csv,
+    /// One JSON object per line
+    Jsonl,
+}
+
+impl InputFormat {
+    fn parse(v: &str) -> anyhow::Result<Self> {
+        match v {
+            "csv" => Ok(InputFormat::Csv),
+            "jsonl" => Ok(InputFormat::Jsonl),
+            _ => bail!("expected `csv` or `jsonl`, got `{}`", v),
+        }
+    }
+}
+
+/// How the bytes of the input encode its text. A UTF-8 byte order mark is
+/// skipped either way.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Encoding {
+    #[default]
+    Utf8,
+    /// Little-endian UTF-16, as Windows tools write it, transcoded to UTF-8
+    /// a block at a time. Rows are still told apart by their offsets in
+    /// the file
+    Utf16Le,
+}
+
+impl Encoding {
+    fn parse(v: &str) -> anyhow::Result<Self> {
+        match v {
+            "utf8" => Ok(Encoding::Utf8),
+            "utf16le" => Ok(Encoding::Utf16Le),
+            _ => bail!("expected `utf8` or `utf16le`, got `{}`", v),
+        }
+    }
+}
+
+/// How the input file is read.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum IoBackend {
+    /// Buffered `read` calls
+    #[default]
+    Read,
+    /// A read-only memory mapping of the whole file
+    Mmap,
+}
+
+impl IoBackend {
+    fn parse(v: &str) -> anyhow::Result<Self> {
+        match v {
+            "read" => Ok(IoBackend::Read),
+            "mmap" => Ok(IoBackend::Mmap),
+            _ => bail!("expected `read` or `mmap`, got `{}`", v),
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            IoBackend::Read => "read",
+            IoBackend::Mmap => "mmap",
+        }
+    }
+}
+
+/// How the work is spread over threads.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Executor {
+    /// `threads` unless the options need a single reader or records that
+    /// span lines, and `pipeline` then. Resolved by [`parse_args`]
+    #[default]
+    Auto,
+    /// A reader dealing chunks of lines out to worker threads over channels
+    Pipeline,
+    /// One scoped thread per byte range of the file, each folding its range
+    /// into its own map
+    Threads,
+    /// The pipeline with async workers on a tokio runtime
+    #[cfg(feature = "runtime-tokio")]
+    Tokio,
+}
+
+impl Executor {
+    fn parse(v: &str) -> anyhow::Result<Self> {
+        match v {
+            "auto" => Ok(Executor::Auto),
+            "pipeline" => Ok(Executor::Pipeline),
+            "threads" => Ok(Executor::Threads),
+            #[cfg(feature = "runtime-tokio")]
+            "tokio" => Ok(Executor::Tokio),
+            #[cfg(not(feature = "runtime-tokio"))]
+            "tokio" => bail!("this build has no tokio runtime, see the `runtime-tokio` feature"),
+            _ => bail!(
+                "expected `auto`, `pipeline`, `threads` or `tokio`, got `{}`",
+                v
+            ),
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Executor::Auto => "auto",
+            Executor::Pipeline => "pipeline",
+            Executor::Threads => "threads",
+            #[cfg(feature = "runtime-tokio")]
+            Executor::Tokio => "tokio",
+        }
+    }
+}
+
+/// Where the workers aggregate.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Strategy {
+    /// A map per worker, merged at the end
+    #[default]
+    Local,
+    /// One sharded map that every worker updates
+    SharedMap,
+    /// A map per worker, updated a parsed block at a time
+    Vectorized,
+    /// A map per worker behind a small table indexed by name prefixes
+    PrefixTable,
+}
+
+impl Strategy {
+    fn parse(v: &str) -> anyhow::Result<Self> {
+        match v {
+            "local" => Ok(Strategy::Local),
+            "shared-map" => Ok(Strategy::SharedMap),
+            "vectorized" => Ok(Strategy::Vectorized),
+            "prefix-table" => Ok(Strategy::PrefixTable),
+            _ => bail!(
+                "expected `local`, `shared-map`, `vectorized` or `prefix-table`, got `{}`",
+                v
+            ),
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Strategy::Local => "local",
+            Strategy::SharedMap => "shared-map",
+            Strategy::Vectorized => "vectorized",
+            Strategy::PrefixTable => "prefix-table",
+        }
+    }
+
+    /// Whether a worker's keys can be spilled to disk, which takes all of
+    /// them being in its map.
+    pub fn spills(self) -> bool {
+        matches!(self, Strategy::Local | Strategy::Vectorized)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Numa {
+    /// Split the work across NUMA nodes when there is more than one
+    Auto,
+    #[default]
+    Off,
+}
+
+impl Numa {
+    fn parse(v: &str) -> anyhow::Result<Self> {
+        match v {
+            "auto" => Ok(Numa::Auto),
+            "off" => Ok(Numa::Off),
+            _ => bail!("expected `auto` or `off`, got `{}`", v),
+        }
+    }
+}
+
+/// How `--partition-output-by` splits the results into files.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Partitioning {
+    /// By the first character of the key, lowercased
+    FirstLetter,
+    /// Into N files by a hash of the key, the same in every build
+    StationHash(usize),
+}
+
+impl Partitioning {
+    fn parse(v: &str) -> anyhow::Result<Self> {
+        match v.split_once(':') {
+            None if v == "first-letter" => Ok(Partitioning::FirstLetter),
+            Some(("station-hash", n)) => match parse_count(n)? {
+                0 => bail!("station-hash needs at least one partition"),
+                n => Ok(Partitioning::StationHash(n)),
+            },
+            _ => bail!("expected `first-letter` or `station-hash:N`, got `{}`", v),
+        }
+    }
+}
+
+/// What is collected per key.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Mode {
+    /// Min, mean and max of the readings
+    #[default]
+    Aggregate,
+    /// Just the number of rows, without parsing readings
+    CountOnly,
+    /// Just the distinct keys
+    ListStations,
+}
+
+/// What happens to rows whose reading is one of the configured null values.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum NullPolicy {
+    /// Drop them silently
+    #[default]
+    Skip,
+    /// Drop them, but report how many there were per station
+    Count,
+}
+
+impl NullPolicy {
+    fn parse(v: &str) -> anyhow::Result<Self> {
+        match v {
+            "skip" => Ok(NullPolicy::Skip),
+            "count" => Ok(NullPolicy::Count),
+            _ => bail!("expected `skip` or `count`, got `{}`", v),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum GroupBy {
+    #[default]
+    Station,
+    Country,
+}
+
+/// How much is said besides the results, all of it on stderr.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Verbosity {
+    /// Only errors and what `--stats` asks for
+    Quiet,
+    #[default]
+    Normal,
+    /// Also the options in effect, how long each phase took and the missing
+    /// readings per station
+    Verbose,
+    /// Also how much each worker got through
+    Workers,
+}
+
+impl GroupBy {
+    fn parse(v: &str) -> anyhow::Result<Self> {
+        match v {
+            "station" => Ok(GroupBy::Station),
+            "country" => Ok(GroupBy::Country),
+            _ => bail!("expected `station` or `country`, got `{}`", v),
+        }
+    }
+
+    /// Name of the key column in tabular output.
+    pub fn label(self) -> &'static str {
+        match self {
+            GroupBy::Station => "station",
+            GroupBy::Country => "country",
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Config {
+    /// The file being aggregated, one of `inputs` in turn
+    pub input: String,
+    pub inputs: Vec<String>,
+    pub range: ValueRange,
+    /// Keys the rows are limited to, all of them when empty
+    pub stations: Vec<String>,
+    pub input_unit: Unit,
+    /// Unit results are reported in, defaults to `input_unit`.
+    pub unit: Option<Unit>,
+    pub group_by_prefix: Option<usize>,
+    /// File of `old;current` station names, applied before the other key
+    /// rules
+    pub aliases: Option<String>,
+    pub key_map: Option<String>,
+    pub normalize_keys: KeyNormalization,
+    pub metadata: Option<String>,
+    pub group_by: GroupBy,
+    pub format: OutputFormat,
+    /// Decimals the readings' aggregates are shown with
+    pub precision: usize,
+    /// HTML report written in addition to the output
+    pub report: Option<PathBuf>,
+    /// Where a record of the run is written, for auditing its results
+    pub manifest: Option<PathBuf>,
+    /// A manifest of an earlier run that this one must match
+    pub verify_manifest: Option<PathBuf>,
+    /// What the input must hash to, checked alongside the scan
+    pub expect_sha256: Option<Digest>,
+    /// Results of each input as well as their total
+    pub per_file: bool,
+    /// Write the results to one file per partition in `partition_dir`
+    /// instead of to stdout
+    pub partition_by: Option<Partitioning>,
+    pub partition_dir: PathBuf,
+    /// Also write the rows behind each partition's results
+    pub partition_rows: bool,
+    /// Run over the results, and shown instead of them
+    pub query: Option<Query>,
+    /// File the rows that pass the filters are copied to
+    pub export_rows: Option<PathBuf>,
+    /// Keep exported rows in input order rather than as workers finish
+    pub preserve_order: bool,
+    /// Aggregates reported per key, each format's own when empty
+    pub agg: Vec<Agg>,
+    /// Keep where each key's minimum and maximum came from, shown with `-v`
+    pub track_extremes: bool,
+    /// Count the lines that repeat one before them in the same input
+    pub detect_duplicates: bool,
+    /// Leave out the rows that repeat one before them in the same input
+    pub dedupe: bool,
+    /// The share of rows `--dedupe` may wrongly leave out, the default's
+    /// when not given
+    pub dedupe_false_positives: Option<f64>,
+    pub sort: Sort,
+    pub collation: Collation,
+    pub color: Color,
+    pub mode: Mode,
+    /// Print about how many rows each input has, from a sample of it,
+    /// instead of reading it all
+    pub estimate_rows: bool,
+    /// Width in seconds of the time windows readings are bucketed into.
+    /// Input lines are `timestamp;station;value` when set.
+    pub window: Option<i64>,
+    /// Fields of each row holding a reading, counted from 1, when there
+    /// are several. Each is aggregated on its own
+    pub value_columns: Vec<u16>,
+    pub input_format: InputFormat,
+    pub encoding: Encoding,
+    pub json_fields: Fields,
+    /// Parse quoted CSV fields, which may contain the delimiter or newlines
+    pub csv_strict: bool,
+    /// Accept integer and exponent readings, not just `-?\d+\.\d+`
+    pub lenient_numbers: bool,
+    /// Every reading is `-?\d?\d\.\d`, so the word-at-a-time parser can
+    /// be used
+    pub strict_1brc: bool,
+    /// Go on without workers that fail, leaving out their results
+    pub best_effort: bool,
+    /// Readings treated as missing rather than as parse errors
+    pub null_values: Vec<String>,
+    pub null_policy: NullPolicy,
+    pub verbosity: Verbosity,
+    /// Show a live dashboard of the run on stderr
+    pub tui: bool,
+    /// Check the results against a sequential run, showing which chunks
+    /// each differing station's readings came from
+    pub debug_provenance: bool,
+    /// Estimated bytes the workers' maps may use together before spilling
+    /// partial aggregates to `spill_dir`
+    pub spill_budget: Option<usize>,
+    pub spill_dir: PathBuf,
+    /// Where results are cached, keyed by the input and the options that
+    /// affect them
+    pub cache_dir: Option<PathBuf>,
+    /// Directory whose new files are aggregated into running totals,
+    /// instead of `input`
+    pub watch_dir: Option<PathBuf>,
+    /// Run `watch_dir` as a service: log records instead of output,
+    /// readiness notifications and reloads on SIGHUP
+    pub daemon: bool,
+    /// Answer queries at a prompt after aggregating, for `1brc repl`
+    pub repl: bool,
+    /// The results are wanted whole once the scan is over, by a
+    /// [`Processor`](crate::processor::Processor), and not written out
+    pub keep_results: bool,
+    /// Check the inputs against the challenge's rules instead, for
+    /// `1brc validate`
+    pub validate: bool,
+    pub max_memory: Option<usize>,
+    /// How many stations to size the maps for up front
+    pub expected_stations: Option<usize>,
+    /// Rates the workers are held to, on average
+    pub max_rows_per_sec: Option<u64>,
+    pub max_bytes_per_sec: Option<u64>,
+    /// How long the scan may take before it is cancelled
+    pub timeout: Option<Duration>,
+    /// Print what was aggregated by then rather than fail
+    pub partial_on_timeout: bool,
+    pub stats: bool,
+    /// Allocations per phase along with the stats
+    pub alloc_stats: bool,
+    /// The profile whose options came before the command line's, and the
+    /// file it was read from
+    pub profile: Option<String>,
+    pub config_file: Option<PathBuf>,
+    pub io: IoBackend,
+    /// Buffers in the read-ahead ring of the `read` backend, 0 to read on
+    /// the dispatching thread instead
+    pub read_ahead: usize,
+    /// Size of each read buffer of the `read` backend, by default 8 KiB
+    /// without read-ahead and 64 KiB with it
+    pub read_buffer: Option<usize>,
+    /// Size of the blocks of lines dealt out to the workers
+    pub chunk_bytes: usize,
+    /// Longest record accepted, beyond which the input is taken to be
+    /// corrupt rather than read on in search of its end
+    pub max_line_bytes: usize,
+    /// Hints for the mapping under `--io mmap`
+    pub madvise: Vec<Advice>,
+    /// Cores the worker threads are pinned to, one thread per core
+    pub pin_cores: Option<Vec<usize>>,
+    pub pin_reader: Option<usize>,
+    /// Niceness of every thread, from -20 to 19
+    pub nice: Option<i32>,
+    /// Every thread in the idle I/O class
+    pub idle_io: bool,
+    /// Readers sharing the input in interleaved stripes
+    pub readers: usize,
+    pub numa: Numa,
+    /// Worker count, by default one per CPU the cgroup quota allows
+    pub threads: Option<usize>,
+    pub executor: Executor,
+    pub strategy: Strategy,
+    /// Use the portable delimiter scanner even where SIMD is available
+    pub force_scalar: bool,
+}
+
+impl Config {
+    pub fn output_unit(&self) -> Unit {
+        self.unit.unwrap_or(self.input_unit)
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            input: DEFAULT_INPUT.to_string(),
+            inputs: vec![DEFAULT_INPUT.to_string()],
+            range: ValueRange::default(),
+            stations: Vec::new(),
+            input_unit: Unit::default(),
+            unit: None,
+            group_by_prefix: None,
+            aliases: None,
+            key_map: None,
+            normalize_keys: KeyNormalization::default(),
+            metadata: None,
+            group_by: GroupBy::default(),
+            format: OutputFormat::default(),
+            precision: 1,
+            report: None,
+            manifest: None,
+            verify_manifest: None,
+            expect_sha256: None,
+            per_file: false,
+            partition_by: None,
+            partition_dir: PathBuf::from("partitions"),
+            partition_rows: false,
+            query: None,
+            export_rows: None,
+            preserve_order: false,
+            agg: vec![],
+            track_extremes: false,
+            detect_duplicates: false,
+            dedupe: false,
+            dedupe_false_positives: None,
+            sort: Sort::default(),
+            collation: Collation::default(),
+            color: Color::default(),
+            mode: Mode::default(),
+            estimate_rows: false,
+            window: None,
+            value_columns: vec![],
+            input_format: InputFormat::default(),
+            encoding: Encoding::default(),
+            json_fields: Fields::default(),
+            csv_strict: false,
+            lenient_numbers: false,
+            strict_1brc: false,
+            best_effort: false,
+            null_values: Vec::new(),
+            null_policy: NullPolicy::default(),
+            verbosity: Verbosity::Normal,
+            tui: false,
+            debug_provenance: false,
+            spill_budget: None,
+            spill_dir: std::env::temp_dir(),
+            cache_dir: None,
+            watch_dir: None,
+            daemon: false,
+            repl: false,
+            keep_results: false,
+            validate: false,
+            max_memory: None,
+            expected_stations: None,
+            max_rows_per_sec: None,
+            timeout: None,
+            partial_on_timeout: false,
+            max_bytes_per_sec: None,
+            stats: false,
+            alloc_stats: false,
+            profile: None,
+            config_file: None,
+            io: IoBackend::default(),
+            read_ahead: 2,
+            read_buffer: None,
+            chunk_bytes: 4 << 20,
+            max_line_bytes: 8 << 10,
+            madvise: Vec::new(),
+            pin_cores: None,
+            pin_reader: None,
+            nice: None,
+            idle_io: false,
+            readers: 1,
+            numa: Numa::default(),
+            threads: None,
+            executor: Executor::default(),
+            strategy: Strategy::default(),
+            force_scalar: false,
+        }
+    }
+}
+
+/// A shell `1brc completions` writes a script for.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Shell {
+    Bash,
+    Zsh,
+    Fish,
+}
+
+impl Shell {
+    pub fn parse(v: &str) -> anyhow::Result<Self> {
+        match v {
+            "bash" => Ok(Shell::Bash),
+            "zsh" => Ok(Shell::Zsh),
+            "fish" => Ok(Shell::Fish),
+            _ => bail!("expected one of bash, zsh, fish, got `{}`", v),
+        }
+    }
+}
+
+pub enum Command {
+    Run(Box<Config>),
+    Diff(DiffArgs),
+    Generate(GenerateArgs),
+    /// Print a completion script for the shell
+    Completions(Shell),
+    /// Print the manual page, in roff
+    Man,
+    Help,
+    Version,
+}
+
+/// The subcommands, with what follows each in the usage.
+pub const SUBCOMMANDS: &[(&str, &str)] = &[
+    ("repl", "[OPTIONS] [--input FILE]"),
+    (
+        "validate",
+        "[--max-line-bytes SIZE] [--input FILE] [FILE...]",
+    ),
+    ("diff", "A B [--tolerance N]"),
+    ("generate", "[GENERATOR-OPTIONS]"),
+    ("completions", "bash|zsh|fish"),
+    ("man", ""),
+];
+
+/// The options every command takes besides [`OPTIONS`]: short, long and
+/// help.
+pub const BUILT_IN: &[(char, &str, &str)] = &[
+    ('h', "help", "Print this help"),
+    ('V', "version", "Print version and build information"),
+];
+
+/// A command line option. Flags have no `value`, and their `apply` is
+/// called with an empty string.
+pub struct Opt<T: 'static = Config> {
+    pub long: &'static str,
+    pub short: Option<char>,
+    pub value: Option<&'static str>,
+    pub help: &'static str,
+    apply: fn(&mut T, &str) -> anyhow::Result<()>,
+}
+
+pub const OPTIONS: &[Opt] = &[
+    Opt {
+        long: "min-value",
+        short: None,
+        value: Some("N"),
+        help: "Exclude readings below N (in the output unit)",
+        apply: |c, v| {
+            c.range.min = Some(parse_number(v)?);
+            Ok(())
+        },
+    },
+    Opt {
+        long: "max-value",
+        short: None,
+        value: Some("N"),
+        help: "Exclude readings above N (in the output unit)",
+        apply: |c, v| {
+            c.range.max = Some(parse_number(v)?);
+            Ok(())
+        },
+    },
+    Opt {
+        long: "station",
+        short: None,
+        value: Some("NAME"),
+        help: "Only include rows of NAME, after key mapping; may be given more than once",
+        apply: |c, v| {
+            c.stations.push(v.to_string());
+            Ok(())
+        },
+    },
+    Opt {
+        long: "unit",
+        short: None,
+        value: Some("c|f"),
+        help: "Report results in Celsius or Fahrenheit",
+        apply: |c, v| {
+            c.unit = Some(Unit::parse(v)?);
+            Ok(())
+        },
+    },
+    Opt {
+        long: "input-unit",
+        short: None,
+        value: Some("c|f"),
+        help: "Unit of the readings in the input (default: c)",
+        apply: |c, v| {
+            c.input_unit = Unit::parse(v)?;
+            Ok(())
+        },
+    },
+    Opt {
+        long: "group-by-prefix",
+        short: None,
+        value: Some("N"),
+        help: "Aggregate stations under the first N words of their name",
+        apply: |c, v| {
+            let n = parse_count(v)?;
+            if n == 0 {
+                bail!("must be at least 1");
+            }
+            c.group_by_prefix = Some(n);
+            Ok(())
+        },
+    },
+    Opt {
+        long: "aliases",
+        short: None,
+        value: Some("FILE"),
+        help: "Aggregate stations that were renamed under their current name, from \
+               `old;current` lines in FILE",
+        apply: |c, v| {
+            c.aliases = Some(v.to_string());
+            Ok(())
+        },
+    },
+    Opt {
+        long: "key-map",
+        short: None,
+        value: Some("FILE"),
+        help: "Aggregate stations under the keys given by `station;key` lines in FILE",
+        apply: |c, v| {
+            c.key_map = Some(v.to_string());
+            Ok(())
+        },
+    },
+    Opt {
+        long: "normalize-keys",
+        short: None,
+        value: Some("none|lower|nfc|nfkc-lower"),
+        help: "Merge station names differing only in case or Unicode normalization form",
+        apply: |c, v| {
+            c.normalize_keys = KeyNormalization::parse(v)?;
+            Ok(())
+        },
+    },
+    Opt {
+        long: "metadata",
+        short: None,
+        value: Some("FILE"),
+        help: "Join `station;country;lat;lon` lines from FILE into CSV and JSON output",
+        apply: |c, v| {
+            c.metadata = Some(v.to_string());
+            Ok(())
+        },
+    },
+    Opt {
+        long: "group-by",
+        short: None,
+        value: Some("station|country"),
+        help: "Aggregate by station or by its metadata country (default: station)",
+        apply: |c, v| {
+            c.group_by = GroupBy::parse(v)?;
+            Ok(())
+        },
+    },
+    Opt {
+        long: "format",
+        short: None,
+        value: Some("text|csv|json|table|markdown|prom"),
+        help: "Output format (default: text)",
+        apply: |c, v| {
+            c.format = OutputFormat::parse(v)?;
+            Ok(())
+        },
+    },
+    Opt {
+        long: "precision",
+        short: None,
+        value: Some("N"),
+        help: "Decimals of the min, mean, max and other aggregates, in every format \
+               (default: 1, as the challenge asks)",
+        apply: |c, v| {
+            let precision = parse_count(v)?;
+            if precision > 15 {
+                bail!("at most 15 decimals are meaningful, got {}", precision);
+            }
+            c.precision = precision;
+            Ok(())
+        },
+    },
+    Opt {
+        long: "report",
+        short: None,
+        value: Some("FILE"),
+        help: "Also write an HTML report of the results, the configuration and throughput to FILE",
+        apply: |c, v| {
+            c.report = Some(PathBuf::from(v));
+            Ok(())
+        },
+    },
+    Opt {
+        long: "manifest",
+        short: None,
+        value: Some("FILE"),
+        help: "Also write the build, options, input hashes, worker byte ranges and a hash of the results to FILE",
+        apply: |c, v| {
+            c.manifest = Some(PathBuf::from(v));
+            Ok(())
+        },
+    },
+    Opt {
+        long: "verify-manifest",
+        short: None,
+        value: Some("FILE"),
+        help: "Fail unless the build, options, inputs and results match the manifest in FILE",
+        apply: |c, v| {
+            c.verify_manifest = Some(PathBuf::from(v));
+            Ok(())
+        },
+    },
+    Opt {
+        long: "expect-sha256",
+        short: None,
+        value: Some("HEX"),
+        help: "Fail the run unless the input has this SHA-256, computed while it is aggregated",
+        apply: |c, v| {
+            c.expect_sha256 = Some(
+                sha256::from_hex(v)
+                    .ok_or_else(|| anyhow!("expected 64 hex digits, got `{}`", v))?,
+            );
+            Ok(())
+        },
+    },
+    Opt {
+        long: "per-file",
+        short: None,
+        value: None,
+        help: "With several input files, show each file's results followed by their total",
+        apply: |c, _| {
+            c.per_file = true;
+            Ok(())
+        },
+    },
+    Opt {
+        long: "partition-output-by",
+        short: None,
+        value: Some("SCHEME"),
+        help: "Write the results to a file per partition, by `first-letter` or `station-hash:N`",
+        apply: |c, v| {
+            c.partition_by = Some(Partitioning::parse(v)?);
+            Ok(())
+        },
+    },
+    Opt {
+        long: "partition-dir",
+        short: None,
+        value: Some("DIR"),
+        help: "Directory for the partition files (default: partitions)",
+        apply: |c, v| {
+            c.partition_dir = PathBuf::from(v);
+            Ok(())
+        },
+    },
+    Opt {
+        long: "partition-rows",
+        short: None,
+        value: None,
+        help: "Also write the rows that made it into the results, by partition",
+        apply: |c, _| {
+            c.partition_rows = true;
+            Ok(())
+        },
+    },
+    Opt {
+        long: "export-rows",
+        short: None,
+        value: Some("FILE"),
+        help: "Also copy the rows that pass --station and the value filters to FILE",
+        apply: |c, v| {
+            c.export_rows = Some(PathBuf::from(v));
+            Ok(())
+        },
+    },
+    Opt {
+        long: "preserve-order",
+        short: None,
+        value: None,
+        help: "Export rows in input order instead of as the workers get to them",
+        apply: |c, _| {
+            c.preserve_order = true;
+            Ok(())
+        },
+    },
+    Opt {
+        long: "query",
+        short: None,
+        value: Some("SQL"),
+        help: "Show the result of SQL such as `SELECT station, max FROM results WHERE max > 40` instead",
+        apply: |c, v| {
+            c.query = Some(Query::parse(v)?);
+            Ok(())
+        },
+    },
+    Opt {
+        long: "agg",
+        short: None,
+        value: Some("LIST"),
+        help: "Report these aggregates, in this order: min, mean, max, count, sum, stddev \
+               and percentiles p0 to p100 (default: min, mean and max)",
+        apply: |c, v| {
+            c.agg = Agg::parse_list(v)?;
+            Ok(())
+        },
+    },
+    Opt {
+        long: "track-extremes",
+        short: None,
+        value: None,
+        help: "Keep the byte offset of the row of each key's min and max, shown with -v, and its line with -vv",
+        apply: |c, _| {
+            c.track_extremes = true;
+            Ok(())
+        },
+    },
+    Opt {
+        long: "detect-duplicates",
+        short: None,
+        value: None,
+        help: "Count the lines that repeat an earlier line of the same input exactly",
+        apply: |c, _| {
+            c.detect_duplicates = true;
+            Ok(())
+        },
+    },
+    Opt {
+        long: "dedupe",
+        short: None,
+        value: None,
+        help: "Leave out the rows that repeat an earlier row of the same input exactly, \
+               telling them apart with a Bloom filter",
+        apply: |c, _| {
+            c.dedupe = true;
+            Ok(())
+        },
+    },
+    Opt {
+        long: "dedupe-fp",
+        short: None,
+        value: Some("RATE"),
+        help: "Share of distinct rows --dedupe may take for repeats and leave out, \
+               from 0.00001 to 0.1 (default: 0.0001)",
+        apply: |c, v| {
+            let rate = parse_number(v)?;
+            if !(bloom::MIN_FALSE_POSITIVES..=0.1).contains(&rate) {
+                bail!("expected a rate from 0.00001 to 0.1, got {}", v);
+            }
+            c.dedupe_false_positives = Some(rate);
+            Ok(())
+        },
+    },
+    Opt {
+        long: "sort-by",
+        short: None,
+        value: Some("name|min|mean|max|count"),
+        help: "Order the results by this field, ties by name (default: name)",
+        apply: |c, v| {
+            c.sort.field = SortField::parse(v)?;
+            Ok(())
+        },
+    },
+    Opt {
+        long: "desc",
+        short: None,
+        value: None,
+        help: "Sort in descending order",
+        apply: |c, _| {
+            c.sort.descending = true;
+            Ok(())
+        },
+    },
+    Opt {
+        long: "collation",
+        short: None,
+        value: Some("bytes|unicode|locale:xx"),
+        help: "How names are ordered when sorting by name (default: bytes)",
+        apply: |c, v| {
+            c.collation = Collation::parse(v)?;
+            Ok(())
+        },
+    },
+    Opt {
+        long: "color",
+        short: None,
+        value: Some("auto|always|never"),
+        help: "Color --format table output (default: auto)",
+        apply: |c, v| {
+            c.color = Color::parse(v)?;
+            Ok(())
+        },
+    },
+    Opt {
+        long: "count-only",
+        short: None,
+        value: None,
+        help: "Only count the rows per station, without parsing readings",
+        apply: |c, _| set_mode(c, Mode::CountOnly),
+    },
+    Opt {
+        long: "list-stations",
+        short: None,
+        value: None,
+        help: "Only list the distinct stations, without parsing readings",
+        apply: |c, _| set_mode(c, Mode::ListStations),
+    },
+    Opt {
+        long: "estimate-rows",
+        short: None,
+        value: None,
+        help: "Only estimate the rows of each input from a sample of it, with 95% bounds",
+        apply: |c, _| {
+            c.estimate_rows = true;
+            Ok(())
+        },
+    },
+    Opt {
+        long: "window",
+        short: None,
+        value: Some("DURATION"),
+        help: "Read `timestamp;station;value` lines and aggregate per window (e.g. 15m, 1h)",
+        apply: |c, v| {
+            c.window = Some(crate::time::parse_duration(v)?);
+            Ok(())
+        },
+    },
+    Opt {
+        long: "value-columns",
+        short: None,
+        value: Some("LIST"),
+        help: "Read several readings per row from these fields, counted from 1, and aggregate \
+               each per station (e.g. 2,3,4 for `station;temp;humidity;pressure`)",
+        apply: |c, v| {
+            let mut columns = vec![];
+            for column in v.split(',') {
+                let column: u16 = column
+                    .trim()
+                    .parse()
+                    .map_err(|_| anyhow!("expected a field number, got `{}`", column))?;
+                if columns.contains(&column) {
+                    bail!("field {} is listed twice", column);
+                }
+                columns.push(column);
+            }
+            c.value_columns = columns;
+            Ok(())
+        },
+    },
+    Opt {
+        long: "input-format",
+        short: None,
+        value: Some("csv|jsonl"),
+        help: "Read `station;value` lines or JSON objects, one per line (default: csv)",
+        apply: |c, v| {
+            c.input_format = InputFormat::parse(v)?;
+            Ok(())
+        },
+    },
+    Opt {
+        long: "encoding",
+        short: None,
+        value: Some("utf8|utf16le"),
+        help: "Read the input as UTF-8 or as little-endian UTF-16 (default: utf8)",
+        apply: |c, v| {
+            c.encoding = Encoding::parse(v)?;
+            Ok(())
+        },
+    },
+    Opt {
+        long: "station-field",
+        short: None,
+        value: Some("NAME"),
+        help: "JSON field holding the station name (default: station)",
+        apply: |c, v| {
+            c.json_fields.station = v.to_string();
+            Ok(())
+        },
+    },
+    Opt {
+        long: "value-field",
+        short: None,
+        value: Some("NAME"),
+        help: "JSON field holding the reading (default: temp)",
+        apply: |c, v| {
+            c.json_fields.value = v.to_string();
+            Ok(())
+        },
+    },
+    Opt {
+        long: "timestamp-field",
+        short: None,
+        value: Some("NAME"),
+        help: "JSON field holding the timestamp used with --window (default: timestamp)",
+        apply: |c, v| {
+            c.json_fields.timestamp = v.to_string();
+            Ok(())
+        },
+    },
+    Opt {
+        long: "csv-strict",
+        short: None,
+        value: None,
+        help: "Parse RFC 4180 quoted fields, which may contain `;`, quotes or newlines",
+        apply: |c, _| {
+            c.csv_strict = true;
+            Ok(())
+        },
+    },
+    Opt {
+        long: "lenient-numbers",
+        short: None,
+        value: None,
+        help: "Accept readings like `5` or `1.2e1`, not just decimals like `5.0`",
+        apply: |c, _| {
+            c.lenient_numbers = true;
+            Ok(())
+        },
+    },
+    Opt {
+        long: "strict-1brc",
+        short: None,
+        value: None,
+        help: "Only take readings like `-12.3` or `4.5`, as the challenge writes them, with a faster parser",
+        apply: |c, _| {
+            c.strict_1brc = true;
+            Ok(())
+        },
+    },
+    Opt {
+        long: "null-values",
+        short: None,
+        value: Some("LIST"),
+        help: "Comma separated readings that mean missing, `''` for empty (e.g. NA,null,'')",
+        apply: |c, v| {
+            c.null_values = v
+                .split(',')
+                .map(|n| match n.trim() {
+                    "''" | "\"\"" => String::new(),
+                    n => n.to_string(),
+                })
+                .collect();
+            Ok(())
+        },
+    },
+    Opt {
+        long: "null-policy",
+        short: None,
+        value: Some("skip|count"),
+        help: "Skip rows with missing readings silently, or count them (default: skip)",
+        apply: |c, v| {
+            c.null_policy = NullPolicy::parse(v)?;
+            Ok(())
+        },
+    },
+    Opt {
+        long: "verbose",
+        short: Some('v'),
+        value: None,
+        help: "Say more on stderr: the options, phase times and missing readings, with -vv per worker",
+        apply: |c, _| {
+            if c.verbosity == Verbosity::Quiet {
+                bail!("--quiet and --verbose cannot be combined");
+            }
+            c.verbosity = match c.verbosity {
+                Verbosity::Normal => Verbosity::Verbose,
+                _ => Verbosity::Workers,
+            };
+            Ok(())
+        },
+    },
+    Opt {
+        long: "quiet",
+        short: Some('q'),
+        value: None,
+        help: "Print the results alone, and nothing on stderr but errors and --stats",
+        apply: |c, _| {
+            if c.verbosity > Verbosity::Normal {
+                bail!("--quiet and --verbose cannot be combined");
+            }
+            c.verbosity = Verbosity::Quiet;
+            Ok(())
+        },
+    },
+    Opt {
+        long: "tui",
+        short: None,
+        value: None,
+        help: "Show live progress, throughput and the hottest stations on stderr",
+        apply: |c, _| {
+            c.tui = true;
+            Ok(())
+        },
+    },
+    Opt {
+        long: "debug-provenance",
+        short: None,
+        value: None,
+        help: "Compare the results with a sequential run and show where differing stations' \
+               readings came from",
+        apply: |c, _| {
+            c.debug_provenance = true;
+            Ok(())
+        },
+    },
+    Opt {
+        long: "spill-budget",
+        short: None,
+        value: Some("SIZE"),
+        help: "Spill partial aggregates to disk once the maps use about SIZE (e.g. 512M)",
+        apply: |c, v| {
+            c.spill_budget = Some(parse_size(v)?);
+            Ok(())
+        },
+    },
+    Opt {
+        long: "expected-stations",
+        short: None,
+        value: Some("N"),
+        help: "Size the maps for about N stations up front instead of growing them (see --stats)",
+        apply: |c, v| {
+            c.expected_stations = Some(parse_count(v)?);
+            Ok(())
+        },
+    },
+    Opt {
+        long: "spill-dir",
+        short: None,
+        value: Some("DIR"),
+        help: "Directory for spill files (default: the system temp directory)",
+        apply: |c, v| {
+            c.spill_dir = PathBuf::from(v);
+            Ok(())
+        },
+    },
+    Opt {
+        long: "watch-dir",
+        short: None,
+        value: Some("DIR"),
+        help: "Instead of one file, keep adding new files in DIR to running totals, moving them to DIR/done",
+        apply: |c, v| {
+            c.watch_dir = Some(PathBuf::from(v));
+            Ok(())
+        },
+    },
+    Opt {
+        long: "daemon",
+        short: None,
+        value: None,
+        help: "Run --watch-dir as a systemd service: logs to the journal or as JSON, readiness, reload on SIGHUP",
+        apply: |c, _| {
+            c.daemon = true;
+            Ok(())
+        },
+    },
+    Opt {
+        long: "cache-dir",
+        short: None,
+        value: Some("DIR"),
+        help: "Reuse the results of an earlier run over the same input and options from DIR",
+        apply: |c, v| {
+            c.cache_dir = Some(PathBuf::from(v));
+            Ok(())
+        },
+    },
+    Opt {
+        long: "max-memory",
+        short: None,
+        value: Some("SIZE"),
+        help: "Keep maps, queues and buffers under SIZE, spilling and throttling as needed",
+        apply: |c, v| {
+            c.max_memory = Some(parse_size(v)?);
+            Ok(())
+        },
+    },
+    Opt {
+        long: "max-rows-per-sec",
+        short: None,
+        value: Some("N"),
+        help: "Aggregate at most N rows a second on average, sleeping between blocks",
+        apply: |c, v| {
+            let n = parse_count(v)?;
+            if n == 0 {
+                bail!("the rate must be at least one row a second");
+            }
+            c.max_rows_per_sec = Some(n as u64);
+            Ok(())
+        },
+    },
+    Opt {
+        long: "max-bytes-per-sec",
+        short: None,
+        value: Some("SIZE"),
+        help: "Read at most SIZE of input a second on average, sleeping between blocks",
+        apply: |c, v| {
+            let n = parse_size(v)?;
+            if n == 0 {
+                bail!("the rate must be at least one byte a second");
+            }
+            c.max_bytes_per_sec = Some(n as u64);
+            Ok(())
+        },
+    },
+    Opt {
+        long: "timeout",
+        short: None,
+        value: Some("DURATION"),
+        help: "Give up on the run after DURATION (e.g. 300s, 5m), exiting with 124",
+        apply: |c, v| {
+            let secs = crate::time::parse_duration(v)?;
+            c.timeout = Some(Duration::from_secs(secs as u64));
+            Ok(())
+        },
+    },
+    Opt {
+        long: "partial-on-timeout",
+        short: None,
+        value: None,
+        help: "Print the results of the input read so far when --timeout runs out",
+        apply: |c, _| {
+            c.partial_on_timeout = true;
+            Ok(())
+        },
+    },
+    Opt {
+        long: "io",
+        short: None,
+        value: Some("read|mmap"),
+        help: "Read the input with buffered reads or a memory mapping (default: read)",
+        apply: |c, v| {
+            c.io = IoBackend::parse(v)?;
+            Ok(())
+        },
+    },
+    Opt {
+        long: "read-ahead",
+        short: None,
+        value: Some("N"),
+        help: "Buffers read ahead on a separate thread by the read backend, 0 for none (default: 2)",
+        apply: |c, v| {
+            c.read_ahead = parse_count(v)?;
+            Ok(())
+        },
+    },
+    Opt {
+        long: "read-buffer",
+        short: None,
+        value: Some("SIZE"),
+        help: "Size of each buffer of the read backend (default: 8K, or 64K with read-ahead)",
+        apply: |c, v| {
+            let size = parse_size(v)?;
+            if size == 0 {
+                bail!("the read buffer must be at least one byte");
+            }
+            c.read_buffer = Some(size);
+            Ok(())
+        },
+    },
+    Opt {
+        long: "chunk-bytes",
+        short: None,
+        value: Some("SIZE"),
+        help: "Deal the input out to the workers in blocks of about SIZE (default: 4M)",
+        apply: |c, v| {
+            c.chunk_bytes = parse_size(v)?;
+            if c.chunk_bytes == 0 {
+                bail!("the chunk size must be at least one byte");
+            }
+            Ok(())
+        },
+    },
+    Opt {
+        long: "max-line-bytes",
+        short: None,
+        value: Some("SIZE"),
+        help: "Fail on a line or record longer than SIZE, as malformed, instead of reading on \
+               for its end (default: 8K)",
+        apply: |c, v| {
+            c.max_line_bytes = parse_size(v)?;
+            if c.max_line_bytes == 0 {
+                bail!("the line length must be at least one byte");
+            }
+            Ok(())
+        },
+    },
+    Opt {
+        long: "madvise",
+        short: None,
+        value: Some("LIST"),
+        help: "Comma separated mapping hints: sequential, willneed, hugepage",
+        apply: |c, v| {
+            c.madvise = v.split(',').map(Advice::parse).collect::<Result<_, _>>()?;
+            Ok(())
+        },
+    },
+    Opt {
+        long: "threads",
+        short: Some('t'),
+        value: Some("N"),
+        help: "Number of workers (default: the CPUs available, within any cgroup quota)",
+        apply: |c, v| {
+            let n = parse_count(v)?;
+            if n == 0 {
+                bail!("must be at least 1");
+            }
+            c.threads = Some(n);
+            Ok(())
+        },
+    },
+    Opt {
+        long: "executor",
+        short: None,
+        value: Some("auto|pipeline|threads|tokio"),
+        help: "Deal chunks out to worker threads, fold byte ranges on threads, or deal chunks \
+               out to async workers (default: auto, threads unless an option needs the pipeline)",
+        apply: |c, v| {
+            c.executor = Executor::parse(v)?;
+            Ok(())
+        },
+    },
+    Opt {
+        long: "strategy",
+        short: None,
+        value: Some("local|shared-map|vectorized|prefix-table"),
+        help: "Aggregate into a map per worker and merge them, all into one sharded map, \
+               per worker a parsed block at a time, or per worker behind a table of name \
+               prefixes (default: local)",
+        apply: |c, v| {
+            c.strategy = Strategy::parse(v)?;
+            Ok(())
+        },
+    },
+    Opt {
+        long: "best-effort",
+        short: None,
+        value: None,
+        help: "Leave out the results of a worker that fails instead of stopping",
+        apply: |c, _| {
+            c.best_effort = true;
+            Ok(())
+        },
+    },
+    Opt {
+        long: "pin-cores",
+        short: None,
+        value: Some("LIST"),
+        help: "Run one worker thread per core in LIST (like 0-15 or 0,2,4-7), pinned to it",
+        apply: |c, v| {
+            c.pin_cores = Some(affinity::parse_core_list(v)?);
+            Ok(())
+        },
+    },
+    Opt {
+        long: "pin-reader",
+        short: None,
+        value: Some("CORE"),
+        help: "Pin the reading threads to CORE",
+        apply: |c, v| {
+            c.pin_reader = Some(affinity::parse_core(v)?);
+            Ok(())
+        },
+    },
+    Opt {
+        long: "nice",
+        short: None,
+        value: Some("N"),
+        help: "Run every thread at niceness N, from -20 to 19, as nice(1) does",
+        apply: |c, v| {
+            let n: i32 = v
+                .parse()
+                .map_err(|_| anyhow!("expected a niceness, got `{}`", v))?;
+            if !(-20..=19).contains(&n) {
+                bail!("the niceness must be from -20 to 19");
+            }
+            c.nice = Some(n);
+            Ok(())
+        },
+    },
+    Opt {
+        long: "idle-io",
+        short: None,
+        value: None,
+        help: "Read only when the disk is otherwise idle, as ionice -c 3 does (Linux only)",
+        apply: |c, _| {
+            c.idle_io = true;
+            Ok(())
+        },
+    },
+    Opt {
+        long: "readers",
+        short: None,
+        value: Some("N"),
+        help: "Read the input in interleaved stripes on N threads feeding the workers (default: 1)",
+        apply: |c, v| {
+            let n = parse_count(v)?;
+            if n == 0 {
+                bail!("must be at least 1");
+            }
+            c.readers = n;
+            Ok(())
+        },
+    },
+    Opt {
+        long: "numa",
+        short: None,
+        value: Some("auto|off"),
+        help: "Read and aggregate part of the file on each NUMA node (default: off)",
+        apply: |c, v| {
+            c.numa = Numa::parse(v)?;
+            Ok(())
+        },
+    },
+    Opt {
+        long: "force-scalar",
+        short: None,
+        value: None,
+        help: "Scan for delimiters without SIMD instructions, for debugging",
+        apply: |c, _| {
+            c.force_scalar = true;
+            Ok(())
+        },
+    },
+    Opt {
+        long: "stats",
+        short: None,
+        value: None,
+        help: "Print run statistics such as peak memory use",
+        apply: |c, _| {
+            c.stats = true;
+            Ok(())
+        },
+    },
+    Opt {
+        long: "alloc-stats",
+        short: None,
+        value: None,
+        help: "With --stats, count allocations per phase (needs the alloc-stats feature)",
+        apply: |c, _| {
+            c.alloc_stats = true;
+            Ok(())
+        },
+    },
+    Opt {
+        long: "profile",
+        short: None,
+        value: Some("NAME"),
+        help: "Start from the options of [profile.NAME] in the config file, which the command line overrides",
+        apply: |c, v| {
+            c.profile = Some(v.to_string());
+            Ok(())
+        },
+    },
+    Opt {
+        long: "config",
+        short: None,
+        value: Some("FILE"),
+        help: "Config file the profiles are read from (default: ~/.config/1brc/config.toml)",
+        apply: |c, v| {
+            c.config_file = Some(PathBuf::from(v));
+            Ok(())
+        },
+    },
+];
+
+/// The options of `generate`, which takes none of [`OPTIONS`].
+pub const GENERATOR_OPTIONS: &[Opt<GenerateArgs>] = &[
+    Opt {
+        long: "rows",
+        short: None,
+        value: Some("N"),
+        help: "Write N rows (default 1000000000)",
+        apply: |g, v| {
+            g.rows = Some(parse_count(v)? as u64);
+            Ok(())
+        },
+    },
+    Opt {
+        long: "bytes",
+        short: None,
+        value: Some("SIZE"),
+        help: "Write as many rows as make up at least SIZE, such as 13G, before compression",
+        apply: |g, v| {
+            g.bytes = Some(parse_size(v)? as u64);
+            Ok(())
+        },
+    },
+    Opt {
+        long: "seed",
+        short: None,
+        value: Some("N"),
+        help: "Draw the readings from seed N, the same rows for the same seed (default 0)",
+        apply: |g, v| {
+            g.seed = v
+                .parse()
+                .map_err(|_| anyhow!("`{}` is not a non-negative integer", v))?;
+            Ok(())
+        },
+    },
+    Opt {
+        long: "output",
+        short: None,
+        value: Some("FILE"),
+        help: "Write the rows to FILE, or to stdout for - (default measurements.txt)",
+        apply: |g, v| {
+            g.output = PathBuf::from(v);
+            Ok(())
+        },
+    },
+    Opt {
+        long: "stations-file",
+        short: None,
+        value: Some("FILE"),
+        help: "Take the stations from FILE, one name;mean or name;mean;stddev a line \
+               (default: those of the original challenge)",
+        apply: |g, v| {
+            g.stations_file = Some(PathBuf::from(v));
+            Ok(())
+        },
+    },
+    Opt {
+        long: "distribution",
+        short: None,
+        value: Some("gaussian|uniform|bimodal"),
+        help: "How a station's readings spread around its mean (default gaussian)",
+        apply: |g, v| {
+            g.distribution = Distribution::parse(v)?;
+            Ok(())
+        },
+    },
+    Opt {
+        long: "skew",
+        short: None,
+        value: Some("none|zipf:S"),
+        help: "How often each station comes up: all as often, or the nth of the list \
+               1/n^S as often as the first (default none)",
+        apply: |g, v| {
+            g.skew = Skew::parse(v)?;
+            Ok(())
+        },
+    },
+    Opt {
+        long: "edge-cases",
+        short: None,
+        value: None,
+        help: "Mix in names and readings at the limits of the rules, and keep a row across \
+               every 4 KiB boundary, often cut inside a character",
+        apply: |g, _| {
+            g.edge_cases = true;
+            Ok(())
+        },
+    },
+    Opt {
+        long: "shards",
+        short: None,
+        value: Some("N"),
+        help: "Split the rows between N files written in parallel, FILE-0.txt and on for \
+               --output FILE.txt, each of a seed of its own",
+        apply: |g, v| {
+            g.shards = match parse_count(v)? {
+                0 => bail!("must be at least 1"),
+                n => n as u64,
+            };
+            Ok(())
+        },
+    },
+    Opt {
+        long: "compress",
+        short: None,
+        value: Some("none|gzip"),
+        help: "Compress what is written, adding .gz to the file name (default none)",
+        apply: |g, v| {
+            g.compress = Compress::parse(v)?;
+            Ok(())
+        },
+    },
+    Opt {
+        long: "progress",
+        short: None,
+        value: None,
+        help: "Show how far along it is on stderr, besides the rows per second at the end",
+        apply: |g, _| {
+            g.progress = true;
+            Ok(())
+        },
+    },
+];
+
+fn parse_number(v: &str) -> anyhow::Result<f64> {
+    v.parse().map_err(|_| anyhow!("`{}` is not a number", v))
+}
+
+fn parse_count(v: &str) -> anyhow::Result<usize> {
+    v.parse()
+        .map_err(|_| anyhow!("`{}` is not a non-negative integer", v))
+}
+
+/// Parses a byte size with an optional binary suffix, such as `64K`, `4M`,
+/// `2GiB` or `1T`.
+fn set_mode(c: &mut Config, mode: Mode) -> anyhow::Result<()> {
+    if c.mode != Mode::Aggregate && c.mode != mode {
+        bail!("--count-only and --list-stations cannot be combined");
+    }
+    c.mode = mode;
+    Ok(())
+}
+
+pub fn parse_size(v: &str) -> anyhow::Result<usize> {
+    let split = v.find(|c: char| !c.is_ascii_digit()).unwrap_or(v.len());
+    let (digits, suffix) = v.split_at(split);
+    let n: usize = digits
+        .parse()
+        .map_err(|_| anyhow!("`{}` is not a size", v))?;
+    let shift = match suffix.to_ascii_uppercase().as_str() {
+        "" | "B" => 0,
+        "K" | "KB" | "KIB" => 10,
+        "M" | "MB" | "MIB" => 20,
+        "G" | "GB" | "GIB" => 30,
+        "T" | "TB" | "TIB" => 40,
+        _ => bail!("unknown size suffix `{}`", suffix),
+    };
+    n.checked_mul(1 << shift)
+        .ok_or_else(|| anyhow!("`{}` is too large", v))
+}
+
+/// Sets the option `--long` as the command line would, with `value` if it
+/// takes one.
+pub fn set_option(config: &mut Config, long: &str, value: Option<&str>) -> anyhow::Result<()> {
+    let opt = find_long(long).ok_or_else(|| anyhow!("unknown option --{}", long))?;
+    apply(
+        opt,
+        config,
+        value.map(str::to_string),
+        &mut std::iter::empty(),
+    )
+}
+
+fn find_long(name: &str) -> Option<&'static Opt> {
+    OPTIONS.iter().find(|o| o.long == name)
+}
+
+fn find_short(name: char) -> Option<&'static Opt> {
+    OPTIONS.iter().find(|o| o.short == Some(name))
+}
+
+/// The value of the last `--name` in `args`.
+fn scan(args: &[String], name: &str) -> Option<String> {
+    let mut value = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if let Some(rest) = arg
+            .strip_prefix("--")
+            .and_then(|arg| arg.strip_prefix(name))
+        {
+            if rest.is_empty() {
+                value = args.next().cloned();
+            } else if let Some(inline) = rest.strip_prefix('=') {
+                value = Some(inline.to_string());
+            }
+        }
+    }
+    value
+}
+
+/// The options of the profile `args` select with `--profile`, if any, as
+/// arguments to go before them.
+fn profile_args(args: &[String]) -> anyhow::Result<Vec<String>> {
+    let Some(name) = scan(args, "profile") else {
+        return Ok(vec![]);
+    };
+    let path = match scan(args, "config") {
+        Some(path) => PathBuf::from(path),
+        None => profile::default_path()
+            .ok_or_else(|| anyhow!("--profile needs --config without a home directory"))?,
+    };
+    let mut out = vec![];
+    for (long, value) in profile::load(&path, &name)? {
+        let context = || format!("profile {} in {}", name, path.display());
+        let opt = find_long(&long)
+            .filter(|opt| !matches!(opt.long, "profile" | "config"))
+            .ok_or_else(|| anyhow!("{}: unknown option {}", context(), long))?;
+        match (opt.value, value) {
+            (Some(_), Some(value)) => out.push(format!("--{}={}", long, value)),
+            (None, None) => out.push(format!("--{}", long)),
+            (Some(_), None) => bail!("{}: {} takes a value, not true", context(), long),
+            (None, Some(_)) => bail!("{}: {} is a flag, to be set to true", context(), long),
+        }
+    }
+    Ok(out)
+}
+
+pub fn parse_args<I: IntoIterator<Item = String>>(args: I) -> anyhow::Result<Command> {
+    let mut config = Config::default();
+    let mut inputs = Vec::new();
+    let mut args = args.into_iter().peekable();
+    if args.next_if(|arg| arg == "diff").is_some() {
+        return parse_diff(args);
+    }
+    if args.next_if(|arg| arg == "generate").is_some() {
+        return parse_generate(args);
+    }
+    if args.next_if(|arg| arg == "completions").is_some() {
+        let shell = match (args.next(), args.next()) {
+            (Some(shell), None) => Shell::parse(&shell)?,
+            _ => bail!("completions takes one shell: bash, zsh or fish"),
+        };
+        return Ok(Command::Completions(shell));
+    }
+    if args.next_if(|arg| arg == "man").is_some() {
+        if args.next().is_some() {
+            bail!("man takes no arguments");
+        }
+        return Ok(Command::Man);
+    }
+    config.repl = args.next_if(|arg| arg == "repl").is_some();
+    config.validate = !config.repl && args.next_if(|arg| arg == "validate").is_some();
+    let args: Vec<String> = args.collect();
+    let mut args = profile_args(&args)?.into_iter().chain(args).peekable();
+
+    while let Some(arg) = args.next() {
+        if arg == "-h" || arg == "--help" {
+            return Ok(Command::Help);
+        }
+        if arg == "-V" || arg == "--version" {
+            return Ok(Command::Version);
+        }
+        // The REPL and validate also take their input as an option
+        if config.repl || config.validate {
+            if arg == "--input" {
+                inputs.push(
+                    args.next()
+                        .ok_or_else(|| anyhow!("--input requires a value"))?,
+                );
+                continue;
+            }
+            if let Some(input) = arg.strip_prefix("--input=") {
+                inputs.push(input.to_string());
+                continue;
+            }
+        }
+
+        let (opt, inline) = if let Some(long) = arg.strip_prefix("--") {
+            let (name, inline) = match long.split_once('=') {
+                Some((name, value)) => (name, Some(value.to_string())),
+                None => (long, None),
+            };
+            let opt = find_long(name).ok_or_else(|| anyhow!("unknown option --{}", name))?;
+            (opt, inline)
+        } else if arg.len() > 1
+            && arg.starts_with('-')
+            && !arg[1..].starts_with(|c: char| c.is_ascii_digit())
+        {
+            let mut chars = arg[1..].chars();
+            let name = chars.next().unwrap();
+            let mut rest = chars.as_str();
+            let mut opt = find_short(name).ok_or_else(|| anyhow!("unknown option -{}", name))?;
+            // Flags may be run together, as in `-vv`
+            while opt.value.is_none() && !rest.is_empty() {
+                (opt.apply)(&mut config, "")
+                    .map_err(|e| anyhow!("invalid value for --{}: {}", opt.long, e))?;
+                let mut chars = rest.chars();
+                let name = chars.next().unwrap();
+                rest = chars.as_str();
+                opt = find_short(name).ok_or_else(|| anyhow!("unknown option -{}", name))?;
+            }
+            (opt, (!rest.is_empty()).then(|| rest.to_string()))
+        } else {
+            inputs.push(arg);
+            continue;
+        };
+
+        apply(opt, &mut config, inline, &mut args)?;
+    }
+    if config.watch_dir.is_some() && !inputs.is_empty() {
+        bail!("--watch-dir cannot be combined with an input file");
+    }
+    if !inputs.is_empty() {
+        config.input = inputs[0].clone();
+        config.inputs = inputs;
+    }
+    check(&mut config)?;
+    Ok(Command::Run(Box::new(config)))
+}
+
+/// Checks that the options of a run go together, and picks the executor
+/// for `--executor auto`. The command line and
+/// [`ProcessorBuilder`](crate::processor::ProcessorBuilder) both end here.
+pub fn check(config: &mut Config) -> anyhow::Result<()> {
+    if Tracking::of(&config.agg, config.track_extremes).any()
+        && (config.cache_dir.is_some() || config.watch_dir.is_some())
+    {
+        // Saved results only hold what every key has
+        bail!(
+            "--agg stddev and percentiles, and --track-extremes, cannot be combined with --cache-dir or --watch-dir"
+        );
+    }
+    // The offsets are into the one file
+    if config.track_extremes && config.inputs.len() > 1 {
+        bail!("--track-extremes takes a single input file");
+    }
+    if config.detect_duplicates || config.dedupe {
+        reads_the_input(config, "--detect-duplicates and --dedupe")?;
+    }
+    if config.dedupe_false_positives.is_some() && !config.dedupe {
+        bail!("--dedupe-fp only applies to --dedupe");
+    }
+    if config.expect_sha256.is_some() {
+        if config.inputs.len() > 1 {
+            bail!("--expect-sha256 takes a single input file");
+        }
+        reads_the_input(config, "--expect-sha256")?;
+    }
+    if config.daemon && config.watch_dir.is_none() {
+        bail!("--daemon only applies to --watch-dir");
+    }
+    if config.watch_dir.is_some() {
+        if config.cache_dir.is_some() {
+            bail!("--watch-dir cannot be combined with --cache-dir");
+        }
+        if config.report.is_some() {
+            bail!("--watch-dir cannot be combined with --report");
+        }
+        if config.manifest.is_some() || config.verify_manifest.is_some() {
+            bail!("--watch-dir cannot be combined with --manifest or --verify-manifest");
+        }
+        if config.partition_by.is_some() {
+            bail!("--watch-dir cannot be combined with --partition-output-by");
+        }
+    }
+    if config.per_file && config.format == OutputFormat::Prom {
+        bail!("--per-file cannot be combined with --format prom");
+    }
+    if config.partition_by.is_some() {
+        if config.per_file {
+            bail!("--partition-output-by cannot be combined with --per-file");
+        }
+        // The check would write every row again
+        if config.partition_rows && config.debug_provenance {
+            bail!("--partition-rows cannot be combined with --debug-provenance");
+        }
+    } else if config.partition_rows {
+        bail!("--partition-rows only applies to --partition-output-by");
+    }
+    if config.repl
+        && (config.watch_dir.is_some() || config.per_file || config.partition_by.is_some())
+    {
+        bail!("repl cannot be combined with --watch-dir, --per-file or --partition-output-by");
+    }
+    if config.repl && (config.manifest.is_some() || config.verify_manifest.is_some()) {
+        bail!("repl cannot be combined with --manifest or --verify-manifest");
+    }
+    if config.validate && config.watch_dir.is_some() {
+        bail!("validate cannot be combined with --watch-dir");
+    }
+    if config.estimate_rows && (config.repl || config.validate || config.watch_dir.is_some()) {
+        bail!("--estimate-rows cannot be combined with repl, validate or --watch-dir");
+    }
+    if config.partial_on_timeout && config.timeout.is_none() {
+        bail!("--partial-on-timeout only applies to --timeout");
+    }
+    if config.timeout.is_some()
+        && (config.validate || config.estimate_rows || config.watch_dir.is_some())
+    {
+        bail!("--timeout cannot be combined with validate, --estimate-rows or --watch-dir");
+    }
+    if config.query.is_some() {
+        if config.format == OutputFormat::Prom {
+            bail!("--query cannot be combined with --format prom");
+        }
+        if config.per_file || config.partition_by.is_some() {
+            bail!("--query cannot be combined with --per-file or --partition-output-by");
+        }
+    }
+    // Before the scan, rather than once its results are in
+    if let Some(query) = &config.query {
+        query
+            .check(&output::results_header(config))
+            .map_err(|e| anyhow!("invalid value for --query: {}", e))?;
+    }
+    if config.export_rows.is_some() {
+        if config.debug_provenance {
+            bail!("--export-rows cannot be combined with --debug-provenance");
+        }
+        if config.watch_dir.is_some() {
+            bail!("--watch-dir cannot be combined with --export-rows");
+        }
+    }
+    if config.executor == Executor::Auto {
+        // Each thread reading its own range needs no communication until
+        // the final merge, but gives up file order and splits the file
+        // blindly
+        let pipeline = config.csv_strict
+            || config.numa == Numa::Auto
+            || config.preserve_order
+            || config.readers > 1;
+        config.executor = if pipeline {
+            Executor::Pipeline
+        } else {
+            Executor::Threads
+        };
+    }
+    if config.preserve_order {
+        if config.export_rows.is_none() {
+            bail!("--preserve-order only applies to --export-rows");
+        }
+        // Only a single reader hands out chunks in file order
+        if config.executor == Executor::Threads || config.numa != Numa::Off {
+            bail!("--preserve-order cannot be combined with --executor threads or --numa auto");
+        }
+    }
+    if let (Some(min), Some(max)) = (config.range.min, config.range.max)
+        && min > max
+    {
+        bail!("--min-value must not be greater than --max-value");
+    }
+    if config.csv_strict && config.input_format != InputFormat::Csv {
+        bail!("--csv-strict only applies to --input-format csv");
+    }
+    if config.csv_strict && config.encoding == Encoding::Utf16Le {
+        // Quotes are only counted in UTF-8
+        bail!("--csv-strict cannot be combined with --encoding utf16le");
+    }
+    if !config.madvise.is_empty() && config.io != IoBackend::Mmap {
+        bail!("--madvise only applies to --io mmap");
+    }
+    if config.read_buffer.is_some() && config.io != IoBackend::Read {
+        // The mapping is read in place
+        bail!("--read-buffer only applies to --io read");
+    }
+    if config.mode != Mode::Aggregate {
+        // These need the readings parsed
+        if config.range.is_set() {
+            bail!(
+                "--count-only and --list-stations cannot be combined with --min-value or --max-value"
+            );
+        }
+        if config.null_policy == NullPolicy::Count {
+            bail!("--count-only and --list-stations cannot be combined with --null-policy count");
+        }
+        if matches!(
+            config.sort.field,
+            SortField::Min | SortField::Mean | SortField::Max
+        ) {
+            bail!("--count-only and --list-stations can only --sort-by name or count");
+        }
+        if !config.agg.is_empty() {
+            bail!("--count-only and --list-stations cannot be combined with --agg");
+        }
+        if config.track_extremes {
+            bail!("--count-only and --list-stations cannot be combined with --track-extremes");
+        }
+        if config.detect_duplicates || config.dedupe {
+            bail!(
+                "--count-only and --list-stations cannot be combined with --detect-duplicates or --dedupe"
+            );
+        }
+    }
+    if config.config_file.is_some() && config.profile.is_none() {
+        bail!("--config only applies to --profile");
+    }
+    if config.strict_1brc && config.lenient_numbers {
+        bail!("--strict-1brc cannot be combined with --lenient-numbers");
+    }
+    if config.executor == Executor::Threads {
+        splits_the_file(config, "--executor threads")?;
+        if config.numa == Numa::Auto {
+            bail!("--numa auto cannot be combined with --executor threads");
+        }
+    }
+    if config.numa == Numa::Auto {
+        if config.pin_cores.is_some() {
+            bail!("--numa auto cannot be combined with --pin-cores");
+        }
+        splits_the_file(config, "--numa auto")?;
+    }
+    if !config.strategy.spills() && (config.spill_budget.is_some() || config.max_memory.is_some()) {
+        // Not every key is in a map of the worker's own
+        bail!(
+            "--strategy {} cannot be combined with --spill-budget or --max-memory",
+            config.strategy.name()
+        );
+    }
+    if config.strategy == Strategy::SharedMap && config.debug_provenance {
+        // It cannot tell the workers' shares apart
+        bail!("--strategy shared-map cannot be combined with --debug-provenance");
+    }
+    if config.strategy == Strategy::SharedMap && config.best_effort {
+        // A failed worker's readings are in the map along with the others'
+        bail!("--strategy shared-map cannot be combined with --best-effort");
+    }
+    if config.strategy == Strategy::Vectorized
+        && (config.input_format != InputFormat::Csv || config.csv_strict)
+    {
+        // Its parsing stage only splits plain lines
+        bail!("--strategy vectorized only applies to --input-format csv without --csv-strict");
+    }
+    if !config.value_columns.is_empty() {
+        if config.input_format != InputFormat::Csv {
+            bail!("--value-columns only applies to --input-format csv");
+        }
+        // After the timestamp, if any, comes the station
+        let station = 1 + u16::from(config.window.is_some());
+        if config.value_columns.iter().any(|&column| column <= station) {
+            bail!(
+                "--value-columns must come after the station, which is field {}",
+                station
+            );
+        }
+        if config.mode != Mode::Aggregate {
+            bail!("--count-only and --list-stations cannot be combined with --value-columns");
+        }
+        if config.strategy == Strategy::Vectorized {
+            bail!("--strategy vectorized cannot be combined with --value-columns");
+        }
+        if config.cache_dir.is_some() || config.watch_dir.is_some() {
+            // Saved results have no column in their keys
+            bail!("--value-columns cannot be combined with --cache-dir or --watch-dir");
+        }
+    }
+    if config.alloc_stats {
+        if cfg!(not(feature = "alloc-stats")) {
+            bail!("this build does not count allocations, see the `alloc-stats` feature");
+        }
+        if !config.stats {
+            bail!("--alloc-stats requires --stats");
+        }
+    }
+    if config.readers > 1 {
+        if config.executor == Executor::Threads {
+            bail!("--readers cannot be combined with --executor threads, whose workers read");
+        }
+        splits_the_file(config, "--readers")?;
+        if config.preserve_order {
+            bail!("--readers cannot be combined with --preserve-order");
+        }
+    }
+    if config.group_by == GroupBy::Country {
+        if config.metadata.is_none() {
+            bail!("--group-by country requires --metadata");
+        }
+        if config.key_map.is_some() || config.group_by_prefix.is_some() {
+            bail!("--group-by country cannot be combined with --key-map or --group-by-prefix");
+        }
+    }
+    Ok(())
+}
+
+/// `diff A B [--tolerance N]`
+/// Fails `options`, which need the rows of the input, along with a cache
+/// that may answer the run without reading it.
+fn reads_the_input(config: &Config, options: &str) -> anyhow::Result<()> {
+    if config.cache_dir.is_some() || config.watch_dir.is_some() {
+        bail!(
+            "{} cannot be combined with --cache-dir or --watch-dir",
+            options
+        );
+    }
+    Ok(())
+}
+
+/// Fails `option`, which has the file split into byte ranges that start
+/// wherever a line does, along with `--csv-strict`, whose records may span
+/// lines.
+fn splits_the_file(config: &Config, option: &str) -> anyhow::Result<()> {
+    if config.csv_strict {
+        bail!("{} cannot be combined with --csv-strict", option);
+    }
+    Ok(())
+}
+
+fn parse_diff(mut args: impl Iterator<Item = String>) -> anyhow::Result<Command> {
+    let mut files = vec![];
+    let mut tolerance = 0.0;
+    while let Some(arg) = args.next() {
+        if arg == "-h" || arg == "--help" {
+            return Ok(Command::Help);
+        }
+        if let Some(value) = arg.strip_prefix("--tolerance") {
+            let value = match value.strip_prefix('=') {
+                Some(value) => value.to_string(),
+                None if value.is_empty() => args
+                    .next()
+                    .ok_or_else(|| anyhow!("--tolerance requires a value"))?,
+                None => bail!("unknown option {}", arg),
+            };
+            tolerance = parse_number(&value)
+                .map_err(|e| anyhow!("invalid value for --tolerance: {}", e))?;
+            if tolerance < 0.0 {
+                bail!("invalid value for --tolerance: must not be negative");
+            }
+        } else if arg.starts_with("--") {
+            bail!("unknown option {}", arg);
+        } else {
+            files.push(PathBuf::from(arg));
+        }
+    }
+    let Ok([a, b]) = <[PathBuf; 2]>::try_from(files) else {
+        bail!("diff takes two result files");
+    };
+    Ok(Command::Diff(DiffArgs { a, b, tolerance }))
+}
+
+/// `generate [GENERATOR-OPTIONS]`, which only have long names.
+fn parse_generate(mut args: impl Iterator<Item = String>) -> anyhow::Result<Command> {
+    let mut generate = GenerateArgs::default();
+    while let Some(arg) = args.next() {
+        if arg == "-h" || arg == "--help" {
+            return Ok(Command::Help);
+        }
+        let Some(long) = arg.strip_prefix("--") else {
+            bail!("generate takes no files, see --output: `{}`", arg);
+        };
+        let (name, inline) = match long.split_once('=') {
+            Some((name, value)) => (name, Some(value.to_string())),
+            None => (long, None),
+        };
+        let opt = GENERATOR_OPTIONS
+            .iter()
+            .find(|o| o.long == name)
+            .ok_or_else(|| anyhow!("unknown option --{}", name))?;
+        apply(opt, &mut generate, inline, &mut args)?;
+    }
+    if generate.rows.is_some() && generate.bytes.is_some() {
+        bail!("--rows cannot be combined with --bytes");
+    }
+    if generate.shards > 1 && generate.output.as_os_str() == "-" {
+        bail!("--shards cannot be combined with --output -");
+    }
+    Ok(Command::Generate(generate))
+}
+
+/// Applies `opt` to `target`, with the value given `inline` in the same
+/// argument or the next of `args`.
+fn apply<T>(
+    opt: &Opt<T>,
+    target: &mut T,
+    inline: Option<String>,
+    args: &mut impl Iterator<Item = String>,
+) -> anyhow::Result<()> {
+    let value = match (opt.value, inline) {
+        (Some(_), Some(value)) => value,
+        (Some(_), None) => args
+            .next()
+            .ok_or_else(|| anyhow!("--{} requires a value", opt.long))?,
+        (None, Some(_)) => bail!("--{} does not take a value", opt.long),
+        (None, None) => String::new(),
+    };
+    (opt.apply)(target, &value).map_err(|e| anyhow!("invalid value for --{}: {}", opt.long, e))
+}
+
+/// What the program does, as the help and the manual page give it.
+pub fn description() -> String {
+    format!(
+        "FILE defaults to {}; the results of several files are added together. \
+         `repl` aggregates once and then takes queries at a prompt. \
+         `validate` checks every line against the rules of the original challenge \
+         without aggregating, exiting with 1 if any line breaks them. \
+         `diff` compares two saved outputs of any format, reporting missing keys \
+         and fields that differ by more than N. \
+         `generate` writes rows in the challenge's format for a run to be measured on, \
+         the same rows for the same --seed. \
+         `completions` and `man` print a completion script for the shell and the \
+         manual page.",
+        DEFAULT_INPUT
+    )
+}
+
+pub fn usage() -> String {
+    let mut out = String::from("Usage: 1brc [OPTIONS] [FILE...]\n");
+    for (name, rest) in SUBCOMMANDS {
+        out.push_str(format!("       1brc {} {}", name, rest).trim_end());
+        out.push('\n');
+    }
+    out.push_str(&format!("\n{}\n\nOptions:\n", description()));
+    let mut rows = option_rows(OPTIONS);
+    for &(short, long, help) in BUILT_IN {
+        rows.push((format!("-{}, --{}", short, long), help));
+    }
+    push_rows(&mut out, rows);
+    out.push_str("\nGenerator options:\n");
+    push_rows(&mut out, option_rows(GENERATOR_OPTIONS));
+    out
+}
+
+/// The flag and help of each of `opts`.
+fn option_rows<T>(opts: &[Opt<T>]) -> Vec<(String, &'static str)> {
+    opts.iter()
+        .map(|o| {
+            let mut flag = match o.short {
+                Some(s) => format!("-{}, --{}", s, o.long),
+                None => format!("    --{}", o.long),
+            };
+            if let Some(value) = o.value {
+                flag.push(' ');
+                flag.push_str(value);
+            }
+            (flag, o.help)
+        })
+        .collect()
+}
+
+fn push_rows(out: &mut String, rows: Vec<(String, &str)>) {
+    let width = rows.iter().map(|(flag, _)| flag.len()).max().unwrap_or(0);
+    for (flag, help) in rows {
+        out.push_str(&format!("  {:<width$}  {}\n", flag, help, width = width));
+    }
+}
@@ -0,0 +1,96 @@
+//! `--collation`: orders of station names other than UTF-8 byte order,
+//! for reports meant for people. With no ICU data at hand, `unicode`
+//! approximates the default order of the Unicode Collation Algorithm:
+//! letters compare without their accents first, then by accent, then by
+//! case. `locale:xx` adds the letters a few languages sort on their own.
+
+use crate::normalize;
+
+/// Per language, the letters it sorts as letters of their own, each right
+/// after another one, in order.
+const TAILORINGS: &[(&str, &[(char, char)])] = &[
+    ("da", &[('æ', 'z'), ('ø', 'z'), ('å', 'z')]),
+    ("de", &[]),
+    ("en", &[]),
+    ("es", &[('ñ', 'n')]),
+    ("fi", &[('å', 'z'), ('ä', 'z'), ('ö', 'z')]),
+    ("nb", &[('æ', 'z'), ('ø', 'z'), ('å', 'z')]),
+    ("nn", &[('æ', 'z'), ('ø', 'z'), ('å', 'z')]),
+    ("no", &[('æ', 'z'), ('ø', 'z'), ('å', 'z')]),
+    (
+        "pl",
+        &[
+            ('ą', 'a'),
+            ('ć', 'c'),
+            ('ę', 'e'),
+            ('ł', 'l'),
+            ('ń', 'n'),
+            ('ó', 'o'),
+            ('ś', 's'),
+            ('ź', 'z'),
+            ('ż', 'z'),
+        ],
+    ),
+    ("sv", &[('å', 'z'), ('ä', 'z'), ('ö', 'z')]),
+    (
+        "tr",
+        &[
+            ('ç', 'c'),
+            ('ğ', 'g'),
+            ('ı', 'h'),
+            ('ö', 'o'),
+            ('ş', 's'),
+            ('ü', 'u'),
+        ],
+    ),
+];
+
+/// The tailoring of `language`, if there is one.
+pub fn tailoring(language: &str) -> Option<&'static [(char, char)]> {
+    TAILORINGS
+        .iter()
+        .find(|(name, _)| *name == language)
+        .map(|(_, letters)| *letters)
+}
+
+pub fn languages() -> impl Iterator<Item = &'static str> {
+    TAILORINGS.iter().map(|(name, _)| *name)
+}
+
+/// A sort key for `name`: the primary weights of its letters, then their
+/// accents, then their case, each level ending in a zero. Names with equal
+/// keys are ordered by their bytes.
+pub fn key(name: &str, tailoring: &[(char, char)]) -> Vec<u32> {
+    // Room below each letter's weight for the letters sorted after it
+    let weight = |c: u32| c << 6;
+    let mut primary = Vec::with_capacity(name.len());
+    let mut secondary = Vec::new();
+    let mut tertiary = Vec::new();
+    let mut buf = [0; 4];
+    for c in name.chars() {
+        let lower = c.to_lowercase().next().unwrap_or(c);
+        tertiary.push(u32::from(lower != c));
+        let tailored = tailoring.iter().enumerate().find(|(_, (l, _))| *l == lower);
+        if let Some((i, &(_, after))) = tailored {
+            let rank = tailoring[..i].iter().filter(|(_, a)| *a == after).count();
+            primary.push(weight(after as u32) + 1 + rank as u32);
+            secondary.push(0);
+            continue;
+        }
+        for d in normalize::nfkd(lower.encode_utf8(&mut buf)) {
+            if normalize::is_combining(d) {
+                secondary.push(d);
+            } else {
+                let d =
+                    char::from_u32(d).map_or(d, |d| d.to_lowercase().next().unwrap_or(d) as u32);
+                primary.push(weight(d));
+                secondary.push(0);
+            }
+        }
+    }
+    primary.push(0);
+    primary.extend(secondary);
+    primary.push(0);
+    primary.extend(tertiary);
+    primary
+}
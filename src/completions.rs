@@ -0,0 +1,288 @@
+//! `1brc completions` and `1brc man`, written from the option table so that
+//! they cannot fall behind it.
+//!
+//! An option's value placeholder says what it completes to: the choices of
+//! one like `read|mmap`, files for `FILE`, directories for `DIR`, and
+//! nothing for the rest.
+
+use crate::cli::{self, GENERATOR_OPTIONS, OPTIONS, Opt, SUBCOMMANDS, Shell};
+use crate::version;
+
+const SHELLS: [&str; 3] = ["bash", "zsh", "fish"];
+
+enum Value {
+    Flag,
+    Choices(Vec<&'static str>),
+    File,
+    Dir,
+    Other(&'static str),
+}
+
+fn value<T>(opt: &Opt<T>) -> Value {
+    match opt.value {
+        None => Value::Flag,
+        Some("FILE") => Value::File,
+        Some("DIR") => Value::Dir,
+        // A choice such as `locale:xx` has to be written out
+        Some(value) if value.contains('|') => Value::Choices(
+            value
+                .split('|')
+                .filter(|choice| !choice.contains(':'))
+                .collect(),
+        ),
+        Some(value) => Value::Other(value),
+    }
+}
+
+fn subcommands() -> Vec<&'static str> {
+    SUBCOMMANDS.iter().map(|(name, _)| *name).collect()
+}
+
+pub fn script(shell: Shell) -> String {
+    match shell {
+        Shell::Bash => bash(),
+        Shell::Zsh => zsh(),
+        Shell::Fish => fish(),
+    }
+}
+
+/// The words of `opts`, and the cases completing their values.
+fn bash_options<T>(opts: &[Opt<T>]) -> (Vec<String>, String) {
+    let mut words = vec![];
+    let mut cases = String::new();
+    for opt in opts {
+        let mut names = format!("--{}", opt.long);
+        if let Some(short) = opt.short {
+            names = format!("-{}|{}", short, names);
+        }
+        words.push(format!("--{}", opt.long));
+        words.extend(opt.short.map(|short| format!("-{}", short)));
+        let reply = match value(opt) {
+            Value::Flag => continue,
+            Value::Choices(choices) => {
+                format!(
+                    "COMPREPLY=($(compgen -W \"{}\" -- \"$cur\"))",
+                    choices.join(" ")
+                )
+            }
+            Value::File => "COMPREPLY=($(compgen -f -- \"$cur\"))".to_string(),
+            Value::Dir => "COMPREPLY=($(compgen -d -- \"$cur\"))".to_string(),
+            Value::Other(_) => "COMPREPLY=()".to_string(),
+        };
+        cases.push_str(&format!(
+            "        {})\n            {}\n            return ;;\n",
+            names, reply
+        ));
+    }
+    (words, cases)
+}
+
+fn bash() -> String {
+    let (mut words, cases) = bash_options(OPTIONS);
+    let (generator_words, generator_cases) = bash_options(GENERATOR_OPTIONS);
+    for (short, long, _) in cli::BUILT_IN {
+        words.push(format!("--{}", long));
+        words.push(format!("-{}", short));
+    }
+    format!(
+        r#"# bash completion for 1brc, from `1brc completions bash`
+
+_1brc() {{
+    local cur=${{COMP_WORDS[COMP_CWORD]}}
+    local prev=${{COMP_WORDS[COMP_CWORD-1]}}
+    if [[ ${{COMP_WORDS[1]}} == generate ]]; then
+        case $prev in
+{generator_cases}        esac
+        COMPREPLY=($(compgen -W "{generator_words}" -- "$cur"))
+        return
+    fi
+    case $prev in
+{cases}        completions)
+            COMPREPLY=($(compgen -W "{shells}" -- "$cur"))
+            return ;;
+    esac
+    if [[ $cur == -* ]]; then
+        COMPREPLY=($(compgen -W "{words}" -- "$cur"))
+    elif [[ $COMP_CWORD -eq 1 ]]; then
+        COMPREPLY=($(compgen -W "{subcommands}" -- "$cur") $(compgen -f -- "$cur"))
+    else
+        COMPREPLY=($(compgen -f -- "$cur"))
+    fi
+}}
+
+complete -o filenames -F _1brc 1brc
+"#,
+        shells = SHELLS.join(" "),
+        words = words.join(" "),
+        generator_words = generator_words.join(" "),
+        subcommands = subcommands().join(" "),
+    )
+}
+
+/// `help` inside the brackets of a zsh `_arguments` spec, itself in single
+/// quotes.
+fn zsh_help(help: &str) -> String {
+    help.replace('\'', "'\\''")
+        .replace('[', "\\[")
+        .replace(']', "\\]")
+        .replace(':', "\\:")
+}
+
+/// The `_arguments` specs of `opts`.
+fn zsh_options<T>(opts: &[Opt<T>]) -> Vec<String> {
+    let mut specs = vec![];
+    for opt in opts {
+        let help = zsh_help(opt.help);
+        let argument = match value(opt) {
+            Value::Flag => String::new(),
+            Value::Choices(choices) => format!(":{}:({})", opt.long, choices.join(" ")),
+            Value::File => ":FILE:_files".to_string(),
+            Value::Dir => ":DIR:_files -/".to_string(),
+            Value::Other(name) => format!(":{}: ", name),
+        };
+        // `=` takes the value in the same word or the next
+        let equals = if argument.is_empty() { "" } else { "=" };
+        specs.push(format!("'--{}{}[{}]{}'", opt.long, equals, help, argument));
+        if let Some(short) = opt.short {
+            specs.push(format!("'-{}[{}]{}'", short, help, argument));
+        }
+    }
+    specs
+}
+
+fn zsh() -> String {
+    let mut specs = zsh_options(OPTIONS);
+    for (short, long, help) in cli::BUILT_IN {
+        specs.push(format!(
+            "'(- *)'{{-{},--{}}}'[{}]'",
+            short,
+            long,
+            zsh_help(help)
+        ));
+    }
+    specs.push(format!(
+        "'1: :_alternative \"commands:command:({})\" \"files:file:_files\"'",
+        subcommands().join(" ")
+    ));
+    specs.push("'*:file:_files'".to_string());
+    format!(
+        "#compdef 1brc\n\n# zsh completion for 1brc, from `1brc completions zsh`\n\n\
+         _1brc() {{\n    if [[ $words[2] == completions ]]; then\n        \
+         _arguments '2:shell:({shells})'\n        return\n    fi\n    \
+         if [[ $words[2] == generate ]]; then\n        \
+         _arguments \\\n            '1: :(generate)' \\\n            {generator}\n        \
+         return\n    fi\n    \
+         _arguments -s \\\n        {specs}\n}}\n\n_1brc \"$@\"\n",
+        shells = SHELLS.join(" "),
+        specs = specs.join(" \\\n        "),
+        generator = zsh_options(GENERATOR_OPTIONS).join(" \\\n            "),
+    )
+}
+
+/// `s` in fish's single quotes.
+fn fish_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\\', "\\\\").replace('\'', "\\'"))
+}
+
+fn fish() -> String {
+    let mut out = String::from("# fish completion for 1brc, from `1brc completions fish`\n\n");
+    out.push_str(&format!(
+        "complete -c 1brc -n __fish_use_subcommand -a {}\n",
+        fish_quote(&subcommands().join(" "))
+    ));
+    out.push_str(&format!(
+        "complete -c 1brc -n '__fish_seen_subcommand_from completions' -f -a {}\n",
+        fish_quote(&SHELLS.join(" "))
+    ));
+    fish_options(&mut out, OPTIONS, "");
+    fish_options(
+        &mut out,
+        GENERATOR_OPTIONS,
+        " -n '__fish_seen_subcommand_from generate'",
+    );
+    for (short, long, help) in cli::BUILT_IN {
+        out.push_str(&format!(
+            "complete -c 1brc -l {} -s {} -d {}\n",
+            long,
+            short,
+            fish_quote(help)
+        ));
+    }
+    out
+}
+
+/// The lines of `opts`, completed where `condition` holds.
+fn fish_options<T>(out: &mut String, opts: &[Opt<T>], condition: &str) {
+    for opt in opts {
+        let mut line = format!("complete -c 1brc{} -l {}", condition, opt.long);
+        if let Some(short) = opt.short {
+            line.push_str(&format!(" -s {}", short));
+        }
+        match value(opt) {
+            Value::Flag => {}
+            Value::Choices(choices) => {
+                line.push_str(&format!(" -x -a {}", fish_quote(&choices.join(" "))));
+            }
+            Value::File => line.push_str(" -r -F"),
+            Value::Dir => line.push_str(" -x -a '(__fish_complete_directories)'"),
+            Value::Other(_) => line.push_str(" -x"),
+        }
+        line.push_str(&format!(" -d {}\n", fish_quote(opt.help)));
+        out.push_str(&line);
+    }
+}
+
+/// `s` as roff text, which must not start a line with a control character.
+fn roff(s: &str) -> String {
+    let s = s.replace('\\', "\\e").replace('-', "\\-");
+    if s.starts_with(['.', '\'']) {
+        format!("\\&{}", s)
+    } else {
+        s
+    }
+}
+
+pub fn man() -> String {
+    let mut out = format!(
+        ".TH 1BRC 1 \"\" \"1brc {}\" \"User Commands\"\n\
+         .SH NAME\n1brc \\- aggregate weather station readings\n\
+         .SH SYNOPSIS\n.B 1brc\n[\\fIOPTIONS\\fR] [\\fIFILE\\fR...]\n",
+        version::VERSION
+    );
+    for (name, rest) in SUBCOMMANDS {
+        out.push_str(&format!(".br\n.B 1brc {}\n", name));
+        if !rest.is_empty() {
+            out.push_str(&format!("{}\n", roff(rest)));
+        }
+    }
+    out.push_str(&format!(
+        ".SH DESCRIPTION\n{}\n.SH OPTIONS\n",
+        roff(&cli::description())
+    ));
+    man_options(&mut out, OPTIONS);
+    for (short, long, help) in cli::BUILT_IN {
+        out.push_str(&format!(
+            ".TP\n\\fB\\-{}\\fR, \\fB\\-\\-{}\\fR\n{}\n",
+            short,
+            long,
+            roff(help)
+        ));
+    }
+    out.push_str(".SH GENERATOR OPTIONS\n");
+    man_options(&mut out, GENERATOR_OPTIONS);
+    out
+}
+
+fn man_options<T>(out: &mut String, opts: &[Opt<T>]) {
+    for opt in opts {
+        out.push_str(".TP\n");
+        if let Some(short) = opt.short {
+            out.push_str(&format!("\\fB\\-{}\\fR, ", short));
+        }
+        out.push_str(&format!("\\fB\\-\\-{}\\fR", roff(opt.long)));
+        if let Some(value) = opt.value {
+            out.push_str(&format!(" \\fI{}\\fR", roff(value)));
+        }
+        out.push_str(&format!("\n{}\n", roff(opt.help)));
+    }
+}
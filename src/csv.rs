@@ -0,0 +1,129 @@
+//! RFC 4180 style records for `--csv-strict`, where fields may be quoted to
+//! contain the delimiter, quotes (doubled) or line breaks.
+
+use anyhow::bail;
+use std::borrow::Cow;
+
+pub const DELIMITER: char = ';';
+
+/// Returns whether `record` ends inside a quoted field, meaning the next
+/// physical line belongs to the same record.
+pub fn is_open(record: &str) -> bool {
+    // Escaped quotes come in pairs, so only the parity matters
+    record.bytes().filter(|&b| b == b'"').count() % 2 == 1
+}
+
+/// Splits a complete record into its fields, unquoting where needed.
+pub fn split_record(record: &str) -> anyhow::Result<Vec<Cow<'_, str>>> {
+    split_record_on(record, DELIMITER)
+}
+
+/// [`split_record`] with another delimiter, such as the `,` of the
+/// binary's own CSV output.
+pub fn split_record_on(record: &str, delimiter: char) -> anyhow::Result<Vec<Cow<'_, str>>> {
+    let mut fields = Vec::new();
+    let mut rest = record;
+    loop {
+        if let Some(quoted) = rest.strip_prefix('"') {
+            let mut value = String::new();
+            let mut remaining = quoted;
+            loop {
+                let Some(end) = remaining.find('"') else {
+                    bail!("unterminated quoted field");
+                };
+                value.push_str(&remaining[..end]);
+                remaining = &remaining[end + 1..];
+                if let Some(after) = remaining.strip_prefix('"') {
+                    value.push('"');
+                    remaining = after;
+                } else {
+                    break;
+                }
+            }
+            fields.push(Cow::Owned(value));
+            if remaining.is_empty() {
+                return Ok(fields);
+            }
+            let Some(after) = remaining.strip_prefix(delimiter) else {
+                bail!("unexpected characters after quoted field");
+            };
+            rest = after;
+        } else {
+            match rest.find(delimiter) {
+                Some(end) => {
+                    fields.push(Cow::Borrowed(&rest[..end]));
+                    rest = &rest[end + 1..];
+                }
+                None => {
+                    fields.push(Cow::Borrowed(rest));
+                    return Ok(fields);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fields(record: &str) -> Vec<String> {
+        split_record(record)
+            .unwrap()
+            .into_iter()
+            .map(Cow::into_owned)
+            .collect()
+    }
+
+    fn error(record: &str) -> String {
+        match split_record(record) {
+            Ok(fields) => panic!("{} split into {:?}", record, fields),
+            Err(e) => e.to_string(),
+        }
+    }
+
+    #[test]
+    fn unquoted_fields_are_borrowed() {
+        assert_eq!(fields("Hamburg;12.0"), ["Hamburg", "12.0"]);
+        assert_eq!(fields("Hamburg;;"), ["Hamburg", "", ""]);
+        assert_eq!(fields(""), [""]);
+        let split = split_record("Hamburg;12.0").unwrap();
+        assert!(split.iter().all(|field| matches!(field, Cow::Borrowed(_))));
+    }
+
+    #[test]
+    fn quoted_fields_hold_delimiters_and_doubled_quotes() {
+        assert_eq!(
+            fields("\"Hamburg; Altona\";12.0"),
+            ["Hamburg; Altona", "12.0"]
+        );
+        assert_eq!(fields("\"The \"\"Rock\"\"\";3.1"), ["The \"Rock\"", "3.1"]);
+        assert_eq!(fields("\"\";1.0"), ["", "1.0"]);
+        assert_eq!(fields("Oslo;\"-4.5\""), ["Oslo", "-4.5"]);
+        // A quote inside an unquoted field is kept as it is
+        assert_eq!(fields("O\"Hare;1.0"), ["O\"Hare", "1.0"]);
+        assert_eq!(split_record_on("\"a,b\",c", ',').unwrap(), ["a,b", "c"]);
+    }
+
+    #[test]
+    fn quoted_fields_hold_line_breaks() {
+        assert!(is_open("\"Line"));
+        assert!(!is_open("\"Line\nBreak\";1.0"));
+        assert!(is_open("\"Line\nBreak \"\"quoted\"\""));
+        assert!(!is_open("Hamburg;12.0"));
+        assert_eq!(fields("\"Line\r\nBreak\";1.0"), ["Line\r\nBreak", "1.0"]);
+    }
+
+    #[test]
+    fn malformed_records_are_rejected() {
+        assert_eq!(error("\"Hamburg;12.0"), "unterminated quoted field");
+        assert_eq!(
+            error("\"Ham\"burg;12.0"),
+            "unexpected characters after quoted field"
+        );
+        assert_eq!(
+            error("\"Hamburg\" ;12.0"),
+            "unexpected characters after quoted field"
+        );
+    }
+}
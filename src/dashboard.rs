@@ -0,0 +1,218 @@
+//! `--tui`: a live view of the run on stderr, with progress, throughput,
+//! how the work is spread over the workers and the hottest stations so
+//! far. Terminals that cannot move the cursor get a one-line progress bar
+//! instead, and anything that is not a terminal gets nothing.
+
+use crate::ThreadData;
+use crate::memory::format_bytes;
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::io::{self, IsTerminal, Write};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Bytes a worker gets through before it reports them.
+pub const REPORT_BYTES: u64 = 1 << 20;
+
+const REFRESH: Duration = Duration::from_millis(250);
+/// Stations in the table
+const TOP: usize = 10;
+/// Workers shown one per line, before the rest are summed up
+const MAX_WORKERS: usize = 16;
+const BAR_WIDTH: usize = 30;
+
+/// What the workers report for the dashboard to show.
+pub struct Progress {
+    started: Instant,
+    /// Size of the input
+    total: u64,
+    workers: Vec<Worker>,
+    finished: AtomicBool,
+}
+
+#[derive(Default)]
+struct Worker {
+    bytes: AtomicU64,
+    /// The worker's stations with the highest maximum, merged by the
+    /// dashboard into the overall ones
+    top: Mutex<Vec<(String, f64)>>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Style {
+    Dashboard,
+    Bar,
+}
+
+impl Progress {
+    pub fn new(total: u64, workers: usize) -> Self {
+        Progress {
+            started: Instant::now(),
+            total,
+            workers: (0..workers).map(|_| Worker::default()).collect(),
+            finished: AtomicBool::new(false),
+        }
+    }
+
+    /// Hands over the bytes `data` has got through since its last report,
+    /// and every so often its hottest stations.
+    pub fn report(&self, worker: usize, data: &mut ThreadData) {
+        let Some(slot) = self.workers.get(worker) else {
+            return;
+        };
+        slot.bytes.fetch_add(data.unreported, Ordering::Relaxed);
+        data.unreported = 0;
+        if data.published.is_some_and(|at| at.elapsed() < REFRESH) {
+            return;
+        }
+        data.published = Some(Instant::now());
+        // A station's overall maximum is its maximum on some worker, where
+        // it must be among the hottest, so these are enough to merge
+        *slot.top.lock().unwrap() = top(data
+            .stats()
+            .map(|(key, stat)| (key.name.as_str(), stat.max)));
+    }
+
+    /// Draws the dashboard on stderr until [`Progress::finish`], if stderr
+    /// is a terminal.
+    pub fn spawn(self: &std::sync::Arc<Self>) -> Option<thread::JoinHandle<()>> {
+        if !io::stderr().is_terminal() {
+            return None;
+        }
+        let style = match std::env::var("TERM") {
+            Ok(term) if term != "dumb" => Style::Dashboard,
+            _ => Style::Bar,
+        };
+        let progress = self.clone();
+        Some(thread::spawn(move || {
+            let mut drawn = 0;
+            loop {
+                let finished = progress.finished.load(Ordering::Relaxed);
+                drawn = progress.draw(style, drawn, finished);
+                if finished {
+                    break;
+                }
+                thread::park_timeout(REFRESH);
+            }
+        }))
+    }
+
+    /// Stops the dashboard after a last frame.
+    pub fn finish(&self, dashboard: Option<thread::JoinHandle<()>>) {
+        self.finished.store(true, Ordering::Relaxed);
+        if let Some(dashboard) = dashboard {
+            dashboard.thread().unpark();
+            let _ = dashboard.join();
+        }
+    }
+
+    /// Replaces the `drawn` lines of the last frame with a new one and
+    /// returns its height.
+    fn draw(&self, style: Style, drawn: usize, finished: bool) -> usize {
+        let elapsed = self.started.elapsed().as_secs_f64();
+        let bytes: Vec<u64> = self
+            .workers
+            .iter()
+            .map(|w| w.bytes.load(Ordering::Relaxed))
+            .collect();
+        // The last reports of the workers are smaller than `REPORT_BYTES`
+        let done = if finished {
+            self.total
+        } else {
+            bytes.iter().sum::<u64>().min(self.total)
+        };
+        let fraction = if self.total == 0 {
+            1.0
+        } else {
+            done as f64 / self.total as f64
+        };
+        let status = format!(
+            "{} {:>3.0}%  {} of {}  {}/s  {:.1} s",
+            bar(fraction, BAR_WIDTH),
+            fraction * 100.0,
+            format_bytes(done as usize),
+            format_bytes(self.total as usize),
+            format_bytes((done as f64 / elapsed.max(1e-3)) as usize),
+            elapsed
+        );
+
+        let mut stderr = io::stderr().lock();
+        if style == Style::Bar {
+            let _ = write!(stderr, "\r{}", status);
+            if finished {
+                let _ = writeln!(stderr);
+            }
+            let _ = stderr.flush();
+            return 1;
+        }
+
+        let mut frame = String::new();
+        // Back to the top of the last frame, clearing it
+        if drawn > 0 {
+            let _ = write!(frame, "\x1b[{}A", drawn);
+        }
+        frame.push_str("\r\x1b[J");
+        let _ = writeln!(frame, "\x1b[1m1brc\x1b[0m {}", status);
+        let sum = bytes.iter().sum::<u64>().max(1);
+        let busiest = bytes.iter().copied().max().unwrap_or(0).max(1);
+        for (i, &worker) in bytes.iter().enumerate().take(MAX_WORKERS) {
+            let _ = writeln!(
+                frame,
+                "  worker {:<3} {} {:>3.0}%",
+                i,
+                bar(worker as f64 / busiest as f64, BAR_WIDTH / 2),
+                worker as f64 * 100.0 / sum as f64
+            );
+        }
+        if bytes.len() > MAX_WORKERS {
+            let _ = writeln!(frame, "  and {} more workers", bytes.len() - MAX_WORKERS);
+        }
+        let tops: Vec<Vec<(String, f64)>> = self
+            .workers
+            .iter()
+            .map(|w| w.top.lock().unwrap().clone())
+            .collect();
+        let top = top(tops
+            .iter()
+            .flatten()
+            .map(|(name, max)| (name.as_str(), *max)));
+        if !top.is_empty() {
+            let _ = writeln!(frame, "  hottest so far");
+            let width = top.iter().map(|(name, _)| name.chars().count()).max();
+            for (name, max) in &top {
+                let _ = writeln!(
+                    frame,
+                    "  {:<width$}  {:>5.1}",
+                    name,
+                    max,
+                    width = width.unwrap_or(0)
+                );
+            }
+        }
+        let _ = stderr.write_all(frame.as_bytes());
+        let _ = stderr.flush();
+        frame.matches('\n').count()
+    }
+}
+
+/// The stations with the highest maximum, each once.
+fn top<'a>(stations: impl Iterator<Item = (&'a str, f64)>) -> Vec<(String, f64)> {
+    let mut maxima: HashMap<&str, f64> = HashMap::new();
+    for (name, max) in stations {
+        let entry = maxima.entry(name).or_insert(max);
+        *entry = entry.max(max);
+    }
+    let mut top: Vec<(&str, f64)> = maxima.into_iter().collect();
+    top.sort_by(|a, b| b.1.total_cmp(&a.1).then_with(|| a.0.cmp(b.0)));
+    top.truncate(TOP);
+    top.into_iter()
+        .map(|(name, max)| (name.to_string(), max))
+        .collect()
+}
+
+fn bar(fraction: f64, width: usize) -> String {
+    let filled = ((fraction.clamp(0.0, 1.0) * width as f64).round() as usize).min(width);
+    format!("[{}{}]", "#".repeat(filled), " ".repeat(width - filled))
+}
@@ -0,0 +1,368 @@
+//! `1brc diff A B`: compares two saved outputs of the binary, in any of its
+//! output formats, station by station.
+
+use crate::output::{self, char_width};
+use crate::{csv, jsonl};
+use anyhow::{Context, bail};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// The fields that are compared; others, like metadata, are ignored.
+const FIELDS: [&str; 4] = ["min", "mean", "max", "count"];
+
+#[derive(Clone, Debug)]
+pub struct DiffArgs {
+    pub a: PathBuf,
+    pub b: PathBuf,
+    /// Largest difference of a field that still counts as equal
+    pub tolerance: f64,
+}
+
+/// The fields of each key, which includes the window if there is one.
+type ResultSet = BTreeMap<String, BTreeMap<&'static str, f64>>;
+
+/// Prints how the two outputs differ and returns whether they do.
+pub fn run(args: &DiffArgs) -> anyhow::Result<bool> {
+    let a = load(&args.a)?;
+    let b = load(&args.b)?;
+    let (a_name, b_name) = (args.a.display(), args.b.display());
+
+    let mut out = output::stdout();
+    let mut differing = 0;
+    for key in a.keys().filter(|key| !b.contains_key(*key)) {
+        writeln!(out, "only in {}: {}", a_name, key)?;
+        differing += 1;
+    }
+    for key in b.keys().filter(|key| !a.contains_key(*key)) {
+        writeln!(out, "only in {}: {}", b_name, key)?;
+        differing += 1;
+    }
+    let mut compared = 0;
+    for (key, a_fields) in &a {
+        let Some(b_fields) = b.get(key) else {
+            continue;
+        };
+        compared += 1;
+        let mut deltas = vec![];
+        for field in FIELDS {
+            if let (Some(x), Some(y)) = (a_fields.get(field), b_fields.get(field)) {
+                // Allow for the decimal rounding of both sides
+                if (y - x).abs() > args.tolerance + 1e-9 {
+                    deltas.push(if field == "count" {
+                        format!("{} {} vs {} ({:+})", field, x, y, y - x)
+                    } else {
+                        format!("{} {:.1} vs {:.1} ({:+.1})", field, x, y, y - x)
+                    });
+                }
+            }
+        }
+        if !deltas.is_empty() {
+            writeln!(out, "{}: {}", key, deltas.join(", "))?;
+            differing += 1;
+        }
+    }
+    writeln!(
+        out,
+        "{} keys in both, {} differing, with a tolerance of {}",
+        compared, differing, args.tolerance
+    )?;
+    out.flush()?;
+    Ok(differing > 0)
+}
+
+fn load(path: &Path) -> anyhow::Result<ResultSet> {
+    let content =
+        fs::read_to_string(path).with_context(|| format!("failed to read {}", path.display()))?;
+    parse(&content).with_context(|| format!("failed to parse {}", path.display()))
+}
+
+/// Recognises the format by its first line of results, skipping the
+/// binary's other messages.
+fn parse(content: &str) -> anyhow::Result<ResultSet> {
+    let lines: Vec<&str> = content.lines().filter(|line| !is_message(line)).collect();
+    let Some(first) = lines.iter().position(|line| !line.trim().is_empty()) else {
+        return Ok(ResultSet::new());
+    };
+    let lines = &lines[first..];
+    let head = lines[0].trim();
+    if head == "[" || head == "[]" {
+        parse_json(lines)
+    } else if head.starts_with('{') && head.ends_with('}') {
+        parse_text(head)
+    } else if head.starts_with("# HELP") || head.starts_with("# TYPE") {
+        parse_prom(lines)
+    } else if head.starts_with('|') {
+        parse_markdown(lines)
+    } else if lines
+        .get(1)
+        .is_some_and(|line| line.starts_with('-') && line.chars().all(|c| c == '-' || c == ' '))
+    {
+        parse_table(lines)
+    } else if head.contains(',') {
+        parse_csv(lines)
+    } else {
+        // `--list-stations` text output, one name per line
+        Ok(lines
+            .iter()
+            .map(|line| (line.to_string(), BTreeMap::new()))
+            .collect())
+    }
+}
+
+fn is_message(line: &str) -> bool {
+    [
+        "Processed ",
+        "File processed successfully",
+        "Excluded ",
+        "Skipped ",
+        "Stats:",
+        "Resumed totals",
+        "Totals after ",
+    ]
+    .iter()
+    .any(|prefix| line.starts_with(prefix))
+        // The lines under `Stats:` and per station missing counts, but not
+        // the objects of JSON output
+        || line.starts_with("  ") && !line.trim_start().starts_with('{')
+}
+
+fn number(field: &str, value: &str) -> anyhow::Result<f64> {
+    value
+        .trim()
+        .parse()
+        .with_context(|| format!("invalid {} `{}`", field, value))
+}
+
+/// A row of named cells, keyed by its first cell and the `window` cell.
+fn add_row(set: &mut ResultSet, header: &[String], cells: &[String]) -> anyhow::Result<()> {
+    if cells.len() != header.len() {
+        bail!("expected {} columns, got {}", header.len(), cells.len());
+    }
+    let mut key = cells[0].clone();
+    let mut fields = BTreeMap::new();
+    for (name, cell) in header.iter().zip(cells).skip(1) {
+        if name == "window" {
+            key = format!("{}@{}", key, cell);
+        } else if let Some(field) = FIELDS.iter().find(|f| *f == name) {
+            fields.insert(*field, number(name, cell)?);
+        }
+    }
+    set.insert(key, fields);
+    Ok(())
+}
+
+/// `{name=min/mean/max, ...}` or `{name=count, ...}`. Names may contain
+/// `, ` themselves, so a piece only ends an entry once it has a value.
+fn parse_text(line: &str) -> anyhow::Result<ResultSet> {
+    let mut set = ResultSet::new();
+    let inner = &line[1..line.len() - 1];
+    let mut entry = String::new();
+    for piece in inner.split(", ") {
+        if !entry.is_empty() {
+            entry.push_str(", ");
+        }
+        entry.push_str(piece);
+        let Some((name, value)) = entry.rsplit_once('=') else {
+            continue;
+        };
+        let numbers: Vec<f64> = match value.split('/').map(str::parse).collect() {
+            Ok(numbers) => numbers,
+            Err(_) => continue,
+        };
+        let fields = match numbers[..] {
+            [min, mean, max] => BTreeMap::from([("min", min), ("mean", mean), ("max", max)]),
+            [count] => BTreeMap::from([("count", count)]),
+            _ => continue,
+        };
+        set.insert(name.to_string(), fields);
+        entry.clear();
+    }
+    if !entry.is_empty() {
+        bail!("no value in `{}`", entry);
+    }
+    Ok(set)
+}
+
+fn parse_csv(lines: &[&str]) -> anyhow::Result<ResultSet> {
+    let split = |line: &str| -> anyhow::Result<Vec<String>> {
+        Ok(csv::split_record_on(line, ',')?
+            .into_iter()
+            .map(|cell| cell.into_owned())
+            .collect())
+    };
+    let header = split(lines[0])?;
+    let mut set = ResultSet::new();
+    for line in &lines[1..] {
+        add_row(&mut set, &header, &split(line)?)?;
+    }
+    Ok(set)
+}
+
+fn parse_json(lines: &[&str]) -> anyhow::Result<ResultSet> {
+    let mut set = ResultSet::new();
+    for line in lines {
+        let line = line.trim().trim_end_matches(',');
+        if !line.starts_with('{') {
+            continue;
+        }
+        let object = jsonl::parse_object(line)?;
+        let (header, cells): (Vec<String>, Vec<String>) = object.into_iter().unzip();
+        add_row(&mut set, &header, &cells)?;
+    }
+    Ok(set)
+}
+
+fn parse_markdown(lines: &[&str]) -> anyhow::Result<ResultSet> {
+    let split = |line: &str| -> Vec<String> {
+        let line = line.trim().trim_start_matches('|');
+        let line = line.strip_suffix('|').unwrap_or(line);
+        // Cut at the bars that are not escaped, and trimmed before the
+        // escapes are undone, so that a name may end in a line break
+        let mut cells = vec![String::new()];
+        let mut chars = line.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' => {
+                    let cell = cells.last_mut().unwrap();
+                    cell.push(c);
+                    cell.extend(chars.next());
+                }
+                '|' => cells.push(String::new()),
+                c => cells.last_mut().unwrap().push(c),
+            }
+        }
+        cells
+            .iter()
+            .map(|cell| unescape_markdown(cell.trim()))
+            .collect()
+    };
+    let header = split(lines[0]);
+    let mut set = ResultSet::new();
+    // The second line sets the alignment
+    for line in lines.iter().skip(2) {
+        add_row(&mut set, &header, &split(line))?;
+    }
+    Ok(set)
+}
+
+/// Undoes the escapes of a Markdown cell, leaving other backslashes be.
+fn unescape_markdown(cell: &str) -> String {
+    let mut out = String::with_capacity(cell.len());
+    let mut chars = cell.chars().peekable();
+    while let Some(c) = chars.next() {
+        match (c, chars.peek()) {
+            ('\\', Some(&escaped @ ('|' | '\\' | 'n' | 'r'))) => {
+                out.push(match escaped {
+                    'n' => '\n',
+                    'r' => '\r',
+                    c => c,
+                });
+                chars.next();
+            }
+            (c, _) => out.push(c),
+        }
+    }
+    out
+}
+
+/// Columns are where the rule under the header has dashes, counted in
+/// terminal columns. Colors are stripped first.
+fn parse_table(lines: &[&str]) -> anyhow::Result<ResultSet> {
+    let mut columns = vec![];
+    let mut start = None;
+    for (i, c) in lines[1].chars().chain([' ']).enumerate() {
+        match (c, start) {
+            ('-', None) => start = Some(i),
+            (' ', Some(s)) => {
+                columns.push(s..i);
+                start = None;
+            }
+            _ => {}
+        }
+    }
+    let split = |line: &str| -> Vec<String> {
+        let mut cells = vec![String::new(); columns.len()];
+        let mut width = 0;
+        let mut chars = line.chars();
+        while let Some(c) = chars.next() {
+            if c == '\x1b' {
+                // Skip to the end of the escape sequence
+                chars.by_ref().find(|c| c.is_ascii_alphabetic());
+                continue;
+            }
+            if let Some(i) = columns.iter().position(|range| range.contains(&width)) {
+                cells[i].push(c);
+            }
+            width += char_width(c);
+        }
+        cells.iter().map(|cell| cell.trim().to_string()).collect()
+    };
+    let header = split(lines[0]);
+    let mut set = ResultSet::new();
+    for line in &lines[2..] {
+        add_row(&mut set, &header, &split(line))?;
+    }
+    Ok(set)
+}
+
+/// `metric{label="key",window="..."} value` samples of the known gauges.
+fn parse_prom(lines: &[&str]) -> anyhow::Result<ResultSet> {
+    let mut set = ResultSet::new();
+    for line in lines {
+        if line.starts_with('#') || line.trim().is_empty() {
+            continue;
+        }
+        let Some((metric, rest)) = line.split_once('{') else {
+            bail!("expected labels in `{}`", line);
+        };
+        let field = match metric {
+            "station_temp_min" => Some("min"),
+            "station_temp_mean" => Some("mean"),
+            "station_temp_max" => Some("max"),
+            "station_readings" => Some("count"),
+            _ => None,
+        };
+        let (labels, value) = parse_labels(rest).with_context(|| format!("in `{}`", line))?;
+        let mut key = labels.first().cloned().unwrap_or_default();
+        if let Some(window) = labels.get(1) {
+            key = format!("{}@{}", key, window);
+        }
+        let fields = set.entry(key).or_default();
+        if let Some(field) = field {
+            fields.insert(field, number(field, value)?);
+        }
+    }
+    Ok(set)
+}
+
+/// The values of `a="x",b="y"}`, and what follows the braces.
+fn parse_labels(s: &str) -> anyhow::Result<(Vec<String>, &str)> {
+    let mut values = vec![];
+    let mut rest = s;
+    loop {
+        if let Some(after) = rest.strip_prefix('}') {
+            return Ok((values, after.trim()));
+        }
+        let Some((_, after)) = rest.split_once("=\"") else {
+            bail!("malformed labels");
+        };
+        let mut value = String::new();
+        let mut chars = after.char_indices();
+        let end = loop {
+            match chars.next() {
+                Some((_, '\\')) => match chars.next() {
+                    Some((_, 'n')) => value.push('\n'),
+                    Some((_, c)) => value.push(c),
+                    None => bail!("unterminated label value"),
+                },
+                Some((i, '"')) => break i,
+                Some((_, c)) => value.push(c),
+                None => bail!("unterminated label value"),
+            }
+        };
+        values.push(value);
+        rest = after[end + 1..].trim_start_matches(',');
+    }
+}
@@ -0,0 +1,118 @@
+//! `--detect-duplicates`: each worker keeps the XXH64 of every line it
+//! reads in a set of its own, and once the scan is done the sets are
+//! added together, so that a line is counted as a duplicate wherever in
+//! the input its first copy was.
+//!
+//! Lines are told apart by their hashes alone. Two different lines that
+//! hash the same are taken for one, which at a billion distinct lines has
+//! a few percent chance of happening once. The sets take some 16 bytes per
+//! distinct line.
+//!
+//! `--dedupe` leaves the repeats out instead, telling them apart with a
+//! Bloom filter that all workers share. That takes a few bytes per row
+//! whatever the rows are, at the price of leaving out the odd row that
+//! is not a repeat, about one in 10,000 unless `--dedupe-fp` says
+//! otherwise.
+
+use crate::bloom::Bloom;
+use crate::cli::{Config, Verbosity};
+use crate::input::{self, Source};
+use crate::xxhash::xxh64;
+use std::collections::HashSet;
+use std::hash::{BuildHasherDefault, Hasher};
+
+/// The line hashes one worker has seen.
+#[derive(Debug, Default)]
+pub struct Seen {
+    hashes: HashSet<u64, BuildHasherDefault<Identity>>,
+    /// Lines hashed, including repeats
+    lines: u64,
+}
+
+impl Seen {
+    /// Adds `line`, without its terminator. Empty lines are left out.
+    pub fn add(&mut self, line: &str) {
+        if line.is_empty() {
+            return;
+        }
+        self.lines += 1;
+        self.hashes.insert(xxh64(line.as_bytes(), 0));
+    }
+}
+
+/// What the workers' sets came to.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Duplicates {
+    /// Lines that were not empty
+    pub lines: u64,
+    pub distinct: u64,
+}
+
+impl Duplicates {
+    pub fn count(seen: Vec<Seen>) -> Duplicates {
+        let lines = seen.iter().map(|seen| seen.lines).sum();
+        // The other sets go into the largest
+        let mut seen = seen;
+        seen.sort_unstable_by_key(|seen| std::cmp::Reverse(seen.hashes.len()));
+        let mut sets = seen.into_iter().map(|seen| seen.hashes);
+        let mut all = sets.next().unwrap_or_default();
+        for set in sets {
+            all.extend(set);
+        }
+        Duplicates {
+            lines,
+            distinct: all.len() as u64,
+        }
+    }
+
+    /// Lines that repeat one before them.
+    pub fn repeated(self) -> u64 {
+        self.lines - self.distinct
+    }
+
+    /// Adds the duplicates of another input, those within it only.
+    pub fn merge(&mut self, other: Duplicates) {
+        self.lines += other.lines;
+        self.distinct += other.distinct;
+    }
+}
+
+const DEFAULT_FALSE_POSITIVES: f64 = 1e-4;
+
+/// The filter `--dedupe` checks rows against, sized for the rows `source`
+/// looks to have and an eighth more.
+pub fn filter(config: &Config, source: &Source) -> std::io::Result<Bloom> {
+    let rows = input::estimate_lines(source)?.lines;
+    let rows = rows + rows / 8;
+    let rate = config
+        .dedupe_false_positives
+        .unwrap_or(DEFAULT_FALSE_POSITIVES);
+    let bloom = Bloom::new(rows, rate);
+    if config.verbosity >= Verbosity::Verbose {
+        eprintln!(
+            "Dedupe filter: {} for {} rows at a false-positive rate of {}",
+            crate::memory::format_bytes(bloom.bytes()),
+            rows,
+            rate
+        );
+    }
+    Ok(bloom)
+}
+
+/// The hashes are hashes already.
+#[derive(Default)]
+struct Identity(u64);
+
+impl Hasher for Identity {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, _: &[u8]) {
+        unreachable!("only u64 keys are hashed");
+    }
+
+    fn write_u64(&mut self, n: u64) {
+        self.0 = n;
+    }
+}
@@ -0,0 +1,88 @@
+//! `--export-rows`: the raw rows that pass the station and value filters,
+//! copied out alongside the aggregation, so that the tool doubles as a
+//! parallel grep over measurement files.
+
+use crate::cli::Config;
+use anyhow::Context as _;
+use std::collections::{BTreeMap, VecDeque};
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::sync::Mutex;
+
+pub struct Export {
+    preserve_order: bool,
+    out: Mutex<Out>,
+}
+
+struct Out {
+    file: BufWriter<File>,
+    /// Under `--preserve-order`, the chunks handed out but not written
+    /// yet, in file order
+    dispatched: VecDeque<u64>,
+    /// Rows of chunks that finished before one ahead of them
+    pending: BTreeMap<u64, String>,
+}
+
+impl Export {
+    pub fn create(config: &Config) -> anyhow::Result<Self> {
+        let path = config.export_rows.as_ref().expect("an export file");
+        let file =
+            File::create(path).with_context(|| format!("failed to create {}", path.display()))?;
+        Ok(Export {
+            preserve_order: config.preserve_order,
+            out: Mutex::new(Out {
+                file: BufWriter::new(file),
+                dispatched: VecDeque::new(),
+                pending: BTreeMap::new(),
+            }),
+        })
+    }
+
+    pub fn preserves_order(&self) -> bool {
+        self.preserve_order
+    }
+
+    /// Notes that the chunk at `offset` has been handed to a worker, after
+    /// the ones before it in the file.
+    pub fn dispatch(&self, offset: u64) {
+        if self.preserve_order {
+            self.out.lock().unwrap().dispatched.push_back(offset);
+        }
+    }
+
+    /// Writes the rows of the chunk at `offset`, each ending in a newline.
+    /// Under `--preserve-order` they are held back until the chunks before
+    /// them have been written, so every dispatched chunk must be written,
+    /// if only with no rows.
+    pub fn write(&self, offset: u64, rows: &str) -> io::Result<()> {
+        let mut out = self.out.lock().unwrap();
+        if !self.preserve_order {
+            return out.file.write_all(rows.as_bytes());
+        }
+        if out.dispatched.front() != Some(&offset) {
+            out.pending.insert(offset, rows.to_string());
+            return Ok(());
+        }
+        out.dispatched.pop_front();
+        out.file.write_all(rows.as_bytes())?;
+        while let Some(next) = out.dispatched.front().copied()
+            && let Some(rows) = out.pending.remove(&next)
+        {
+            out.dispatched.pop_front();
+            out.file.write_all(rows.as_bytes())?;
+        }
+        Ok(())
+    }
+
+    /// Writes anything still held back, which is only left when chunks were
+    /// lost with a failed worker, and flushes the file.
+    pub fn finish(&self) -> io::Result<()> {
+        let mut out = self.out.lock().unwrap();
+        out.dispatched.clear();
+        let Out { file, pending, .. } = &mut *out;
+        for rows in std::mem::take(pending).into_values() {
+            file.write_all(rows.as_bytes())?;
+        }
+        file.flush()
+    }
+}
@@ -0,0 +1,306 @@
+//! The text, CSV and JSON forms the `1brc` binary writes its results in,
+//! a row at a time, so that a sink writing results of its own, or a
+//! process embedding the aggregation, can write them the same way without
+//! holding the whole output.
+
+use crate::time::format_timestamp;
+use std::fmt::{Display, Write as _};
+use std::io::{self, Write};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    /// `{name=a/b/c, ...}` as the challenge prints it, or one name per line
+    /// when the rows have no values
+    Text,
+    Csv,
+    /// An array of one object per row
+    Json,
+}
+
+/// A value of a column that only CSV and JSON show.
+#[derive(Clone, Copy, Debug)]
+pub enum Cell<'a> {
+    /// Quoted in JSON
+    Text(&'a str),
+    Number(f64),
+}
+
+/// The columns of the rows to be written.
+#[derive(Clone, Debug, Default)]
+pub struct Layout {
+    /// What the names are, such as `station`
+    pub label: String,
+    /// Whether the rows have a time window, and a value column
+    pub windowed: bool,
+    pub columns: bool,
+    /// The values each row has, in order
+    pub values: Vec<String>,
+    /// Further columns for CSV and JSON, such as a station's country
+    pub details: Vec<String>,
+}
+
+/// Writes rows laid out as a [`Layout`] says, in the order they are given.
+pub struct Writer<W: Write> {
+    out: W,
+    format: Format,
+    layout: Layout,
+    rows: usize,
+    /// Reused for each row
+    line: String,
+}
+
+impl<W: Write> Writer<W> {
+    /// Starts the output, with the header in CSV.
+    pub fn new(format: Format, layout: Layout, mut out: W) -> io::Result<Self> {
+        match format {
+            Format::Text if !layout.values.is_empty() => out.write_all(b"{")?,
+            Format::Text => {}
+            Format::Csv => {
+                let mut header = layout.label.clone();
+                if layout.windowed {
+                    header.push_str(",window");
+                }
+                if layout.columns {
+                    header.push_str(",column");
+                }
+                for name in layout.values.iter().chain(&layout.details) {
+                    header.push(',');
+                    header.push_str(name);
+                }
+                out.write_all(header.as_bytes())?;
+            }
+            Format::Json => out.write_all(b"[")?,
+        }
+        Ok(Writer {
+            out,
+            format,
+            layout,
+            rows: 0,
+            line: String::new(),
+        })
+    }
+
+    /// Writes the row of `name`, with one of `values` per value column and
+    /// `details`, unless it has none, one per detail column.
+    pub fn row(
+        &mut self,
+        name: &str,
+        window: Option<i64>,
+        column: Option<u16>,
+        values: impl IntoIterator<Item = impl Display>,
+        details: Option<&[Cell]>,
+    ) -> io::Result<()> {
+        let Writer {
+            out, layout, line, ..
+        } = self;
+        line.clear();
+        let first = self.rows == 0;
+        self.rows += 1;
+        match self.format {
+            Format::Text => {
+                if !first {
+                    line.push_str(if layout.values.is_empty() { "\n" } else { ", " });
+                }
+                line.push_str(name);
+                if let Some(window) = window {
+                    let _ = write!(line, "@{}", format_timestamp(window));
+                }
+                if let Some(column) = column {
+                    let _ = write!(line, "#{}", column);
+                }
+                for (i, value) in values.into_iter().enumerate() {
+                    line.push(if i == 0 { '=' } else { '/' });
+                    let _ = write!(line, "{}", value);
+                }
+            }
+            Format::Csv => {
+                line.push('\n');
+                line.push_str(&csv_field(name));
+                if let Some(window) = window {
+                    let _ = write!(line, ",{}", format_timestamp(window));
+                }
+                if let Some(column) = column {
+                    let _ = write!(line, ",{}", column);
+                }
+                for value in values {
+                    let _ = write!(line, ",{}", value);
+                }
+                match details {
+                    Some(details) => {
+                        for cell in details {
+                            match cell {
+                                Cell::Text(text) => {
+                                    let _ = write!(line, ",{}", csv_field(text));
+                                }
+                                Cell::Number(number) => {
+                                    let _ = write!(line, ",{}", number);
+                                }
+                            }
+                        }
+                    }
+                    None => line.push_str(&",".repeat(layout.details.len())),
+                }
+            }
+            Format::Json => {
+                if !first {
+                    line.push(',');
+                }
+                let _ = write!(line, "\n  {{\"{}\": {}", layout.label, json_string(name));
+                if let Some(window) = window {
+                    let _ = write!(line, ", \"window\": \"{}\"", format_timestamp(window));
+                }
+                if let Some(column) = column {
+                    let _ = write!(line, ", \"column\": {}", column);
+                }
+                for (name, value) in layout.values.iter().zip(values) {
+                    let _ = write!(line, ", \"{}\": {}", name, value);
+                }
+                for (name, cell) in layout.details.iter().zip(details.unwrap_or_default()) {
+                    match cell {
+                        Cell::Text(text) => {
+                            let _ = write!(line, ", \"{}\": {}", name, json_string(text));
+                        }
+                        Cell::Number(number) => {
+                            let _ = write!(line, ", \"{}\": {}", name, number);
+                        }
+                    }
+                }
+                line.push('}');
+            }
+        }
+        out.write_all(line.as_bytes())
+    }
+
+    /// Whether nothing has been written, as by a list of names with no rows
+    /// yet. Every other output has at least its brackets or header.
+    pub fn is_empty(&self) -> bool {
+        self.format == Format::Text && self.layout.values.is_empty() && self.rows == 0
+    }
+
+    /// Ends the output, without a trailing newline or a flush, and returns
+    /// where it went.
+    pub fn finish(mut self) -> io::Result<W> {
+        match self.format {
+            Format::Text if !self.layout.values.is_empty() => self.out.write_all(b"}")?,
+            Format::Json if self.rows > 0 => self.out.write_all(b"\n]")?,
+            Format::Json => self.out.write_all(b"]")?,
+            _ => {}
+        }
+        Ok(self.out)
+    }
+}
+
+/// `field` as a CSV field, quoted if it has to be.
+pub fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+/// `s` as a JSON string.
+pub fn json_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const VALUES: &[&str] = &["min", "mean", "max"];
+
+    /// A name, its values and its details
+    type Row<'a> = (&'a str, &'a [&'a str], Option<&'a [Cell<'a>]>);
+
+    fn write(format: Format, layout: Layout, rows: &[Row]) -> String {
+        let mut writer = Writer::new(format, layout, Vec::new()).unwrap();
+        for (name, values, details) in rows {
+            writer.row(name, None, None, *values, *details).unwrap();
+        }
+        String::from_utf8(writer.finish().unwrap()).unwrap()
+    }
+
+    fn layout(values: &[&str], details: &[&str]) -> Layout {
+        Layout {
+            label: "station".to_string(),
+            values: values.iter().map(|v| v.to_string()).collect(),
+            details: details.iter().map(|d| d.to_string()).collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn text_is_the_challenge_format() {
+        let rows: &[Row] = &[
+            ("Abha", &["-1.0", "2.5", "6.0"], None),
+            ("Zürich", &["0.0", "0.0", "0.0"], None),
+        ];
+        assert_eq!(
+            write(Format::Text, layout(VALUES, &[]), rows),
+            "{Abha=-1.0/2.5/6.0, Zürich=0.0/0.0/0.0}"
+        );
+        assert_eq!(write(Format::Text, layout(VALUES, &[]), &[]), "{}");
+        let names: &[Row] = &[("Abha", &[], None), ("Zürich", &[], None)];
+        assert_eq!(write(Format::Text, layout(&[], &[]), names), "Abha\nZürich");
+    }
+
+    #[test]
+    fn only_a_list_of_no_names_is_empty() {
+        let empty = |format, values| {
+            Writer::new(format, layout(values, &[]), Vec::new())
+                .unwrap()
+                .is_empty()
+        };
+        assert!(empty(Format::Text, &[]));
+        assert!(!empty(Format::Text, VALUES));
+        assert!(!empty(Format::Csv, &[]));
+        assert!(!empty(Format::Json, &[]));
+        let mut names = Writer::new(Format::Text, layout(&[], &[]), Vec::new()).unwrap();
+        names.row("Abha", None, None, [""; 0], None).unwrap();
+        assert!(!names.is_empty());
+    }
+
+    #[test]
+    fn csv_quotes_fields_and_leaves_missing_details_empty() {
+        let country = [Cell::Text("SA"), Cell::Number(18.25)];
+        let rows: &[Row] = &[
+            ("Abha", &["1.0"], Some(&country[..])),
+            ("St. \"John's\", NL", &["2.0"], None),
+        ];
+        assert_eq!(
+            write(Format::Csv, layout(&["mean"], &["country", "lat"]), rows),
+            "station,mean,country,lat\nAbha,1.0,SA,18.25\n\"St. \"\"John's\"\", NL\",2.0,,"
+        );
+    }
+
+    #[test]
+    fn json_escapes_names_and_leaves_out_missing_details() {
+        let country = [Cell::Text("SA")];
+        let rows: &[Row] = &[
+            ("Abha", &["4"], Some(&country[..])),
+            ("A \"b\"\n", &["2"], None),
+        ];
+        assert_eq!(
+            write(Format::Json, layout(&["count"], &["country"]), rows),
+            "[\n  {\"station\": \"Abha\", \"count\": 4, \"country\": \"SA\"},\n  {\"station\": \"A \\\"b\\\"\\n\", \"count\": 2}\n]"
+        );
+        assert_eq!(write(Format::Json, layout(&["count"], &[]), &[]), "[]");
+    }
+}
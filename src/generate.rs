@@ -0,0 +1,644 @@
+//! `1brc generate`: writes rows in the challenge's format, `name;reading`
+//! with one decimal, for the aggregation to be run and measured on. The
+//! stations and the means their readings spread around are those of the
+//! original challenge's generator, in `data/stations.txt`, or the ones of
+//! `--stations-file`, in the same format. They come up as often as each
+//! other, or under `--skew` a few of them much more often than the rest,
+//! which is where strategies sharing a map part ways with those keeping
+//! one a thread.
+//!
+//! `--edge-cases` mixes in names and readings at the limits of the rules,
+//! and keeps a row across every 4 KiB boundary, often cut inside a
+//! character, for parsers to be tried on.
+//!
+//! The rows come in blocks, each drawn from a seed of its own that follows
+//! from `--seed` and where the block is, so that a seed always gives the
+//! same rows. `--shards` splits them between files written in parallel,
+//! each of a seed that follows from `--seed` and the shard, and
+//! `--compress gzip` writes them through [`GzipWriter`]. The rows are
+//! `--rows` of them or as many as make up `--bytes`, and how fast they
+//! came is reported at the end, and under `--progress` as they come.
+
+use crate::gzip::GzipWriter;
+use crate::memory;
+use crate::rng::Rng;
+use crate::spec;
+use crate::xxhash::xxh64;
+use anyhow::{Context as _, anyhow, bail};
+use std::fs::{self, File};
+use std::io::{self, BufWriter, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const DEFAULT_STATIONS: &str = include_str!("../data/stations.txt");
+/// The spread of a station's readings without one of its own
+const DEFAULT_STDDEV: f64 = 10.0;
+/// Rows drawn from one seed
+const BLOCK_ROWS: u64 = 1 << 16;
+const DEFAULT_ROWS: u64 = 1_000_000_000;
+/// How often `--progress` is redrawn on a terminal, and written elsewhere
+const REFRESH: Duration = Duration::from_millis(250);
+const REFRESH_LOG: Duration = Duration::from_secs(5);
+
+#[derive(Clone, Debug)]
+pub struct GenerateArgs {
+    /// A billion without these
+    pub rows: Option<u64>,
+    /// As many rows as make up at least these bytes, before compression
+    pub bytes: Option<u64>,
+    pub seed: u64,
+    /// Where the rows are written, `-` for stdout
+    pub output: PathBuf,
+    pub stations_file: Option<PathBuf>,
+    pub distribution: Distribution,
+    pub skew: Skew,
+    pub edge_cases: bool,
+    /// How many files the rows are split between, each of a seed of its
+    /// own
+    pub shards: u64,
+    pub compress: Compress,
+    pub progress: bool,
+}
+
+impl Default for GenerateArgs {
+    fn default() -> Self {
+        GenerateArgs {
+            rows: None,
+            bytes: None,
+            seed: 0,
+            output: PathBuf::from("measurements.txt"),
+            stations_file: None,
+            distribution: Distribution::default(),
+            skew: Skew::default(),
+            edge_cases: false,
+            shards: 1,
+            compress: Compress::None,
+            progress: false,
+        }
+    }
+}
+
+/// How a station's readings spread around its mean.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Distribution {
+    #[default]
+    Gaussian,
+    /// Evenly over as wide a range as has the station's spread
+    Uniform,
+    /// Two peaks a spread either side of the mean, each half as wide
+    Bimodal,
+}
+
+impl Distribution {
+    pub fn parse(v: &str) -> anyhow::Result<Self> {
+        match v {
+            "gaussian" => Ok(Distribution::Gaussian),
+            "uniform" => Ok(Distribution::Uniform),
+            "bimodal" => Ok(Distribution::Bimodal),
+            _ => bail!("expected `gaussian`, `uniform` or `bimodal`, got `{}`", v),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Compress {
+    None,
+    Gzip,
+}
+
+impl Compress {
+    pub fn parse(v: &str) -> anyhow::Result<Self> {
+        match v {
+            "none" => Ok(Compress::None),
+            "gzip" => Ok(Compress::Gzip),
+            _ => bail!("expected `none` or `gzip`, got `{}`", v),
+        }
+    }
+}
+
+/// How often each station comes up.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Skew {
+    /// As often as every other
+    #[default]
+    None,
+    /// The nth station of the list as often as the first over n to the
+    /// power of the exponent, so that a few make up most of the rows
+    Zipf(f64),
+}
+
+impl Skew {
+    pub fn parse(v: &str) -> anyhow::Result<Self> {
+        if v == "none" {
+            return Ok(Skew::None);
+        }
+        match v.strip_prefix("zipf:").map(str::parse::<f64>) {
+            Some(Ok(exponent)) if exponent.is_finite() && exponent > 0.0 => {
+                Ok(Skew::Zipf(exponent))
+            }
+            _ => bail!("expected `none` or `zipf:S` with S above 0, got `{}`", v),
+        }
+    }
+}
+
+/// Picks the stations as often as the skew has them come up.
+enum Picker {
+    Even(u64),
+    /// The running totals of the stations' weights
+    Weighted(Vec<f64>),
+}
+
+impl Picker {
+    fn new(stations: usize, skew: Skew) -> Self {
+        match skew {
+            Skew::None => Picker::Even(stations as u64),
+            Skew::Zipf(exponent) => Picker::Weighted(
+                (1..=stations)
+                    .scan(0.0, |total, rank| {
+                        *total += (rank as f64).powf(-exponent);
+                        Some(*total)
+                    })
+                    .collect(),
+            ),
+        }
+    }
+
+    fn pick(&self, rng: &mut Rng) -> usize {
+        match self {
+            Picker::Even(stations) => rng.below(*stations) as usize,
+            Picker::Weighted(totals) => {
+                let at = rng.unit() * totals[totals.len() - 1];
+                totals
+                    .partition_point(|&total| total <= at)
+                    .min(totals.len() - 1)
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct Station {
+    name: String,
+    mean: f64,
+    stddev: f64,
+}
+
+impl Station {
+    /// A reading in tenths, within the challenge's -99.9 to 99.9.
+    fn reading(&self, distribution: Distribution, rng: &mut Rng) -> i64 {
+        let value = match distribution {
+            Distribution::Gaussian => self.mean + self.stddev * rng.gaussian(),
+            Distribution::Uniform => {
+                self.mean + self.stddev * 3f64.sqrt() * (2.0 * rng.unit() - 1.0)
+            }
+            Distribution::Bimodal => {
+                let side = match rng.below(2) {
+                    0 => -self.stddev,
+                    _ => self.stddev,
+                };
+                self.mean + side + self.stddev / 2.0 * rng.gaussian()
+            }
+        };
+        ((value * 10.0).round() as i64).clamp(-999, 999)
+    }
+}
+
+/// Writes the rows `args` asks for.
+pub fn run(args: &GenerateArgs) -> anyhow::Result<()> {
+    let stations = match &args.stations_file {
+        Some(path) => {
+            let text = fs::read_to_string(path)
+                .with_context(|| format!("cannot read {}", path.display()))?;
+            parse_stations(&text).with_context(|| format!("in {}", path.display()))?
+        }
+        None => parse_stations(DEFAULT_STATIONS).expect("the built-in stations are valid"),
+    };
+    let generator = Generator {
+        args,
+        picker: Picker::new(stations.len(), args.skew),
+        stations,
+        edge_names: match args.edge_cases {
+            true => edge_names(),
+            false => vec![],
+        },
+    };
+    // `measurements.txt` is written as `measurements.txt.gz`
+    let (output, suffix) = match (args.compress, args.output.extension()) {
+        (Compress::None, _) => (args.output.clone(), ""),
+        (Compress::Gzip, Some(ext)) if ext == "gz" => (args.output.with_extension(""), ".gz"),
+        (Compress::Gzip, _) => (args.output.clone(), ".gz"),
+    };
+    let limit = match (args.rows, args.bytes) {
+        (_, Some(bytes)) => Limit::Bytes(bytes),
+        (rows, None) => Limit::Rows(rows.unwrap_or(DEFAULT_ROWS)),
+    };
+    let progress = Progress {
+        rows: AtomicU64::new(0),
+        bytes: AtomicU64::new(0),
+        started: Instant::now(),
+    };
+    let done = AtomicBool::new(false);
+    thread::scope(|scope| {
+        let shown = args
+            .progress
+            .then(|| scope.spawn(|| progress.show(limit, &done)));
+        let result = match args.shards {
+            1 => {
+                let mut path = output.into_os_string();
+                if path != "-" {
+                    path.push(suffix);
+                }
+                generator.write(Path::new(&path), limit, args.seed, &progress)
+            }
+            _ => generator.write_shards(&output, suffix, limit, &progress),
+        };
+        done.store(true, Ordering::Relaxed);
+        if let Some(shown) = shown {
+            shown.thread().unpark();
+        }
+        result
+    })?;
+    eprintln!("Generated {}", progress.summary());
+    Ok(())
+}
+
+/// How much is generated, into one file or between the shards.
+#[derive(Clone, Copy, Debug)]
+enum Limit {
+    Rows(u64),
+    /// As many rows as make up at least these bytes
+    Bytes(u64),
+}
+
+impl Limit {
+    fn total(self) -> u64 {
+        match self {
+            Limit::Rows(n) | Limit::Bytes(n) => n,
+        }
+    }
+
+    /// What shard `shard` of `shards` holds of it.
+    fn share(self, shard: u64, shards: u64) -> Self {
+        let up_to =
+            |shard: u64| (u128::from(self.total()) * u128::from(shard) / u128::from(shards)) as u64;
+        let n = up_to(shard + 1) - up_to(shard);
+        match self {
+            Limit::Rows(_) => Limit::Rows(n),
+            Limit::Bytes(_) => Limit::Bytes(n),
+        }
+    }
+}
+
+/// What the writers have written so far, for `--progress` and the report
+/// at the end.
+struct Progress {
+    rows: AtomicU64,
+    bytes: AtomicU64,
+    started: Instant,
+}
+
+impl Progress {
+    fn add(&self, rows: u64, bytes: u64) {
+        self.rows.fetch_add(rows, Ordering::Relaxed);
+        self.bytes.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    /// Writes how far the writers are towards `limit` on stderr until
+    /// `done`: a line redrawn in place on a terminal, and every few seconds
+    /// a line of its own elsewhere.
+    fn show(&self, limit: Limit, done: &AtomicBool) {
+        let terminal = io::stderr().is_terminal();
+        let refresh = match terminal {
+            true => REFRESH,
+            false => REFRESH_LOG,
+        };
+        loop {
+            thread::park_timeout(refresh);
+            if done.load(Ordering::Relaxed) {
+                break;
+            }
+            let done = match limit {
+                Limit::Rows(_) => self.rows.load(Ordering::Relaxed),
+                Limit::Bytes(_) => self.bytes.load(Ordering::Relaxed),
+            };
+            let percent = match limit.total() {
+                0 => 100.0,
+                total => (done as f64 * 100.0 / total as f64).min(100.0),
+            };
+            let line = format!("{:>3.0}%  {}", percent, self.summary());
+            match terminal {
+                true => eprint!("\r{}\x1b[K", line),
+                false => eprintln!("{}", line),
+            }
+        }
+        if terminal {
+            eprint!("\r\x1b[K");
+        }
+    }
+
+    /// The rows, the bytes and how fast they came.
+    fn summary(&self) -> String {
+        let rows = self.rows.load(Ordering::Relaxed);
+        let bytes = self.bytes.load(Ordering::Relaxed);
+        let seconds = self.started.elapsed().as_secs_f64().max(1e-3);
+        format!(
+            "{} rows, {} in {:.1} s: {:.1} M rows/s, {}/s",
+            rows,
+            memory::format_bytes(bytes as usize),
+            seconds,
+            rows as f64 / seconds / 1e6,
+            memory::format_bytes((bytes as f64 / seconds) as usize)
+        )
+    }
+}
+
+/// Where shard `shard` of `output` goes: `measurements-3.txt` for
+/// `measurements.txt`.
+fn shard_path(output: &Path, shard: u64) -> PathBuf {
+    let stem = output.file_stem().unwrap_or_default().to_string_lossy();
+    output.with_file_name(match output.extension() {
+        Some(ext) => format!("{}-{}.{}", stem, shard, ext.to_string_lossy()),
+        None => format!("{}-{}", stem, shard),
+    })
+}
+
+struct Generator<'a> {
+    args: &'a GenerateArgs,
+    stations: Vec<Station>,
+    picker: Picker,
+    /// The names of `--edge-cases`, none without it
+    edge_names: Vec<String>,
+}
+
+impl Generator<'_> {
+    /// Writes `limit` of rows drawn from `seed` to `path`, or to stdout
+    /// for `-`.
+    fn write(
+        &self,
+        path: &Path,
+        limit: Limit,
+        seed: u64,
+        progress: &Progress,
+    ) -> anyhow::Result<()> {
+        let out: Box<dyn Write> = match path.to_str() {
+            Some("-") => Box::new(io::stdout().lock()),
+            _ => Box::new(
+                File::create(path).with_context(|| format!("cannot create {}", path.display()))?,
+            ),
+        };
+        let out = BufWriter::with_capacity(1 << 20, out);
+        match self.args.compress {
+            Compress::None => self
+                .write_rows(out, limit, seed, progress)
+                .and_then(|mut out| out.flush()),
+            Compress::Gzip => self
+                .write_rows(GzipWriter::new(out), limit, seed, progress)
+                .and_then(GzipWriter::finish)
+                .and_then(|mut out| out.flush()),
+        }
+        .with_context(|| format!("cannot write {}", path.display()))
+    }
+
+    fn write_rows<W: Write>(
+        &self,
+        mut out: W,
+        limit: Limit,
+        seed: u64,
+        progress: &Progress,
+    ) -> io::Result<W> {
+        let mut buf = Vec::new();
+        let (mut written, mut offset) = (0, 0);
+        for block in 0.. {
+            let rows = match limit {
+                Limit::Rows(rows) if written < rows => (rows - written).min(BLOCK_ROWS),
+                Limit::Bytes(bytes) if offset < bytes => BLOCK_ROWS,
+                _ => break,
+            };
+            buf.clear();
+            self.block(seed, block, rows, offset, &mut buf);
+            let mut rows = rows;
+            if let Limit::Bytes(bytes) = limit
+                && offset + buf.len() as u64 > bytes
+            {
+                // Up to the end of the row the limit falls in
+                let last = (bytes - offset - 1) as usize;
+                let end = last + buf[last..].iter().position(|&b| b == b'\n').unwrap();
+                buf.truncate(end + 1);
+                rows = buf.iter().filter(|&&b| b == b'\n').count() as u64;
+            }
+            out.write_all(&buf)?;
+            written += rows;
+            offset += buf.len() as u64;
+            progress.add(rows, buf.len() as u64);
+        }
+        Ok(out)
+    }
+
+    /// Splits `limit` between `--shards` files, each of a seed that
+    /// follows from `--seed` and the shard, written by as many workers as
+    /// there are cores, each taking the next shard.
+    fn write_shards(
+        &self,
+        output: &Path,
+        suffix: &str,
+        limit: Limit,
+        progress: &Progress,
+    ) -> anyhow::Result<()> {
+        let shards = self.args.shards;
+        let next = AtomicU64::new(0);
+        let workers = thread::available_parallelism().map_or(1, |n| n.get());
+        thread::scope(|scope| {
+            let workers: Vec<_> = (0..workers.min(shards as usize))
+                .map(|_| {
+                    scope.spawn(|| -> anyhow::Result<()> {
+                        loop {
+                            let shard = next.fetch_add(1, Ordering::Relaxed);
+                            if shard >= shards {
+                                return Ok(());
+                            }
+                            let mut path = shard_path(output, shard).into_os_string();
+                            path.push(suffix);
+                            let seed = xxh64(&shard.to_le_bytes(), self.args.seed);
+                            let share = limit.share(shard, shards);
+                            self.write(Path::new(&path), share, seed, progress)?;
+                        }
+                    })
+                })
+                .collect();
+            workers
+                .into_iter()
+                .try_for_each(|worker| worker.join().unwrap())
+        })
+    }
+
+    /// The `rows` rows of block `block` of `seed`, which starts `offset`
+    /// bytes into the file.
+    fn block(&self, seed: u64, block: u64, rows: u64, offset: u64, buf: &mut Vec<u8>) {
+        let mut rng = Rng::new(xxh64(&block.to_le_bytes(), seed));
+        let mut row = 0;
+        while row < rows {
+            if self.args.edge_cases {
+                let at = offset + buf.len() as u64;
+                let distance = (BOUNDARY - at % BOUNDARY) as usize;
+                // Room for the fillers and the row across
+                if distance < STRADDLE_WINDOW && rows - row > MAX_FILLERS {
+                    row += self.straddle(distance, &mut rng, buf);
+                    continue;
+                }
+                if rng.below(8) == 0 {
+                    self.edge_row(&mut rng, buf);
+                    row += 1;
+                    continue;
+                }
+            }
+            let station = &self.stations[self.picker.pick(&mut rng)];
+            buf.extend_from_slice(station.name.as_bytes());
+            buf.push(b';');
+            push_reading(buf, station.reading(self.args.distribution, &mut rng));
+            buf.push(b'\n');
+            row += 1;
+        }
+    }
+
+    /// A row of an edge name and an edge reading.
+    fn edge_row(&self, rng: &mut Rng, buf: &mut Vec<u8>) {
+        let name = &self.edge_names[rng.below(self.edge_names.len() as u64) as usize];
+        buf.extend_from_slice(name.as_bytes());
+        buf.push(b';');
+        buf.extend_from_slice(EDGE_READINGS[rng.below(EDGE_READINGS.len() as u64) as usize]);
+        buf.push(b'\n');
+    }
+
+    /// Rows up to the boundary `distance` bytes on and then an edge row
+    /// across it, cut after any of its bytes but the last, and so often
+    /// inside a character of the names of [`edge_names`]. Returns how many
+    /// rows it wrote.
+    fn straddle(&self, distance: usize, rng: &mut Rng, buf: &mut Vec<u8>) -> u64 {
+        let mut across = vec![];
+        self.edge_row(rng, &mut across);
+        let cut = match distance.checked_sub(MIN_FILLER) {
+            _ if distance < across.len() => distance,
+            // Too short for a filler, so the row ends at the boundary
+            None | Some(0) => distance,
+            Some(room) => 1 + rng.below((across.len() - 1).min(room) as u64) as usize,
+        };
+        let mut fill = distance - cut;
+        let mut rows = 1;
+        while fill > 0 {
+            // All of it, or as much as leaves enough for one more
+            let len = match fill <= MAX_FILLER {
+                true => fill,
+                false => (fill - MIN_FILLER).min(MAX_FILLER),
+            };
+            buf.resize(buf.len() + len - FILLER_READING.len(), b'x');
+            buf.extend_from_slice(FILLER_READING);
+            fill -= len;
+            rows += 1;
+        }
+        buf.extend_from_slice(&across);
+        rows
+    }
+}
+
+/// The boundary `--edge-cases` keeps a row across every multiple of,
+/// whichever block size reads the file
+const BOUNDARY: u64 = 4096;
+/// How close to a boundary a row goes across it
+const STRADDLE_WINDOW: usize = 256;
+/// What ends the rows of `x`s that pad up to a row across a boundary
+const FILLER_READING: &[u8] = b";0.0\n";
+const MIN_FILLER: usize = 1 + FILLER_READING.len();
+const MAX_FILLER: usize = spec::MAX_NAME_BYTES + FILLER_READING.len();
+/// The most fillers that pad up to a row across a boundary: a window's
+/// worth less the shortest cut
+const MAX_FILLERS: u64 = STRADDLE_WINDOW.div_ceil(MAX_FILLER) as u64;
+/// The readings at the ends of the range and at the edges of their digits
+const EDGE_READINGS: &[&[u8]] = &[
+    b"-99.9", b"99.9", b"-0.0", b"0.0", b"-0.1", b"0.1", b"-9.9", b"9.9", b"-10.0", b"10.0",
+];
+
+/// The names of `--edge-cases`: at the 100-byte limit in characters of
+/// every width, and at the other end, one byte.
+fn edge_names() -> Vec<String> {
+    let limit = spec::MAX_NAME_BYTES;
+    vec![
+        "x".repeat(limit),
+        "é".repeat(limit / 2),
+        format!("x{}", "€".repeat((limit - 1) / 3)),
+        "🌧".repeat(limit / 4),
+        format!("x{}xxx", "🌧".repeat(limit / 4 - 1)),
+        format!("{}é", "x".repeat(limit - 2)),
+        "A".to_string(),
+        "é".to_string(),
+        "São Paulo 東京 🌧".to_string(),
+    ]
+}
+
+/// `tenths` as the challenge writes readings, such as `-7.3` or `12.0`.
+fn push_reading(buf: &mut Vec<u8>, tenths: i64) {
+    if tenths < 0 {
+        buf.push(b'-');
+    }
+    let tenths = tenths.unsigned_abs();
+    if tenths >= 100 {
+        buf.push(b'0' + (tenths / 100) as u8);
+    }
+    buf.extend_from_slice(&[
+        b'0' + (tenths / 10 % 10) as u8,
+        b'.',
+        b'0' + (tenths % 10) as u8,
+    ]);
+}
+
+/// The stations of `text`, one `name;mean` or `name;mean;stddev` a line.
+/// Empty lines and those starting with `#` are left out.
+fn parse_stations(text: &str) -> anyhow::Result<Vec<Station>> {
+    let mut stations = vec![];
+    for (i, line) in text.lines().enumerate() {
+        let line = line.strip_suffix('\r').unwrap_or(line);
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let station = parse_station(line).with_context(|| format!("line {}", i + 1))?;
+        stations.push(station);
+    }
+    if stations.is_empty() {
+        bail!("no stations");
+    }
+    Ok(stations)
+}
+
+fn parse_station(line: &str) -> anyhow::Result<Station> {
+    let mut fields = line.split(';');
+    let name = fields.next().unwrap_or_default();
+    if name.is_empty() || name.len() > spec::MAX_NAME_BYTES {
+        bail!("a name must be 1 to {} bytes", spec::MAX_NAME_BYTES);
+    }
+    let number = |field: Option<&str>, what: &str| -> anyhow::Result<Option<f64>> {
+        field
+            .map(|v| {
+                v.trim()
+                    .parse()
+                    .map_err(|_| anyhow!("`{}` is not a {}", v, what))
+            })
+            .transpose()
+    };
+    let mean = number(fields.next(), "mean")?.ok_or_else(|| anyhow!("expected `name;mean`"))?;
+    let stddev = number(fields.next(), "spread")?.unwrap_or(DEFAULT_STDDEV);
+    if fields.next().is_some() {
+        bail!("expected `name;mean` or `name;mean;stddev`");
+    }
+    if !(-99.9..=99.9).contains(&mean) {
+        bail!("the mean must be from -99.9 to 99.9");
+    }
+    if stddev.is_nan() || stddev < 0.0 {
+        bail!("the spread must not be negative");
+    }
+    Ok(Station {
+        name: name.to_string(),
+        mean,
+        stddev,
+    })
+}
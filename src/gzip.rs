@@ -0,0 +1,416 @@
+//! gzip (RFC 1952) around deflate (RFC 1951), for the generator to write
+//! compressed rows without a dependency. Repeats are found by LZ77 over the
+//! last 32 KiB and coded with the fixed Huffman codes, which need no tables
+//! in the stream: larger than a full compressor's, but rows of a few
+//! hundred station names repeat enough for most of the saving anyway.
+
+use std::io::{self, Write};
+
+/// How far back a match may start
+const WINDOW: usize = 32 * 1024;
+/// The input coded as one block
+const BLOCK: usize = 64 * 1024;
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+const HASH_BITS: u32 = 15;
+/// The earlier places with the same hash tried for each match
+const CHAIN: usize = 16;
+const NONE: u64 = u64::MAX;
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DISTANCE_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+
+/// The fixed code of each literal and length symbol, bit-reversed for
+/// writing from the lowest bit, and its length.
+const LITERAL_CODES: [(u16, u8); 288] = {
+    let mut codes = [(0, 0); 288];
+    let mut symbol = 0;
+    while symbol < 288 {
+        let (code, len) = match symbol {
+            0..=143 => (0x30 + symbol, 8),
+            144..=255 => (0x190 + symbol - 144, 9),
+            256..=279 => (symbol - 256, 7),
+            _ => (0xC0 + symbol - 280, 8),
+        };
+        codes[symbol] = (reverse(code as u16, len), len);
+        symbol += 1;
+    }
+    codes
+};
+
+const fn reverse(code: u16, len: u8) -> u16 {
+    code.reverse_bits() >> (16 - len as u32)
+}
+
+const CRC_TABLE: [u32; 256] = {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = match crc & 1 {
+                1 => 0xEDB8_8320 ^ (crc >> 1),
+                _ => crc >> 1,
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+/// The CRC-32 of gzip and zip, of `bytes` after those `crc` is of.
+pub fn crc32(crc: u32, bytes: &[u8]) -> u32 {
+    let mut crc = !crc;
+    for &byte in bytes {
+        crc = CRC_TABLE[((crc ^ byte as u32) & 0xFF) as usize] ^ (crc >> 8);
+    }
+    !crc
+}
+
+/// Compresses what is written to it into `inner`. Nothing is complete
+/// until [`GzipWriter::finish`], which writes the last block and the
+/// trailer; `flush` only flushes `inner`.
+pub struct GzipWriter<W: Write> {
+    inner: W,
+    /// Up to a window of input already coded, then what is not yet
+    buf: Vec<u8>,
+    /// Where `buf` starts in the input
+    base: u64,
+    /// Where the input not yet coded starts in `buf`
+    start: usize,
+    /// Where the first place not in the chains is in the input
+    hashed: u64,
+    bits: Bits,
+    /// The latest place in the input of each hash of three bytes
+    head: Vec<u64>,
+    /// The place before each of the last window with the same hash, at the
+    /// place modulo the window
+    prev: Vec<u64>,
+    crc: u32,
+    /// The input's length, modulo 2^32 as gzip keeps it
+    size: u32,
+}
+
+impl<W: Write> GzipWriter<W> {
+    pub fn new(inner: W) -> Self {
+        let mut bits = Bits::default();
+        // No name or time, and an unknown system
+        bits.out
+            .extend_from_slice(&[0x1F, 0x8B, 8, 0, 0, 0, 0, 0, 0, 0xFF]);
+        GzipWriter {
+            inner,
+            buf: Vec::with_capacity(WINDOW + BLOCK),
+            base: 0,
+            start: 0,
+            hashed: 0,
+            bits,
+            head: vec![NONE; 1 << HASH_BITS],
+            prev: vec![NONE; WINDOW],
+            crc: 0,
+            size: 0,
+        }
+    }
+
+    /// Codes the rest and writes the trailer, returning `inner`.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.deflate(true);
+        self.bits.align();
+        self.bits.out.extend_from_slice(&self.crc.to_le_bytes());
+        self.bits.out.extend_from_slice(&self.size.to_le_bytes());
+        self.inner.write_all(&self.bits.out)?;
+        Ok(self.inner)
+    }
+
+    /// Codes the input in `buf` as a block, keeping a window of it.
+    fn deflate(&mut self, last: bool) {
+        self.bits.put(u32::from(last), 1);
+        // Fixed codes
+        self.bits.put(1, 2);
+        let mut i = self.start;
+        while i < self.buf.len() {
+            self.chain_up_to(i);
+            let (len, distance) = self.longest_match(i);
+            if len >= MIN_MATCH {
+                self.bits.length(len);
+                self.bits.distance(distance);
+                i += len;
+            } else {
+                self.bits.literal(self.buf[i] as usize);
+                i += 1;
+            }
+        }
+        self.bits.literal(256);
+
+        let keep = self.buf.len().min(WINDOW);
+        let dropped = self.buf.len() - keep;
+        self.buf.drain(..dropped);
+        self.base += dropped as u64;
+        self.start = keep;
+    }
+
+    /// Chains the places of `buf` before `end` that are not yet, as far
+    /// as three bytes follow them.
+    fn chain_up_to(&mut self, end: usize) {
+        let end = end.min(self.buf.len().saturating_sub(MIN_MATCH - 1));
+        for i in (self.hashed - self.base) as usize..end {
+            let h = hash(&self.buf, i);
+            let at = self.base + i as u64;
+            self.prev[at as usize % WINDOW] = self.head[h];
+            self.head[h] = at;
+            self.hashed = at + 1;
+        }
+    }
+
+    /// The longest match for `buf[at..]` among the earlier places of its
+    /// hash, as its length and how far back it starts.
+    fn longest_match(&self, at: usize) -> (usize, usize) {
+        let data = &self.buf[..];
+        let mut best = (0, 0);
+        if at + MIN_MATCH > data.len() {
+            return best;
+        }
+        let limit = (data.len() - at).min(MAX_MATCH);
+        let here = self.base + at as u64;
+        let mut candidate = self.head[hash(data, at)];
+        for _ in 0..CHAIN {
+            if candidate == NONE || here - candidate > WINDOW as u64 {
+                break;
+            }
+            let from = (candidate - self.base) as usize;
+            // Only as long as the best if it ends the same
+            if data[from + best.0] == data[at + best.0] {
+                let len = common(&data[from..from + limit], &data[at..at + limit]);
+                if len > best.0 {
+                    best = (len, at - from);
+                    if len == limit {
+                        break;
+                    }
+                }
+            }
+            let next = self.prev[candidate as usize % WINDOW];
+            // Past the window, the place was taken by a later one
+            if next >= candidate {
+                break;
+            }
+            candidate = next;
+        }
+        best
+    }
+}
+
+fn hash(data: &[u8], at: usize) -> usize {
+    let three = u32::from_le_bytes([data[at], data[at + 1], data[at + 2], 0]);
+    (three.wrapping_mul(0x9E37_79B1) >> (32 - HASH_BITS)) as usize
+}
+
+/// How many bytes `a` and `b`, of the same length, start with alike.
+fn common(a: &[u8], b: &[u8]) -> usize {
+    let mut n = 0;
+    while n + 8 <= a.len() {
+        let word = |s: &[u8]| u64::from_le_bytes(s[n..n + 8].try_into().unwrap());
+        let differ = word(a) ^ word(b);
+        if differ != 0 {
+            return n + (differ.trailing_zeros() / 8) as usize;
+        }
+        n += 8;
+    }
+    n + a[n..]
+        .iter()
+        .zip(&b[n..])
+        .take_while(|(a, b)| a == b)
+        .count()
+}
+
+impl<W: Write> Write for GzipWriter<W> {
+    fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+        self.crc = crc32(self.crc, bytes);
+        self.size = self.size.wrapping_add(bytes.len() as u32);
+        let mut rest = bytes;
+        while !rest.is_empty() {
+            let take = (BLOCK - (self.buf.len() - self.start)).min(rest.len());
+            self.buf.extend_from_slice(&rest[..take]);
+            rest = &rest[take..];
+            if self.buf.len() - self.start == BLOCK {
+                self.deflate(false);
+                self.inner.write_all(&self.bits.out)?;
+                self.bits.out.clear();
+            }
+        }
+        Ok(bytes.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Bits written from the lowest of each byte, as deflate packs them.
+#[derive(Default)]
+struct Bits {
+    out: Vec<u8>,
+    pending: u64,
+    count: u32,
+}
+
+impl Bits {
+    fn put(&mut self, value: u32, bits: u32) {
+        self.pending |= u64::from(value) << self.count;
+        self.count += bits;
+        while self.count >= 8 {
+            self.out.push(self.pending as u8);
+            self.pending >>= 8;
+            self.count -= 8;
+        }
+    }
+
+    fn align(&mut self) {
+        if self.count > 0 {
+            self.out.push(self.pending as u8);
+            self.pending = 0;
+            self.count = 0;
+        }
+    }
+
+    fn literal(&mut self, symbol: usize) {
+        let (code, len) = LITERAL_CODES[symbol];
+        self.put(u32::from(code), u32::from(len));
+    }
+
+    fn length(&mut self, len: usize) {
+        let i = LENGTH_BASE.partition_point(|&base| base as usize <= len) - 1;
+        self.literal(257 + i);
+        self.put(
+            (len - LENGTH_BASE[i] as usize) as u32,
+            u32::from(LENGTH_EXTRA[i]),
+        );
+    }
+
+    fn distance(&mut self, distance: usize) {
+        let i = DISTANCE_BASE.partition_point(|&base| base as usize <= distance) - 1;
+        self.put(u32::from(reverse(i as u16, 5)), 5);
+        self.put(
+            (distance - DISTANCE_BASE[i] as usize) as u32,
+            u32::from(DISTANCE_EXTRA[i]),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rng::Rng;
+
+    /// Inflates a gzip stream of fixed-code blocks, checking its trailer.
+    fn gunzip(stream: &[u8]) -> Vec<u8> {
+        assert_eq!(&stream[..3], &[0x1F, 0x8B, 8]);
+        let mut at = 10 * 8;
+        let mut bit = |n: u32| -> usize {
+            let mut value = 0;
+            for i in 0..n {
+                value |= ((stream[at / 8] >> (at % 8)) as usize & 1) << i;
+                at += 1;
+            }
+            value
+        };
+        // Huffman codes come from their highest bit
+        let code = |bit: &mut dyn FnMut(u32) -> usize, n: u32| {
+            (0..n).fold(0, |code, _| code << 1 | bit(1))
+        };
+        let mut out = vec![];
+        loop {
+            let last = bit(1) == 1;
+            assert_eq!(bit(2), 1, "fixed codes");
+            loop {
+                let mut symbol = code(&mut bit, 7);
+                symbol = match symbol {
+                    0..=0x17 => symbol + 256,
+                    _ => match symbol << 1 | bit(1) {
+                        c @ 0x30..=0xBF => c - 0x30,
+                        c @ 0xC0..=0xC7 => c - 0xC0 + 280,
+                        c => (c << 1 | bit(1)) - 0x190 + 144,
+                    },
+                };
+                match symbol {
+                    ..256 => out.push(symbol as u8),
+                    256 => break,
+                    _ => {
+                        let i = symbol - 257;
+                        let len = LENGTH_BASE[i] as usize + bit(LENGTH_EXTRA[i] as u32);
+                        let d = code(&mut bit, 5);
+                        let distance = DISTANCE_BASE[d] as usize + bit(DISTANCE_EXTRA[d] as u32);
+                        for _ in 0..len {
+                            out.push(out[out.len() - distance]);
+                        }
+                    }
+                }
+            }
+            if last {
+                break;
+            }
+        }
+        let trailer = &stream[at.div_ceil(8)..];
+        assert_eq!(trailer.len(), 8);
+        assert_eq!(trailer[..4], crc32(0, &out).to_le_bytes());
+        assert_eq!(trailer[4..], (out.len() as u32).to_le_bytes());
+        out
+    }
+
+    fn gzip(input: &[u8], piece: usize) -> Vec<u8> {
+        let mut writer = GzipWriter::new(vec![]);
+        for piece in input.chunks(piece.max(1)) {
+            writer.write_all(piece).unwrap();
+        }
+        writer.finish().unwrap()
+    }
+
+    #[test]
+    fn crc32_has_the_check_value() {
+        assert_eq!(crc32(0, b"123456789"), 0xCBF4_3926);
+        assert_eq!(crc32(crc32(0, b"1234"), b"56789"), 0xCBF4_3926);
+        assert_eq!(crc32(0, b""), 0);
+    }
+
+    #[test]
+    fn streams_inflate_to_their_input() {
+        let mut rng = Rng::new(1);
+        let noise: Vec<u8> = (0..100_000).map(|_| rng.next_u64() as u8).collect();
+        let names = ["Abha", "Zürich", "東京", "St. John's"];
+        let rows: String = (0..40_000)
+            .map(|i| format!("{};{}.{}\n", names[i % 4], i % 97, i % 10))
+            .collect();
+        let runs = vec![b'x'; 300_000];
+        for input in [&b""[..], b"a", b"aaaa", &noise, rows.as_bytes(), &runs] {
+            for piece in [1, 4096, BLOCK, input.len()] {
+                if piece == 1 && input.len() > 5000 {
+                    continue;
+                }
+                assert_eq!(
+                    gunzip(&gzip(input, piece)),
+                    input,
+                    "{} in {}",
+                    input.len(),
+                    piece
+                );
+            }
+        }
+        // Coded from the repeats
+        assert!(gzip(rows.as_bytes(), BLOCK).len() * 3 < rows.len());
+        assert!(gzip(&runs, BLOCK).len() * 100 < runs.len());
+    }
+}
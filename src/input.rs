@@ -0,0 +1,637 @@
+//! The input file behind the configured I/O backend, read in blocks of
+//! whole lines, either whole or in byte ranges for readers working in
+//! parallel, and decoded to UTF-8 without a byte order mark.
+
+use crate::cli::{Config, Encoding, IoBackend};
+use crate::memory;
+use crate::mmap::{self, Mmap};
+use crate::readahead::ReadAhead;
+use crate::simd;
+use crate::xxhash::xxh64;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom};
+use std::ops::{Deref, Range};
+use std::path::PathBuf;
+use std::sync::Arc;
+
+/// Size of each buffer in the read-ahead ring.
+const READ_AHEAD_BLOCK: usize = 64 * 1024;
+/// What `BufReader` would pick
+const DEFAULT_BUFFER: usize = 8 * 1024;
+/// Read past the end of a range for the rest of its last line, at first
+const TAIL: usize = 4 * 1024;
+/// Bytes each of several readers takes at a time
+const STRIPE: u64 = 64 << 20;
+/// Places lines are counted at to estimate them, and bytes of lines each
+const SAMPLES: u64 = 64;
+const SAMPLE: usize = 64 << 10;
+const UTF8_BOM: &[u8] = b"\xEF\xBB\xBF";
+const UTF16LE_BOM: &[u8] = b"\xFF\xFE";
+const UTF16BE_BOM: &[u8] = b"\xFE\xFF";
+
+/// Runs of whole lines with their terminators, about `block_size` bytes
+/// each, and the byte offset each starts at.
+pub type Blocks = Box<dyn Iterator<Item = io::Result<(u64, Block)>> + Send>;
+
+/// A block of whole lines, read into a string of its own or, from a UTF-8
+/// mapping, left where it is in the mapping.
+pub enum Block {
+    Owned(String),
+    /// Bytes of the mapping found to be UTF-8 as the block was cut
+    Mapped(Arc<Mmap>, Range<usize>),
+}
+
+impl Block {
+    /// Bytes of memory the block holds besides the mapping.
+    pub fn heap_size(&self) -> usize {
+        match self {
+            Block::Owned(text) => text.capacity(),
+            Block::Mapped(..) => 0,
+        }
+    }
+}
+
+impl Deref for Block {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        match self {
+            Block::Owned(text) => text,
+            // SAFETY: checked to be UTF-8 when the block was made, and the
+            // mapping is read-only
+            Block::Mapped(map, range) => unsafe {
+                std::str::from_utf8_unchecked(&map[range.clone()])
+            },
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Source {
+    backing: Backing,
+    max_line: usize,
+    utf16: bool,
+    /// Length of the byte order mark the first line starts after
+    bom: u64,
+}
+
+#[derive(Clone)]
+enum Backing {
+    /// Read through a `BufReader`, or through a read-ahead thread with a
+    /// ring of `read_ahead` buffers, with a file handle per range. Either
+    /// way the buffers are `buffer` bytes
+    File {
+        path: PathBuf,
+        len: u64,
+        read_ahead: usize,
+        buffer: usize,
+    },
+    Mmap(Arc<Mmap>),
+}
+
+impl Source {
+    pub fn open(config: &Config) -> io::Result<Source> {
+        let mut file = File::open(&config.input)?;
+        let mut head = [0; 3];
+        let n = read_full(&mut file, &mut head)?;
+        let head = &head[..n];
+        let utf16 = config.encoding == Encoding::Utf16Le;
+        let bom = if head.starts_with(UTF8_BOM) {
+            UTF8_BOM.len()
+        } else if utf16 && head.starts_with(UTF16LE_BOM) {
+            UTF16LE_BOM.len()
+        } else if head.starts_with(UTF16LE_BOM) || head.starts_with(UTF16BE_BOM) {
+            let hint = match head.starts_with(UTF16LE_BOM) {
+                true => "; read it with --encoding utf16le",
+                false => ", only little-endian UTF-16 can be read",
+            };
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{} is UTF-16{}", config.input, hint),
+            ));
+        } else {
+            0
+        };
+        let backing = match config.io {
+            IoBackend::Read => Backing::File {
+                path: PathBuf::from(&config.input),
+                len: file.metadata()?.len(),
+                read_ahead: config.read_ahead,
+                buffer: config.read_buffer.unwrap_or(match config.read_ahead {
+                    0 => DEFAULT_BUFFER,
+                    _ => READ_AHEAD_BLOCK,
+                }),
+            },
+            IoBackend::Mmap => Backing::Mmap(Arc::new(Mmap::map(&file, &config.madvise)?)),
+        };
+        Ok(Source {
+            backing,
+            max_line: config.max_line_bytes,
+            utf16,
+            bom: bom as u64,
+        })
+    }
+
+    pub fn len(&self) -> u64 {
+        match &self.backing {
+            Backing::File { len, .. } => *len,
+            Backing::Mmap(map) => map.len() as u64,
+        }
+    }
+
+    /// Bytes of read buffer each range's reader holds.
+    pub fn buffer_size(&self) -> usize {
+        match &self.backing {
+            Backing::File {
+                read_ahead, buffer, ..
+            } => (*read_ahead).max(1) * buffer,
+            Backing::Mmap(_) => 0,
+        }
+    }
+
+    /// The lines starting within `range`, in blocks that end at the last
+    /// line that fits into `block_size` bytes. Longer lines make a block of
+    /// their own, up to `--max-line-bytes`, past which reading fails. A line
+    /// that the start of the range falls inside of belongs to the previous
+    /// range. With `csv_strict`, blocks only end outside of quoted fields.
+    /// UTF-16 ranges are taken to start and end on even bytes.
+    pub fn blocks(
+        &self,
+        range: Range<u64>,
+        block_size: usize,
+        csv_strict: bool,
+    ) -> io::Result<Blocks> {
+        let range = match self.utf16 {
+            true => range.start.next_multiple_of(2)..range.end.next_multiple_of(2),
+            false => range,
+        };
+        // Start at the last character of the previous range, to find the
+        // end of the line containing it
+        let unit = if self.utf16 { 2 } else { 1 };
+        let from = match range.start {
+            0 => self.bom,
+            start => start - unit,
+        };
+        let (mut reader, mut pos): (Box<dyn Read + Send>, u64) = match &self.backing {
+            Backing::File {
+                path,
+                read_ahead,
+                buffer,
+                ..
+            } => {
+                let mut file = File::open(path)?;
+                file.seek(SeekFrom::Start(from))?;
+                let mut reader: Box<dyn BufRead + Send> = match read_ahead {
+                    0 => Box::new(BufReader::with_capacity(*buffer, file)),
+                    n => Box::new(ReadAhead::new(file, *n, *buffer)?),
+                };
+                let mut pos = from;
+                if range.start > 0 && !self.utf16 {
+                    pos += reader.skip_until(b'\n')? as u64;
+                }
+                (reader, pos)
+            }
+            Backing::Mmap(map) if !self.utf16 => {
+                let mut pos = from as usize;
+                if range.start > 0 {
+                    pos = mmap::Lines::new(map.clone(), range.start as usize, range.end as usize)
+                        .position();
+                }
+                return Ok(Box::new(MapBlocks {
+                    map: map.clone(),
+                    pos,
+                    end: usize::try_from(range.end).unwrap_or(usize::MAX),
+                    block_size: block_size.max(1),
+                    max_line: self.max_line,
+                    csv_strict,
+                    done: false,
+                }));
+            }
+            // Transcoded into blocks of their own like a file's
+            Backing::Mmap(map) => {
+                let reader = MapReader {
+                    map: map.clone(),
+                    pos: from as usize,
+                };
+                (Box::new(reader), from)
+            }
+        };
+        if range.start > 0 && self.utf16 {
+            pos += skip_utf16_line(&mut reader)?;
+        }
+        Ok(Box::new(ByteBlocks {
+            reader,
+            pos,
+            end: range.end,
+            block_size: block_size.max(1),
+            max_line: self.max_line,
+            csv_strict,
+            utf16: self.utf16,
+            carry: Vec::new(),
+            done: false,
+        }))
+    }
+
+    /// The blocks of stripes `reader`, `reader + readers` and so on of
+    /// `range`, for one of `readers` readers sharing it. A line belongs to
+    /// the stripe it starts in, as with ranges.
+    pub fn stripes(
+        &self,
+        range: Range<u64>,
+        reader: usize,
+        readers: usize,
+        block_size: usize,
+        csv_strict: bool,
+    ) -> Blocks {
+        let source = self.clone();
+        let end = range.end;
+        let starts = (range.start + reader as u64 * STRIPE..end).step_by(readers * STRIPE as usize);
+        Box::new(starts.flat_map(move |start| {
+            match source.blocks(start..(start + STRIPE).min(end), block_size, csv_strict) {
+                Ok(blocks) => blocks,
+                Err(e) => Box::new(std::iter::once(Err(e))),
+            }
+        }))
+    }
+}
+
+/// Reads blocks of whole lines, carrying the partial line at the end of
+/// each read over to the next block.
+struct ByteBlocks {
+    reader: Box<dyn Read + Send>,
+    /// Offset of the next block
+    pos: u64,
+    end: u64,
+    block_size: usize,
+    max_line: usize,
+    csv_strict: bool,
+    /// The input is little-endian UTF-16, and the blocks its transcoding
+    utf16: bool,
+    /// Bytes read past the end of the last block
+    carry: Vec<u8>,
+    done: bool,
+}
+
+/// Where the block in `buf` may end: the first line start at or past
+/// `limit`, which finishes the range, or else the last line start.
+fn block_end(buf: &[u8], limit: usize, utf16: bool, csv_strict: bool) -> Option<(usize, bool)> {
+    if utf16 {
+        let mut last = None;
+        for (i, unit) in buf.chunks_exact(2).enumerate() {
+            if unit == b"\n\0" {
+                let start = 2 * i + 2;
+                if start >= limit {
+                    return Some((start, true));
+                }
+                last = Some((start, false));
+            }
+        }
+        return last;
+    }
+    if !csv_strict {
+        if limit <= buf.len()
+            && let Some(i) = simd::find(&buf[limit.saturating_sub(1)..], b'\n')
+        {
+            return Some((limit.saturating_sub(1) + i + 1, true));
+        }
+        return buf
+            .iter()
+            .rposition(|&b| b == b'\n')
+            .map(|i| (i + 1, false));
+    }
+    // Escaped quotes come in pairs, so a line ends a record wherever
+    // the count so far is even
+    let mut open = false;
+    let mut last = None;
+    for (i, &b) in buf.iter().enumerate() {
+        match b {
+            b'"' => open = !open,
+            b'\n' if !open => {
+                if i + 1 >= limit {
+                    return Some((i + 1, true));
+                }
+                last = Some((i + 1, false));
+            }
+            _ => {}
+        }
+    }
+    last
+}
+
+impl Iterator for ByteBlocks {
+    type Item = io::Result<(u64, Block)>;
+
+    fn next(&mut self) -> Option<io::Result<(u64, Block)>> {
+        if self.done || self.pos >= self.end {
+            return None;
+        }
+        let mut buf = std::mem::take(&mut self.carry);
+        let limit = usize::try_from(self.end - self.pos).unwrap_or(usize::MAX);
+        let (cut, finished) = loop {
+            let cut = block_end(&buf, limit, self.utf16, self.csv_strict);
+            if let Some((cut, finished)) = cut
+                && (finished || buf.len() >= self.block_size)
+            {
+                break (cut, finished);
+            }
+            // The carried line starts the buffer, and has not ended yet
+            if cut.is_none() && buf.len() > self.max_line {
+                self.done = true;
+                return Some(Err(too_long(self.pos, self.max_line)));
+            }
+            // Up to a full block, or what is left of the range and a bit
+            // for its last line, or more for a line longer than that
+            let filled = buf.len();
+            let cap = self.block_size.min(limit.saturating_add(TAIL));
+            let want = if filled < cap { cap } else { filled * 2 };
+            buf.resize(want, 0);
+            let n = match read_full(&mut self.reader, &mut buf[filled..]) {
+                Ok(n) => n,
+                Err(e) => return Some(Err(e)),
+            };
+            buf.truncate(filled + n);
+            if n == 0 {
+                // What is left is the last block, whose last line may have
+                // no terminator, but whose quoted fields must be closed
+                match block_end(&buf, limit, self.utf16, self.csv_strict) {
+                    Some((cut, true)) => break (cut, true),
+                    _ if self.csv_strict && csv_open(&buf) => {
+                        return Some(Err(io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            "unterminated quoted field at end of input",
+                        )));
+                    }
+                    _ => break (buf.len(), true),
+                }
+            }
+        };
+        self.carry = buf.split_off(cut);
+        self.done = finished;
+        let offset = self.pos;
+        self.pos += cut as u64;
+        if buf.is_empty() {
+            return None;
+        }
+        let block = match self.utf16 {
+            true => from_utf16(&buf),
+            false => {
+                String::from_utf8(buf).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+            }
+        };
+        Some(block.map(|block| (offset, Block::Owned(block))))
+    }
+}
+
+/// Cuts the blocks of a UTF-8 mapping as [`ByteBlocks`] would, from the
+/// bytes it would have read, but hands out the lines where they are.
+struct MapBlocks {
+    map: Arc<Mmap>,
+    /// Offset of the next block
+    pos: usize,
+    end: usize,
+    block_size: usize,
+    max_line: usize,
+    csv_strict: bool,
+    done: bool,
+}
+
+impl Iterator for MapBlocks {
+    type Item = io::Result<(u64, Block)>;
+
+    fn next(&mut self) -> Option<io::Result<(u64, Block)>> {
+        if self.done || self.pos >= self.end {
+            return None;
+        }
+        let rest = &self.map[self.pos.min(self.map.len())..];
+        let limit = self.end - self.pos;
+        let mut len = 0;
+        let (cut, finished) = loop {
+            let buf = &rest[..len];
+            let cut = block_end(buf, limit, false, self.csv_strict);
+            if let Some((cut, finished)) = cut
+                && (finished || len >= self.block_size)
+            {
+                break (cut, finished);
+            }
+            if cut.is_none() && len > self.max_line {
+                self.done = true;
+                return Some(Err(too_long(self.pos as u64, self.max_line)));
+            }
+            // What is left is the last block, as with a file
+            if len == rest.len() {
+                if self.csv_strict && csv_open(buf) {
+                    return Some(Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "unterminated quoted field at end of input",
+                    )));
+                }
+                break (len, true);
+            }
+            let cap = self.block_size.min(limit.saturating_add(TAIL));
+            len = if len < cap { cap } else { len * 2 }.min(rest.len());
+        };
+        self.done = finished;
+        let offset = self.pos;
+        self.pos += cut;
+        if cut == 0 {
+            return None;
+        }
+        Some(match std::str::from_utf8(&rest[..cut]) {
+            Ok(_) => Ok((
+                offset as u64,
+                Block::Mapped(self.map.clone(), offset..offset + cut),
+            )),
+            Err(e) => Err(io::Error::new(io::ErrorKind::InvalidData, e)),
+        })
+    }
+}
+
+/// Little-endian UTF-16 `bytes` as UTF-8.
+fn from_utf16(bytes: &[u8]) -> io::Result<String> {
+    let invalid = |what: String| io::Error::new(io::ErrorKind::InvalidData, what);
+    if bytes.len() % 2 == 1 {
+        return Err(invalid("UTF-16 input ends in half a character".to_string()));
+    }
+    let units = bytes
+        .chunks_exact(2)
+        .map(|unit| u16::from_le_bytes([unit[0], unit[1]]));
+    char::decode_utf16(units)
+        .collect::<Result<String, _>>()
+        .map_err(|e| invalid(e.to_string()))
+}
+
+/// Reads past the next UTF-16 line terminator, returning the bytes read.
+fn skip_utf16_line(reader: &mut dyn Read) -> io::Result<u64> {
+    let mut skipped = 0;
+    let mut unit = [0; 2];
+    while read_full(reader, &mut unit)? == 2 {
+        skipped += 2;
+        if &unit == b"\n\0" {
+            break;
+        }
+    }
+    Ok(skipped)
+}
+
+/// The error for a line starting at byte `at` that has gone on for more
+/// than `max` bytes, which the row checks give as well.
+pub fn too_long(at: u64, max: usize) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!(
+            "the line at byte {} is longer than {} (--max-line-bytes)",
+            at,
+            memory::format_bytes(max)
+        ),
+    )
+}
+
+/// How many lines an input has, and bounds that hold the true count 95%
+/// of the time.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Estimate {
+    pub lines: u64,
+    pub low: u64,
+    pub high: u64,
+    /// Every line was counted, so the bounds are the count
+    pub exact: bool,
+}
+
+/// About how many lines `source` has, from the lines per byte of a block
+/// in each of equal parts of it, or all of its lines if that is about as
+/// quick.
+pub fn estimate_lines(source: &Source) -> io::Result<Estimate> {
+    let len = source.len();
+    if len <= 2 * SAMPLES * SAMPLE as u64 {
+        let mut lines = 0;
+        for block in source.blocks(0..len, 1 << 20, false)? {
+            lines += block?.1.lines().count() as u64;
+        }
+        return Ok(Estimate {
+            lines,
+            low: lines,
+            high: lines,
+            exact: true,
+        });
+    }
+    let (mut rates, mut counted, mut read) = (vec![], 0, 0);
+    for i in 0..SAMPLES {
+        // Somewhere in the part rather than at its start, so that the
+        // samples do not keep to the same place in an input that repeats
+        let part = i * len / SAMPLES..(i + 1) * len / SAMPLES;
+        let start = part.start + xxh64(&i.to_le_bytes(), 0) % (part.end - part.start);
+        let mut blocks = source.blocks(start..len, SAMPLE, false)?;
+        let Some(block) = blocks.next() else {
+            continue;
+        };
+        let (offset, block) = block?;
+        // The next block starts where this one ends in the file, which in
+        // UTF-16 is not its length in UTF-8
+        let end = blocks.next().transpose()?.map_or(len, |(end, _)| end);
+        let lines = block.lines().count() as u64;
+        rates.push(lines as f64 / (end - offset).max(1) as f64);
+        counted += lines;
+        read += end - offset;
+    }
+    let n = rates.len().max(1) as f64;
+    let mean = rates.iter().sum::<f64>() / n;
+    let variance = rates.iter().map(|r| (r - mean).powi(2)).sum::<f64>() / (n - 1.0).max(1.0);
+    // Less is unknown the more of the input the samples cover
+    let unread = 1.0 - (read as f64 / len as f64).min(1.0);
+    let margin = 1.96 * (variance / n * unread).sqrt() * len as f64;
+    let lines = mean * len as f64;
+    Ok(Estimate {
+        lines: lines.round() as u64,
+        low: ((lines - margin).floor().max(0.0) as u64).max(counted),
+        high: (lines + margin).ceil() as u64,
+        exact: false,
+    })
+}
+
+/// The line, counted from 1, that each of `offsets` in the file at `path`
+/// falls on, read up to the last of them. In UTF-16 the offsets are of
+/// whole characters, and only `\n\0` pairs are newlines.
+pub fn line_numbers(path: &str, encoding: Encoding, offsets: &[u64]) -> io::Result<Vec<u64>> {
+    let count_newlines = match encoding {
+        Encoding::Utf8 => count_newlines,
+        Encoding::Utf16Le => count_utf16_newlines,
+    };
+    let mut sorted: Vec<(u64, usize)> = offsets.iter().copied().zip(0..).collect();
+    sorted.sort_unstable();
+    let mut lines = vec![0; offsets.len()];
+    let mut file = File::open(path)?;
+    // Of even length, so that UTF-16 characters start on even bytes in it
+    let mut buf = vec![0; 1 << 20];
+    // Newlines before `start`, where `buf` begins in the file
+    let (mut start, mut newlines) = (0, 1);
+    let mut next = sorted.iter().peekable();
+    while next.peek().is_some() {
+        let n = read_full(&mut file, &mut buf)?;
+        let block = &buf[..n];
+        let mut pos = 0;
+        while let Some(&&(offset, i)) = next.peek() {
+            if n > 0 && offset >= start + n as u64 {
+                break;
+            }
+            // Past the end of the file, which a row cannot start at
+            let end = (offset.saturating_sub(start) as usize).min(n);
+            newlines += count_newlines(&block[pos..end]);
+            pos = end;
+            lines[i] = newlines;
+            next.next();
+        }
+        newlines += count_newlines(&block[pos..]);
+        start += n as u64;
+    }
+    Ok(lines)
+}
+
+fn count_newlines(mut bytes: &[u8]) -> u64 {
+    let mut count = 0;
+    while let Some(i) = simd::find(bytes, b'\n') {
+        count += 1;
+        bytes = &bytes[i + 1..];
+    }
+    count
+}
+
+fn count_utf16_newlines(bytes: &[u8]) -> u64 {
+    bytes
+        .chunks_exact(2)
+        .filter(|&unit| unit == b"\n\0")
+        .count() as u64
+}
+
+/// Reads until `buf` is full or the input ends, returning the bytes read.
+fn read_full(reader: &mut dyn Read, buf: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(filled)
+}
+
+fn csv_open(buf: &[u8]) -> bool {
+    buf.iter().filter(|&&b| b == b'"').count() % 2 == 1
+}
+
+/// The mapping read as a stream, for [`ByteBlocks`] to transcode from UTF-16.
+struct MapReader {
+    map: Arc<Mmap>,
+    pos: usize,
+}
+
+impl Read for MapReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let rest = &self.map[self.pos.min(self.map.len())..];
+        let n = rest.len().min(buf.len());
+        buf[..n].copy_from_slice(&rest[..n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
@@ -0,0 +1,526 @@
+//! A minimal JSON Lines record reader. It only understands enough JSON to
+//! pull a few top-level fields out of each object and skips everything
+//! else without building a document.
+
+use anyhow::{anyhow, bail};
+
+/// Names of the fields to extract from each object.
+#[derive(Clone, Debug)]
+pub struct Fields {
+    pub station: String,
+    pub value: String,
+    pub timestamp: String,
+}
+
+impl Default for Fields {
+    fn default() -> Self {
+        Fields {
+            station: "station".to_string(),
+            value: "temp".to_string(),
+            timestamp: "timestamp".to_string(),
+        }
+    }
+}
+
+/// The raw text of the extracted fields. Numbers are returned as their
+/// source text so they go through the same parsing as the other formats.
+#[derive(Debug, Default)]
+pub struct Record<'a> {
+    pub station: Option<&'a str>,
+    pub value: Option<&'a str>,
+    pub timestamp: Option<&'a str>,
+}
+
+/// Holds the buffers strings with escape sequences are decoded into, so
+/// they can be reused from line to line.
+#[derive(Debug, Default)]
+pub struct Parser {
+    station: String,
+    key: String,
+}
+
+#[derive(Clone, Copy)]
+enum Field {
+    Station,
+    Value,
+    Timestamp,
+    Other,
+}
+
+impl Parser {
+    /// Parses a single object. `null` fields are reported as missing.
+    pub fn parse<'a>(&'a mut self, line: &'a str, fields: &Fields) -> anyhow::Result<Record<'a>> {
+        let mut cursor = Cursor {
+            bytes: line.as_bytes(),
+            line,
+            pos: 0,
+        };
+        let mut station = None;
+        let mut value = None;
+        let mut timestamp = None;
+        let mut decoded_station = false;
+
+        cursor.expect(b'{')?;
+        if !cursor.eat(b'}') {
+            loop {
+                let key = cursor.string(&mut self.key)?;
+                let field = match key {
+                    Str::Raw(k) | Str::Decoded(k) if k == fields.station => Field::Station,
+                    Str::Raw(k) | Str::Decoded(k) if k == fields.value => Field::Value,
+                    Str::Raw(k) | Str::Decoded(k) if k == fields.timestamp => Field::Timestamp,
+                    _ => Field::Other,
+                };
+                cursor.expect(b':')?;
+                match field {
+                    Field::Station => {
+                        if cursor.null() {
+                            station = None;
+                        } else {
+                            match cursor.string(&mut self.station)? {
+                                Str::Raw(s) => {
+                                    station = Some(s);
+                                    decoded_station = false;
+                                }
+                                Str::Decoded(_) => {
+                                    station = None;
+                                    decoded_station = true;
+                                }
+                            }
+                        }
+                    }
+                    Field::Value => value = cursor.scalar()?,
+                    Field::Timestamp => timestamp = cursor.scalar()?,
+                    Field::Other => cursor.skip_value()?,
+                }
+                if cursor.eat(b',') {
+                    continue;
+                }
+                cursor.expect(b'}')?;
+                break;
+            }
+        }
+        cursor.skip_ws();
+        if cursor.pos != cursor.bytes.len() {
+            bail!("trailing characters after JSON object");
+        }
+
+        if decoded_station {
+            station = Some(self.station.as_str());
+        }
+        Ok(Record {
+            station,
+            value,
+            timestamp,
+        })
+    }
+}
+
+/// Every top-level field of an object with the source text of its value,
+/// whatever the value is, so that values can be compared as written.
+pub fn raw_fields(text: &str) -> anyhow::Result<Vec<(String, &str)>> {
+    let mut cursor = Cursor {
+        bytes: text.as_bytes(),
+        line: text,
+        pos: 0,
+    };
+    let mut buf = String::new();
+    let mut fields = Vec::new();
+    cursor.expect(b'{')?;
+    if !cursor.eat(b'}') {
+        loop {
+            let key = match cursor.string(&mut buf)? {
+                Str::Raw(k) | Str::Decoded(k) => k.to_string(),
+            };
+            cursor.expect(b':')?;
+            cursor.skip_ws();
+            let start = cursor.pos;
+            cursor.skip_value()?;
+            fields.push((key, &text[start..cursor.pos]));
+            if cursor.eat(b',') {
+                continue;
+            }
+            cursor.expect(b'}')?;
+            break;
+        }
+    }
+    cursor.skip_ws();
+    if cursor.pos != cursor.bytes.len() {
+        bail!("trailing characters after JSON object");
+    }
+    Ok(fields)
+}
+
+/// Every top-level field of an object that holds a string or a number,
+/// with strings decoded and numbers as their source text. Other values
+/// are skipped.
+pub fn parse_object(line: &str) -> anyhow::Result<Vec<(String, String)>> {
+    let mut cursor = Cursor {
+        bytes: line.as_bytes(),
+        line,
+        pos: 0,
+    };
+    let mut buf = String::new();
+    let mut fields = Vec::new();
+    cursor.expect(b'{')?;
+    if !cursor.eat(b'}') {
+        loop {
+            let key = match cursor.string(&mut buf)? {
+                Str::Raw(k) | Str::Decoded(k) => k.to_string(),
+            };
+            cursor.expect(b':')?;
+            match cursor.peek() {
+                Some(b'"') => {
+                    let value = match cursor.string(&mut buf)? {
+                        Str::Raw(v) | Str::Decoded(v) => v.to_string(),
+                    };
+                    fields.push((key, value));
+                }
+                Some(b'{' | b'[') => cursor.skip_value()?,
+                _ => {
+                    if let Some(value) = cursor.scalar()? {
+                        fields.push((key, value.to_string()));
+                    }
+                }
+            }
+            if cursor.eat(b',') {
+                continue;
+            }
+            cursor.expect(b'}')?;
+            break;
+        }
+    }
+    cursor.skip_ws();
+    if cursor.pos != cursor.bytes.len() {
+        bail!("trailing characters after JSON object");
+    }
+    Ok(fields)
+}
+
+enum Str<'a, 'b> {
+    Raw(&'a str),
+    Decoded(&'b str),
+}
+
+struct Cursor<'a> {
+    bytes: &'a [u8],
+    line: &'a str,
+    pos: usize,
+}
+
+impl<'a> Cursor<'a> {
+    fn skip_ws(&mut self) {
+        while self.pos < self.bytes.len() && self.bytes[self.pos].is_ascii_whitespace() {
+            self.pos += 1;
+        }
+    }
+
+    fn peek(&mut self) -> Option<u8> {
+        self.skip_ws();
+        self.bytes.get(self.pos).copied()
+    }
+
+    fn eat(&mut self, c: u8) -> bool {
+        if self.peek() == Some(c) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, c: u8) -> anyhow::Result<()> {
+        if self.eat(c) {
+            Ok(())
+        } else {
+            bail!("expected `{}` at column {}", c as char, self.pos + 1)
+        }
+    }
+
+    fn null(&mut self) -> bool {
+        if self.peek() == Some(b'n') && self.bytes[self.pos..].starts_with(b"null") {
+            self.pos += 4;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Reads a string, borrowing it from the line unless it contains escape
+    /// sequences, in which case it is decoded into `buf`.
+    fn string<'b>(&mut self, buf: &'b mut String) -> anyhow::Result<Str<'a, 'b>> {
+        self.expect(b'"')?;
+        let start = self.pos;
+        while let Some(&c) = self.bytes.get(self.pos) {
+            match c {
+                b'"' => {
+                    self.pos += 1;
+                    return Ok(Str::Raw(&self.line[start..self.pos - 1]));
+                }
+                b'\\' => {
+                    buf.clear();
+                    buf.push_str(&self.line[start..self.pos]);
+                    self.decode_rest(buf)?;
+                    return Ok(Str::Decoded(buf));
+                }
+                _ => self.pos += 1,
+            }
+        }
+        bail!("unterminated string")
+    }
+
+    fn decode_rest(&mut self, buf: &mut String) -> anyhow::Result<()> {
+        loop {
+            let start = self.pos;
+            while let Some(&c) = self.bytes.get(self.pos) {
+                if c == b'"' || c == b'\\' {
+                    break;
+                }
+                self.pos += 1;
+            }
+            buf.push_str(&self.line[start..self.pos]);
+            match self.bytes.get(self.pos) {
+                Some(b'"') => {
+                    self.pos += 1;
+                    return Ok(());
+                }
+                Some(b'\\') => {
+                    let escape = *self
+                        .bytes
+                        .get(self.pos + 1)
+                        .ok_or_else(|| anyhow!("unterminated string"))?;
+                    self.pos += 2;
+                    match escape {
+                        b'"' => buf.push('"'),
+                        b'\\' => buf.push('\\'),
+                        b'/' => buf.push('/'),
+                        b'b' => buf.push('\u{8}'),
+                        b'f' => buf.push('\u{c}'),
+                        b'n' => buf.push('\n'),
+                        b'r' => buf.push('\r'),
+                        b't' => buf.push('\t'),
+                        b'u' => buf.push(self.unicode_escape()?),
+                        _ => bail!("invalid escape `\\{}`", escape as char),
+                    }
+                }
+                _ => bail!("unterminated string"),
+            }
+        }
+    }
+
+    fn hex4(&mut self) -> anyhow::Result<u32> {
+        let hex = self
+            .line
+            .get(self.pos..self.pos + 4)
+            .ok_or_else(|| anyhow!("truncated \\u escape"))?;
+        self.pos += 4;
+        u32::from_str_radix(hex, 16).map_err(|_| anyhow!("invalid \\u escape"))
+    }
+
+    fn unicode_escape(&mut self) -> anyhow::Result<char> {
+        let high = self.hex4()?;
+        let code = if (0xD800..0xDC00).contains(&high) {
+            if !self.bytes[self.pos..].starts_with(b"\\u") {
+                bail!("unpaired surrogate in \\u escape");
+            }
+            self.pos += 2;
+            let low = self.hex4()?;
+            if !(0xDC00..0xE000).contains(&low) {
+                bail!("unpaired surrogate in \\u escape");
+            }
+            0x10000 + ((high - 0xD800) << 10) + (low - 0xDC00)
+        } else {
+            high
+        };
+        char::from_u32(code).ok_or_else(|| anyhow!("invalid \\u escape"))
+    }
+
+    /// Reads a number (or a string holding one, as some exporters quote
+    /// numbers) and returns its text, or `None` for `null`.
+    fn scalar(&mut self) -> anyhow::Result<Option<&'a str>> {
+        match self.peek() {
+            Some(b'"') => {
+                let mut buf = String::new();
+                match self.string(&mut buf)? {
+                    Str::Raw(s) => Ok(Some(s)),
+                    Str::Decoded(_) => bail!("unexpected escape sequence in number"),
+                }
+            }
+            _ if self.null() => Ok(None),
+            _ => {
+                let start = self.pos;
+                while let Some(&c) = self.bytes.get(self.pos) {
+                    if matches!(c, b',' | b'}' | b']') || c.is_ascii_whitespace() {
+                        break;
+                    }
+                    self.pos += 1;
+                }
+                if start == self.pos {
+                    bail!("expected a value at column {}", start + 1);
+                }
+                Ok(Some(&self.line[start..self.pos]))
+            }
+        }
+    }
+
+    fn skip_value(&mut self) -> anyhow::Result<()> {
+        let mut depth = 0usize;
+        loop {
+            match self.peek() {
+                Some(b'"') => {
+                    self.pos += 1;
+                    while let Some(&c) = self.bytes.get(self.pos) {
+                        self.pos += if c == b'\\' { 2 } else { 1 };
+                        if c == b'"' {
+                            break;
+                        }
+                    }
+                }
+                Some(b'{' | b'[') => {
+                    depth += 1;
+                    self.pos += 1;
+                    continue;
+                }
+                Some(b'}' | b']') if depth > 0 => {
+                    depth -= 1;
+                    self.pos += 1;
+                }
+                Some(b',' | b':') if depth > 0 => {
+                    self.pos += 1;
+                    continue;
+                }
+                Some(_) => {
+                    self.scalar()?;
+                }
+                None => bail!("unexpected end of line"),
+            }
+            if depth == 0 {
+                return Ok(());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(line: &str) -> anyhow::Result<(Option<String>, Option<String>, Option<String>)> {
+        let mut parser = Parser::default();
+        let record = parser.parse(line, &Fields::default())?;
+        let owned = |s: Option<&str>| s.map(str::to_string);
+        Ok((
+            owned(record.station),
+            owned(record.value),
+            owned(record.timestamp),
+        ))
+    }
+
+    fn station(line: &str) -> String {
+        parse(line).unwrap().0.expect("a station")
+    }
+
+    fn error(line: &str) -> String {
+        match parse(line) {
+            Ok(record) => panic!("{} parsed as {:?}", line, record),
+            Err(e) => e.to_string(),
+        }
+    }
+
+    #[test]
+    fn fields_are_found_in_any_order_among_others() {
+        let (station, value, timestamp) = parse(
+            r#"{"id": 7, "temp": -3.4, "tags": ["a", {"b": "}"}], "station": "Hamburg", "timestamp": "2024-01-01"}"#,
+        )
+        .unwrap();
+        assert_eq!(station.as_deref(), Some("Hamburg"));
+        assert_eq!(value.as_deref(), Some("-3.4"));
+        assert_eq!(timestamp.as_deref(), Some("2024-01-01"));
+        // Quoted numbers and nulls
+        let (station, value, timestamp) = parse(r#"{"station":null,"temp":"12.0"}"#).unwrap();
+        assert_eq!(
+            (station, value, timestamp),
+            (None, Some("12.0".into()), None)
+        );
+        assert_eq!(parse("{}").unwrap(), (None, None, None));
+    }
+
+    #[test]
+    fn escaped_quotes_and_backslashes_are_decoded() {
+        assert_eq!(
+            station(r#"{"station": "Sa\"o \\ Paulo"}"#),
+            "Sa\"o \\ Paulo"
+        );
+        assert_eq!(station(r#"{"station": "a\/b\tc"}"#), "a/b\tc");
+        // Escapes in a skipped field do not end it early
+        assert_eq!(
+            station(r#"{"note": "say \"hi\", }", "station": "Oslo"}"#),
+            "Oslo"
+        );
+    }
+
+    #[test]
+    fn unicode_escapes_decode_surrogate_pairs() {
+        assert_eq!(
+            station(r#"{"station": "S\u00e3o Paulo"}"#),
+            "S\u{E3}o Paulo"
+        );
+        assert_eq!(
+            station(r#"{"station": "\u6771\u4EAC"}"#),
+            "\u{6771}\u{4EAC}"
+        );
+        assert_eq!(
+            station(r#"{"station": "\ud83c\udf0a Beach"}"#),
+            "\u{1F30A} Beach"
+        );
+        assert_eq!(
+            error(r#"{"station": "\ud83c Beach"}"#),
+            "unpaired surrogate in \\u escape"
+        );
+        assert_eq!(
+            error(r#"{"station": "\ud83cA"}"#),
+            "unpaired surrogate in \\u escape"
+        );
+        assert_eq!(error(r#"{"station": "\udf0a"}"#), "invalid \\u escape");
+    }
+
+    #[test]
+    fn line_breaks_are_only_escaped() {
+        assert_eq!(station(r#"{"station": "Line\nBreak\r"}"#), "Line\nBreak\r");
+        // A raw line break ends the record, leaving the string open
+        assert_eq!(error("{\"station\": \"Line"), "unterminated string");
+    }
+
+    #[test]
+    fn malformed_objects_are_rejected() {
+        let cases = [
+            ("", "expected `{` at column 1"),
+            (r#"["station"]"#, "expected `{` at column 1"),
+            (r#"{"station" "Oslo"}"#, "expected `:` at column 12"),
+            (r#"{"station": "Oslo""#, "expected `}` at column 19"),
+            (
+                r#"{"station": "Oslo"} x"#,
+                "trailing characters after JSON object",
+            ),
+            (r#"{"station": "O\qslo"}"#, "invalid escape `\\q`"),
+            (r#"{"temp": }"#, "expected a value at column 10"),
+            (
+                r#"{"temp": "1\u0032"}"#,
+                "unexpected escape sequence in number",
+            ),
+        ];
+        for (line, expected) in cases {
+            assert_eq!(error(line), expected, "{}", line);
+        }
+    }
+
+    #[test]
+    fn raw_fields_keep_values_as_written() {
+        let text = "{\n  \"a\": {\"b\": [1, 2]},\n  \"c\": \"d\\\"\",\n  \"e\": null\n}\n";
+        let fields = raw_fields(text).unwrap();
+        let fields: Vec<(&str, &str)> = fields.iter().map(|(k, v)| (k.as_str(), *v)).collect();
+        assert_eq!(
+            fields,
+            [("a", r#"{"b": [1, 2]}"#), ("c", r#""d\"""#), ("e", "null")]
+        );
+    }
+}
@@ -0,0 +1,216 @@
+use crate::cli::{Config, GroupBy, KeyNormalization};
+use crate::metadata::Metadata;
+use crate::normalize;
+use anyhow::{Context, bail};
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fs;
+
+/// Rules mapping a raw station name onto the key its readings are
+/// aggregated under.
+#[derive(Debug, Default)]
+pub struct Keys {
+    /// Old names of stations, with their current ones
+    aliases: HashMap<String, String>,
+    map: HashMap<String, String>,
+    prefix_words: Option<usize>,
+    normalization: KeyNormalization,
+    /// Key for stations missing from `map`, instead of their own name
+    unmapped: Option<&'static str>,
+}
+
+/// Key that stations without metadata are grouped under by country.
+pub const UNKNOWN_COUNTRY: &str = "unknown";
+
+impl Keys {
+    pub fn from_config(config: &Config, metadata: Option<&Metadata>) -> anyhow::Result<Self> {
+        let mut keys = Keys {
+            aliases: HashMap::new(),
+            map: HashMap::new(),
+            prefix_words: config.group_by_prefix,
+            normalization: config.normalize_keys,
+            unmapped: None,
+        };
+        if let Some(path) = &config.aliases {
+            keys.aliases = resolve_aliases(
+                path,
+                load_mapping(path, "aliases", "old;current")?
+                    .into_iter()
+                    .map(|(old, current)| {
+                        (
+                            normalize_key(keys.normalization, &old).into_owned(),
+                            normalize_key(keys.normalization, &current).into_owned(),
+                        )
+                    })
+                    .collect(),
+            )?;
+        }
+        if let Some(path) = &config.key_map {
+            // Normalize the mapped names so they match what `apply` looks up
+            keys.map = load_mapping(path, "key map", "station;key")?
+                .into_iter()
+                .map(|(station, key)| {
+                    (
+                        normalize_key(keys.normalization, &station).into_owned(),
+                        key,
+                    )
+                })
+                .collect();
+        }
+        if let (GroupBy::Country, Some(metadata)) = (config.group_by, metadata) {
+            keys.map = metadata
+                .countries()
+                .map(|(station, country)| (station.to_string(), country.to_string()))
+                .collect();
+            keys.unmapped = Some(UNKNOWN_COUNTRY);
+        }
+        Ok(keys)
+    }
+
+    /// Names are normalized first, and old names replaced by current ones.
+    /// Mapped stations then take their key from the mapping file,
+    /// everything else falls back to the prefix rule, if any.
+    pub fn apply<'a>(&'a self, name: &'a str) -> Cow<'a, str> {
+        let name = normalize_key(self.normalization, name);
+        let name = match self.aliases.get(name.as_ref()) {
+            Some(current) => Cow::Borrowed(current.as_str()),
+            None => name,
+        };
+        if let Some(key) = self.map.get(name.as_ref()) {
+            return Cow::Borrowed(key);
+        }
+        if let Some(key) = self.unmapped {
+            return Cow::Borrowed(key);
+        }
+        match (self.prefix_words, name) {
+            (Some(n), Cow::Borrowed(name)) => Cow::Borrowed(first_words(name, n)),
+            (Some(n), Cow::Owned(name)) => Cow::Owned(first_words(&name, n).to_string()),
+            (None, name) => name,
+        }
+    }
+}
+
+pub fn normalize_key(normalization: KeyNormalization, name: &str) -> Cow<'_, str> {
+    match normalization {
+        KeyNormalization::None => Cow::Borrowed(name),
+        KeyNormalization::Lower => lowercase(name),
+        KeyNormalization::Nfc => normalize::nfc(name),
+        // Compatibility forms can decompose to capitals, as ϓ to Ύ
+        KeyNormalization::NfkcLower => match normalize::nfkc(name) {
+            Cow::Borrowed(name) => lowercase(name),
+            Cow::Owned(name) => Cow::Owned(lowercase(&name).into_owned()),
+        },
+    }
+}
+
+/// `name` in lowercase, borrowed if it has nothing to lower: besides
+/// capitals, titlecase letters such as ǅ have a lowercase form.
+fn lowercase(name: &str) -> Cow<'_, str> {
+    match name
+        .chars()
+        .any(|c| c.is_uppercase() || c.to_lowercase().ne(std::iter::once(c)))
+    {
+        true => Cow::Owned(name.to_lowercase()),
+        false => Cow::Borrowed(name),
+    }
+}
+
+fn first_words(name: &str, n: usize) -> &str {
+    match name.split_whitespace().nth(n) {
+        Some(word) => {
+            // Cut just before the (n+1)th word, then drop the separator
+            let end = word.as_ptr() as usize - name.as_ptr() as usize;
+            name[..end].trim_end()
+        }
+        None => name.trim(),
+    }
+}
+
+/// Points every old name at the name the station has now, through any
+/// renames in between, so that one lookup is enough.
+fn resolve_aliases(
+    path: &str,
+    aliases: HashMap<String, String>,
+) -> anyhow::Result<HashMap<String, String>> {
+    let mut resolved = HashMap::with_capacity(aliases.len());
+    for old in aliases.keys() {
+        let mut current = &aliases[old];
+        let mut renames = 1;
+        while let Some(next) = aliases.get(current) {
+            if renames > aliases.len() {
+                bail!("{}: {} is renamed in a cycle", path, old);
+            }
+            current = next;
+            renames += 1;
+        }
+        resolved.insert(old.clone(), current.clone());
+    }
+    Ok(resolved)
+}
+
+/// Reads `format` lines, such as `station;key`, skipping blank lines and
+/// `#` comments.
+fn load_mapping(path: &str, what: &str, format: &str) -> anyhow::Result<HashMap<String, String>> {
+    let content =
+        fs::read_to_string(path).with_context(|| format!("failed to read {} {}", what, path))?;
+    let mut map = HashMap::new();
+    for (i, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let Some((station, key)) = line.split_once(';') else {
+            bail!("{}:{}: expected `{}`", path, i + 1, format);
+        };
+        map.insert(station.to_string(), key.to_string());
+    }
+    Ok(map)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn is_borrowed(name: Cow<'_, str>) -> bool {
+        matches!(name, Cow::Borrowed(_))
+    }
+
+    #[test]
+    fn lower_catches_titlecase_letters() {
+        for (name, lowered) in [
+            ("ǅemal", "ǆemal"),
+            ("ǈubljana", "ǉubljana"),
+            ("ǋjegoš", "ǌjegoš"),
+            ("ǲenovo", "ǳenovo"),
+            ("HAMBURG", "hamburg"),
+        ] {
+            assert_eq!(normalize_key(KeyNormalization::Lower, name), lowered);
+        }
+        assert!(is_borrowed(normalize_key(KeyNormalization::Lower, "ǆemal")));
+    }
+
+    #[test]
+    fn nfkc_lower_normalizes_before_lowering() {
+        // ϓ decomposes to Υ and an acute accent, which compose to Ύ
+        assert_eq!(
+            normalize_key(KeyNormalization::NfkcLower, "\u{3D3}"),
+            "\u{3CD}"
+        );
+        assert_eq!(normalize_key(KeyNormalization::NfkcLower, "ﬁji"), "fiji");
+        // ǅ is D and ž as a compatibility form
+        assert_eq!(
+            normalize_key(KeyNormalization::NfkcLower, "ǅemal"),
+            "džemal"
+        );
+        assert_eq!(
+            normalize_key(KeyNormalization::NfkcLower, "Sa\u{300}o Paulo"),
+            "s\u{E0}o paulo"
+        );
+        for name in ["hamburg", "s\u{E0}o paulo", "\u{D55C}"] {
+            assert!(is_borrowed(normalize_key(
+                KeyNormalization::NfkcLower,
+                name
+            )));
+        }
+    }
+}
@@ -0,0 +1,1768 @@
+//! The `1brc` aggregation, which the binary in `src/main.rs` runs from the
+//! command line and other programs run through a
+//! [`processor::Processor`]. The parsing and aggregation building blocks
+//! are exposed as well, so that the fuzz targets in `fuzz/` can drive them
+//! (`cargo fuzz run parse_record`, or `stat_merge`, on a nightly toolchain).
+//! [`results::Results`] can also be saved and restored by processes that
+//! embed the aggregation, and written out with [`format::Writer`]. What
+//! goes wrong along the way without stopping a run is a [`warning::Warning`].
+
+/// Prints a diagnostic to stderr, unless `--quiet` asked for the results
+/// alone.
+macro_rules! note {
+    ($config:expr, $($arg:tt)*) => {
+        if $config.verbosity > $crate::cli::Verbosity::Quiet {
+            eprintln!($($arg)*);
+        }
+    };
+}
+
+mod affinity;
+mod agg;
+mod alloc;
+mod arena;
+#[cfg(feature = "runtime-tokio")]
+mod async_pipeline;
+mod batch;
+pub mod bloom;
+mod cache;
+mod cgroup;
+mod checksum;
+pub mod cli;
+mod collate;
+mod completions;
+pub mod csv;
+mod dashboard;
+mod diff;
+mod duplicates;
+mod export;
+pub mod format;
+mod generate;
+pub mod gzip;
+mod input;
+pub mod jsonl;
+mod keys;
+mod manifest;
+mod memory;
+mod metadata;
+mod mmap;
+mod name;
+mod normalize;
+mod numa;
+mod output;
+pub mod parse;
+mod partition;
+mod perf;
+mod phase;
+mod pipeline;
+mod prefix;
+mod priority;
+pub mod processor;
+mod profile;
+mod provenance;
+mod query;
+mod readahead;
+mod repl;
+mod report;
+pub mod results;
+pub mod rng;
+mod service;
+pub mod sha256;
+mod shared;
+pub mod simd;
+pub mod spec;
+mod spill;
+pub mod stat;
+mod throttle;
+pub mod time;
+mod timeout;
+mod unicode_tables;
+mod validate;
+mod version;
+pub mod warning;
+mod watch;
+mod worker;
+pub mod xxhash;
+
+use agg::{Extra, Tracking};
+use anyhow::Context as _;
+use arena::Arena;
+use bloom::Bloom;
+use cache::Cache;
+use checksum::Check;
+use cli::{
+    Collation, Command, Config, Encoding, Executor, InputFormat, Mode, NullPolicy, Numa, Sort,
+    Strategy, Verbosity,
+};
+use dashboard::Progress;
+use duplicates::{Duplicates, Seen};
+use export::Export;
+use input::{Block, Source};
+use keys::Keys;
+use manifest::{Assigned, Manifest};
+use memory::MemoryTracker;
+use metadata::Metadata;
+use name::Name;
+use numa::Node;
+use partition::RowWriter;
+use phase::Phase;
+use prefix::{Added, PrefixTable};
+use provenance::Provenance;
+use results::Results;
+use shared::SharedMap;
+use spill::Spiller;
+use stat::Stat;
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap};
+use std::io::Write;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, LazyLock, Mutex};
+use std::time::Instant;
+use throttle::Throttle;
+use timeout::TimedOut;
+use warning::{Warning, Warnings};
+
+/// What readings are aggregated by: the station key plus, for timestamped
+/// input, the start of the time window and, for rows of several readings,
+/// the column the reading came from.
+/// A long name lives in a worker's [`Arena`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+struct Key {
+    name: Name,
+    window: Option<i64>,
+    /// Counted from 1, as given to `--value-columns`
+    column: Option<u16>,
+}
+
+impl Key {
+    /// A key for looking up `name`, copying nothing but a short name.
+    ///
+    /// # Safety
+    ///
+    /// The result, unlike a [`Key::stored`] copy of it, must not be stored
+    /// anywhere that outlives `name`.
+    unsafe fn transient(name: &str, window: Option<i64>, column: Option<u16>) -> Self {
+        Key {
+            // SAFETY: upheld by the caller
+            name: unsafe { Name::transient(name) },
+            window,
+            column,
+        }
+    }
+
+    /// The key with its name copied into `arena` if it is too long to be
+    /// inline, so that it can be kept as long as the arena.
+    fn stored(self, arena: &mut Arena) -> Self {
+        Key {
+            name: Name::new(&self.name, arena),
+            ..self
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct ThreadData {
+    stat: HashMap<Key, Stat>,
+    /// What `--agg` needs beyond `stat`, if anything
+    extras: HashMap<Key, Extra>,
+    /// In front of `stat` under `--strategy prefix-table`, holding keys
+    /// that are not in it
+    prefix: Option<PrefixTable>,
+    /// Lines got through in blocks
+    lines: u64,
+    /// Backs the names in `stat`
+    arena: Arena,
+    excluded: u64,
+    /// Rows without a reading, per station
+    missing: HashMap<String, u64>,
+    /// Estimated size of `stat`
+    map_bytes: usize,
+    spill: Option<Spiller>,
+    provenance: Option<Provenance>,
+    /// Rows not yet handed to the `RowWriter`, per partition
+    rows: HashMap<String, String>,
+    /// Exported rows of the current chunk
+    export: String,
+    /// Bytes got through since the last report to the dashboard
+    unreported: u64,
+    /// When the dashboard last got this worker's hottest stations
+    published: Option<Instant>,
+    /// The lines seen so far, under `--detect-duplicates`
+    seen: Option<Seen>,
+    /// Rows `--dedupe` left out
+    deduped: u64,
+}
+
+impl ThreadData {
+    /// Every key and its stat so far, wherever it is kept.
+    fn stats(&self) -> impl Iterator<Item = (&Key, &Stat)> {
+        self.stat
+            .iter()
+            .chain(self.prefix.iter().flat_map(PrefixTable::iter))
+    }
+
+    /// Moves the keys of the prefix table into `stat`, for merging.
+    fn flush_prefix(&mut self) {
+        if let Some(prefix) = &mut self.prefix {
+            self.stat.extend(prefix.drain());
+        }
+    }
+}
+
+/// The run's warnings, printed as they come and counted for `--stats`.
+static WARNINGS: LazyLock<Warnings> =
+    LazyLock::new(|| Warnings::new(|warning| eprintln!("warning: {}", warning)));
+
+fn warn(warning: Warning) {
+    WARNINGS.warn(warning);
+}
+
+/// State shared by the reader and all workers.
+struct Context {
+    config: Arc<Config>,
+    keys: Keys,
+    memory: MemoryTracker,
+    /// Set when a worker fails or `--timeout` runs out, to stop the others
+    /// early
+    cancelled: Arc<AtomicBool>,
+    sinks: Arc<Sinks>,
+    progress: Option<Arc<Progress>>,
+    /// The map all workers aggregate into under `--strategy shared-map`
+    shared: Option<SharedMap>,
+    tracking: Tracking,
+    throttle: Option<Throttle>,
+    /// The byte ranges the input was handed out in, for `--manifest`
+    assigned: Mutex<Vec<Assigned>>,
+    /// The rows seen so far, under `--dedupe`
+    dedupe: Option<Bloom>,
+}
+
+impl Context {
+    fn assign(&self, to: String, range: &std::ops::Range<u64>) {
+        self.assigned.lock().unwrap().push(Assigned {
+            to,
+            range: range.clone(),
+        });
+    }
+}
+
+/// Where the rows that pass the filters are copied, besides being
+/// aggregated.
+#[derive(Default)]
+struct Sinks {
+    partitions: Option<RowWriter>,
+    export: Option<Export>,
+}
+
+impl Sinks {
+    fn new(config: &Config) -> anyhow::Result<Self> {
+        Ok(Sinks {
+            partitions: match config.partition_rows {
+                true => Some(RowWriter::new(config)?),
+                false => None,
+            },
+            export: match config.export_rows {
+                Some(_) => Some(Export::create(config)?),
+                None => None,
+            },
+        })
+    }
+
+    fn is_empty(&self) -> bool {
+        self.partitions.is_none() && self.export.is_none()
+    }
+}
+
+/// Estimated bytes held by a chunk of lines.
+fn chunk_cost(chunk: &Block) -> usize {
+    chunk.heap_size() + std::mem::size_of::<Block>()
+}
+
+/// The merged results of a run, before they are formatted.
+#[derive(Debug, Default)]
+struct Summary {
+    lines: u64,
+    excluded: u64,
+    /// Rows without a reading, per station
+    missing: BTreeMap<String, u64>,
+    stats: HashMap<Key, Stat>,
+    /// Of the same keys as `stats`, when `--agg` needs them
+    extras: HashMap<Key, Extra>,
+    /// How the scan split the input, empty for cached results
+    assigned: Vec<Assigned>,
+    /// The results were written out as they were merged, and are not in
+    /// `stats`
+    streamed: bool,
+    /// Under `--detect-duplicates`
+    duplicates: Option<Duplicates>,
+    /// Rows `--dedupe` left out
+    deduped: u64,
+    /// Cut short by `--timeout`, and of only part of the input
+    timed_out: bool,
+}
+
+impl Summary {
+    /// The final value of each aggregate.
+    fn results(&self) -> HashMap<Key, StatResult> {
+        self.stats
+            .iter()
+            .map(|(key, stat)| (*key, StatResult::new(stat, self.extras.get(key))))
+            .collect()
+    }
+
+    /// Adds `other` into these results, copying the names of keys that
+    /// are new into `arena`.
+    fn merge(&mut self, other: &Summary, arena: &mut Arena) {
+        self.lines += other.lines;
+        self.excluded += other.excluded;
+        self.deduped += other.deduped;
+        self.timed_out |= other.timed_out;
+        if let Some(duplicates) = other.duplicates {
+            self.duplicates.get_or_insert_default().merge(duplicates);
+        }
+        for (station, count) in &other.missing {
+            *self.missing.entry(station.clone()).or_default() += count;
+        }
+        for (key, stat) in &other.stats {
+            match self.stats.get_mut(key) {
+                Some(total) => total.merge(stat),
+                None => {
+                    self.stats.insert(key.stored(arena), stat.clone());
+                }
+            }
+        }
+        for (key, extra) in &other.extras {
+            match self.extras.get_mut(key) {
+                Some(total) => total.merge(extra),
+                None => {
+                    self.extras.insert(key.stored(arena), extra.clone());
+                }
+            }
+        }
+    }
+
+    /// With names of their own, as saved to disk.
+    fn to_results(&self) -> Results {
+        Results {
+            lines: self.lines,
+            excluded: self.excluded,
+            missing: self.missing.clone(),
+            stats: self
+                .stats
+                .iter()
+                .map(|(key, stat)| {
+                    let key = results::Key {
+                        name: key.name.to_string(),
+                        window: key.window,
+                    };
+                    (key, stat.clone())
+                })
+                .collect(),
+        }
+    }
+
+    /// Copies the names into `arena`.
+    fn from_results(results: Results, arena: &mut Arena) -> Summary {
+        Summary {
+            lines: results.lines,
+            excluded: results.excluded,
+            missing: results.missing,
+            stats: results
+                .stats
+                .into_iter()
+                .map(|(key, stat)| {
+                    let key = Key {
+                        name: Name::new(&key.name, arena),
+                        window: key.window,
+                        column: None,
+                    };
+                    (key, stat)
+                })
+                .collect(),
+            ..Default::default()
+        }
+    }
+}
+
+struct StatResult {
+    min: f64,
+    max: f64,
+    mean: f64,
+    sum: f64,
+    count: i64,
+    extra: Option<Extra>,
+}
+
+impl StatResult {
+    fn new(stat: &Stat, extra: Option<&Extra>) -> Self {
+        StatResult {
+            min: stat.min,
+            max: stat.max,
+            mean: stat.sum / stat.count as f64,
+            sum: stat.sum,
+            count: stat.count,
+            extra: extra.cloned(),
+        }
+    }
+
+    /// NaN unless `--agg` asked for it.
+    fn stddev(&self) -> f64 {
+        self.extra
+            .as_ref()
+            .map_or(f64::NAN, |extra| extra.stddev(self.mean, self.count))
+    }
+
+    /// NaN unless `--agg` asked for it.
+    fn percentile(&self, p: u8) -> f64 {
+        self.extra
+            .as_ref()
+            .map_or(f64::NAN, |extra| extra.percentile(p, self.count))
+    }
+}
+
+fn read_file_in_chunks(config: Arc<Config>, num_threads: usize) -> anyhow::Result<()> {
+    let start = Instant::now();
+    phase::enter(Phase::Setup);
+    if config.verbosity >= Verbosity::Verbose {
+        print_config(&config, num_threads);
+    }
+    let metadata = match &config.metadata {
+        Some(path) => Some(Metadata::load(path, config.normalize_keys)?),
+        None => None,
+    };
+
+    // Keys point into these, so they are dropped only after the results
+    let mut arenas = vec![];
+    let mut files = vec![];
+    let mut from_cache = true;
+    let sinks = Arc::new(Sinks::new(&config)?);
+    for input in &config.inputs {
+        let config = Arc::new(Config {
+            input: input.clone(),
+            ..(*config).clone()
+        });
+        let (summary, cached) =
+            summarize(&config, metadata.as_ref(), num_threads, &sinks, &mut arenas)?;
+        from_cache &= cached;
+        files.push((input.as_str(), summary));
+    }
+    let assigned: Vec<(String, Vec<Assigned>)> = files
+        .iter_mut()
+        .map(|(input, summary)| (input.to_string(), std::mem::take(&mut summary.assigned)))
+        .collect();
+    let mut merged = Arena::default();
+    let summary = if files.len() == 1 && !config.per_file {
+        files.pop().expect("one file").1
+    } else {
+        let mut total = Summary::default();
+        for (_, summary) in &files {
+            total.merge(summary, &mut merged);
+        }
+        if files.len() > 1 {
+            note!(
+                config,
+                "Processed {} lines total in {} files",
+                total.lines,
+                files.len()
+            );
+        }
+        total
+    };
+
+    timeout::check(&config)?;
+    phase::enter(Phase::Output);
+    if config.partition_by.is_some() {
+        print_counts(&config, &summary);
+        let parts = partition::write_results(&config, &summary, metadata.as_ref())?;
+        note!(
+            config,
+            "Wrote {} partitions to {}",
+            parts,
+            config.partition_dir.display()
+        );
+        if let Some(rows) = &sinks.partitions {
+            let files = rows.finish().with_context(|| {
+                format!("failed to write rows to {}", config.partition_dir.display())
+            })?;
+            note!(config, "Wrote the rows of {} partitions", files);
+        }
+    } else if config.per_file {
+        print_counts(&config, &summary);
+        let files: Vec<(&str, HashMap<Key, StatResult>)> = files
+            .iter()
+            .map(|(input, summary)| (*input, summary.results()))
+            .collect();
+        let mut out = output::stdout();
+        writeln!(
+            out,
+            "{}",
+            output::format_per_file(&config, &files, &summary.results(), metadata.as_ref())
+        )?;
+        out.flush()?;
+    } else if !summary.streamed {
+        print_summary(&config, &summary, metadata.as_ref())?;
+    }
+    if let Some(path) = &config.report {
+        let mut bytes = 0;
+        for input in &config.inputs {
+            bytes += std::fs::metadata(input)?.len();
+        }
+        let run = report::Run {
+            config: &config,
+            lines: summary.lines,
+            bytes,
+            elapsed: start.elapsed(),
+            workers: num_threads,
+            cached: from_cache,
+        };
+        let table = output::results_table(&config, &summary.results(), metadata.as_ref());
+        report::write(path, &run, &table)?;
+    }
+    if config.manifest.is_some() || config.verify_manifest.is_some() {
+        let results = summary.results();
+        let counts = manifest::Counts {
+            lines: summary.lines,
+            readings: results.values().map(|stat| stat.count as u64).sum(),
+            excluded: summary.excluded,
+            missing: summary.missing.values().sum(),
+            keys: results.len(),
+        };
+        let manifest = Manifest::new(&config, num_threads, assigned, &results, counts)?;
+        if let Some(path) = &config.manifest {
+            manifest.write(path)?;
+        }
+        if let Some(path) = &config.verify_manifest {
+            let mismatches = manifest.verify(path)?;
+            if !mismatches.is_empty() {
+                eprintln!("error: this run does not match {}", path.display());
+                for mismatch in mismatches {
+                    eprintln!("  {}", mismatch);
+                }
+                std::process::exit(1);
+            }
+            note!(config, "Matches manifest {}", path.display());
+        }
+    }
+    drop(summary);
+    drop(files);
+    drop(merged);
+    drop(arenas);
+    if config.alloc_stats
+        && let Some(report) = alloc::report()
+    {
+        eprintln!("{}", report);
+    }
+    if config.verbosity >= Verbosity::Verbose {
+        let times: Vec<String> = phase::times()
+            .iter()
+            .map(|(phase, time)| format!("{} {:.3}s", phase.name(), time.as_secs_f64()))
+            .collect();
+        eprintln!("Phases: {}", times.join(", "));
+    }
+    Ok(())
+}
+
+/// Prints about how many rows each input has, for `--estimate-rows`.
+fn estimate_rows(config: &Config) -> anyhow::Result<()> {
+    let mut out = output::stdout();
+    for path in &config.inputs {
+        let config = Config {
+            input: path.clone(),
+            ..config.clone()
+        };
+        let estimate = input::estimate_lines(&Source::open(&config)?)?;
+        match estimate.exact {
+            true => writeln!(
+                out,
+                "{}: {} row{}",
+                path,
+                estimate.lines,
+                if estimate.lines == 1 { "" } else { "s" }
+            )?,
+            false => writeln!(
+                out,
+                "{}: about {} rows (95% confidence: {} to {})",
+                path, estimate.lines, estimate.low, estimate.high
+            )?,
+        }
+        // Each estimate is out before the next input is sampled
+        out.flush()?;
+    }
+    Ok(())
+}
+
+/// The options the run goes by, for `-v`.
+fn print_config(config: &Config, num_threads: usize) {
+    eprintln!("Options:");
+    if let Some(profile) = &config.profile {
+        eprintln!("  profile: {}", profile);
+    }
+    eprintln!("  inputs: {}", config.inputs.join(", "));
+    eprintln!("  workers: {}", num_threads);
+    eprintln!("  executor: {}", config.executor.name());
+    eprintln!("  strategy: {}", config.strategy.name());
+    eprintln!("  io: {}", config.io.name());
+    eprintln!(
+        "  chunk bytes: {}",
+        memory::format_bytes(config.chunk_bytes)
+    );
+    eprintln!("  format: {}", config.format.name());
+}
+
+/// The results for `config.input`, from the cache if it has them and
+/// otherwise from a scan, and whether they were cached.
+fn summarize(
+    config: &Arc<Config>,
+    metadata: Option<&Metadata>,
+    num_threads: usize,
+    sinks: &Arc<Sinks>,
+    arenas: &mut Vec<Arena>,
+) -> anyhow::Result<(Summary, bool)> {
+    let cache = cache_for(config, sinks)?;
+    if let Some(summary) = cache
+        .as_ref()
+        .and_then(|cache| load_cached(config, cache, arenas))
+    {
+        return Ok((summary, true));
+    }
+    let summary = aggregate(config, metadata, num_threads, sinks, arenas)?;
+    if let Some(cache) = &cache {
+        store_cached(config, cache, &summary);
+    }
+    Ok((summary, false))
+}
+
+/// The cache `config.input`'s results are kept in, if the run has one.
+fn cache_for(config: &Config, sinks: &Sinks) -> anyhow::Result<Option<Cache>> {
+    // A provenance check needs the run it checks, and the rows a scan
+    Ok(match &config.cache_dir {
+        Some(dir) if !config.debug_provenance && sinks.is_empty() => Some(Cache::new(dir, config)?),
+        _ => None,
+    })
+}
+
+/// The results `cache` has, noted as a scan's would be.
+fn load_cached(config: &Config, cache: &Cache, arenas: &mut Vec<Arena>) -> Option<Summary> {
+    let mut arena = Arena::default();
+    let summary = cache.load(&mut arena)?;
+    arenas.push(arena);
+    note!(config, "Processed {} lines total", summary.lines);
+    if config.stats {
+        eprintln!("Stats:");
+        eprintln!("  cached results: {}", cache.path().display());
+    }
+    Some(summary)
+}
+
+/// Keeps the results of a scan in `cache`.
+fn store_cached(config: &Config, cache: &Cache, summary: &Summary) {
+    // Results missing a failed worker's share, or the input past a
+    // timeout, are not worth keeping
+    if !config.best_effort && !summary.timed_out {
+        cache.store(summary);
+    }
+}
+
+/// Prints the row counts the options ask for, then the results or what
+/// the query makes of them.
+fn print_summary(
+    config: &Config,
+    summary: &Summary,
+    metadata: Option<&Metadata>,
+) -> anyhow::Result<()> {
+    print_counts(config, summary);
+    let results = summary.results();
+    let mut out = output::stdout();
+    match &config.query {
+        Some(query) => {
+            let table = query.run(&output::results_table(config, &results, metadata))?;
+            writeln!(out, "{}", output::format_query(config, &table))?;
+            out.flush()?;
+        }
+        None => output::write_results(config, &results, metadata, &mut out)?,
+    }
+    Ok(())
+}
+
+/// The rows left out of the results, if the options ask for them.
+fn print_counts(config: &Config, summary: &Summary) {
+    if config.range.is_set() {
+        note!(
+            config,
+            "Excluded {} rows outside the value range",
+            summary.excluded
+        );
+    }
+    if config.null_policy == NullPolicy::Count {
+        let total: u64 = summary.missing.values().sum();
+        note!(config, "Skipped {} rows with missing values", total);
+        if config.verbosity >= Verbosity::Verbose {
+            for (station, count) in &summary.missing {
+                eprintln!("  {}: {} missing", station, count);
+            }
+        }
+    }
+    if config.dedupe {
+        note!(config, "Skipped {} duplicate rows", summary.deduped);
+    }
+    if let Some(duplicates) = summary.duplicates {
+        note!(
+            config,
+            "Found {} duplicate lines of {} ({} distinct)",
+            duplicates.repeated(),
+            duplicates.lines,
+            duplicates.distinct
+        );
+    }
+    if config.track_extremes && config.verbosity >= Verbosity::Verbose {
+        print_extremes(config, summary);
+    }
+}
+
+/// The rows each key's minimum and maximum came from, by key, with the
+/// lines they are on at `-vv`, which means reading the input again.
+fn print_extremes(config: &Config, summary: &Summary) {
+    let mut extremes: Vec<(&Key, agg::Extremes)> = summary
+        .extras
+        .iter()
+        .filter_map(|(key, extra)| Some((key, extra.extremes()?)))
+        .collect();
+    extremes.sort_unstable_by(|a, b| a.0.cmp(b.0));
+    let offsets: Vec<u64> = extremes
+        .iter()
+        .flat_map(|(_, extremes)| [extremes.min.1, extremes.max.1])
+        .collect();
+    let lines = match config.verbosity {
+        Verbosity::Workers => match input::line_numbers(&config.input, config.encoding, &offsets) {
+            Ok(lines) => Some(lines),
+            Err(e) => {
+                eprintln!("Cannot count the lines of {}: {}", config.input, e);
+                None
+            }
+        },
+        _ => None,
+    };
+    eprintln!("Extremes:");
+    for (i, (key, extremes)) in extremes.iter().enumerate() {
+        let mut label = key.name.to_string();
+        if let Some(window) = key.window {
+            label.push_str(&format!("@{}", time::format_timestamp(window)));
+        }
+        if let Some(column) = key.column {
+            label.push_str(&format!("#{}", column));
+        }
+        let at = |j: usize, offset: u64| match &lines {
+            Some(lines) => format!("byte {}, line {}", offset, lines[2 * i + j]),
+            None => format!("byte {}", offset),
+        };
+        eprintln!(
+            "  {}: min {:.1} at {}; max {:.1} at {}",
+            label,
+            extremes.min.0,
+            at(0, extremes.min.1),
+            extremes.max.0,
+            at(1, extremes.max.1)
+        );
+    }
+}
+
+/// Scans the whole file and merges what the workers collected, printing
+/// the line count and, with `--stats`, how the run went. The names of the
+/// keys are kept alive by `arenas`.
+fn aggregate(
+    config: &Arc<Config>,
+    metadata: Option<&Metadata>,
+    num_threads: usize,
+    sinks: &Arc<Sinks>,
+    arenas: &mut Vec<Arena>,
+) -> anyhow::Result<Summary> {
+    let scan = Scan::start(config, metadata, num_threads, sinks)?;
+    let (ctx, source, nodes) = (&scan.ctx, &scan.source, &scan.nodes);
+    let spill_limit = scan.spill_limit;
+    let run = match config.executor {
+        Executor::Pipeline => pipeline::run(ctx, source, nodes, num_threads, spill_limit),
+        Executor::Threads => run_threads(ctx, source, num_threads, spill_limit),
+        Executor::Auto => unreachable!("resolved by parse_args"),
+        #[cfg(feature = "runtime-tokio")]
+        Executor::Tokio => async_pipeline::run(ctx, source, nodes, num_threads, spill_limit),
+    };
+    scan.finish(run, arenas)
+}
+
+/// A scan of `config.input` set up for an executor to run, with the
+/// alarm, dashboard, checksum and counters that watch it.
+struct Scan<'a> {
+    ctx: Arc<Context>,
+    source: Source,
+    nodes: Vec<Node>,
+    metadata: Option<&'a Metadata>,
+    num_threads: usize,
+    spill_limit: Option<usize>,
+    alarm: Option<timeout::Alarm>,
+    dashboard: Option<std::thread::JoinHandle<()>>,
+    check: Option<Check>,
+    counters: Option<perf::Counters>,
+}
+
+impl<'a> Scan<'a> {
+    fn start(
+        config: &Arc<Config>,
+        metadata: Option<&'a Metadata>,
+        num_threads: usize,
+        sinks: &Arc<Sinks>,
+    ) -> anyhow::Result<Self> {
+        // The deadline is the run's, setup included
+        let alarm = timeout::Alarm::set();
+        // Open the file through the configured backend
+        let source = Source::open(config).map_err(|e| worker::ReadError::new(config, e))?;
+        // On a multi-node machine, each node reads and aggregates its own part
+        // of the file on threads pinned to it
+        let nodes = match config.numa {
+            Numa::Auto => numa::detect(),
+            Numa::Off => Vec::new(),
+        };
+        let ctx = Arc::new(Context {
+            keys: Keys::from_config(config, metadata)?,
+            memory: MemoryTracker::new(config.max_memory),
+            cancelled: alarm
+                .as_ref()
+                .map_or_else(Default::default, timeout::Alarm::cancelled),
+            config: config.clone(),
+            sinks: sinks.clone(),
+            progress: config
+                .tui
+                .then(|| Arc::new(Progress::new(source.len(), num_threads.max(nodes.len())))),
+            shared: (config.strategy == Strategy::SharedMap)
+                .then(|| SharedMap::new(map_capacity(config))),
+            tracking: Tracking::of(&config.agg, config.track_extremes),
+            throttle: Throttle::new(config.max_rows_per_sec, config.max_bytes_per_sec),
+            assigned: Mutex::default(),
+            dedupe: match config.dedupe {
+                true => Some(duplicates::filter(config, &source)?),
+                false => None,
+            },
+        });
+
+        let spill_limit = config
+            .spill_budget
+            .or(config.max_memory.map(|limit| limit / 2))
+            .map(|budget| budget / num_threads)
+            // Spilled keys would be missing from the comparison
+            .filter(|_| !config.debug_provenance);
+        let dashboard = ctx.progress.as_ref().and_then(Progress::spawn);
+        let check = config
+            .expect_sha256
+            .map(|expected| Check::spawn(&ctx, expected));
+        phase::enter(Phase::Aggregate);
+        let counters = config.stats.then(perf::Counters::start).flatten();
+        Ok(Scan {
+            ctx,
+            source,
+            nodes,
+            metadata,
+            num_threads,
+            spill_limit,
+            alarm,
+            dashboard,
+            check,
+            counters,
+        })
+    }
+
+    /// Stops what watches the scan and merges what `run` collected.
+    fn finish(
+        self,
+        run: anyhow::Result<(u64, Vec<ThreadData>)>,
+        arenas: &mut Vec<Arena>,
+    ) -> anyhow::Result<Summary> {
+        let Scan {
+            ctx,
+            source,
+            nodes,
+            metadata,
+            num_threads,
+            alarm,
+            dashboard,
+            check,
+            counters,
+            ..
+        } = self;
+        let config = &ctx.config;
+        let sinks = &ctx.sinks;
+        let counts = counters.map(perf::Counters::stop);
+        let timed_out = alarm.is_some_and(timeout::Alarm::stop);
+        if let Some(progress) = &ctx.progress {
+            progress.finish(dashboard);
+        }
+        if let Some(check) = check {
+            // A failed run's error is the one worth reporting
+            if run.is_err() {
+                ctx.cancelled.store(true, Ordering::Relaxed);
+            }
+            check.finish(&config.input)?;
+        }
+        let (line_count, mut thread_datas) = run?;
+        if timed_out {
+            let after = config.timeout.expect("the alarm is set by --timeout");
+            match config.partial_on_timeout {
+                true => crate::warn(Warning::TimedOut { after }),
+                false => return Err(TimedOut(after).into()),
+            }
+        }
+        // Past the deadline after all, with the merge and output still to do
+        timeout::check(config)?;
+        if config.verbosity >= Verbosity::Workers {
+            for (i, data) in thread_datas.iter().enumerate() {
+                let spilled = data.spill.as_ref().map_or(0, |spill| spill.files.len());
+                eprintln!(
+                    "Worker {}: {} lines, {} keys in memory, {} spill files",
+                    i,
+                    data.lines,
+                    data.stat.len(),
+                    spilled
+                );
+            }
+        }
+        phase::enter(Phase::Merge);
+        thread_datas.iter_mut().for_each(ThreadData::flush_prefix);
+
+        if config.debug_provenance {
+            provenance::check(&ctx, &source, &thread_datas)?;
+        }
+        if let Some(rows) = &sinks.partitions {
+            for data in &thread_datas {
+                for (part, buffered) in &data.rows {
+                    rows.write(part, buffered)?;
+                }
+            }
+        }
+        if let Some(export) = &sinks.export {
+            // Rows the threads executor has not handed over yet
+            for data in thread_datas.iter().filter(|d| !d.export.is_empty()) {
+                export.write(0, &data.export)?;
+            }
+            export.finish().with_context(|| {
+                format!(
+                    "failed to write {}",
+                    config.export_rows.as_ref().unwrap().display()
+                )
+            })?;
+        }
+
+        // Merge what the workers collected
+        let mut summary = Summary {
+            lines: line_count,
+            assigned: std::mem::take(&mut ctx.assigned.lock().unwrap()),
+            timed_out,
+            ..Default::default()
+        };
+        let mut spill_files = vec![];
+        let mut seen = vec![];
+        let thread_datas: Vec<ThreadData> = thread_datas
+            .into_iter()
+            .map(|mut r| {
+                summary.excluded += r.excluded;
+                summary.deduped += r.deduped;
+                seen.extend(r.seen.take());
+                for (station, count) in &r.missing {
+                    *summary.missing.entry(station.clone()).or_default() += count;
+                }
+                agg::merge(&mut summary.extras, std::mem::take(&mut r.extras));
+                if let Some(spill) = r.spill.take() {
+                    spill_files.extend(spill.files);
+                }
+                arenas.push(std::mem::take(&mut r.arena));
+                r
+            })
+            .collect();
+        if config.detect_duplicates {
+            summary.duplicates = Some(Duplicates::count(seen));
+        }
+
+        if !spill_files.is_empty() && streams_while_merging(config) {
+            // The runs come out in the order the results are written in, so
+            // each key is written once merged and the results are never held
+            // whole
+            note!(config, "Processed {} lines total", line_count);
+            print_counts(config, &summary);
+            let maps = thread_datas.into_iter().map(|d| d.stat).collect();
+            let mut merged = Arena::default();
+            let mut keys = 0;
+            let mut out = output::stdout();
+            let mut writer = output::ResultWriter::new(
+                config,
+                metadata,
+                config.window.is_some(),
+                !config.value_columns.is_empty(),
+                &mut out,
+            )?;
+            spill::merge(&spill_files, maps, &mut merged, |key, stat| {
+                keys += 1;
+                writer.row(&key, &StatResult::new(&stat, summary.extras.get(&key)))
+            })?;
+            writer.finish()?;
+            out.flush()?;
+            drop(out);
+            arenas.push(merged);
+            summary.streamed = true;
+            if config.stats {
+                print_stats(
+                    &ctx.memory,
+                    spill_files.len(),
+                    config,
+                    &nodes,
+                    num_threads,
+                    keys,
+                    counts,
+                );
+            }
+            return Ok(summary);
+        }
+        if !spill_files.is_empty() {
+            // Some keys only exist on disk, so merge everything as sorted runs
+            let maps = thread_datas.into_iter().map(|d| d.stat).collect();
+            let mut merged = Arena::default();
+            summary.stats.reserve(map_capacity(config));
+            spill::merge(&spill_files, maps, &mut merged, |key, stat| {
+                summary.stats.insert(key, stat);
+                Ok(())
+            })?;
+            arenas.push(merged);
+        } else if let Some(shared) = &ctx.shared {
+            summary.stats = shared.take(arenas);
+        } else {
+            let maps = thread_datas.into_iter().map(|d| d.stat).collect();
+            summary.stats = reduce(maps, cgroup::Limits::detect().parallelism());
+        }
+
+        // A service logs what it has processed instead
+        if config.daemon {
+            return Ok(summary);
+        }
+        note!(config, "Processed {} lines total", line_count);
+        if config.stats {
+            print_stats(
+                &ctx.memory,
+                spill_files.len(),
+                config,
+                &nodes,
+                num_threads,
+                summary.stats.len(),
+                counts,
+            );
+        }
+        Ok(summary)
+    }
+}
+
+/// Whether the results can be written as the spilled runs are merged:
+/// the runs are in key order, so the output has to be too, and nothing
+/// after the merge may need all of the results.
+fn streams_while_merging(config: &Config) -> bool {
+    output::streams(config)
+        && config.sort == Sort::default()
+        && config.collation == Collation::Bytes
+        && config.query.is_none()
+        && config.inputs.len() == 1
+        && !config.per_file
+        && config.partition_by.is_none()
+        && config.report.is_none()
+        && config.manifest.is_none()
+        && config.verify_manifest.is_none()
+        && config.cache_dir.is_none()
+        && config.watch_dir.is_none()
+        && !config.repl
+        && !config.keep_results
+}
+
+/// Splits the file into one byte range per thread, aligned to records, and
+/// lets each thread read its range in blocks and fold them into its own
+/// map, with nothing shared until the maps are reduced by the caller.
+fn run_threads(
+    ctx: &Context,
+    source: &Source,
+    num_threads: usize,
+    spill_limit: Option<usize>,
+) -> anyhow::Result<(u64, Vec<ThreadData>)> {
+    let config = &ctx.config;
+    ctx.memory
+        .buffers
+        .add((source.buffer_size() + config.chunk_bytes) * num_threads);
+    let len = source.len();
+    let results = std::thread::scope(|scope| {
+        let handles: Vec<_> = (0..num_threads)
+            .map(|i| {
+                let range =
+                    len * i as u64 / num_threads as u64..len * (i as u64 + 1) / num_threads as u64;
+                ctx.assign(format!("worker {}", i), &range);
+                let core = config
+                    .pin_cores
+                    .as_ref()
+                    .map(|cores| cores[i % cores.len()]);
+                scope.spawn(move || {
+                    if let Some(core) = core {
+                        affinity::pin_or_warn("worker", &[core]);
+                    }
+                    let mut data = new_thread_data(config, i, spill_limit);
+                    let result = worker::guard(i, |offset| {
+                        *offset = range.start;
+                        let read_error = |e| worker::ReadError::new(config, e);
+                        let blocks = source.blocks(range, config.chunk_bytes, false);
+                        for block in blocks.map_err(read_error)? {
+                            if ctx.cancelled.load(Ordering::Relaxed) {
+                                break;
+                            }
+                            let (block_offset, block) = block.map_err(read_error)?;
+                            *offset = block_offset;
+                            process_chunk(&mut data, &block, block_offset, ctx, i)?;
+                            ctx.memory.check()?;
+                        }
+                        Ok(())
+                    });
+                    if result.is_err() && !config.best_effort {
+                        ctx.cancelled.store(true, Ordering::Relaxed);
+                    }
+                    result.map(|()| data)
+                })
+            })
+            .collect();
+        handles.into_iter().map(|h| h.join().unwrap()).collect()
+    });
+    let thread_datas: Vec<ThreadData> = worker::collect(results, config.best_effort)?;
+    Ok((
+        thread_datas.iter().map(|data| data.lines).sum(),
+        thread_datas,
+    ))
+}
+
+/// Below this many maps they are merged into the largest one on the
+/// calling thread, as starting threads would take longer than the merge.
+const TREE_REDUCE_MAPS: usize = 16;
+
+/// Merges the workers' maps. From [`TREE_REDUCE_MAPS`] maps on, they are
+/// merged pairwise by a pool of at most `pool` threads, which take the
+/// pairs of a round in turn, so that merging takes log2(N) rounds rather
+/// than N without starting a thread per pair. Pairs are kept in worker
+/// order, so the sums are added up the same way on every run.
+fn reduce(mut maps: Vec<HashMap<Key, Stat>>, pool: usize) -> HashMap<Key, Stat> {
+    if maps.len() < TREE_REDUCE_MAPS {
+        let largest = (0..maps.len()).max_by_key(|&i| maps[i].len());
+        let Some(mut into) = largest.map(|i| maps.swap_remove(i)) else {
+            return HashMap::default();
+        };
+        maps.into_iter()
+            .for_each(|from| merge_maps(&mut into, from));
+        return into;
+    }
+    while maps.len() > 1 {
+        let odd = (maps.len() % 2 == 1).then(|| maps.pop().expect("an odd map"));
+        let mut pairs = vec![];
+        while let (Some(b), Some(a)) = (maps.pop(), maps.pop()) {
+            pairs.push(Mutex::new(Some((a, b))));
+        }
+        pairs.reverse();
+        let next = AtomicUsize::new(0);
+        let mut merged: Vec<(usize, HashMap<Key, Stat>)> = std::thread::scope(|scope| {
+            let handles: Vec<_> = (0..pool.clamp(1, pairs.len()))
+                .map(|_| {
+                    scope.spawn(|| {
+                        let mut done = vec![];
+                        loop {
+                            let i = next.fetch_add(1, Ordering::Relaxed);
+                            let Some(pair) = pairs.get(i) else {
+                                return done;
+                            };
+                            let (a, b) = pair.lock().unwrap().take().expect("a pair");
+                            // Into the larger map, with fewer inserts
+                            let (mut into, from) = if a.len() >= b.len() { (a, b) } else { (b, a) };
+                            merge_maps(&mut into, from);
+                            done.push((i, into));
+                        }
+                    })
+                })
+                .collect();
+            handles
+                .into_iter()
+                .flat_map(|h| h.join().unwrap())
+                .collect()
+        });
+        merged.sort_unstable_by_key(|&(i, _)| i);
+        maps = merged.into_iter().map(|(_, map)| map).collect();
+        maps.extend(odd);
+    }
+    maps.pop().unwrap_or_default()
+}
+
+fn merge_maps(into: &mut HashMap<Key, Stat>, from: HashMap<Key, Stat>) {
+    for (key, stat) in from {
+        match into.get_mut(&key) {
+            Some(merged) => merged.merge(&stat),
+            None => {
+                into.insert(key, stat);
+            }
+        }
+    }
+}
+
+fn new_thread_data(config: &Config, worker: usize, spill_limit: Option<usize>) -> ThreadData {
+    ThreadData {
+        spill: spill_limit.map(|limit| Spiller::new(config.spill_dir.clone(), worker, limit)),
+        provenance: config.debug_provenance.then(|| Provenance::new(worker)),
+        prefix: (config.strategy == Strategy::PrefixTable).then(PrefixTable::default),
+        stat: HashMap::with_capacity(map_capacity(config)),
+        seen: config.detect_duplicates.then(Seen::default),
+        ..Default::default()
+    }
+}
+
+/// Room for the stations `--expected-stations` says there will be, and an
+/// eighth more, so that an estimate a little short does not rehash a map
+/// that is then almost full.
+fn map_capacity(config: &Config) -> usize {
+    config.expected_stations.map_or(0, |n| n + n / 8)
+}
+
+fn print_stats(
+    memory: &MemoryTracker,
+    spill_files: usize,
+    config: &Config,
+    nodes: &[Node],
+    num_threads: usize,
+    stations: usize,
+    counts: Option<perf::Counts>,
+) {
+    eprintln!("Stats:");
+    eprintln!("  executor: {}", config.executor.name());
+    eprintln!("  strategy: {}", config.strategy.name());
+    if config.strategy == Strategy::PrefixTable {
+        let (taken, same_prefix) = prefix::collisions();
+        eprintln!(
+            "  prefix table collisions: {} ({} with the same prefix, all resolved by the map)",
+            taken, same_prefix
+        );
+    }
+    eprintln!("  workers: {}", num_threads);
+    eprintln!("  scanner: {}", simd::name());
+    if config.numa == Numa::Auto {
+        eprintln!("  NUMA nodes: {}", numa::describe(nodes));
+    }
+    match memory::peak_rss() {
+        Some(rss) => eprintln!("  peak RSS: {}", memory::format_bytes(rss)),
+        None => eprintln!("  peak RSS: unavailable"),
+    }
+    eprintln!(
+        "  peak map memory: {}",
+        memory::format_bytes(memory.maps.peak())
+    );
+    eprintln!(
+        "  peak queued chunks: {}",
+        memory::format_bytes(memory.queues.peak())
+    );
+    eprintln!(
+        "  read buffers: {}",
+        memory::format_bytes(memory.buffers.peak())
+    );
+    eprintln!("  spill files: {}", spill_files);
+    // What --expected-stations would be for a run like this one
+    eprintln!("  keys: {}", stations);
+    eprintln!("  warnings: {}", WARNINGS.count());
+    for line in counts.iter().flat_map(perf::Counts::lines) {
+        eprintln!("  {}", line);
+    }
+}
+
+// Function to process each chunk of lines, which starts at byte `offset`
+fn process_chunk(
+    data: &mut ThreadData,
+    chunk: &str,
+    offset: u64,
+    ctx: &Context,
+    thread_id: usize,
+) -> anyhow::Result<()> {
+    if let Some(provenance) = &mut data.provenance {
+        provenance.start_chunk(offset);
+    }
+    let lines_before = data.lines;
+    /*
+    println!(
+        "Thread {} processing chunk of {} lines",
+        thread_id,
+        chunk.len()
+    );
+    */
+    // Each line with the byte it starts at in the input, which for UTF-16
+    // is not where it is in the transcoded block
+    let utf16 = ctx.config.encoding == Encoding::Utf16Le;
+    let lines = chunk.split_inclusive('\n').scan(offset, move |next, line| {
+        let at = *next;
+        *next += match utf16 {
+            true => 2 * line.encode_utf16().count() as u64,
+            false => line.len() as u64,
+        };
+        let line = match line.strip_suffix('\n') {
+            Some(line) => line.strip_suffix('\r').unwrap_or(line),
+            None => line,
+        };
+        Some((at, line))
+    });
+    if let Some(seen) = &mut data.seen {
+        lines.clone().for_each(|(_, line)| seen.add(line));
+    }
+    if ctx.config.strategy == Strategy::Vectorized {
+        batch::process(data, lines, ctx, thread_id)?;
+    } else {
+        let mut json = jsonl::Parser::default();
+        // In strict CSV mode a quoted field may span lines, so those are
+        // joined back into one record. Blocks end outside of quoted fields
+        let mut pending = String::new();
+        let mut pending_at = 0;
+        for (at, line) in lines {
+            data.lines += 1;
+            if ctx.config.csv_strict && (!pending.is_empty() || csv::is_open(line)) {
+                if pending.is_empty() {
+                    pending_at = at;
+                } else {
+                    pending.push('\n');
+                }
+                pending.push_str(line);
+                if csv::is_open(&pending) {
+                    continue;
+                }
+                let record = std::mem::take(&mut pending);
+                process_line(data, &record, pending_at, ctx, &mut json, thread_id)?;
+            } else {
+                process_line(data, line, at, ctx, &mut json, thread_id)?;
+            }
+        }
+        if !pending.is_empty() {
+            let cause = anyhow::anyhow!("unterminated quoted field at end of input");
+            return Err(row_error(ctx, pending_at, &pending, cause));
+        }
+    }
+    if let Some(export) = &ctx.sinks.export {
+        export.write(offset, &data.export)?;
+        data.export.clear();
+    }
+    if let Some(throttle) = &ctx.throttle {
+        throttle.wait(
+            data.lines - lines_before,
+            chunk.len() as u64,
+            &ctx.cancelled,
+        );
+    }
+    Ok(())
+}
+
+/// Fails on a record longer than `--max-line-bytes`. Reading gives up on
+/// a line that has not ended within that many bytes, but may let one of up
+/// to twice as many through, so the error is the same read error.
+fn check_length(ctx: &Context, line: &str, at: u64) -> anyhow::Result<()> {
+    match line.len() > ctx.config.max_line_bytes {
+        true => {
+            let e = input::too_long(at, ctx.config.max_line_bytes);
+            Err(worker::ReadError::new(&ctx.config, e).into())
+        }
+        false => Ok(()),
+    }
+}
+
+/// `cause`, which the record `line` at byte `at` cannot be taken for,
+/// as a [`RowError`](worker::RowError). The line it is on is counted from
+/// the start of the input.
+fn row_error(ctx: &Context, at: u64, line: &str, cause: anyhow::Error) -> anyhow::Error {
+    let number = input::line_numbers(&ctx.config.input, ctx.config.encoding, &[at])
+        .ok()
+        .map(|lines| lines[0]);
+    worker::RowError::new(at, number, line, cause).into()
+}
+
+/// Whether `--dedupe` leaves `line` out, as a repeat of one before it.
+fn is_repeat(data: &mut ThreadData, ctx: &Context, line: &str) -> bool {
+    match &ctx.dedupe {
+        Some(bloom) if !line.is_empty() && !bloom.insert(line.as_bytes()) => {
+            data.deduped += 1;
+            true
+        }
+        _ => false,
+    }
+}
+
+/// Parses one record, which starts at byte `at`, and adds its reading to
+/// the worker's map.
+fn process_line(
+    data: &mut ThreadData,
+    line: &str,
+    at: u64,
+    ctx: &Context,
+    json: &mut jsonl::Parser,
+    thread_id: usize,
+) -> anyhow::Result<()> {
+    report_progress(data, line, ctx, thread_id);
+    check_length(ctx, line, at)?;
+    if is_repeat(data, ctx, line) {
+        return Ok(());
+    }
+    let config = &ctx.config;
+    let row = |e| row_error(ctx, at, line, e);
+    let parts: Vec<&str>;
+    let fields: Vec<Cow<str>>;
+    let (timestamp, station, value) = match config.input_format {
+        InputFormat::Csv if config.csv_strict => {
+            fields = csv::split_record(line).map_err(row)?;
+            if !config.value_columns.is_empty() {
+                let fields: Vec<&str> = fields.iter().map(AsRef::as_ref).collect();
+                return process_columns(data, line, at, &fields, ctx);
+            }
+            match (config.window, fields.as_slice()) {
+                (None, [station, value]) => (None, station.as_ref(), Some(value.as_ref())),
+                (Some(_), [timestamp, station, value]) => (
+                    Some(timestamp.as_ref()),
+                    station.as_ref(),
+                    Some(value.as_ref()),
+                ),
+                _ => return Ok(()),
+            }
+        }
+        InputFormat::Csv => {
+            parts = simd::split(line, csv::DELIMITER as u8).collect();
+            if !config.value_columns.is_empty() {
+                return process_columns(data, line, at, &parts, ctx);
+            }
+            match (config.window, parts.as_slice()) {
+                (None, &[station, value]) => (None, station, Some(value)),
+                (Some(_), &[timestamp, station, value]) => (Some(timestamp), station, Some(value)),
+                _ => return Ok(()),
+            }
+        }
+        InputFormat::Jsonl => {
+            if line.trim().is_empty() {
+                return Ok(());
+            }
+            let record = json.parse(line, &config.json_fields).map_err(row)?;
+            match (record.station, record.value, record.timestamp) {
+                (Some(station), value, timestamp)
+                    if config.window.is_none() || timestamp.is_some() =>
+                {
+                    (timestamp, station, value)
+                }
+                _ => return Ok(()),
+            }
+        }
+    };
+    let window = window_of(config, timestamp).map_err(row)?;
+    let name = ctx.keys.apply(station);
+    if !config.stations.is_empty() && !config.stations.iter().any(|s| *s == name) {
+        return Ok(());
+    }
+    let Some(value) = reading(data, config, &name, value).map_err(row)? else {
+        return Ok(());
+    };
+    write_row(data, ctx, line, &name)?;
+    // SAFETY: the lookup key is dropped before `name`
+    add_reading(
+        data,
+        ctx,
+        unsafe { Key::transient(&name, window, None) },
+        value,
+        at,
+    )
+}
+
+/// A row of `--value-columns`, split into `fields`, each of whose readings
+/// is aggregated under its column. The row counts as filtered out only if
+/// none of them is aggregated.
+fn process_columns(
+    data: &mut ThreadData,
+    line: &str,
+    at: u64,
+    fields: &[&str],
+    ctx: &Context,
+) -> anyhow::Result<()> {
+    let config = &ctx.config;
+    let row = |e| row_error(ctx, at, line, e);
+    let station = usize::from(config.window.is_some());
+    let last = config.value_columns.iter().max().copied().unwrap_or(0);
+    if fields.len() < usize::from(last) {
+        return Ok(());
+    }
+    let window = window_of(config, config.window.and(fields.first().copied())).map_err(row)?;
+    let name = ctx.keys.apply(fields[station]);
+    if !config.stations.is_empty() && !config.stations.iter().any(|s| *s == name) {
+        return Ok(());
+    }
+    let mut written = false;
+    for &column in &config.value_columns {
+        let value = fields[usize::from(column) - 1];
+        let Some(value) = reading(data, config, &name, Some(value)).map_err(row)? else {
+            continue;
+        };
+        if !written {
+            write_row(data, ctx, line, &name)?;
+            written = true;
+        }
+        // SAFETY: the lookup key is dropped before `name`
+        let lookup = unsafe { Key::transient(&name, window, Some(column)) };
+        add_reading(data, ctx, lookup, value, at)?;
+    }
+    Ok(())
+}
+
+/// Hands the bytes of `line` over to the dashboard, if there is one.
+fn report_progress(data: &mut ThreadData, line: &str, ctx: &Context, thread_id: usize) {
+    if let Some(progress) = &ctx.progress {
+        data.unreported += line.len() as u64 + 1;
+        if data.unreported >= dashboard::REPORT_BYTES {
+            progress.report(thread_id, data);
+        }
+    }
+}
+
+/// The start of the `--window` that `timestamp` falls in.
+fn window_of(config: &Config, timestamp: Option<&str>) -> anyhow::Result<Option<i64>> {
+    Ok(match (config.window, timestamp) {
+        (Some(width), Some(timestamp)) => {
+            let ts = time::parse_timestamp(timestamp.trim())?;
+            Some(ts.div_euclid(width) * width)
+        }
+        _ => None,
+    })
+}
+
+/// The reading of `name` in the output unit, or `None` for a missing or
+/// excluded one, which is counted instead.
+fn reading(
+    data: &mut ThreadData,
+    config: &Config,
+    name: &str,
+    value: Option<&str>,
+) -> anyhow::Result<Option<f64>> {
+    // Counting and listing only need the key, so the reading is not even
+    // looked at
+    if config.mode != Mode::Aggregate {
+        return Ok(Some(0.0));
+    }
+    let value = match value.map(str::trim) {
+        Some(value) if !config.null_values.iter().any(|n| n == value) => value,
+        // A configured null value or a JSON `null`
+        _ => {
+            if config.null_policy == NullPolicy::Count {
+                *data.missing.entry(name.to_string()).or_default() += 1;
+            }
+            return Ok(None);
+        }
+    };
+    let value = if config.strict_1brc {
+        parse::parse_1brc(value)?
+    } else {
+        parse::parse_value(value, config.lenient_numbers)?
+    };
+    let value = config.input_unit.convert(value, config.output_unit());
+    if !config.range.contains(value) {
+        data.excluded += 1;
+        return Ok(None);
+    }
+    Ok(Some(value))
+}
+
+/// Copies `line` to `--export-rows` and `--partition-rows`, if set.
+fn write_row(data: &mut ThreadData, ctx: &Context, line: &str, name: &str) -> anyhow::Result<()> {
+    if let Some(export) = &ctx.sinks.export {
+        data.export.push_str(line);
+        data.export.push('\n');
+        // Kept whole until the end of the chunk to be written in order
+        if !export.preserves_order() && data.export.len() >= partition::ROW_BUFFER {
+            export.write(0, &data.export)?;
+            data.export.clear();
+        }
+    }
+    if let Some(rows) = &ctx.sinks.partitions {
+        let part = partition::of(ctx.config.partition_by.expect("partitioned output"), name);
+        let buffered = data.rows.entry(part.clone()).or_default();
+        buffered.push_str(line);
+        buffered.push('\n');
+        if buffered.len() >= partition::ROW_BUFFER {
+            rows.write(&part, buffered)?;
+            buffered.clear();
+        }
+    }
+    Ok(())
+}
+
+/// Adds `value`, from the row at byte `at`, to the stat of `lookup`, which
+/// may borrow its name from the line and is only ever stored as a
+/// [`Key::stored`] copy.
+fn add_reading(
+    data: &mut ThreadData,
+    ctx: &Context,
+    lookup: Key,
+    value: f64,
+    at: u64,
+) -> anyhow::Result<()> {
+    if ctx.tracking.any() {
+        agg::track(
+            &mut data.extras,
+            ctx.tracking,
+            lookup,
+            value,
+            at,
+            &mut data.arena,
+        );
+    }
+    if let Some(shared) = &ctx.shared {
+        if let Some(size) = shared.add(lookup, value) {
+            ctx.memory.maps.add(size);
+        }
+        return Ok(());
+    }
+    if let Some(prefix) = &mut data.prefix {
+        let key = match prefix.add(lookup, value, &mut data.arena) {
+            Added::Existing(key) => key,
+            Added::New(key, size) => {
+                ctx.memory.maps.add(size);
+                key
+            }
+            Added::Taken => return add_to_map(data, ctx, lookup, value),
+        };
+        if let Some(provenance) = &mut data.provenance {
+            provenance.record(key);
+        }
+        return Ok(());
+    }
+    add_to_map(data, ctx, lookup, value)
+}
+
+/// [`add_reading`] into the worker's map.
+fn add_to_map(data: &mut ThreadData, ctx: &Context, lookup: Key, value: f64) -> anyhow::Result<()> {
+    if let Some(stat) = data.stat.get_mut(&lookup) {
+        stat.add(value);
+        if let Some(provenance) = &mut data.provenance {
+            let (key, _) = data.stat.get_key_value(&lookup).expect("just updated");
+            provenance.record(*key);
+        }
+        return Ok(());
+    }
+
+    // Only new keys copy their name, into the worker's arena
+    let key = lookup.stored(&mut data.arena);
+    let size = spill::entry_size(&key);
+    data.stat.insert(key, Stat::new(value));
+    if let Some(provenance) = &mut data.provenance {
+        provenance.record(key);
+    }
+    data.map_bytes += size;
+    ctx.memory.maps.add(size);
+    if let Some(spill) = &mut data.spill
+        && spill.maybe_spill(&mut data.stat, data.map_bytes)?
+    {
+        ctx.memory.maps.sub(data.map_bytes);
+        data.map_bytes = 0;
+    }
+    Ok(())
+}
+
+/// The `1brc` command line, which the binary runs with its arguments.
+pub fn main() {
+    let mut config = match cli::parse_args(std::env::args().skip(1)) {
+        Ok(Command::Run(config)) => *config,
+        Ok(Command::Diff(args)) => match diff::run(&args) {
+            Ok(differ) => std::process::exit(i32::from(differ)),
+            Err(e) => {
+                eprintln!("error: {:#}", e);
+                std::process::exit(2);
+            }
+        },
+        Ok(Command::Generate(args)) => match generate::run(&args) {
+            Ok(()) => return,
+            Err(e) => {
+                eprintln!("error: {:#}", e);
+                std::process::exit(1);
+            }
+        },
+        Ok(Command::Completions(shell)) => {
+            print!("{}", completions::script(shell));
+            return;
+        }
+        Ok(Command::Man) => {
+            print!("{}", completions::man());
+            return;
+        }
+        Ok(Command::Help) => {
+            print!("{}", cli::usage());
+            return;
+        }
+        Ok(Command::Version) => {
+            for line in version::lines() {
+                println!("{}", line);
+            }
+            return;
+        }
+        Err(e) => {
+            eprintln!("error: {:#}", e);
+            std::process::exit(2);
+        }
+    };
+    simd::init(config.force_scalar);
+    if let Err(e) = priority::lower(&config) {
+        eprintln!("error: {:#}", e);
+        std::process::exit(2);
+    }
+    if let Some(timeout) = config.timeout {
+        timeout::start(timeout);
+    }
+
+    // Size the defaults to the container rather than the whole machine
+    let limits = cgroup::Limits::detect();
+    let num_threads = config.threads.unwrap_or_else(|| limits.parallelism());
+    if config.spill_budget.is_none() && config.max_memory.is_none() && config.strategy.spills() {
+        config.spill_budget = limits.memory.map(|limit| limit / 2);
+    }
+
+    if config.validate {
+        match validate::run(&config) {
+            Ok(invalid) => std::process::exit(i32::from(invalid)),
+            Err(e) => {
+                eprintln!("error: {:#}", e);
+                std::process::exit(2);
+            }
+        }
+    }
+    if config.estimate_rows {
+        if let Err(e) = estimate_rows(&config) {
+            eprintln!("Error reading file: {}", e);
+            exit_failed(&e);
+        }
+        return;
+    }
+    if config.repl {
+        if let Err(e) = repl::run(config, num_threads) {
+            eprintln!("Error reading file: {}", e);
+            exit_failed(&e);
+        }
+        return;
+    }
+    if let Some(dir) = config.watch_dir.clone() {
+        if let Err(e) = watch::run(config, &dir, num_threads) {
+            eprintln!("Error watching {}: {}", dir.display(), e);
+            exit_failed(&e);
+        }
+        return;
+    }
+    let quiet = config.verbosity == Verbosity::Quiet;
+    match read_file_in_chunks(Arc::new(config), num_threads) {
+        Ok(_) if !quiet => eprintln!("File processed successfully"),
+        Ok(_) => {}
+        Err(e) => {
+            eprintln!("Error reading file: {}", e);
+            exit_failed(&e);
+        }
+    }
+}
+
+/// Exits after a failed run with 1, or as `timeout(1)` does if `e` is that
+/// of a run `--timeout` cut short.
+fn exit_failed(e: &anyhow::Error) -> ! {
+    match e.chain().any(|cause| cause.is::<TimedOut>()) {
+        true => std::process::exit(timeout::EXIT_STATUS),
+        false => std::process::exit(1),
+    }
+}
@@ -0,0 +1,299 @@
+//! `--manifest` and `--verify-manifest`: a record of a run that lets its
+//! results be audited and a rerun be confirmed to have produced the same.
+//! It holds the build, the options that shape the results, a SHA-256 hash
+//! of each input and the byte ranges it was handed out in, one of the
+//! results and how many lines, readings and keys they came to, zeros
+//! included. Hashes are written with the name of their algorithm, as in
+//! `sha256:` and the digest in hex.
+//!
+//! Verifying compares what says whether the results can be trusted to be
+//! the same: the build, each of the options, the inputs and the results.
+//! How the run was executed is recorded but may differ, as the results do
+//! not depend on it.
+
+use crate::agg::Agg;
+use crate::cli::Config;
+use crate::format::json_string;
+use crate::jsonl;
+use crate::sha256::{self, Digest, Sha256};
+use crate::{Key, StatResult, cache};
+use anyhow::Context;
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::fs::{self, File};
+use std::io::Read;
+use std::ops::Range;
+use std::path::Path;
+
+/// A byte range of an input, and the worker or reader it was handed to.
+/// Each takes the lines that start within its range.
+#[derive(Clone, Debug)]
+pub struct Assigned {
+    pub to: String,
+    pub range: Range<u64>,
+}
+
+/// What the results were made of, recorded but not verified, as the
+/// result hash covers them.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Counts {
+    pub lines: u64,
+    /// Readings aggregated
+    pub readings: u64,
+    /// Rows outside the value range
+    pub excluded: u64,
+    /// Rows without a reading
+    pub missing: u64,
+    pub keys: usize,
+}
+
+pub struct Manifest {
+    version: String,
+    /// By name, with their values in JSON
+    options: Vec<(&'static str, String)>,
+    execution: Vec<(&'static str, String)>,
+    counts: Counts,
+    inputs: Vec<Input>,
+    /// Of the input hashes, in order
+    input_hash: Digest,
+    result_hash: Digest,
+}
+
+struct Input {
+    path: String,
+    bytes: u64,
+    hash: Digest,
+    /// Empty for results from `--cache-dir`
+    ranges: Vec<Assigned>,
+}
+
+impl Manifest {
+    /// Describes a run over `inputs`, each with the ranges it was split
+    /// into, that came up with `results`.
+    pub fn new(
+        config: &Config,
+        workers: usize,
+        inputs: Vec<(String, Vec<Assigned>)>,
+        results: &HashMap<Key, StatResult>,
+        counts: Counts,
+    ) -> anyhow::Result<Self> {
+        let mut input_hash = Sha256::default();
+        let mut hashed = vec![];
+        for (path, ranges) in inputs {
+            let (bytes, hash) =
+                hash_file(Path::new(&path)).with_context(|| format!("failed to hash {}", path))?;
+            input_hash.update(&hash);
+            hashed.push(Input {
+                path,
+                bytes,
+                hash,
+                ranges,
+            });
+        }
+        let aggs: Vec<String> = config
+            .agg
+            .iter()
+            .map(|agg| json_string(&agg.name()))
+            .collect();
+        let columns: Vec<String> = config.value_columns.iter().map(u16::to_string).collect();
+        let mut options = cache::aggregation_options(config);
+        options.push(("agg", format!("[{}]", aggs.join(", "))));
+        options.push(("value_columns", format!("[{}]", columns.join(", "))));
+        Ok(Manifest {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            options,
+            execution: vec![
+                ("executor", json_string(config.executor.name())),
+                ("strategy", json_string(config.strategy.name())),
+                ("workers", workers.to_string()),
+                ("readers", config.readers.to_string()),
+                ("io", json_string(config.io.name())),
+                ("chunk_bytes", config.chunk_bytes.to_string()),
+            ],
+            counts,
+            inputs: hashed,
+            input_hash: input_hash.finish(),
+            result_hash: hash_results(config, results),
+        })
+    }
+
+    pub fn write(&self, path: &Path) -> anyhow::Result<()> {
+        fs::write(path, self.to_json())
+            .with_context(|| format!("failed to write manifest {}", path.display()))
+    }
+
+    /// Every verified field that differs from the manifest at `path`, each
+    /// option on its own.
+    pub fn verify(&self, path: &Path) -> anyhow::Result<Vec<String>> {
+        let text = fs::read_to_string(path)
+            .with_context(|| format!("failed to read manifest {}", path.display()))?;
+        let parse = || format!("failed to parse manifest {}", path.display());
+        let expected = jsonl::raw_fields(&text).with_context(parse)?;
+        let options = match field(&expected, "options") {
+            Some(options) => Some(jsonl::raw_fields(options).with_context(parse)?),
+            None => None,
+        };
+        let mut mismatches = vec![];
+        let mut compare = |name: &str, want: Option<&str>, got: &str| match want {
+            Some(want) if want == got => {}
+            Some(want) => mismatches.push(format!("{}: expected {}, got {}", name, want, got)),
+            None => mismatches.push(format!("{}: missing from the manifest", name)),
+        };
+        compare(
+            "version",
+            field(&expected, "version"),
+            &json_string(&self.version),
+        );
+        match &options {
+            Some(options) => {
+                for (option, got) in &self.options {
+                    compare(&format!("options.{}", option), field(options, option), got);
+                }
+            }
+            None => compare("options", None, ""),
+        }
+        compare(
+            "input_hash",
+            field(&expected, "input_hash"),
+            &json_string(&hash_name(&self.input_hash)),
+        );
+        compare(
+            "result_hash",
+            field(&expected, "result_hash"),
+            &json_string(&hash_name(&self.result_hash)),
+        );
+        Ok(mismatches)
+    }
+
+    fn to_json(&self) -> String {
+        let object = |fields: &[(&str, String)]| {
+            let fields: Vec<String> = fields
+                .iter()
+                .map(|(name, value)| format!("\"{}\": {}", name, value))
+                .collect();
+            format!("{{{}}}", fields.join(", "))
+        };
+        let mut out = String::from("{\n");
+        let _ = writeln!(out, "  \"version\": {},", json_string(&self.version));
+        let _ = writeln!(out, "  \"options\": {},", object(&self.options));
+        let _ = writeln!(out, "  \"execution\": {},", object(&self.execution));
+        for (name, hash) in [
+            ("input_hash", &self.input_hash),
+            ("result_hash", &self.result_hash),
+        ] {
+            let _ = writeln!(out, "  \"{}\": \"{}\",", name, hash_name(hash));
+        }
+        let Counts {
+            lines,
+            readings,
+            excluded,
+            missing,
+            keys,
+        } = self.counts;
+        let _ = writeln!(
+            out,
+            "  \"counts\": {{\"lines\": {}, \"readings\": {}, \"excluded\": {}, \"missing\": {}, \"keys\": {}}},",
+            lines, readings, excluded, missing, keys
+        );
+        out.push_str("  \"inputs\": [");
+        for (i, input) in self.inputs.iter().enumerate() {
+            out.push_str(if i == 0 { "\n" } else { ",\n" });
+            let _ = write!(
+                out,
+                "    {{\"path\": {}, \"bytes\": {}, \"hash\": \"{}\", \"ranges\": [",
+                json_string(&input.path),
+                input.bytes,
+                hash_name(&input.hash)
+            );
+            for (j, assigned) in input.ranges.iter().enumerate() {
+                if j > 0 {
+                    out.push_str(", ");
+                }
+                let _ = write!(
+                    out,
+                    "{{\"to\": {}, \"start\": {}, \"end\": {}}}",
+                    json_string(&assigned.to),
+                    assigned.range.start,
+                    assigned.range.end
+                );
+            }
+            out.push_str("]}");
+        }
+        out.push_str("\n  ]\n}\n");
+        out
+    }
+}
+
+/// The source text of the field `name` of an object.
+fn field<'a>(fields: &[(String, &'a str)], name: &str) -> Option<&'a str> {
+    fields
+        .iter()
+        .find(|(field, _)| field == name)
+        .map(|(_, value)| *value)
+}
+
+/// A digest as written, after the name of its algorithm.
+fn hash_name(digest: &Digest) -> String {
+    format!("sha256:{}", sha256::to_hex(digest))
+}
+
+/// What the pipeline calls reader `reader` of node `node`. The workers
+/// behind the readers take whichever blocks come next, and several readers
+/// of a node share its range in interleaved stripes.
+pub fn reader(partitions: usize, node: usize, reader: usize) -> String {
+    match partitions {
+        1 => format!("reader {}", reader),
+        _ => format!("node {} reader {}", node, reader),
+    }
+}
+
+/// The size and SHA-256 hash of the whole contents of `path`.
+fn hash_file(path: &Path) -> std::io::Result<(u64, Digest)> {
+    let mut file = File::open(path)?;
+    let mut hash = Sha256::default();
+    let mut buf = vec![0; 1 << 16];
+    let mut bytes = 0;
+    loop {
+        let n = file.read(&mut buf)?;
+        if n == 0 {
+            return Ok((bytes, hash.finish()));
+        }
+        hash.update(&buf[..n]);
+        bytes += n as u64;
+    }
+}
+
+/// Hashes the keys in order with their counts, and their minimum, maximum
+/// and sum to a tenth, the default precision, whatever `--precision` is.
+/// The mean is left out, as the last bits of a sum added up in another
+/// order by more or fewer workers can tip how it rounds. A sum of readings
+/// with one decimal is far from that, but one of readings with more can
+/// land close enough to a twentieth to round either way too.
+fn hash_results(config: &Config, results: &HashMap<Key, StatResult>) -> Digest {
+    let tenths = |v: f64| (v * 10.0).round() as i64;
+    let mut keys: Vec<&Key> = results.keys().collect();
+    keys.sort();
+    let mut hash = Sha256::default();
+    for key in keys {
+        let result = &results[key];
+        let mut row = format!(
+            "{}\0{:?}\0{:?}\0{}\0{}\0{}\0{}",
+            key.name,
+            key.window,
+            key.column,
+            result.count,
+            tenths(result.min),
+            tenths(result.max),
+            tenths(result.sum)
+        );
+        for agg in &config.agg {
+            if let Agg::Stddev | Agg::Percentile(_) = agg {
+                // To a tenth as well, whatever `--precision` shows
+                let _ = write!(row, "\0{}", agg.format(result, 1));
+            }
+        }
+        row.push('\n');
+        hash.update(row.as_bytes());
+    }
+    hash.finish()
+}
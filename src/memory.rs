@@ -0,0 +1,127 @@
+//! Accounting of the memory held by the pipeline's main consumers, used to
+//! enforce `--max-memory` and to report usage under `--stats`.
+
+use anyhow::bail;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Condvar, Mutex};
+
+/// Current and peak bytes of one component.
+#[derive(Debug, Default)]
+pub struct Gauge {
+    current: AtomicUsize,
+    peak: AtomicUsize,
+}
+
+impl Gauge {
+    pub fn add(&self, n: usize) {
+        let now = self.current.fetch_add(n, Ordering::Relaxed) + n;
+        self.peak.fetch_max(now, Ordering::Relaxed);
+    }
+
+    pub fn sub(&self, n: usize) {
+        self.current.fetch_sub(n, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> usize {
+        self.current.load(Ordering::Relaxed)
+    }
+
+    pub fn peak(&self) -> usize {
+        self.peak.load(Ordering::Relaxed)
+    }
+}
+
+/// Estimated bytes held by the aggregation maps, the chunks queued for or
+/// being processed by workers, and the reader's buffers.
+#[derive(Debug, Default)]
+pub struct MemoryTracker {
+    limit: Option<usize>,
+    pub maps: Gauge,
+    pub queues: Gauge,
+    pub buffers: Gauge,
+}
+
+impl MemoryTracker {
+    pub fn new(limit: Option<usize>) -> Self {
+        MemoryTracker {
+            limit,
+            ..Default::default()
+        }
+    }
+
+    pub fn total(&self) -> usize {
+        self.maps.get() + self.queues.get() + self.buffers.get()
+    }
+
+    /// Fails once the tracked total goes over the limit.
+    pub fn check(&self) -> anyhow::Result<()> {
+        if let Some(limit) = self.limit
+            && self.total() > limit
+        {
+            bail!(
+                "memory limit of {} exceeded (maps {}, queues {}, buffers {})",
+                format_bytes(limit),
+                format_bytes(self.maps.get()),
+                format_bytes(self.queues.get()),
+                format_bytes(self.buffers.get())
+            );
+        }
+        Ok(())
+    }
+}
+
+/// A byte budget for chunks in flight between the reader and the workers.
+/// A chunk larger than the whole budget only waits for the queues to empty.
+#[derive(Debug)]
+pub struct QueueBudget {
+    capacity: usize,
+    used: Mutex<usize>,
+    freed: Condvar,
+}
+
+impl QueueBudget {
+    pub fn new(capacity: usize) -> Self {
+        QueueBudget {
+            capacity,
+            used: Mutex::new(0),
+            freed: Condvar::new(),
+        }
+    }
+
+    /// Blocks until `bytes` fit, then takes them.
+    pub fn acquire(&self, bytes: usize) {
+        let mut used = self.used.lock().unwrap();
+        while *used > 0 && *used + bytes > self.capacity {
+            used = self.freed.wait(used).unwrap();
+        }
+        *used += bytes;
+    }
+
+    pub fn release(&self, bytes: usize) {
+        *self.used.lock().unwrap() -= bytes;
+        self.freed.notify_all();
+    }
+}
+
+/// Peak resident set size of this process, where the platform reports it.
+pub fn peak_rss() -> Option<usize> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|l| l.starts_with("VmHWM:"))?;
+    let kb: usize = line.split_whitespace().nth(1)?.parse().ok()?;
+    Some(kb * 1024)
+}
+
+pub fn format_bytes(n: usize) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut value = n as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} B", n)
+    } else {
+        format!("{:.1} {}", value, UNITS[unit])
+    }
+}
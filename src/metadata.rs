@@ -0,0 +1,58 @@
+use crate::cli::KeyNormalization;
+use crate::keys::normalize_key;
+use anyhow::{Context, bail};
+use std::collections::HashMap;
+use std::fs;
+
+#[derive(Clone, Debug)]
+pub struct StationInfo {
+    pub country: String,
+    pub lat: f64,
+    pub lon: f64,
+}
+
+/// Per-station metadata, keyed by normalized station name.
+#[derive(Debug, Default)]
+pub struct Metadata {
+    stations: HashMap<String, StationInfo>,
+}
+
+impl Metadata {
+    /// Reads `station;country;lat;lon` lines, skipping blank lines and `#`
+    /// comments.
+    pub fn load(path: &str, normalization: KeyNormalization) -> anyhow::Result<Self> {
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("failed to read metadata {}", path))?;
+        let mut stations = HashMap::new();
+        for (i, line) in content.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let parts: Vec<&str> = line.split(';').collect();
+            let [station, country, lat, lon] = parts[..] else {
+                bail!("{}:{}: expected `station;country;lat;lon`", path, i + 1);
+            };
+            let (Ok(lat), Ok(lon)) = (lat.trim().parse(), lon.trim().parse()) else {
+                bail!("{}:{}: invalid coordinates", path, i + 1);
+            };
+            let info = StationInfo {
+                country: country.to_string(),
+                lat,
+                lon,
+            };
+            stations.insert(normalize_key(normalization, station).into_owned(), info);
+        }
+        Ok(Metadata { stations })
+    }
+
+    pub fn get(&self, station: &str) -> Option<&StationInfo> {
+        self.stations.get(station)
+    }
+
+    pub fn countries(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.stations
+            .iter()
+            .map(|(station, info)| (station.as_str(), info.country.as_str()))
+    }
+}
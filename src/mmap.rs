@@ -0,0 +1,366 @@
+//! Read-only memory mapping of the input file for `--io mmap`, with the
+//! `madvise` hints selected through `--madvise`. On Windows the file is
+//! mapped as a view of a file mapping object, where only `willneed` has
+//! an equivalent and the other hints are ignored.
+
+use crate::simd;
+use std::fs::File;
+use std::io;
+use std::ops::Deref;
+use std::sync::Arc;
+
+/// A kernel hint about how the mapping will be accessed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Advice {
+    /// Pages are read in order, so read ahead aggressively and drop them soon
+    Sequential,
+    /// Start reading the whole file in right away
+    WillNeed,
+    /// Align the mapping to 2 MiB and ask for transparent huge pages, which
+    /// cuts the number of page faults on first touch
+    HugePage,
+}
+
+impl Advice {
+    pub fn parse(v: &str) -> anyhow::Result<Self> {
+        match v {
+            "sequential" => Ok(Advice::Sequential),
+            "willneed" => Ok(Advice::WillNeed),
+            "hugepage" => Ok(Advice::HugePage),
+            _ => anyhow::bail!(
+                "expected `sequential`, `willneed` or `hugepage`, got `{}`",
+                v
+            ),
+        }
+    }
+}
+
+pub struct Mmap {
+    ptr: *mut u8,
+    len: usize,
+    /// Start and length of the whole reservation, which is larger than the
+    /// file when the mapping was aligned for huge pages
+    region: (*mut u8, usize),
+}
+
+// The mapping is read-only and owned by the `Mmap`
+unsafe impl Send for Mmap {}
+unsafe impl Sync for Mmap {}
+
+#[cfg(target_os = "linux")]
+mod sys {
+    use std::ffi::{c_int, c_long, c_void};
+
+    pub const PROT_READ: c_int = 1;
+    pub const PROT_NONE: c_int = 0;
+    pub const MAP_PRIVATE: c_int = 0x02;
+    pub const MAP_FIXED: c_int = 0x10;
+    pub const MAP_ANONYMOUS: c_int = 0x20;
+    pub const MADV_SEQUENTIAL: c_int = 2;
+    pub const MADV_WILLNEED: c_int = 3;
+    pub const MADV_HUGEPAGE: c_int = 14;
+    pub const MAP_FAILED: *mut c_void = !0 as *mut c_void;
+
+    unsafe extern "C" {
+        pub fn mmap(
+            addr: *mut c_void,
+            len: usize,
+            prot: c_int,
+            flags: c_int,
+            fd: c_int,
+            offset: c_long,
+        ) -> *mut c_void;
+        pub fn munmap(addr: *mut c_void, len: usize) -> c_int;
+        pub fn madvise(addr: *mut c_void, len: usize, advice: c_int) -> c_int;
+    }
+}
+
+#[cfg(windows)]
+mod sys {
+    use std::ffi::c_void;
+
+    pub type Handle = *mut c_void;
+
+    pub const PAGE_READONLY: u32 = 0x02;
+    pub const FILE_MAP_READ: u32 = 0x04;
+
+    /// `WIN32_MEMORY_RANGE_ENTRY`
+    #[repr(C)]
+    pub struct MemoryRange {
+        pub address: *mut c_void,
+        pub size: usize,
+    }
+
+    #[link(name = "kernel32")]
+    unsafe extern "system" {
+        pub fn CreateFileMappingW(
+            file: Handle,
+            attributes: *mut c_void,
+            protect: u32,
+            size_high: u32,
+            size_low: u32,
+            name: *const u16,
+        ) -> Handle;
+        pub fn MapViewOfFile(
+            mapping: Handle,
+            access: u32,
+            offset_high: u32,
+            offset_low: u32,
+            len: usize,
+        ) -> *mut c_void;
+        pub fn UnmapViewOfFile(address: *const c_void) -> i32;
+        pub fn CloseHandle(handle: Handle) -> i32;
+        pub fn GetCurrentProcess() -> Handle;
+        pub fn PrefetchVirtualMemory(
+            process: Handle,
+            entries: usize,
+            ranges: *const MemoryRange,
+            flags: u32,
+        ) -> i32;
+    }
+}
+
+#[cfg(target_os = "linux")]
+const HUGE_PAGE: usize = 2 << 20;
+
+impl Mmap {
+    #[cfg(target_os = "linux")]
+    pub fn map(file: &File, advice: &[Advice]) -> io::Result<Mmap> {
+        use std::ffi::c_void;
+        use std::os::fd::AsRawFd;
+
+        let len = file.metadata()?.len() as usize;
+        if len == 0 {
+            // Zero-length mappings are rejected, and there is nothing to map
+            return Ok(Mmap {
+                ptr: std::ptr::NonNull::dangling().as_ptr(),
+                len: 0,
+                region: (std::ptr::null_mut(), 0),
+            });
+        }
+
+        let huge = advice.contains(&Advice::HugePage);
+        let fd = file.as_raw_fd();
+        let (ptr, region) = unsafe {
+            if huge {
+                // Reserve enough address space to find a 2 MiB boundary in,
+                // then map the file over the reservation at that boundary
+                let size = len + HUGE_PAGE;
+                let reserved = sys::mmap(
+                    std::ptr::null_mut(),
+                    size,
+                    sys::PROT_NONE,
+                    sys::MAP_PRIVATE | sys::MAP_ANONYMOUS,
+                    -1,
+                    0,
+                );
+                if reserved == sys::MAP_FAILED {
+                    return Err(io::Error::last_os_error());
+                }
+                let aligned = (reserved as usize).next_multiple_of(HUGE_PAGE) as *mut c_void;
+                let ptr = sys::mmap(
+                    aligned,
+                    len,
+                    sys::PROT_READ,
+                    sys::MAP_PRIVATE | sys::MAP_FIXED,
+                    fd,
+                    0,
+                );
+                if ptr == sys::MAP_FAILED {
+                    let err = io::Error::last_os_error();
+                    sys::munmap(reserved, size);
+                    return Err(err);
+                }
+                (ptr, (reserved as *mut u8, size))
+            } else {
+                let ptr = sys::mmap(
+                    std::ptr::null_mut(),
+                    len,
+                    sys::PROT_READ,
+                    sys::MAP_PRIVATE,
+                    fd,
+                    0,
+                );
+                if ptr == sys::MAP_FAILED {
+                    return Err(io::Error::last_os_error());
+                }
+                (ptr, (ptr as *mut u8, len))
+            }
+        };
+        let map = Mmap {
+            ptr: ptr as *mut u8,
+            len,
+            region,
+        };
+
+        for &a in advice {
+            let flag = match a {
+                Advice::Sequential => sys::MADV_SEQUENTIAL,
+                Advice::WillNeed => sys::MADV_WILLNEED,
+                Advice::HugePage => sys::MADV_HUGEPAGE,
+            };
+            // SAFETY: the range is exactly the file mapping
+            if unsafe { sys::madvise(ptr, len, flag) } != 0 {
+                let err = io::Error::last_os_error();
+                // Kernels without THP for this file system refuse the hint,
+                // which only costs performance
+                if a != Advice::HugePage {
+                    return Err(err);
+                }
+            }
+        }
+        Ok(map)
+    }
+
+    #[cfg(windows)]
+    pub fn map(file: &File, advice: &[Advice]) -> io::Result<Mmap> {
+        use std::os::windows::io::AsRawHandle;
+
+        let len = usize::try_from(file.metadata()?.len())
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "file too large to map"))?;
+        if len == 0 {
+            // Empty files cannot be mapped either
+            return Ok(Mmap {
+                ptr: std::ptr::NonNull::dangling().as_ptr(),
+                len: 0,
+                region: (std::ptr::null_mut(), 0),
+            });
+        }
+
+        // SAFETY: the handle is the open file's, and a size of 0 maps all
+        // of it. The view keeps the mapping object alive once its handle
+        // is closed
+        let ptr = unsafe {
+            let mapping = sys::CreateFileMappingW(
+                file.as_raw_handle(),
+                std::ptr::null_mut(),
+                sys::PAGE_READONLY,
+                0,
+                0,
+                std::ptr::null(),
+            );
+            if mapping.is_null() {
+                return Err(io::Error::last_os_error());
+            }
+            let ptr = sys::MapViewOfFile(mapping, sys::FILE_MAP_READ, 0, 0, 0);
+            let err = io::Error::last_os_error();
+            sys::CloseHandle(mapping);
+            if ptr.is_null() {
+                return Err(err);
+            }
+            ptr
+        };
+        let map = Mmap {
+            ptr: ptr as *mut u8,
+            len,
+            region: (ptr as *mut u8, len),
+        };
+
+        if advice.contains(&Advice::WillNeed) {
+            let range = sys::MemoryRange {
+                address: ptr,
+                size: len,
+            };
+            // SAFETY: the range is exactly the view. Like the hint, failing
+            // only costs performance
+            unsafe {
+                sys::PrefetchVirtualMemory(sys::GetCurrentProcess(), 1, &range, 0);
+            }
+        }
+        Ok(map)
+    }
+
+    #[cfg(not(any(target_os = "linux", windows)))]
+    pub fn map(_file: &File, _advice: &[Advice]) -> io::Result<Mmap> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "--io mmap is only supported on Linux and Windows",
+        ))
+    }
+}
+
+impl Deref for Mmap {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        // SAFETY: `ptr` points to `len` mapped, readable bytes
+        unsafe { std::slice::from_raw_parts(self.ptr, self.len) }
+    }
+}
+
+impl Drop for Mmap {
+    fn drop(&mut self) {
+        #[cfg(target_os = "linux")]
+        if self.region.1 > 0 {
+            // SAFETY: the region was mapped by `map` and is no longer borrowed
+            unsafe {
+                sys::munmap(self.region.0 as *mut std::ffi::c_void, self.region.1);
+            }
+        }
+        #[cfg(windows)]
+        if self.region.1 > 0 {
+            // SAFETY: the view was mapped by `map` and is no longer borrowed
+            unsafe {
+                sys::UnmapViewOfFile(self.region.0 as *const std::ffi::c_void);
+            }
+        }
+    }
+}
+
+/// The lines starting in `start..end` of a shared mapping, without their
+/// terminators, as `BufRead::lines` would return them, along with the
+/// offsets they start at.
+pub struct Lines {
+    map: Arc<Mmap>,
+    pos: usize,
+    end: usize,
+}
+
+impl Lines {
+    /// A line that `start` falls inside of belongs to the previous range.
+    pub fn new(map: Arc<Mmap>, start: usize, end: usize) -> Lines {
+        let pos = if start == 0 {
+            0
+        } else {
+            match simd::find(&map[start - 1..], b'\n') {
+                Some(i) => start + i,
+                None => map.len(),
+            }
+        };
+        let end = end.min(map.len());
+        Lines { map, pos, end }
+    }
+
+    /// Offset of the next line.
+    pub fn position(&self) -> usize {
+        self.pos
+    }
+}
+
+impl Iterator for Lines {
+    type Item = io::Result<(u64, String)>;
+
+    fn next(&mut self) -> Option<io::Result<(u64, String)>> {
+        if self.pos >= self.end {
+            return None;
+        }
+        let offset = self.pos as u64;
+        let rest = &self.map[self.pos..];
+        let line = match simd::find(rest, b'\n') {
+            Some(end) => {
+                self.pos += end + 1;
+                let line = &rest[..end];
+                line.strip_suffix(b"\r").unwrap_or(line)
+            }
+            None => {
+                self.pos += rest.len();
+                rest
+            }
+        };
+        Some(
+            std::str::from_utf8(line)
+                .map(|line| (offset, line.to_string()))
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)),
+        )
+    }
+}
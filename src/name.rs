@@ -0,0 +1,119 @@
+//! Station names as they are held in map keys. Most are short enough to be
+//! kept inline in the key, so that comparing two of them on a lookup is a
+//! comparison of two small arrays rather than of what two pointers point
+//! at. Longer names stay in a worker's [`Arena`].
+
+use crate::arena::{self, Arena};
+use std::cmp::Ordering;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::ops::Deref;
+
+/// The longest name kept inline, which keeps a `Name` as small as the
+/// `&str` it replaces plus its discriminant
+pub const INLINE: usize = 22;
+
+#[derive(Clone, Copy)]
+pub struct Name(Repr);
+
+/// Every name of up to [`INLINE`] bytes is inline, padded with zeros, so
+/// that two equal names always have equal representations
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+enum Repr {
+    Inline { len: u8, bytes: [u8; INLINE] },
+    Heap(&'static str),
+}
+
+impl Name {
+    /// `s` as a name, copied into `arena` if it is too long to be inline.
+    pub fn new(s: &str, arena: &mut Arena) -> Self {
+        Name::inline(s).unwrap_or_else(|| Name(Repr::Heap(arena.alloc(s))))
+    }
+
+    /// `s` as a name for looking up a key, copying nothing but a short name.
+    ///
+    /// # Safety
+    ///
+    /// The result must not be stored anywhere that outlives `s`.
+    pub unsafe fn transient(s: &str) -> Self {
+        // SAFETY: upheld by the caller
+        Name::inline(s).unwrap_or_else(|| Name(Repr::Heap(unsafe { arena::transient(s) })))
+    }
+
+    fn inline(s: &str) -> Option<Self> {
+        if s.len() > INLINE {
+            return None;
+        }
+        let mut bytes = [0; INLINE];
+        bytes[..s.len()].copy_from_slice(s.as_bytes());
+        Some(Name(Repr::Inline {
+            len: s.len() as u8,
+            bytes,
+        }))
+    }
+
+    pub fn as_str(&self) -> &str {
+        match &self.0 {
+            // SAFETY: the bytes were copied from a `str`
+            Repr::Inline { len, bytes } => unsafe {
+                std::str::from_utf8_unchecked(&bytes[..*len as usize])
+            },
+            Repr::Heap(s) => s,
+        }
+    }
+
+    /// Bytes the name takes outside of the key.
+    pub fn heap_len(&self) -> usize {
+        match self.0 {
+            Repr::Inline { .. } => 0,
+            Repr::Heap(s) => s.len(),
+        }
+    }
+}
+
+impl Deref for Name {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl PartialEq for Name {
+    fn eq(&self, other: &Self) -> bool {
+        self.0 == other.0
+    }
+}
+
+impl Eq for Name {}
+
+impl Hash for Name {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.0.hash(state);
+    }
+}
+
+// Ordered as the names are, whatever their representation
+impl Ord for Name {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.as_str().cmp(other.as_str())
+    }
+}
+
+impl PartialOrd for Name {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl fmt::Debug for Name {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(self.as_str(), f)
+    }
+}
+
+impl fmt::Display for Name {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
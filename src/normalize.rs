@@ -0,0 +1,208 @@
+//! Unicode normalization forms NFC and NFKC, for merging station names
+//! that only differ in how they were encoded.
+
+use crate::unicode_tables::{
+    CANONICAL_DECOMPOSITION, COMBINING_CLASS, COMPATIBILITY_DECOMPOSITION, COMPOSITION,
+};
+use std::borrow::Cow;
+
+const S_BASE: u32 = 0xAC00;
+const L_BASE: u32 = 0x1100;
+const V_BASE: u32 = 0x1161;
+const T_BASE: u32 = 0x11A7;
+const L_COUNT: u32 = 19;
+const V_COUNT: u32 = 21;
+const T_COUNT: u32 = 28;
+const N_COUNT: u32 = V_COUNT * T_COUNT;
+const S_COUNT: u32 = L_COUNT * N_COUNT;
+
+pub fn nfc(s: &str) -> Cow<'_, str> {
+    normalize(s, false)
+}
+
+pub fn nfkc(s: &str) -> Cow<'_, str> {
+    normalize(s, true)
+}
+
+/// The compatibility decomposition of `s`, with marks in canonical order.
+pub fn nfkd(s: &str) -> Vec<u32> {
+    let mut chars = decompose(s, true);
+    reorder(&mut chars);
+    chars
+}
+
+/// Whether `c` is a combining mark rather than a starter.
+pub fn is_combining(c: u32) -> bool {
+    combining_class(c) != 0
+}
+
+fn normalize(s: &str, compat: bool) -> Cow<'_, str> {
+    if s.is_ascii() {
+        return Cow::Borrowed(s);
+    }
+    let mut chars = decompose(s, compat);
+    reorder(&mut chars);
+    let composed = compose(&chars);
+    if composed == s {
+        Cow::Borrowed(s)
+    } else {
+        Cow::Owned(composed)
+    }
+}
+
+fn combining_class(c: u32) -> u8 {
+    match COMBINING_CLASS.binary_search_by_key(&c, |&(cp, _)| cp) {
+        Ok(i) => COMBINING_CLASS[i].1,
+        Err(_) => 0,
+    }
+}
+
+fn lookup(table: &'static [(u32, &'static [u32])], c: u32) -> Option<&'static [u32]> {
+    table
+        .binary_search_by_key(&c, |&(cp, _)| cp)
+        .ok()
+        .map(|i| table[i].1)
+}
+
+fn decompose(s: &str, compat: bool) -> Vec<u32> {
+    let mut out = Vec::with_capacity(s.len());
+    for c in s.chars() {
+        let c = c as u32;
+        if (S_BASE..S_BASE + S_COUNT).contains(&c) {
+            let index = c - S_BASE;
+            out.push(L_BASE + index / N_COUNT);
+            out.push(V_BASE + (index % N_COUNT) / T_COUNT);
+            if !index.is_multiple_of(T_COUNT) {
+                out.push(T_BASE + index % T_COUNT);
+            }
+            continue;
+        }
+        let decomposition = if compat {
+            lookup(COMPATIBILITY_DECOMPOSITION, c).or_else(|| lookup(CANONICAL_DECOMPOSITION, c))
+        } else {
+            lookup(CANONICAL_DECOMPOSITION, c)
+        };
+        match decomposition {
+            Some(d) => out.extend_from_slice(d),
+            None => out.push(c),
+        }
+    }
+    out
+}
+
+/// Puts every run of non-starters into canonical order.
+fn reorder(chars: &mut [u32]) {
+    let mut start = 0;
+    while start < chars.len() {
+        if combining_class(chars[start]) == 0 {
+            start += 1;
+            continue;
+        }
+        let mut end = start + 1;
+        while end < chars.len() && combining_class(chars[end]) != 0 {
+            end += 1;
+        }
+        chars[start..end].sort_by_key(|&c| combining_class(c));
+        start = end;
+    }
+}
+
+fn compose_pair(a: u32, b: u32) -> Option<u32> {
+    if (L_BASE..L_BASE + L_COUNT).contains(&a) && (V_BASE..V_BASE + V_COUNT).contains(&b) {
+        return Some(S_BASE + ((a - L_BASE) * V_COUNT + (b - V_BASE)) * T_COUNT);
+    }
+    if (S_BASE..S_BASE + S_COUNT).contains(&a)
+        && (a - S_BASE).is_multiple_of(T_COUNT)
+        && (T_BASE + 1..T_BASE + T_COUNT).contains(&b)
+    {
+        return Some(a + (b - T_BASE));
+    }
+    COMPOSITION
+        .binary_search_by_key(&(a, b), |&(pair, _)| pair)
+        .ok()
+        .map(|i| COMPOSITION[i].1)
+}
+
+fn compose(chars: &[u32]) -> String {
+    let mut out: Vec<u32> = Vec::with_capacity(chars.len());
+    let mut starter = None;
+    // Class of the last character appended since the starter, `None` while
+    // the starter is the last character
+    let mut last_class: Option<u8> = None;
+
+    for &c in chars {
+        let class = combining_class(c);
+        if let Some(i) = starter {
+            let blocked = last_class.is_some_and(|last| last == 0 || last >= class);
+            if !blocked && let Some(composed) = compose_pair(out[i], c) {
+                out[i] = composed;
+                continue;
+            }
+        }
+        if class == 0 {
+            starter = Some(out.len());
+            last_class = None;
+        } else {
+            last_class = Some(class);
+        }
+        out.push(c);
+    }
+
+    out.into_iter().filter_map(char::from_u32).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::unicode_tables::{CANONICAL_DECOMPOSITION, COMPATIBILITY_DECOMPOSITION};
+
+    #[test]
+    fn composed_and_decomposed_forms_agree() {
+        let composed = "Caf\u{E9}";
+        let decomposed = "Cafe\u{301}";
+        assert_eq!(nfc(decomposed), composed);
+        assert_eq!(nfkc(decomposed), composed);
+        assert!(matches!(nfc(composed), Cow::Borrowed(_)));
+        assert_eq!(nfkd(composed), [0x43, 0x61, 0x66, 0x65, 0x301]);
+    }
+
+    #[test]
+    fn marks_are_put_in_canonical_order() {
+        // A dot below (220) goes before a circumflex (230) whichever came
+        // first, and both compose onto the e
+        assert_eq!(nfc("e\u{302}\u{323}"), "\u{1EC7}");
+        assert_eq!(nfc("e\u{323}\u{302}"), "\u{1EC7}");
+    }
+
+    #[test]
+    fn compatibility_forms_only_fold_under_nfkc() {
+        assert_eq!(nfc("\u{FB01}ji"), "\u{FB01}ji");
+        assert_eq!(nfkc("\u{FB01}ji"), "fiji");
+        assert_eq!(nfkc("\u{2460}"), "1");
+        // ϓ is Υ with an acute accent under NFKC, which composes to Ύ
+        assert_eq!(nfc("\u{3D3}"), "\u{3D3}");
+        assert_eq!(nfkc("\u{3D3}"), "\u{38E}");
+    }
+
+    #[test]
+    fn hangul_syllables_decompose_and_compose_by_arithmetic() {
+        // 한 is ㅎ, ㅏ and ㄴ as conjoining jamo
+        assert_eq!(nfkd("\u{D55C}"), [0x1112, 0x1161, 0x11AB]);
+        assert_eq!(nfc("\u{1112}\u{1161}\u{11AB}"), "\u{D55C}");
+        assert_eq!(nfc("\u{1112}\u{1161}"), "\u{D558}");
+        assert!(matches!(nfkc("\u{D55C}\u{AD6D}"), Cow::Borrowed(_)));
+    }
+
+    #[test]
+    fn tables_are_sorted_for_binary_search() {
+        assert!(COMBINING_CLASS.windows(2).all(|w| w[0].0 < w[1].0));
+        assert!(CANONICAL_DECOMPOSITION.windows(2).all(|w| w[0].0 < w[1].0));
+        assert!(
+            COMPATIBILITY_DECOMPOSITION
+                .windows(2)
+                .all(|w| w[0].0 < w[1].0)
+        );
+        assert!(COMPOSITION.windows(2).all(|w| w[0].0 < w[1].0));
+        assert!(COMBINING_CLASS.iter().all(|&(_, class)| class != 0));
+    }
+}
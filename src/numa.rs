@@ -0,0 +1,110 @@
+//! NUMA topology detection and placement for `--numa auto`. Each node gets
+//! its own threads pinned to the node's cores, reading and
+//! aggregating its own part of the file, so that the chunks and maps are
+//! allocated in node-local memory on first touch.
+
+use crate::affinity;
+use std::fmt::Write;
+use std::fs;
+#[cfg(feature = "runtime-tokio")]
+use tokio::runtime::{Handle, Runtime};
+
+#[derive(Debug)]
+pub struct Node {
+    pub id: usize,
+    pub cpus: Vec<usize>,
+}
+
+/// The nodes with CPUs, or nothing where the topology is not exposed.
+pub fn detect() -> Vec<Node> {
+    let Ok(entries) = fs::read_dir("/sys/devices/system/node") else {
+        return Vec::new();
+    };
+    let mut nodes: Vec<Node> = entries
+        .filter_map(|entry| {
+            let entry = entry.ok()?;
+            let id = entry
+                .file_name()
+                .to_str()?
+                .strip_prefix("node")?
+                .parse()
+                .ok()?;
+            let cpulist = fs::read_to_string(entry.path().join("cpulist")).ok()?;
+            let cpus = affinity::parse_core_list(cpulist.trim()).ok()?;
+            Some(Node { id, cpus })
+        })
+        .filter(|node| !node.cpus.is_empty())
+        .collect();
+    nodes.sort_by_key(|node| node.id);
+    nodes
+}
+
+/// Describes the topology like `2 (node0: cpus 0-7, node1: cpus 8-15)`.
+pub fn describe(nodes: &[Node]) -> String {
+    let mut out = nodes.len().to_string();
+    for (i, node) in nodes.iter().enumerate() {
+        out.push_str(if i == 0 { " (" } else { ", " });
+        let _ = write!(out, "node{}: cpus {}", node.id, format_cpus(&node.cpus));
+    }
+    if !nodes.is_empty() {
+        out.push(')');
+    }
+    out
+}
+
+fn format_cpus(cpus: &[usize]) -> String {
+    let mut out = String::new();
+    let mut i = 0;
+    while i < cpus.len() {
+        let start = cpus[i];
+        while i + 1 < cpus.len() && cpus[i + 1] == cpus[i] + 1 {
+            i += 1;
+        }
+        if !out.is_empty() {
+            out.push(',');
+        }
+        if cpus[i] == start {
+            let _ = write!(out, "{}", start);
+        } else {
+            let _ = write!(out, "{}-{}", start, cpus[i]);
+        }
+        i += 1;
+    }
+    out
+}
+
+/// One runtime per node, for the async pipeline.
+#[cfg(feature = "runtime-tokio")]
+pub struct Placement {
+    runtimes: Vec<Runtime>,
+}
+
+#[cfg(feature = "runtime-tokio")]
+impl Placement {
+    pub fn new(nodes: &[Node]) -> std::io::Result<Placement> {
+        let runtimes = nodes
+            .iter()
+            .map(|node| affinity::pinned_runtime(node.cpus.clone()))
+            .collect::<Result<_, _>>()?;
+        Ok(Placement { runtimes })
+    }
+
+    pub fn len(&self) -> usize {
+        self.runtimes.len()
+    }
+
+    pub fn handle(&self, node: usize) -> &Handle {
+        self.runtimes[node].handle()
+    }
+}
+
+#[cfg(feature = "runtime-tokio")]
+impl Drop for Placement {
+    /// Stops the node runtimes without blocking, as this happens within the
+    /// main runtime.
+    fn drop(&mut self) {
+        for runtime in self.runtimes.drain(..) {
+            runtime.shutdown_background();
+        }
+    }
+}
@@ -0,0 +1,654 @@
+use crate::agg::Agg;
+use crate::cli::{Collation, Color, Config, GroupBy, Mode, OutputFormat, Sort, SortField};
+use crate::collate;
+use crate::format::{self, Cell, Format, Layout, csv_field, json_string};
+use crate::metadata::{Metadata, StationInfo};
+use crate::time::format_timestamp;
+use crate::{Key, StatResult};
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::io::{self, IsTerminal};
+
+/// A result row, in the order given by `--sort-by`.
+type Row<'a> = (&'a Key, &'a StatResult);
+
+/// The aggregates shown without `--agg`, those of the challenge
+const DEFAULT: &[Agg] = &[Agg::Min, Agg::Mean, Agg::Max];
+/// The same, for the formats with room for the count as well
+const DEFAULT_WITH_COUNT: &[Agg] = &[Agg::Min, Agg::Mean, Agg::Max, Agg::Count];
+
+/// The aggregates `--agg` asks for, or the format's `default` ones.
+fn aggs<'a>(config: &'a Config, default: &'a [Agg]) -> &'a [Agg] {
+    if config.agg.is_empty() {
+        default
+    } else {
+        &config.agg
+    }
+}
+
+/// Renders the final results in the configured format. Metadata columns are
+/// only joined in when aggregating by station.
+pub fn format_results(
+    config: &Config,
+    result: &HashMap<Key, StatResult>,
+    metadata: Option<&Metadata>,
+) -> String {
+    let mut rows: Vec<Row> = result.iter().collect();
+    sort_rows(&mut rows, config.sort, config.collation);
+    let result = &rows;
+    if streams(config) {
+        // Room for rows of about the usual length
+        let mut out = Vec::with_capacity(result.len() * 32);
+        let _ = write_rows(config, result, metadata, &mut out);
+        // Without the newline the rows end with, as the other formats
+        if out.last() == Some(&b'\n') {
+            out.pop();
+        }
+        return String::from_utf8(out).expect("results are UTF-8");
+    }
+    let metadata = metadata.filter(|_| config.group_by == GroupBy::Station);
+    let label = config.group_by.label();
+    match config.format {
+        OutputFormat::Table => format_table(
+            &Table::new(
+                label,
+                config.mode,
+                aggs(config, DEFAULT_WITH_COUNT),
+                config.precision,
+                result,
+                metadata,
+            ),
+            colored(config.color),
+        ),
+        OutputFormat::Prom => format_prom(
+            label,
+            config.mode,
+            aggs(config, DEFAULT_WITH_COUNT),
+            config.precision,
+            result,
+        ),
+        OutputFormat::Markdown => format_markdown(&Table::new(
+            label,
+            config.mode,
+            aggs(config, DEFAULT_WITH_COUNT),
+            config.precision,
+            result,
+            metadata,
+        )),
+        OutputFormat::Text | OutputFormat::Csv | OutputFormat::Json => unreachable!("streamed"),
+    }
+}
+
+/// Writes the final results to `out` as [`format_results`] renders them,
+/// followed by a newline unless there are none to list, without holding all of the output at once in the
+/// formats that can be written a row at a time.
+pub fn write_results(
+    config: &Config,
+    result: &HashMap<Key, StatResult>,
+    metadata: Option<&Metadata>,
+    out: &mut impl io::Write,
+) -> io::Result<()> {
+    if !streams(config) {
+        writeln!(out, "{}", format_results(config, result, metadata))?;
+        return out.flush();
+    }
+    let mut rows: Vec<Row> = result.iter().collect();
+    sort_rows(&mut rows, config.sort, config.collation);
+    write_rows(config, &rows, metadata, out)?;
+    out.flush()
+}
+
+fn write_rows(
+    config: &Config,
+    rows: &[Row],
+    metadata: Option<&Metadata>,
+    out: &mut impl io::Write,
+) -> io::Result<()> {
+    let windowed = rows.iter().any(|(k, _)| k.window.is_some());
+    let columns = rows.iter().any(|(k, _)| k.column.is_some());
+    let mut writer = ResultWriter::new(config, metadata, windowed, columns, out)?;
+    for (key, stat) in rows {
+        writer.row(key, stat)?;
+    }
+    writer.finish()
+}
+
+/// Standard output for the results, locked once and buffered, so that the
+/// rows of CSV and JSON are not written out a line at a time. It is only
+/// written out as it fills up and when flushed.
+pub fn stdout() -> io::BufWriter<io::StdoutLock<'static>> {
+    io::BufWriter::with_capacity(1 << 16, io::stdout().lock())
+}
+
+/// Whether the results can be written as they come, with no column
+/// widths, groups or totals to know first: the text, CSV and JSON formats.
+pub fn streams(config: &Config) -> bool {
+    matches!(
+        config.format,
+        OutputFormat::Text | OutputFormat::Csv | OutputFormat::Json
+    )
+}
+
+/// Writes results in one of the formats that [`streams`], a row at a time
+/// in the order they are given.
+pub struct ResultWriter<'a, W: io::Write> {
+    writer: format::Writer<W>,
+    mode: Mode,
+    aggs: &'a [Agg],
+    precision: usize,
+    metadata: Option<&'a Metadata>,
+}
+
+impl<'a, W: io::Write> ResultWriter<'a, W> {
+    /// Starts the output, with the header of the columns that `windowed`
+    /// and `columns` say the keys have.
+    pub fn new(
+        config: &'a Config,
+        metadata: Option<&'a Metadata>,
+        windowed: bool,
+        columns: bool,
+        out: W,
+    ) -> io::Result<Self> {
+        let metadata = metadata.filter(|_| config.group_by == GroupBy::Station);
+        let aggs = aggs(config, DEFAULT);
+        let format = match config.format {
+            OutputFormat::Text => Format::Text,
+            OutputFormat::Csv => Format::Csv,
+            OutputFormat::Json => Format::Json,
+            _ => unreachable!("not a streamed format"),
+        };
+        let layout = Layout {
+            label: config.group_by.label().to_string(),
+            windowed,
+            columns,
+            values: match config.mode {
+                Mode::Aggregate => aggs.iter().map(|agg| agg.name()).collect(),
+                Mode::CountOnly => vec!["count".to_string()],
+                Mode::ListStations => vec![],
+            },
+            details: match metadata {
+                Some(_) => ["country", "lat", "lon"].map(String::from).to_vec(),
+                None => vec![],
+            },
+        };
+        Ok(ResultWriter {
+            writer: format::Writer::new(format, layout, out)?,
+            mode: config.mode,
+            aggs,
+            precision: config.precision,
+            metadata,
+        })
+    }
+
+    pub fn row(&mut self, key: &Key, stat: &StatResult) -> io::Result<()> {
+        let values: Vec<String> = match self.mode {
+            Mode::Aggregate => self
+                .aggs
+                .iter()
+                .map(|agg| agg.format(stat, self.precision))
+                .collect(),
+            Mode::CountOnly => vec![stat.count.to_string()],
+            Mode::ListStations => vec![],
+        };
+        let info = self.metadata.map(|m| m.get(&key.name));
+        let details = info.flatten().map(|StationInfo { country, lat, lon }| {
+            [Cell::Text(country), Cell::Number(*lat), Cell::Number(*lon)]
+        });
+        self.writer.row(
+            &key.name,
+            key.window,
+            key.column,
+            values,
+            details.as_ref().map(|d| &d[..]),
+        )
+    }
+
+    /// Ends the output with a newline, unless it is empty, which only an
+    /// empty list of stations is, and without a flush.
+    pub fn finish(self) -> io::Result<()> {
+        let empty = self.writer.is_empty();
+        let mut out = self.writer.finish()?;
+        if !empty {
+            writeln!(out)?;
+        }
+        Ok(())
+    }
+}
+
+/// `--per-file`: each input's results and then their total, nested under
+/// `files` and `total` in JSON, behind a leading `file` column in CSV and
+/// as headed sections otherwise.
+pub fn format_per_file(
+    config: &Config,
+    files: &[(&str, HashMap<Key, StatResult>)],
+    total: &HashMap<Key, StatResult>,
+    metadata: Option<&Metadata>,
+) -> String {
+    let mut out = String::new();
+    match config.format {
+        OutputFormat::Json => {
+            // Nested one level deeper than on their own
+            let nest = |result| format_results(config, result, metadata).replace('\n', "\n  ");
+            out.push_str("{\n  \"files\": {");
+            for (i, (file, result)) in files.iter().enumerate() {
+                let separator = if i == 0 { "" } else { "," };
+                let _ = write!(out, "{}\n    {}: ", separator, json_string(file));
+                out.push_str(&nest(result).replace('\n', "\n  "));
+            }
+            let _ = write!(out, "\n  }},\n  \"total\": {}\n}}", nest(total));
+        }
+        OutputFormat::Csv => {
+            let parts = files
+                .iter()
+                .map(|(file, result)| (csv_field(file), result))
+                .chain([("(total)".to_string(), total)]);
+            for (i, (file, result)) in parts.enumerate() {
+                let csv = format_results(config, result, metadata);
+                let mut lines = csv.lines();
+                let header = lines.next().unwrap_or_default();
+                if i == 0 {
+                    let _ = write!(out, "file,{}", header);
+                }
+                for line in lines {
+                    let _ = write!(out, "\n{},{}", file, line);
+                }
+            }
+        }
+        _ => {
+            for (file, result) in files {
+                let _ = writeln!(out, "{}:", file);
+                out.push_str(&format_results(config, result, metadata));
+                out.push_str("\n\n");
+            }
+            out.push_str("total:\n");
+            out.push_str(&format_results(config, total, metadata));
+        }
+    }
+    out
+}
+
+/// Whether the table output is in color.
+fn colored(color: Color) -> bool {
+    match color {
+        Color::Always => true,
+        Color::Never => false,
+        Color::Auto => std::io::stdout().is_terminal() && std::env::var_os("NO_COLOR").is_none(),
+    }
+}
+
+/// Ties are broken by name, in ascending order either way.
+fn sort_rows(rows: &mut Vec<Row>, sort: Sort, collation: Collation) {
+    let value: Option<fn(&StatResult) -> f64> = match sort.field {
+        SortField::Name => None,
+        SortField::Min => Some(|s| s.min),
+        SortField::Mean => Some(|s| s.mean),
+        SortField::Max => Some(|s| s.max),
+        SortField::Count => Some(|s| s.count as f64),
+    };
+    // Collation keys are only built once per row, and are empty for the
+    // byte order of the keys themselves
+    let tailoring = match collation {
+        Collation::Bytes => None,
+        Collation::Unicode => Some(&[][..]),
+        Collation::Locale(letters) => Some(letters),
+    };
+    let mut keyed: Vec<(Vec<u32>, Row)> = rows
+        .drain(..)
+        .map(|row| match tailoring {
+            Some(tailoring) => (collate::key(&row.0.name, tailoring), row),
+            None => (Vec::new(), row),
+        })
+        .collect();
+    keyed.sort_unstable_by(|(a_key, a), (b_key, b)| {
+        let by_name = || a_key.cmp(b_key).then_with(|| a.0.cmp(b.0));
+        let order = match value {
+            Some(value) => value(a.1).total_cmp(&value(b.1)),
+            None => by_name(),
+        };
+        let order = if sort.descending {
+            order.reverse()
+        } else {
+            order
+        };
+        order.then_with(by_name)
+    });
+    rows.extend(keyed.into_iter().map(|(_, row)| row));
+}
+
+/// The results as cells, for the formats that lay them out as a table.
+pub struct Table {
+    pub header: Vec<String>,
+    pub rows: Vec<Vec<String>>,
+}
+
+impl Table {
+    fn new(
+        label: &str,
+        mode: Mode,
+        aggs: &[Agg],
+        precision: usize,
+        result: &[Row],
+        metadata: Option<&Metadata>,
+    ) -> Self {
+        let windowed = result.iter().any(|(k, _)| k.window.is_some());
+        let columns = result.iter().any(|(k, _)| k.column.is_some());
+        let header = header(label, windowed, columns, mode, aggs, metadata.is_some());
+
+        let rows = result
+            .iter()
+            .map(|(key, stat)| {
+                let mut row = vec![key.name.to_string()];
+                if windowed {
+                    row.push(key.window.map(format_timestamp).unwrap_or_default());
+                }
+                if columns {
+                    row.push(key.column.map(|c| c.to_string()).unwrap_or_default());
+                }
+                match mode {
+                    Mode::Aggregate => {
+                        row.extend(aggs.iter().map(|agg| agg.format(stat, precision)))
+                    }
+                    Mode::CountOnly => row.push(stat.count.to_string()),
+                    Mode::ListStations => {}
+                }
+                if let Some(metadata) = metadata {
+                    match metadata.get(&key.name) {
+                        Some(info) => row.extend([
+                            info.country.clone(),
+                            info.lat.to_string(),
+                            info.lon.to_string(),
+                        ]),
+                        None => row.extend([String::new(), String::new(), String::new()]),
+                    }
+                }
+                row
+            })
+            .collect();
+        Table { header, rows }
+    }
+
+    /// Whether a column holds text, which is left-aligned, rather than
+    /// numbers.
+    pub fn is_text(&self, column: usize) -> bool {
+        is_text(&self.header[column])
+    }
+}
+
+/// Whether the column called `name` holds text.
+pub fn is_text(name: &str) -> bool {
+    matches!(name, "station" | "country" | "window")
+}
+
+fn header(
+    label: &str,
+    windowed: bool,
+    columns: bool,
+    mode: Mode,
+    aggs: &[Agg],
+    metadata: bool,
+) -> Vec<String> {
+    let mut header = vec![label.to_string()];
+    if windowed {
+        header.push("window".to_string());
+    }
+    if columns {
+        header.push("column".to_string());
+    }
+    match mode {
+        Mode::Aggregate => header.extend(aggs.iter().map(|agg| agg.name())),
+        Mode::CountOnly => header.push("count".to_string()),
+        Mode::ListStations => {}
+    }
+    if metadata {
+        header.extend(["country", "lat", "lon"].map(String::from));
+    }
+    header
+}
+
+/// The columns [`results_table`] will have, before there are results.
+pub fn results_header(config: &Config) -> Vec<String> {
+    header(
+        config.group_by.label(),
+        config.window.is_some(),
+        !config.value_columns.is_empty(),
+        config.mode,
+        aggs(config, DEFAULT_WITH_COUNT),
+        config.metadata.is_some() && config.group_by == GroupBy::Station,
+    )
+}
+
+/// The results as a [`Table`], in the configured order.
+pub fn results_table(
+    config: &Config,
+    result: &HashMap<Key, StatResult>,
+    metadata: Option<&Metadata>,
+) -> Table {
+    let metadata = metadata.filter(|_| config.group_by == GroupBy::Station);
+    let mut rows: Vec<Row> = result.iter().collect();
+    sort_rows(&mut rows, config.sort, config.collation);
+    Table::new(
+        config.group_by.label(),
+        config.mode,
+        aggs(config, DEFAULT_WITH_COUNT),
+        config.precision,
+        &rows,
+        metadata,
+    )
+}
+
+/// The result of a `--query`, in the configured format.
+pub fn format_query(config: &Config, table: &Table) -> String {
+    match config.format {
+        OutputFormat::Csv => {
+            let mut out = table.header.join(",");
+            for row in &table.rows {
+                let cells: Vec<String> = row.iter().map(|cell| csv_field(cell)).collect();
+                let _ = write!(out, "\n{}", cells.join(","));
+            }
+            out
+        }
+        OutputFormat::Json => {
+            let mut out = String::from("[");
+            for (i, row) in table.rows.iter().enumerate() {
+                let fields: Vec<String> = row
+                    .iter()
+                    .enumerate()
+                    .map(|(column, cell)| {
+                        let value = if cell.is_empty() {
+                            "null".to_string()
+                        } else if table.is_text(column) {
+                            json_string(cell)
+                        } else {
+                            cell.clone()
+                        };
+                        format!("\"{}\": {}", table.header[column], value)
+                    })
+                    .collect();
+                let separator = if i == 0 { "" } else { "," };
+                let _ = write!(out, "{}\n  {{{}}}", separator, fields.join(", "));
+            }
+            out.push_str("\n]");
+            out
+        }
+        OutputFormat::Markdown => format_markdown(table),
+        // Prom is ruled out with --query, and text is best read as a table
+        _ => format_table(table, colored(config.color)),
+    }
+}
+
+/// Left-aligned station names and right-aligned numbers, with the header
+/// in bold and, when `colored`, minimums in blue and maximums in red.
+fn format_table(table: &Table, colored: bool) -> String {
+    let Table { header, rows } = table;
+    let mut widths: Vec<usize> = header.iter().map(|h| display_width(h)).collect();
+    for row in rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(display_width(cell));
+        }
+    }
+    // ANSI styles of the header and of the numbers in each column
+    let bold = |_: &str| colored.then_some("1");
+    let highlight = |name: &str| match name {
+        "min" if colored => Some("34"),
+        "max" if colored => Some("31"),
+        _ => None,
+    };
+    let plain = |_: &str| None;
+
+    let mut out = String::new();
+    let mut line = |cells: &[&str], style: &dyn Fn(&str) -> Option<&'static str>| {
+        let mut line = String::new();
+        for (i, cell) in cells.iter().enumerate() {
+            if i > 0 {
+                line.push_str("  ");
+            }
+            let pad = " ".repeat(widths[i] - display_width(cell));
+            let cell = match style(&header[i]) {
+                Some(code) => format!("\x1b[{}m{}\x1b[0m", code, cell),
+                None => cell.to_string(),
+            };
+            if table.is_text(i) {
+                line.push_str(&cell);
+                line.push_str(&pad);
+            } else {
+                line.push_str(&pad);
+                line.push_str(&cell);
+            }
+        }
+        out.push_str(line.trim_end());
+        out.push('\n');
+    };
+    line(
+        &header.iter().map(String::as_str).collect::<Vec<_>>(),
+        &bold,
+    );
+    let rule: Vec<String> = widths.iter().map(|w| "-".repeat(*w)).collect();
+    line(&rule.iter().map(String::as_str).collect::<Vec<_>>(), &plain);
+    for row in rows {
+        line(
+            &row.iter().map(String::as_str).collect::<Vec<_>>(),
+            &highlight,
+        );
+    }
+    out.pop();
+    out
+}
+
+/// A gauge's name, help text and value.
+type Metric<'a> = (String, String, Box<dyn Fn(&StatResult) -> String + 'a>);
+
+/// Gauges in the Prometheus text format, as read by node_exporter's
+/// textfile collector. Each key is a label, along with its window if any.
+fn format_prom(label: &str, mode: Mode, aggs: &[Agg], precision: usize, result: &[Row]) -> String {
+    let metrics: Vec<Metric> = match mode {
+        Mode::Aggregate => aggs
+            .iter()
+            .map(|&agg| {
+                let (name, help) = agg.metric();
+                (
+                    name,
+                    help,
+                    Box::new(move |s: &StatResult| agg.format(s, precision)) as _,
+                )
+            })
+            .collect(),
+        Mode::CountOnly => vec![(
+            "station_readings".to_string(),
+            "Number of rows".to_string(),
+            Box::new(|s: &StatResult| s.count.to_string()),
+        )],
+        Mode::ListStations => vec![(
+            "station_present".to_string(),
+            "Presence in the input".to_string(),
+            Box::new(|_: &StatResult| "1".to_string()),
+        )],
+    };
+    let mut out = String::new();
+    for (name, help, value) in &metrics {
+        let _ = writeln!(out, "# HELP {} {} per {}.", name, help, label);
+        let _ = writeln!(out, "# TYPE {} gauge", name);
+        for (key, stat) in result {
+            let _ = write!(out, "{}{{{}=\"{}\"", name, label, prom_label(&key.name));
+            if let Some(window) = key.window {
+                let _ = write!(out, ",window=\"{}\"", format_timestamp(window));
+            }
+            if let Some(column) = key.column {
+                let _ = write!(out, ",column=\"{}\"", column);
+            }
+            let _ = writeln!(out, "}} {}", value(stat));
+        }
+    }
+    out.pop();
+    out
+}
+
+/// Escapes a label value as the exposition format requires.
+fn prom_label(s: &str) -> String {
+    s.replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// A GitHub-flavored Markdown table, with numbers right-aligned.
+fn format_markdown(table: &Table) -> String {
+    let cells = |cells: &[&str]| {
+        let cells: Vec<String> = cells.iter().map(|cell| markdown_cell(cell)).collect();
+        format!("| {} |", cells.join(" | "))
+    };
+    let mut out = cells(&table.header.iter().map(String::as_str).collect::<Vec<_>>());
+    let alignment: Vec<&str> = (0..table.header.len())
+        .map(|i| if table.is_text(i) { ":--" } else { "--:" })
+        .collect();
+    out.push('\n');
+    out.push_str(&cells(&alignment));
+    for row in &table.rows {
+        out.push('\n');
+        out.push_str(&cells(&row.iter().map(String::as_str).collect::<Vec<_>>()));
+    }
+    out
+}
+
+/// Escapes a cell so that it stays on its row and in its column. Line
+/// breaks, which a table cell cannot hold, are written as JSON writes them,
+/// and so are backslashes, so that `diff` can read the name back.
+fn markdown_cell(cell: &str) -> String {
+    let mut out = String::with_capacity(cell.len());
+    for c in cell.chars() {
+        match c {
+            '|' => out.push_str("\\|"),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// Terminal columns taken by `s`.
+fn display_width(s: &str) -> usize {
+    s.chars().map(char_width).sum()
+}
+
+/// None for combining marks, two for wide East Asian characters and emoji,
+/// one for everything else.
+pub fn char_width(c: char) -> usize {
+    match c as u32 {
+        0x300..=0x36F | 0x200B..=0x200F | 0xFE00..=0xFE0F => 0,
+        0x1100..=0x115F
+        | 0x2E80..=0x303E
+        | 0x3041..=0x33FF
+        | 0x3400..=0x4DBF
+        | 0x4E00..=0x9FFF
+        | 0xA000..=0xA4CF
+        | 0xAC00..=0xD7A3
+        | 0xF900..=0xFAFF
+        | 0xFE30..=0xFE4F
+        | 0xFF00..=0xFF60
+        | 0xFFE0..=0xFFE6
+        | 0x1F300..=0x1F64F
+        | 0x1F900..=0x1F9FF
+        | 0x20000..=0x3FFFD => 2,
+        _ => 1,
+    }
+}
@@ -0,0 +1,277 @@
+use anyhow::{anyhow, bail};
+
+const POW10: [f64; 23] = [
+    1e0, 1e1, 1e2, 1e3, 1e4, 1e5, 1e6, 1e7, 1e8, 1e9, 1e10, 1e11, 1e12, 1e13, 1e14, 1e15, 1e16,
+    1e17, 1e18, 1e19, 1e20, 1e21, 1e22,
+];
+
+/// Largest mantissa that is still exactly representable as an f64.
+const MAX_EXACT: u64 = 1 << 53;
+
+/// Parses a reading written as `-?\d+\.\d+`. The common case is decoded as
+/// a fixed-point integer, which is exact (and so matches `str::parse`)
+/// while the digits fit in 53 bits. Other spellings, such as `5` or
+/// `1.2e1`, are only accepted when `lenient` is set, through the slower
+/// general parser. Readings that are not finite, as `nan`, `inf` or ones
+/// too large for an `f64`, are never accepted.
+pub fn parse_value(s: &str, lenient: bool) -> anyhow::Result<f64> {
+    let value = match parse_fixed(s.as_bytes()) {
+        Some(Fixed::Exact(value)) => return Ok(value),
+        Some(Fixed::Long) => s.parse().ok(),
+        None => {
+            let value = s.parse().ok().filter(|value: &f64| value.is_finite());
+            if value.is_some() && !lenient {
+                bail!(
+                    "invalid reading `{}` (integers and exponents need --lenient-numbers)",
+                    s
+                );
+            }
+            value
+        }
+    };
+    value
+        .filter(|value| value.is_finite())
+        .ok_or_else(|| anyhow!("invalid reading `{}`", s))
+}
+
+enum Fixed {
+    Exact(f64),
+    /// Well formed, but too many digits for the fast path
+    Long,
+}
+
+fn parse_fixed(b: &[u8]) -> Option<Fixed> {
+    let (negative, digits) = match b.first() {
+        Some(b'-') => (true, &b[1..]),
+        _ => (false, b),
+    };
+
+    let mut mantissa: u64 = 0;
+    let mut int_digits = 0;
+    let mut frac_digits = 0;
+    let mut seen_dot = false;
+    for &c in digits {
+        match c {
+            b'0'..=b'9' => {
+                mantissa = mantissa
+                    .saturating_mul(10)
+                    .saturating_add(u64::from(c - b'0'));
+                if seen_dot {
+                    frac_digits += 1;
+                } else {
+                    int_digits += 1;
+                }
+            }
+            b'.' if !seen_dot => seen_dot = true,
+            _ => return None,
+        }
+    }
+    if int_digits == 0 || frac_digits == 0 {
+        return None;
+    }
+    if mantissa >= MAX_EXACT || frac_digits >= POW10.len() {
+        return Some(Fixed::Long);
+    }
+
+    let value = mantissa as f64 / POW10[frac_digits];
+    Some(Fixed::Exact(if negative { -value } else { value }))
+}
+
+/// Parses a reading matching `-?\d?\d\.\d`, as in the original
+/// challenge's input, without looking at one byte at a time. The bytes are
+/// loaded into one word, checked against the pattern with masks and the
+/// digits combined with a multiplication. Anything else is an error.
+pub fn parse_1brc(s: &str) -> anyhow::Result<f64> {
+    let b = s.as_bytes();
+    if !(3..=5).contains(&b.len()) {
+        bail!("invalid reading `{}` (expected -?\\d?\\d\\.\\d)", s);
+    }
+    let mut buf = [0u8; 8];
+    buf[..b.len()].copy_from_slice(b);
+    let word = u64::from_le_bytes(buf);
+    if !is_1brc(word, b.len()) {
+        bail!("invalid reading `{}` (expected -?\\d?\\d\\.\\d)", s);
+    }
+    let value = parse_tenths(word) as f64 / 10.0;
+    // Keep the sign of `-0.0`, as `str::parse` does
+    Ok(if b[0] == b'-' { -value.abs() } else { value })
+}
+
+/// Whether the `len` low bytes of `word` are `-?\d?\d\.\d`.
+fn is_1brc(word: u64, len: usize) -> bool {
+    let signed = word & 0xFF == u64::from(b'-');
+    if !matches!((signed, len), (false, 3 | 4) | (true, 4 | 5)) {
+        return false;
+    }
+    // The dot is always next to last, and every other byte after the sign
+    // a digit, marked with 0x01
+    let dot = 8 * (len - 2);
+    let digits = 0x01_0101_0101 & ((1 << (8 * len)) - 1) & !(1 << dot) & !u64::from(signed);
+    (word >> dot) & 0xFF == u64::from(b'.')
+        // 0x30 to 0x3F
+        && word & (digits * 0xF0) == digits * 0x30
+        // and of those 0x30 to 0x39, which 6 does not carry out of
+        && (word + digits * 0x06) & (digits * 0xF0) == digits * 0x30
+}
+
+/// Decodes the reading in the low bytes of `word` into tenths.
+fn parse_tenths(word: u64) -> i64 {
+    // `-` has bit 4 clear where digits have it set, so this is all ones
+    // for a negative reading and zero otherwise
+    let negative = ((!word << 59) as i64) >> 63;
+    let sign_mask = !(negative as u64 & 0xFF);
+    // The dot is the first byte after the sign with bit 4 clear, and
+    // garbage without one is taken to have it in the last place
+    let dot = (!word & 0x1010_1000).trailing_zeros().min(28);
+    let shift = 28 - dot;
+    // Line the digits up as `tens, ones, tenths` in bytes 1, 2 and 4 and
+    // keep only their values
+    let digits = ((word & sign_mask) << shift) & 0x0F_000F_0F00;
+    // One multiplication sums 100 * tens + 10 * ones + tenths into bits
+    // 32 and up
+    let abs = ((digits.wrapping_mul(0x640A_0001)) >> 32) & 0x3FF;
+    (abs as i64 ^ negative) - negative
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Every spelling `-?\d?\d\.\d` allows, including a leading zero.
+    fn all_readings() -> Vec<String> {
+        let mut out = Vec::new();
+        for sign in ["", "-"] {
+            for int in 0..10 {
+                for frac in 0..10 {
+                    out.push(format!("{}{}.{}", sign, int, frac));
+                }
+            }
+            for int in 0..100 {
+                for frac in 0..10 {
+                    out.push(format!("{}{:02}.{}", sign, int, frac));
+                }
+            }
+        }
+        out
+    }
+
+    #[test]
+    fn swar_matches_std_over_the_whole_domain() {
+        let readings = all_readings();
+        assert_eq!(readings.len(), 2 * (10 + 100) * 10);
+        for s in &readings {
+            let expected: f64 = s.parse().unwrap();
+            assert_eq!(
+                parse_1brc(s).unwrap().to_bits(),
+                expected.to_bits(),
+                "{}",
+                s
+            );
+        }
+    }
+
+    #[test]
+    fn swar_ignores_bytes_past_the_reading() {
+        for s in all_readings() {
+            let mut buf = [0u8; 8];
+            buf[..s.len()].copy_from_slice(s.as_bytes());
+            // What follows a reading in its line or in the next one
+            for filler in [b'\n', b';', b'9', 0xFF] {
+                for b in &mut buf[s.len()..] {
+                    *b = filler;
+                }
+                let tenths = parse_tenths(u64::from_le_bytes(buf));
+                let expected = (s.parse::<f64>().unwrap() * 10.0).round() as i64;
+                assert_eq!(tenths, expected, "{:?} followed by {:#x}", s, filler);
+            }
+        }
+    }
+
+    #[test]
+    fn swar_rejects_wrong_lengths() {
+        for s in [
+            "", "1", "12", "123.45", "-123.4", "1.05", "a.b", "--1", "1.5.", "-1.", "+1.5", "1:5",
+            "1.5\r", "-.55",
+        ] {
+            assert!(parse_1brc(s).is_err(), "{}", s);
+        }
+    }
+
+    #[test]
+    fn swar_accepts_exactly_the_challenge_spellings() {
+        // Next to every byte a digit or dot could be mistaken for
+        let alphabet = b"-.09/:+ ~";
+        let matches = |s: &[u8]| {
+            let digits = s.strip_prefix(b"-").unwrap_or(s);
+            matches!(digits.len(), 3 | 4)
+                && digits[digits.len() - 2] == b'.'
+                && digits
+                    .iter()
+                    .enumerate()
+                    .all(|(i, c)| i == digits.len() - 2 || c.is_ascii_digit())
+        };
+        let mut strings = vec![vec![]];
+        for _ in 0..5 {
+            strings = strings
+                .iter()
+                .flat_map(|s| alphabet.iter().map(move |&c| [&s[..], &[c]].concat()))
+                .collect();
+            for s in &strings {
+                let text = std::str::from_utf8(s).unwrap();
+                assert_eq!(parse_1brc(text).is_ok(), matches(s), "{}", text);
+            }
+        }
+    }
+
+    #[test]
+    fn fixed_point_matches_std_over_the_domain() {
+        for s in all_readings() {
+            let expected: f64 = s.parse().unwrap();
+            assert_eq!(
+                parse_value(&s, false).unwrap().to_bits(),
+                expected.to_bits(),
+                "{}",
+                s
+            );
+        }
+    }
+
+    #[test]
+    fn readings_that_are_not_finite_are_invalid() {
+        for s in ["nan", "NaN", "inf", "-inf", "infinity", "1e400", "-1e400"] {
+            for lenient in [false, true] {
+                assert_eq!(
+                    parse_value(s, lenient).unwrap_err().to_string(),
+                    format!("invalid reading `{}`", s)
+                );
+            }
+        }
+        let long = format!("{}.5", "9".repeat(400));
+        assert_eq!(
+            parse_value(&long, false).unwrap_err().to_string(),
+            format!("invalid reading `{}`", long)
+        );
+    }
+
+    #[test]
+    fn only_integers_and_exponents_get_the_lenient_hint() {
+        for s in ["5", "-12", "1.2e1", "3E-2"] {
+            assert_eq!(
+                parse_value(s, false).unwrap_err().to_string(),
+                format!(
+                    "invalid reading `{}` (integers and exponents need --lenient-numbers)",
+                    s
+                )
+            );
+            assert_eq!(parse_value(s, true).unwrap(), s.parse::<f64>().unwrap());
+        }
+        for s in ["", "-", "1.2.3", "abc", "--1.0"] {
+            for lenient in [false, true] {
+                assert_eq!(
+                    parse_value(s, lenient).unwrap_err().to_string(),
+                    format!("invalid reading `{}`", s)
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,119 @@
+//! `--partition-output-by`: the results split across files by key, and
+//! with `--partition-rows` the rows behind them too, so that downstream
+//! systems can load the parts in parallel.
+
+use crate::cache::Fnv;
+use crate::cli::{Config, InputFormat, OutputFormat, Partitioning};
+use crate::metadata::Metadata;
+use crate::{Key, StatResult, Summary, output};
+use anyhow::Context as _;
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+/// A worker hands its rows for a partition over once it has this many
+/// bytes of them.
+pub const ROW_BUFFER: usize = 64 * 1024;
+
+/// The partition `name` belongs to, as it appears in file names.
+pub fn of(partitioning: Partitioning, name: &str) -> String {
+    match partitioning {
+        Partitioning::FirstLetter => match name.chars().next() {
+            Some(c) if c.is_alphanumeric() => c.to_lowercase().collect(),
+            // Punctuation makes for awkward or invalid file names
+            _ => "_".to_string(),
+        },
+        Partitioning::StationHash(n) => {
+            let mut hash = Fnv::default();
+            hash.write(name.as_bytes());
+            let width = (n - 1).to_string().len();
+            format!("{:0width$}", hash.0 % n as u64, width = width)
+        }
+    }
+}
+
+/// Writes the results of each partition to its own file under
+/// `--partition-dir`, in the configured format, and returns how many
+/// files that was.
+pub fn write_results(
+    config: &Config,
+    summary: &Summary,
+    metadata: Option<&Metadata>,
+) -> anyhow::Result<usize> {
+    let partitioning = config.partition_by.expect("partitioned output");
+    let mut parts: BTreeMap<String, HashMap<Key, StatResult>> = BTreeMap::new();
+    for (key, stat) in &summary.stats {
+        parts
+            .entry(of(partitioning, &key.name))
+            .or_default()
+            .insert(*key, StatResult::new(stat, summary.extras.get(key)));
+    }
+    let dir = &config.partition_dir;
+    fs::create_dir_all(dir).with_context(|| format!("failed to create {}", dir.display()))?;
+    for (part, result) in &parts {
+        let extension = match config.format {
+            OutputFormat::Text | OutputFormat::Table => "txt",
+            OutputFormat::Csv => "csv",
+            OutputFormat::Json => "json",
+            OutputFormat::Markdown => "md",
+            OutputFormat::Prom => "prom",
+        };
+        let path = dir.join(format!("{}.{}", part, extension));
+        File::create(&path)
+            .map(BufWriter::new)
+            .and_then(|mut out| {
+                output::write_results(config, result, metadata, &mut out)?;
+                out.flush()
+            })
+            .with_context(|| format!("failed to write {}", path.display()))?;
+    }
+    Ok(parts.len())
+}
+
+/// The rows behind the results, as they were read, in one file per
+/// partition that all workers append to.
+pub struct RowWriter {
+    dir: PathBuf,
+    extension: &'static str,
+    files: Mutex<HashMap<String, BufWriter<File>>>,
+}
+
+impl RowWriter {
+    pub fn new(config: &Config) -> anyhow::Result<Self> {
+        let dir = config.partition_dir.clone();
+        fs::create_dir_all(&dir).with_context(|| format!("failed to create {}", dir.display()))?;
+        Ok(RowWriter {
+            dir,
+            extension: match config.input_format {
+                InputFormat::Csv => "csv",
+                InputFormat::Jsonl => "jsonl",
+            },
+            files: Mutex::default(),
+        })
+    }
+
+    /// Appends rows, each ending in a newline, to the file of `part`.
+    pub fn write(&self, part: &str, rows: &str) -> io::Result<()> {
+        let mut files = self.files.lock().unwrap();
+        let file = match files.get_mut(part) {
+            Some(file) => file,
+            None => {
+                let path = self.dir.join(format!("{}.rows.{}", part, self.extension));
+                let file = BufWriter::new(File::create(path)?);
+                files.entry(part.to_string()).or_insert(file)
+            }
+        };
+        file.write_all(rows.as_bytes())
+    }
+
+    /// Flushes every file, returning how many there are.
+    pub fn finish(&self) -> io::Result<usize> {
+        let mut files = self.files.lock().unwrap();
+        for file in files.values_mut() {
+            file.flush()?;
+        }
+        Ok(files.len())
+    }
+}
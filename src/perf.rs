@@ -0,0 +1,199 @@
+//! Hardware counters over the scan, for `--stats` in builds with the
+//! `perf-counters` feature on Linux, so that a change can be judged by
+//! its instructions per cycle and not only by the time it takes.
+//!
+//! Each counter is opened with `perf_event_open` on the thread that is
+//! about to start the scan, to be inherited by every thread it spawns. A
+//! child's counts are added to the parent's as it exits, so once the
+//! workers are joined the parent's counters hold the whole scan. Only user
+//! space is counted, which the default `perf_event_paranoid` allows.
+
+use std::fmt::Write;
+
+/// What is counted, in the order shown.
+#[cfg(all(feature = "perf-counters", target_os = "linux"))]
+const EVENTS: [(&str, u32, u64); 5] = [
+    // PERF_TYPE_SOFTWARE, PERF_COUNT_SW_TASK_CLOCK
+    ("task clock", 1, 1),
+    // PERF_TYPE_HARDWARE and its generic events
+    ("cycles", 0, 0),
+    ("instructions", 0, 1),
+    ("cache misses", 0, 3),
+    ("branch misses", 0, 5),
+];
+
+/// Counters running over a scan.
+pub struct Counters {
+    #[cfg(all(feature = "perf-counters", target_os = "linux"))]
+    events: Vec<std::io::Result<sys::Counter>>,
+}
+
+/// What the counters came to, or why one could not be read.
+pub struct Counts(Vec<(&'static str, Result<u64, String>)>);
+
+impl Counters {
+    /// Starts counting on this thread and the threads it spawns from now
+    /// on, or `None` in builds without the feature.
+    pub fn start() -> Option<Counters> {
+        #[cfg(all(feature = "perf-counters", target_os = "linux"))]
+        return Some(Counters {
+            events: EVENTS
+                .iter()
+                .map(|&(_, kind, config)| sys::Counter::open(kind, config))
+                .collect(),
+        });
+        #[cfg(not(all(feature = "perf-counters", target_os = "linux")))]
+        None
+    }
+
+    /// Stops counting. Threads spawned since [`start`](Counters::start)
+    /// must have exited for their counts to be in.
+    pub fn stop(self) -> Counts {
+        #[cfg(all(feature = "perf-counters", target_os = "linux"))]
+        return Counts(
+            EVENTS
+                .iter()
+                .zip(self.events)
+                .map(|(&(name, ..), counter)| {
+                    let count = counter.and_then(sys::Counter::stop);
+                    (name, count.map_err(|e| e.to_string()))
+                })
+                .collect(),
+        );
+        #[cfg(not(all(feature = "perf-counters", target_os = "linux")))]
+        Counts(Vec::new())
+    }
+}
+
+impl Counts {
+    fn get(&self, name: &str) -> Option<u64> {
+        self.0
+            .iter()
+            .find(|(n, _)| *n == name)
+            .and_then(|(_, count)| count.as_ref().ok().copied())
+    }
+
+    /// One line per counter, as `--stats` shows them.
+    pub fn lines(&self) -> Vec<String> {
+        let mut lines = vec![];
+        for (name, count) in &self.0 {
+            let mut line = format!("{}: ", name);
+            match count {
+                // In nanoseconds, summed over the threads
+                Ok(ns) if *name == "task clock" => {
+                    let _ = write!(line, "{:.3}s of CPU time", *ns as f64 / 1e9);
+                }
+                Ok(count) => {
+                    let _ = write!(line, "{}", count);
+                }
+                Err(e) => {
+                    let _ = write!(line, "unavailable ({})", e);
+                }
+            }
+            if *name == "instructions"
+                && let (Some(instructions), Some(cycles)) =
+                    (self.get("instructions"), self.get("cycles"))
+                && cycles > 0
+            {
+                let _ = write!(
+                    line,
+                    " ({:.2} per cycle)",
+                    instructions as f64 / cycles as f64
+                );
+            }
+            lines.push(line);
+        }
+        lines
+    }
+}
+
+#[cfg(all(feature = "perf-counters", target_os = "linux"))]
+mod sys {
+    use std::ffi::{c_int, c_long, c_ulong};
+    use std::fs::File;
+    use std::io::{self, Read};
+    use std::os::fd::{AsRawFd, FromRawFd};
+
+    #[cfg(target_arch = "x86_64")]
+    const SYS_PERF_EVENT_OPEN: c_long = 298;
+    #[cfg(target_arch = "aarch64")]
+    const SYS_PERF_EVENT_OPEN: c_long = 241;
+
+    const PERF_EVENT_IOC_ENABLE: c_ulong = 0x2400;
+    const PERF_EVENT_IOC_DISABLE: c_ulong = 0x2401;
+
+    /// `disabled`, `inherit`, `exclude_kernel` and `exclude_hv`
+    const FLAGS: u64 = 1 | 1 << 1 | 1 << 5 | 1 << 6;
+
+    /// The first version of `perf_event_attr`, which has every field
+    /// needed here
+    #[repr(C)]
+    #[derive(Default)]
+    struct Attr {
+        kind: u32,
+        size: u32,
+        config: u64,
+        sample_period: u64,
+        sample_type: u64,
+        read_format: u64,
+        flags: u64,
+        wakeup_events: u32,
+        bp_type: u32,
+        config1: u64,
+    }
+
+    unsafe extern "C" {
+        fn syscall(number: c_long, ...) -> c_long;
+        fn ioctl(fd: c_int, request: c_ulong, ...) -> c_int;
+    }
+
+    pub struct Counter(File);
+
+    impl Counter {
+        pub fn open(kind: u32, config: u64) -> io::Result<Counter> {
+            let attr = Attr {
+                kind,
+                size: size_of::<Attr>() as u32,
+                config,
+                flags: FLAGS,
+                ..Default::default()
+            };
+            // SAFETY: `attr` is a valid perf_event_attr of the size it
+            // gives; pid 0 and cpu -1 count this thread on any CPU
+            let fd = unsafe {
+                syscall(
+                    SYS_PERF_EVENT_OPEN,
+                    &attr as *const Attr,
+                    0 as c_int,
+                    -1 as c_int,
+                    -1 as c_int,
+                    0 as c_ulong,
+                )
+            };
+            if fd < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            // SAFETY: the descriptor was just opened and is owned by no one
+            // else
+            let counter = Counter(unsafe { File::from_raw_fd(fd as c_int) });
+            counter.ioctl(PERF_EVENT_IOC_ENABLE)?;
+            Ok(counter)
+        }
+
+        pub fn stop(mut self) -> io::Result<u64> {
+            self.ioctl(PERF_EVENT_IOC_DISABLE)?;
+            let mut count = [0; 8];
+            self.0.read_exact(&mut count)?;
+            Ok(u64::from_ne_bytes(count))
+        }
+
+        fn ioctl(&self, request: c_ulong) -> io::Result<()> {
+            // SAFETY: the descriptor is a perf event, which takes both
+            // requests without an argument
+            if unsafe { ioctl(self.0.as_raw_fd(), request, 0 as c_int) } != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(())
+        }
+    }
+}
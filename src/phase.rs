@@ -0,0 +1,54 @@
+//! The phases of a run, which allocations are counted by in `alloc-stats`
+//! builds and whose times `-v` shows. With several inputs the run goes
+//! through the phases again for each, and the times add up.
+
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Where the run is.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Phase {
+    Setup,
+    Aggregate,
+    Merge,
+    Output,
+}
+
+impl Phase {
+    pub const ALL: [Phase; 4] = [Phase::Setup, Phase::Aggregate, Phase::Merge, Phase::Output];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Phase::Setup => "setup",
+            Phase::Aggregate => "aggregate",
+            Phase::Merge => "merge",
+            Phase::Output => "output",
+        }
+    }
+}
+
+/// The phase the run is in and since when, and the time of those it left
+static CURRENT: Mutex<Option<(Phase, Instant, [Duration; 4])>> = Mutex::new(None);
+
+/// Leaves the current phase for `phase`.
+pub fn enter(phase: Phase) {
+    crate::alloc::enter(phase);
+    let now = Instant::now();
+    let mut current = CURRENT.lock().unwrap();
+    let mut times = [Duration::ZERO; 4];
+    if let Some((left, since, so_far)) = *current {
+        times = so_far;
+        times[left as usize] += now - since;
+    }
+    *current = Some((phase, now, times));
+}
+
+/// The time spent in each phase so far, including the current one.
+pub fn times() -> [(Phase, Duration); 4] {
+    let mut times = [Duration::ZERO; 4];
+    if let Some((phase, since, so_far)) = *CURRENT.lock().unwrap() {
+        times = so_far;
+        times[phase as usize] += since.elapsed();
+    }
+    Phase::ALL.map(|phase| (phase, times[phase as usize]))
+}
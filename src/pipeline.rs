@@ -0,0 +1,209 @@
+//! The default executor: one reader per NUMA node, or just one, splits the
+//! file into blocks of whole lines and deals them out to worker threads
+//! over bounded channels.
+
+use crate::input::{Block, Blocks, Source};
+use crate::memory::QueueBudget;
+use crate::numa::Node;
+use crate::worker::ReadError;
+use crate::{
+    Context, ThreadData, affinity, chunk_cost, manifest, new_thread_data, process_chunk, worker,
+};
+use std::sync::atomic::Ordering;
+use std::sync::mpsc::{SendError, SyncSender, sync_channel};
+use std::thread;
+
+pub fn run(
+    ctx: &Context,
+    source: &Source,
+    nodes: &[Node],
+    num_threads: usize,
+    spill_limit: Option<usize>,
+) -> anyhow::Result<(u64, Vec<ThreadData>)> {
+    let partitions = if nodes.len() > 1 { nodes.len() } else { 1 };
+    // Every node needs at least one worker for its part of the file
+    let num_threads = num_threads.max(partitions);
+    let config = &ctx.config;
+    let readers = config.readers;
+    ctx.memory
+        .buffers
+        .add(source.buffer_size() * partitions * readers);
+
+    // Under a memory limit, half of it goes to the maps (which spill past
+    // their share) and a quarter to queued chunks
+    let budget = config.max_memory.map(|limit| QueueBudget::new(limit / 4));
+    let budget = budget.as_ref();
+
+    thread::scope(|scope| {
+        // Each worker has its own channel so that every chunk is processed
+        // by exactly one worker
+        let mut senders: Vec<Vec<_>> = (0..partitions).map(|_| Vec::new()).collect();
+        let mut handles = vec![];
+        for i in 0..num_threads {
+            let (tx, rx) = sync_channel::<Chunk>(16);
+            let node = i * partitions / num_threads;
+            senders[node].push(tx);
+            let cores = if partitions > 1 {
+                Some(&nodes[node].cpus[..])
+            } else {
+                config.pin_cores.as_ref().map(|cores| {
+                    let core = i % cores.len();
+                    &cores[core..=core]
+                })
+            };
+            handles.push(scope.spawn(move || {
+                if let Some(cores) = cores {
+                    affinity::pin_or_warn("worker", cores);
+                }
+                let mut data = new_thread_data(config, i, spill_limit);
+                // Chunks still queued on failure are dropped with `rx`
+                let result = worker::guard(i, |offset| {
+                    for chunk in rx {
+                        if ctx.cancelled.load(Ordering::Relaxed) {
+                            break;
+                        }
+                        *offset = chunk.offset;
+                        process_chunk(&mut data, &chunk.text, chunk.offset, ctx, i)?;
+                        drop(chunk);
+                        ctx.memory.check()?;
+                    }
+                    Ok(())
+                });
+                if result.is_err() && !config.best_effort {
+                    ctx.cancelled.store(true, Ordering::Relaxed);
+                }
+                result.map(|()| data)
+            }));
+        }
+
+        // Read the file and send chunks to the workers
+        let block_size = config.chunk_bytes;
+        let read = if partitions > 1 || readers > 1 {
+            let len = source.len();
+            let mut threads = vec![];
+            for (node, senders) in senders.into_iter().enumerate() {
+                let range = len * node as u64 / partitions as u64
+                    ..len * (node as u64 + 1) / partitions as u64;
+                let pin = if partitions > 1 {
+                    Some(&nodes[node].cpus[..])
+                } else {
+                    config.pin_reader.as_ref().map(std::slice::from_ref)
+                };
+                // The node's readers share its workers
+                for reader in 0..readers {
+                    ctx.assign(manifest::reader(partitions, node, reader), &range);
+                    let range = range.clone();
+                    let senders = senders.clone();
+                    threads.push(scope.spawn(move || {
+                        if let Some(cpus) = pin {
+                            affinity::pin_or_warn("reader", cpus);
+                        }
+                        let blocks = if readers > 1 {
+                            source.stripes(range, reader, readers, block_size, config.csv_strict)
+                        } else {
+                            source
+                                .blocks(range, block_size, config.csv_strict)
+                                .map_err(|e| ReadError::new(config, e))?
+                        };
+                        read_blocks(ctx, blocks, senders, budget)
+                    }));
+                }
+            }
+            threads
+                .into_iter()
+                .try_for_each(|reader| reader.join().unwrap())
+        } else {
+            // The workers are already running, so pinning this thread does
+            // not carry over to them
+            if let Some(core) = config.pin_reader {
+                affinity::pin_or_warn("reader", &[core]);
+            }
+            let senders = senders.pop().expect("one partition");
+            ctx.assign(manifest::reader(1, 0, 0), &(0..source.len()));
+            source
+                .blocks(0..source.len(), block_size, config.csv_strict)
+                .map_err(|e| ReadError::new(config, e).into())
+                .and_then(|blocks| read_blocks(ctx, blocks, senders, budget))
+        };
+
+        // Wait for all worker threads to complete. A failed worker is the
+        // cause of anything the reader ran into sending to it
+        let results = handles.into_iter().map(|h| h.join().unwrap()).collect();
+        let thread_datas: Vec<ThreadData> = worker::collect(results, config.best_effort)?;
+        read?;
+        Ok((
+            thread_datas.iter().map(|data| data.lines).sum(),
+            thread_datas,
+        ))
+    })
+}
+
+/// A block of lines starting at byte `offset`, counted against the queue
+/// budget until it is dropped, whether processed or not.
+struct Chunk<'a> {
+    offset: u64,
+    text: Block,
+    cost: usize,
+    ctx: &'a Context,
+    budget: Option<&'a QueueBudget>,
+}
+
+impl Drop for Chunk<'_> {
+    fn drop(&mut self) {
+        self.ctx.memory.queues.sub(self.cost);
+        if let Some(budget) = self.budget {
+            budget.release(self.cost);
+        }
+    }
+}
+
+/// Sends `blocks` round-robin to `senders`. Dropping the senders at the end
+/// signals the workers to finish.
+fn read_blocks<'a>(
+    ctx: &'a Context,
+    blocks: Blocks,
+    mut senders: Vec<SyncSender<Chunk<'a>>>,
+    budget: Option<&'a QueueBudget>,
+) -> anyhow::Result<()> {
+    let mut next_worker = 0;
+    for block in blocks {
+        let (offset, text) = block.map_err(|e| ReadError::new(&ctx.config, e))?;
+        let cost = chunk_cost(&text);
+        if let Some(budget) = budget {
+            budget.acquire(cost);
+        }
+        ctx.memory.queues.add(cost);
+        let mut chunk = Chunk {
+            offset,
+            text,
+            cost,
+            ctx,
+            budget,
+        };
+        ctx.memory.check()?;
+        if let Some(export) = &ctx.sinks.export {
+            export.dispatch(offset);
+        }
+        // A worker that failed has dropped its receiver, so its share goes
+        // to the others
+        loop {
+            if senders.is_empty() || ctx.cancelled.load(Ordering::Relaxed) {
+                return Ok(());
+            }
+            match senders[next_worker].send(chunk) {
+                Ok(()) => {
+                    next_worker = (next_worker + 1) % senders.len();
+                    break;
+                }
+                Err(SendError(unsent)) => {
+                    chunk = unsent;
+                    senders.remove(next_worker);
+                    if next_worker >= senders.len() {
+                        next_worker = 0;
+                    }
+                }
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,127 @@
+//! `--strategy prefix-table`: a small direct-mapped table in front of each
+//! worker's map, indexed by the first 8 bytes of the name. A station that
+//! finds its slot free keeps it for the rest of the run, so most lookups
+//! of a few hundred stations end in one slot with one comparison of the
+//! prefix, and only the stations whose slot is taken go on to the map.
+//!
+//! The prefix only picks out candidates: a slot is taken as a key's once
+//! the whole key compares equal, so names sharing their first 8 bytes are
+//! never merged. `--stats` counts the lookups that found their slot taken,
+//! the table's collisions, each of which cost one probe more than a hit,
+//! the one into the map.
+
+use crate::arena::Arena;
+use crate::{Key, Stat};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// A power of two, well above the stations of the usual inputs
+const SLOTS: usize = 4096;
+
+#[derive(Debug)]
+struct Slot {
+    /// The first 8 bytes of the name, padded with zeros
+    prefix: u64,
+    key: Key,
+    stat: Stat,
+}
+
+/// Where [`PrefixTable::add`] put a reading.
+pub enum Added {
+    /// Into the slot of its key
+    Existing(Key),
+    /// Into a slot just claimed for a new key, which added this many bytes
+    New(Key, usize),
+    /// Nowhere, as its slot belongs to another key
+    Taken,
+}
+
+/// Collisions of every worker's table, added as each one is drained
+static COLLISIONS: [AtomicU64; 2] = [AtomicU64::new(0), AtomicU64::new(0)];
+
+#[derive(Debug, Default)]
+pub struct PrefixTable {
+    /// Allocated on the first reading
+    slots: Vec<Option<Slot>>,
+    /// Lookups whose slot belonged to another key
+    taken: u64,
+    /// Those of them whose name shares its first 8 bytes with the slot's
+    same_prefix: u64,
+}
+
+impl PrefixTable {
+    /// Adds `value` to the stat of `lookup`, if the key has a slot or its
+    /// slot is still free. A key whose slot is taken is never in the table,
+    /// so its stat is in the worker's map or yet to be.
+    pub fn add(&mut self, lookup: Key, value: f64, arena: &mut Arena) -> Added {
+        if self.slots.is_empty() {
+            self.slots.resize_with(SLOTS, || None);
+        }
+        let prefix = prefix(&lookup.name);
+        let slot = &mut self.slots[index(prefix, &lookup)];
+        match slot {
+            Some(slot) if slot.prefix == prefix && slot.key == lookup => {
+                slot.stat.add(value);
+                Added::Existing(slot.key)
+            }
+            Some(slot) => {
+                self.taken += 1;
+                self.same_prefix += (slot.prefix == prefix) as u64;
+                Added::Taken
+            }
+            None => {
+                let key = lookup.stored(arena);
+                *slot = Some(Slot {
+                    prefix,
+                    key,
+                    stat: Stat::new(value),
+                });
+                Added::New(key, crate::spill::entry_size(&key))
+            }
+        }
+    }
+
+    /// The keys with a slot and their stats.
+    pub fn iter(&self) -> impl Iterator<Item = (&Key, &Stat)> {
+        self.slots
+            .iter()
+            .flatten()
+            .map(|slot| (&slot.key, &slot.stat))
+    }
+
+    /// Everything added so far, leaving the table empty.
+    pub fn drain(&mut self) -> impl Iterator<Item = (Key, Stat)> + '_ {
+        COLLISIONS[0].fetch_add(std::mem::take(&mut self.taken), Ordering::Relaxed);
+        COLLISIONS[1].fetch_add(std::mem::take(&mut self.same_prefix), Ordering::Relaxed);
+        self.slots
+            .drain(..)
+            .flatten()
+            .map(|slot| (slot.key, slot.stat))
+    }
+}
+
+/// The lookups of the drained tables whose slot was taken, and how many of
+/// them had the prefix of the slot's key.
+pub fn collisions() -> (u64, u64) {
+    (
+        COLLISIONS[0].load(Ordering::Relaxed),
+        COLLISIONS[1].load(Ordering::Relaxed),
+    )
+}
+
+fn prefix(name: &str) -> u64 {
+    let mut bytes = [0; 8];
+    let len = name.len().min(8);
+    bytes[..len].copy_from_slice(&name.as_bytes()[..len]);
+    u64::from_le_bytes(bytes)
+}
+
+/// Fibonacci hashing of the prefix, the length, the window and the column,
+/// so that names sharing their first 8 bytes still spread out when the
+/// rest of their keys differ
+fn index(prefix: u64, key: &Key) -> usize {
+    let mixed = prefix
+        ^ (key.name.len() as u64).rotate_left(56)
+        ^ key.window.unwrap_or(0) as u64
+        ^ (key.column.unwrap_or(0) as u64).rotate_left(48);
+    (mixed.wrapping_mul(0x9e37_79b9_7f4a_7c15) >> (64 - SLOTS.trailing_zeros())) as usize
+}
@@ -0,0 +1,87 @@
+//! Lowering the run's CPU and I/O priority for `--nice` and `--idle-io`,
+//! so that a long aggregation gives way to whatever else the machine is
+//! doing.
+//!
+//! Both are set on the main thread before it starts any other. Linux keeps
+//! them per thread, and a new thread starts with those of the thread that
+//! spawned it, so the readers and workers of every executor, tokio's
+//! included, run with them too.
+
+use crate::cli::Config;
+use anyhow::Context as _;
+use std::io;
+
+/// Applies the priorities `config` asks for to the calling thread, and so
+/// to every thread it goes on to start.
+pub fn lower(config: &Config) -> anyhow::Result<()> {
+    if let Some(nice) = config.nice {
+        set_nice(nice).with_context(|| format!("cannot set the niceness to {}", nice))?;
+    }
+    if config.idle_io {
+        set_idle_io().context("cannot set the idle I/O class")?;
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn set_nice(nice: i32) -> io::Result<()> {
+    use std::ffi::{c_int, c_uint};
+
+    unsafe extern "C" {
+        fn setpriority(which: c_int, who: c_uint, prio: c_int) -> c_int;
+    }
+    const PRIO_PROCESS: c_int = 0;
+
+    // SAFETY: who 0 is the calling thread on Linux and the process
+    // elsewhere
+    if unsafe { setpriority(PRIO_PROCESS, 0, nice) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn set_nice(_nice: i32) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "niceness is only supported on Unix",
+    ))
+}
+
+/// Puts the calling thread in the idle I/O class, as `ionice -c 3` does,
+/// whose reads wait for the disk to have nothing else to do.
+#[cfg(target_os = "linux")]
+fn set_idle_io() -> io::Result<()> {
+    use std::ffi::{c_int, c_long};
+
+    unsafe extern "C" {
+        fn syscall(number: c_long, ...) -> c_long;
+    }
+    // glibc has no wrapper for it
+    #[cfg(target_arch = "x86_64")]
+    const SYS_IOPRIO_SET: c_long = 251;
+    #[cfg(target_arch = "x86")]
+    const SYS_IOPRIO_SET: c_long = 289;
+    #[cfg(target_arch = "arm")]
+    const SYS_IOPRIO_SET: c_long = 314;
+    #[cfg(not(any(target_arch = "x86_64", target_arch = "x86", target_arch = "arm")))]
+    const SYS_IOPRIO_SET: c_long = 30;
+    const IOPRIO_WHO_PROCESS: c_int = 1;
+    const IOPRIO_CLASS_IDLE: c_int = 3;
+    const IOPRIO_CLASS_SHIFT: c_int = 13;
+
+    let ioprio = IOPRIO_CLASS_IDLE << IOPRIO_CLASS_SHIFT;
+    // SAFETY: ioprio_set takes three ints, and who 0 is the calling thread
+    if unsafe { syscall(SYS_IOPRIO_SET, IOPRIO_WHO_PROCESS, 0 as c_int, ioprio) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn set_idle_io() -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "I/O classes are only supported on Linux",
+    ))
+}
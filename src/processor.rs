@@ -0,0 +1,462 @@
+//! The aggregation for programs that embed it: a [`ProcessorBuilder`] takes
+//! the options the command line would, and checks them the same way once
+//! it builds the [`Processor`].
+//!
+//! ```no_run
+//! use onebrc::cli::Executor;
+//! use onebrc::processor::ProcessorBuilder;
+//!
+//! let processor = ProcessorBuilder::new("measurements.txt")
+//!     .threads(4)
+//!     .executor(Executor::Threads)
+//!     .option("unit", "fahrenheit")
+//!     .build()?;
+//! let results = processor.run_blocking()?;
+//! println!("{} stations in {} lines", results.stats.len(), results.lines);
+//! # anyhow::Ok(())
+//! ```
+
+use crate::cli::{self, Config, Executor, Strategy, Verbosity};
+use crate::metadata::Metadata;
+use crate::results::Results;
+use crate::{Sinks, Tracking, cgroup};
+use anyhow::bail;
+use std::sync::Arc;
+
+/// The options of a [`Processor`], checked when it is built. Options
+/// without a method of their own are set by their long name, and a value
+/// that does not parse fails [`build`](ProcessorBuilder::build) as an
+/// invalid combination does.
+pub struct ProcessorBuilder {
+    config: Config,
+    inputs: Vec<String>,
+    /// The first option that did not parse
+    error: Option<anyhow::Error>,
+}
+
+impl ProcessorBuilder {
+    /// The command line's defaults for reading `input`, without the
+    /// diagnostics it prints to stderr.
+    pub fn new(input: impl Into<String>) -> Self {
+        ProcessorBuilder {
+            config: Config {
+                verbosity: Verbosity::Quiet,
+                keep_results: true,
+                ..Config::default()
+            },
+            inputs: vec![input.into()],
+            error: None,
+        }
+    }
+
+    /// Another input, whose results are added to those of the others.
+    pub fn input(mut self, input: impl Into<String>) -> Self {
+        self.inputs.push(input.into());
+        self
+    }
+
+    /// Workers to aggregate on, by default as many as the CPUs the process
+    /// may use.
+    pub fn threads(mut self, threads: usize) -> Self {
+        self.config.threads = Some(threads);
+        self
+    }
+
+    pub fn executor(mut self, executor: Executor) -> Self {
+        self.config.executor = executor;
+        self
+    }
+
+    pub fn strategy(mut self, strategy: Strategy) -> Self {
+        self.config.strategy = strategy;
+        self
+    }
+
+    /// Leaves out readings below `min`, as `--min-value` does.
+    pub fn min_value(mut self, min: f64) -> Self {
+        self.config.range.min = Some(min);
+        self
+    }
+
+    /// Leaves out readings above `max`, as `--max-value` does.
+    pub fn max_value(mut self, max: f64) -> Self {
+        self.config.range.max = Some(max);
+        self
+    }
+
+    /// Sets `--long` to `value`, as in `option("agg", "p95")`.
+    pub fn option(self, long: &str, value: &str) -> Self {
+        self.set(long, Some(value))
+    }
+
+    /// Sets the flag `--long`, as in `flag("lenient-numbers")`.
+    pub fn flag(self, long: &str) -> Self {
+        self.set(long, None)
+    }
+
+    fn set(mut self, long: &str, value: Option<&str>) -> Self {
+        if self.error.is_none()
+            && let Err(e) = cli::set_option(&mut self.config, long, value)
+        {
+            self.error = Some(e);
+        }
+        self
+    }
+
+    /// Checks the options as the command line does, along with those that
+    /// only a command line run can have.
+    pub fn build(self) -> anyhow::Result<Processor> {
+        if let Some(e) = self.error {
+            return Err(e);
+        }
+        let mut config = self.config;
+        config.input = self.inputs[0].clone();
+        config.inputs = self.inputs;
+        // What the run prints or writes out instead of returning, or sets
+        // for the whole process
+        let printed = [
+            ("--watch-dir", config.watch_dir.is_some()),
+            ("--per-file", config.per_file),
+            ("--partition-output-by", config.partition_by.is_some()),
+            ("--query", config.query.is_some()),
+            ("--report", config.report.is_some()),
+            ("--manifest", config.manifest.is_some()),
+            ("--verify-manifest", config.verify_manifest.is_some()),
+            ("--estimate-rows", config.estimate_rows),
+            ("--tui", config.tui),
+            ("--timeout", config.timeout.is_some()),
+            ("--nice", config.nice.is_some()),
+            ("--idle-io", config.idle_io),
+        ];
+        if let Some((option, _)) = printed.iter().find(|(_, set)| *set) {
+            bail!("{} only applies to the command line", option);
+        }
+        cli::check(&mut config)?;
+        // Results hold what every key has, as saved ones do
+        if Tracking::of(&config.agg, config.track_extremes).any() {
+            bail!(
+                "--agg stddev and percentiles, and --track-extremes, only apply to the command line"
+            );
+        }
+        if !config.value_columns.is_empty() {
+            bail!("--value-columns only applies to the command line");
+        }
+        let threads = config
+            .threads
+            .unwrap_or_else(|| cgroup::Limits::detect().parallelism());
+        Ok(Processor { config, threads })
+    }
+}
+
+/// A run of the aggregation over its inputs, with options that go
+/// together.
+pub struct Processor {
+    config: Config,
+    threads: usize,
+}
+
+impl Processor {
+    /// The options it runs with, `--executor auto` resolved.
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    pub fn threads(&self) -> usize {
+        self.threads
+    }
+
+    /// Reads every input on the configured executor, or takes its results
+    /// from `--cache-dir`, and adds them up. With `--executor tokio` the
+    /// run has a runtime of its own, so it must not be made from inside
+    /// one; [`run`](Processor::run) is for that.
+    pub fn run_blocking(&self) -> anyhow::Result<Results> {
+        let (metadata, sinks) = self.setup()?;
+        let mut results = Results::default();
+        for config in self.inputs() {
+            // Results own their names, so the arenas go with each input
+            let mut arenas = vec![];
+            let (summary, _) = crate::summarize(
+                &config,
+                metadata.as_ref(),
+                self.threads,
+                &sinks,
+                &mut arenas,
+            )?;
+            results.merge(summary.to_results());
+        }
+        Ok(results)
+    }
+
+    /// What [`run_blocking`](Processor::run_blocking) returns, from the
+    /// tokio pipeline on the runtime it is awaited on, whatever the
+    /// executor.
+    #[cfg(feature = "runtime-tokio")]
+    pub async fn run(&self) -> anyhow::Result<Results> {
+        let (metadata, sinks) = self.setup()?;
+        let mut results = Results::default();
+        for config in self.inputs() {
+            let mut arenas = vec![];
+            let cache = crate::cache_for(&config, &sinks)?;
+            let cached = cache
+                .as_ref()
+                .and_then(|cache| crate::load_cached(&config, cache, &mut arenas));
+            let summary = match cached {
+                Some(summary) => summary,
+                None => {
+                    let scan =
+                        crate::Scan::start(&config, metadata.as_ref(), self.threads, &sinks)?;
+                    let run = crate::async_pipeline::run_pipeline(
+                        &scan.ctx,
+                        &scan.source,
+                        &scan.nodes,
+                        self.threads,
+                        scan.spill_limit,
+                    )
+                    .await;
+                    let summary = scan.finish(run, &mut arenas)?;
+                    if let Some(cache) = &cache {
+                        crate::store_cached(&config, cache, &summary);
+                    }
+                    summary
+                }
+            };
+            results.merge(summary.to_results());
+        }
+        Ok(results)
+    }
+
+    /// The metadata and the row sinks every input shares.
+    fn setup(&self) -> anyhow::Result<(Option<Metadata>, Arc<Sinks>)> {
+        let metadata = match &self.config.metadata {
+            Some(path) => Some(Metadata::load(path, self.config.normalize_keys)?),
+            None => None,
+        };
+        Ok((metadata, Arc::new(Sinks::new(&self.config)?)))
+    }
+
+    /// The options for each input on its own.
+    fn inputs(&self) -> impl Iterator<Item = Arc<Config>> + '_ {
+        self.config.inputs.iter().map(|input| {
+            Arc::new(Config {
+                input: input.clone(),
+                ..self.config.clone()
+            })
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    /// A file under the temporary directory holding `text`, removed when
+    /// dropped.
+    struct Input(PathBuf);
+
+    impl Input {
+        fn new(name: &str, text: &str) -> Self {
+            let path = std::env::temp_dir().join(format!("1brc-{}-{}", std::process::id(), name));
+            std::fs::write(&path, text).unwrap();
+            Input(path)
+        }
+
+        fn path(&self) -> &str {
+            self.0.to_str().unwrap()
+        }
+    }
+
+    impl Drop for Input {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.0);
+        }
+    }
+
+    /// The results as saved, to compare them whole.
+    #[cfg(feature = "runtime-tokio")]
+    fn snapshot(results: &Results) -> Vec<u8> {
+        let mut bytes = vec![];
+        results.serialize(&mut bytes).unwrap();
+        bytes
+    }
+
+    fn error(builder: ProcessorBuilder) -> String {
+        match builder.build() {
+            Ok(_) => panic!("built"),
+            Err(e) => e.to_string(),
+        }
+    }
+
+    #[test]
+    fn build_resolves_the_executor() {
+        let processor = ProcessorBuilder::new("m.txt").threads(3).build().unwrap();
+        assert_eq!(processor.config().executor, Executor::Threads);
+        assert_eq!(processor.threads(), 3);
+        let processor = ProcessorBuilder::new("m.txt")
+            .flag("csv-strict")
+            .build()
+            .unwrap();
+        assert_eq!(processor.config().executor, Executor::Pipeline);
+        let processor = ProcessorBuilder::new("a.txt")
+            .input("b.txt")
+            .build()
+            .unwrap();
+        assert_eq!(processor.config().input, "a.txt");
+        assert_eq!(processor.config().inputs, ["a.txt", "b.txt"]);
+    }
+
+    #[test]
+    fn build_rejects_options_that_do_not_go_together() {
+        let cases = [
+            (
+                ProcessorBuilder::new("m.txt")
+                    .min_value(10.0)
+                    .max_value(-10.0),
+                "--min-value must not be greater than --max-value",
+            ),
+            (
+                ProcessorBuilder::new("m.txt")
+                    .executor(Executor::Threads)
+                    .flag("csv-strict"),
+                "--executor threads cannot be combined with --csv-strict",
+            ),
+            (
+                ProcessorBuilder::new("m.txt")
+                    .executor(Executor::Threads)
+                    .option("readers", "2"),
+                "--readers cannot be combined with --executor threads, whose workers read",
+            ),
+            (
+                ProcessorBuilder::new("m.txt")
+                    .strategy(Strategy::SharedMap)
+                    .flag("best-effort"),
+                "--strategy shared-map cannot be combined with --best-effort",
+            ),
+            (
+                ProcessorBuilder::new("m.txt")
+                    .strategy(Strategy::SharedMap)
+                    .option("spill-budget", "1M"),
+                "--strategy shared-map cannot be combined with --spill-budget or --max-memory",
+            ),
+            (
+                ProcessorBuilder::new("m.txt")
+                    .strategy(Strategy::Vectorized)
+                    .option("input-format", "jsonl"),
+                "--strategy vectorized only applies to --input-format csv without --csv-strict",
+            ),
+            (
+                ProcessorBuilder::new("m.txt").option("value-columns", "1"),
+                "--value-columns must come after the station, which is field 1",
+            ),
+            (
+                ProcessorBuilder::new("m.txt")
+                    .option("value-columns", "2")
+                    .option("input-format", "jsonl"),
+                "--value-columns only applies to --input-format csv",
+            ),
+            (
+                ProcessorBuilder::new("m.txt")
+                    .strategy(Strategy::Vectorized)
+                    .option("value-columns", "2"),
+                "--strategy vectorized cannot be combined with --value-columns",
+            ),
+            (
+                ProcessorBuilder::new("m.txt")
+                    .flag("count-only")
+                    .option("agg", "p95"),
+                "--count-only and --list-stations cannot be combined with --agg",
+            ),
+            (
+                ProcessorBuilder::new("m.txt")
+                    .option("agg", "stddev")
+                    .option("cache-dir", "cache"),
+                "--agg stddev and percentiles, and --track-extremes, cannot be combined with --cache-dir or --watch-dir",
+            ),
+            (
+                ProcessorBuilder::new("m.txt").option("agg", "stddev"),
+                "--agg stddev and percentiles, and --track-extremes, only apply to the command line",
+            ),
+            (
+                ProcessorBuilder::new("m.txt").option("value-columns", "2,3"),
+                "--value-columns only applies to the command line",
+            ),
+            (
+                ProcessorBuilder::new("m.txt").option("watch-dir", "incoming"),
+                "--watch-dir only applies to the command line",
+            ),
+        ];
+        for (builder, expected) in cases {
+            assert_eq!(error(builder), expected);
+        }
+    }
+
+    #[test]
+    fn options_that_do_not_parse_fail_the_build() {
+        assert_eq!(
+            error(ProcessorBuilder::new("m.txt").option("no-such-option", "1")),
+            "unknown option --no-such-option"
+        );
+        assert_eq!(
+            error(ProcessorBuilder::new("m.txt").flag("unit")),
+            "--unit requires a value"
+        );
+        assert!(
+            error(ProcessorBuilder::new("m.txt").option("threads", "many"))
+                .starts_with("invalid value for --threads")
+        );
+    }
+
+    #[test]
+    fn run_blocking_adds_up_every_input() {
+        let a = Input::new("run-a.txt", "Hamburg;12.0\nBulawayo;8.9\nHamburg;-3.4\n");
+        let b = Input::new("run-b.txt", "Bulawayo;30.1\nPalembang;38.8\n");
+        let results = ProcessorBuilder::new(a.path())
+            .input(b.path())
+            .threads(2)
+            .build()
+            .unwrap()
+            .run_blocking()
+            .unwrap();
+        assert_eq!(results.lines, 5);
+        let stations: Vec<(&str, f64, f64, i64)> = results
+            .stats
+            .iter()
+            .map(|(key, stat)| (key.name.as_str(), stat.min, stat.max, stat.count))
+            .collect();
+        assert_eq!(
+            stations,
+            [
+                ("Bulawayo", 8.9, 30.1, 2),
+                ("Hamburg", -3.4, 12.0, 2),
+                ("Palembang", 38.8, 38.8, 1)
+            ]
+        );
+    }
+
+    #[cfg(feature = "runtime-tokio")]
+    #[test]
+    fn run_and_run_blocking_have_the_same_results() {
+        let text: String = (0..2000)
+            .map(|i| format!("Station {};{}.{}\n", i % 37, i % 90 - 40, i % 10))
+            .collect();
+        let a = Input::new("same-a.txt", &text);
+        let b = Input::new("same-b.txt", "Hamburg;12.0\nStation 3;99.9\n");
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(2)
+            .build()
+            .unwrap();
+        for executor in [Executor::Threads, Executor::Pipeline, Executor::Tokio] {
+            let processor = ProcessorBuilder::new(a.path())
+                .input(b.path())
+                .threads(1)
+                .executor(executor)
+                .option("chunk-bytes", "4K")
+                .build()
+                .unwrap();
+            let blocking = processor.run_blocking().unwrap();
+            let awaited = runtime.block_on(processor.run()).unwrap();
+            assert_eq!(awaited.lines, 2002);
+            assert_eq!(snapshot(&awaited), snapshot(&blocking));
+        }
+    }
+}
@@ -0,0 +1,190 @@
+//! Named bundles of options in a config file, selected with `--profile`.
+//!
+//! The file is a small subset of TOML: `[profile.NAME]` tables of
+//! `option = value` pairs, where the keys are long option names. A string,
+//! number or `true` is the option's value, `true` sets a flag, and an array
+//! gives a repeatable option once per element:
+//!
+//! ```toml
+//! [profile.server]
+//! threads = 64
+//! io = "mmap"
+//! chunk-bytes = "16M"
+//! numa = "auto"
+//! station = ["Abha", "Zürich"]
+//! ```
+//!
+//! A profile's options come before the command line's, so an option given
+//! on the command line overrides what the profile says.
+
+use anyhow::{Context, anyhow, bail};
+use std::path::{Path, PathBuf};
+
+/// Where the config file is looked for without `--config`.
+pub fn default_path() -> Option<PathBuf> {
+    let base = match std::env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(std::env::var_os("HOME")?).join(".config"),
+    };
+    Some(base.join("1brc").join("config.toml"))
+}
+
+/// The options profile `name` in the file at `path` sets, as
+/// `(long name, value)` with no value for a flag.
+pub fn load(path: &Path, name: &str) -> anyhow::Result<Vec<(String, Option<String>)>> {
+    let text = std::fs::read_to_string(path)
+        .with_context(|| format!("reading config file {}", path.display()))?;
+    let mut profiles = parse(&text).with_context(|| format!("in {}", path.display()))?;
+    match profiles.iter().position(|(profile, _)| profile == name) {
+        Some(i) => Ok(profiles.swap_remove(i).1),
+        None => {
+            let names: Vec<&str> = profiles.iter().map(|(name, _)| name.as_str()).collect();
+            bail!(
+                "no profile {} in {} (it has {})",
+                name,
+                path.display(),
+                if names.is_empty() {
+                    "none".to_string()
+                } else {
+                    names.join(", ")
+                }
+            )
+        }
+    }
+}
+
+type Profile = (String, Vec<(String, Option<String>)>);
+
+fn parse(text: &str) -> anyhow::Result<Vec<Profile>> {
+    let mut profiles: Vec<Profile> = vec![];
+    for (i, line) in text.lines().enumerate() {
+        let line = strip_comment(line).trim();
+        if line.is_empty() {
+            continue;
+        }
+        let context = || format!("line {}", i + 1);
+        if let Some(header) = line.strip_prefix('[') {
+            let name = header
+                .strip_suffix(']')
+                .and_then(|header| header.trim().strip_prefix("profile."))
+                .map(|name| name.trim().trim_matches('"'))
+                .filter(|name| !name.is_empty())
+                .ok_or_else(|| anyhow!("expected [profile.NAME], got {}", line))
+                .with_context(context)?;
+            if profiles.iter().any(|(profile, _)| profile == name) {
+                return Err(anyhow!("profile {} is defined twice", name)).with_context(context);
+            }
+            profiles.push((name.to_string(), vec![]));
+            continue;
+        }
+        let (key, value) = line
+            .split_once('=')
+            .ok_or_else(|| anyhow!("expected option = value, got {}", line))
+            .with_context(context)?;
+        let Some((_, options)) = profiles.last_mut() else {
+            return Err(anyhow!("{} is outside any [profile.NAME]", key.trim()))
+                .with_context(context);
+        };
+        let key = key.trim().to_string();
+        for value in values(value.trim()).with_context(context)? {
+            options.push((key.clone(), value));
+        }
+    }
+    Ok(profiles)
+}
+
+/// `line` up to a `#` outside quotes.
+fn strip_comment(line: &str) -> &str {
+    let mut quote = None;
+    let mut escaped = false;
+    for (i, c) in line.char_indices() {
+        match (quote, c) {
+            (Some('"'), '\\') if !escaped => {
+                escaped = true;
+                continue;
+            }
+            (Some(q), c) if c == q && !escaped => quote = None,
+            (None, '"' | '\'') => quote = Some(c),
+            (None, '#') => return &line[..i],
+            _ => {}
+        }
+        escaped = false;
+    }
+    line
+}
+
+/// The values of `value`, one unless it is an array, with `true` as no
+/// value, that of a flag.
+fn values(value: &str) -> anyhow::Result<Vec<Option<String>>> {
+    let Some(elements) = value.strip_prefix('[') else {
+        return Ok(vec![scalar(value)?]);
+    };
+    let elements = elements
+        .strip_suffix(']')
+        .ok_or_else(|| anyhow!("an array must end on its line: {}", value))?;
+    let mut out = vec![];
+    let mut rest = elements.trim();
+    while !rest.is_empty() {
+        let end = match rest.chars().next() {
+            Some(q @ ('"' | '\'')) => {
+                closing_quote(rest, q).ok_or_else(|| anyhow!("unterminated string: {}", rest))? + 1
+            }
+            _ => rest.find(',').unwrap_or(rest.len()),
+        };
+        out.push(scalar(rest[..end].trim())?);
+        rest = rest[end..].trim_start();
+        rest = rest.strip_prefix(',').unwrap_or(rest).trim_start();
+    }
+    Ok(out)
+}
+
+/// The byte offset of the quote closing the string `s` starts.
+fn closing_quote(s: &str, quote: char) -> Option<usize> {
+    let mut escaped = false;
+    for (i, c) in s.char_indices().skip(1) {
+        if c == quote && !escaped {
+            return Some(i);
+        }
+        escaped = quote == '"' && c == '\\' && !escaped;
+    }
+    None
+}
+
+fn scalar(value: &str) -> anyhow::Result<Option<String>> {
+    match value.chars().next() {
+        Some('"') => {
+            let end = closing_quote(value, '"').filter(|&end| end == value.len() - 1);
+            let inner = end
+                .map(|end| &value[1..end])
+                .ok_or_else(|| anyhow!("unterminated string: {}", value))?;
+            let mut out = String::with_capacity(inner.len());
+            let mut chars = inner.chars();
+            while let Some(c) = chars.next() {
+                if c != '\\' {
+                    out.push(c);
+                    continue;
+                }
+                match chars.next() {
+                    Some('"') => out.push('"'),
+                    Some('\\') => out.push('\\'),
+                    Some('n') => out.push('\n'),
+                    Some('t') => out.push('\t'),
+                    other => bail!("unknown escape \\{} in {}", other.unwrap_or(' '), value),
+                }
+            }
+            Ok(Some(out))
+        }
+        Some('\'') => value
+            .strip_prefix('\'')
+            .and_then(|value| value.strip_suffix('\''))
+            .filter(|inner| !inner.contains('\''))
+            .map(|inner| Some(inner.to_string()))
+            .ok_or_else(|| anyhow!("unterminated string: {}", value)),
+        _ if value == "true" => Ok(None),
+        _ if value == "false" => bail!("a flag is left out rather than set to false"),
+        _ if !value.is_empty() && !value.contains(char::is_whitespace) => {
+            Ok(Some(value.to_string()))
+        }
+        _ => bail!("expected a string, number or true, got {}", value),
+    }
+}
@@ -0,0 +1,146 @@
+//! `--debug-provenance`: which chunks each station's readings came from,
+//! checked against a sequential run over the whole file, for finding
+//! chunks that the dispatch drops or counts twice.
+
+use crate::input::Source;
+use crate::{Context, Key, Stat, ThreadData, new_thread_data, process_chunk};
+use std::collections::{BTreeSet, HashMap};
+use std::fmt::Write;
+
+/// Readings per station of one worker, by the offset of the chunk they
+/// were in.
+#[derive(Debug, Default)]
+pub struct Provenance {
+    worker: usize,
+    chunk: u64,
+    chunks: HashMap<Key, Vec<(u64, u64)>>,
+}
+
+impl Provenance {
+    pub fn new(worker: usize) -> Self {
+        Provenance {
+            worker,
+            ..Default::default()
+        }
+    }
+
+    /// Attributes the following readings to the chunk at `offset`.
+    pub fn start_chunk(&mut self, offset: u64) {
+        self.chunk = offset;
+    }
+
+    pub fn record(&mut self, key: Key) {
+        let chunks = self.chunks.entry(key).or_default();
+        match chunks.last_mut() {
+            Some((offset, count)) if *offset == self.chunk => *count += 1,
+            _ => chunks.push((self.chunk, 1)),
+        }
+    }
+}
+
+/// Aggregates the whole file again on this thread, in blocks like a single
+/// reader would, and compares it with the workers' results. Any station
+/// that differs is dumped along with the chunks that contributed to it on
+/// either side.
+pub fn check(ctx: &Context, source: &Source, thread_datas: &[ThreadData]) -> anyhow::Result<()> {
+    let mut baseline = new_thread_data(&ctx.config, 0, None);
+    let blocks = source.blocks(
+        0..source.len(),
+        ctx.config.chunk_bytes,
+        ctx.config.csv_strict,
+    )?;
+    for block in blocks {
+        let (offset, block) = block?;
+        process_chunk(&mut baseline, &block, offset, ctx, 0)?;
+    }
+    baseline.flush_prefix();
+
+    let mut parallel: HashMap<Key, Stat> = HashMap::new();
+    for data in thread_datas {
+        for (key, stat) in &data.stat {
+            match parallel.get_mut(key) {
+                Some(merged) => merged.merge(stat),
+                None => {
+                    parallel.insert(*key, stat.clone());
+                }
+            }
+        }
+    }
+    let keys: BTreeSet<&Key> = parallel.keys().chain(baseline.stat.keys()).collect();
+    let differing: Vec<&Key> = keys
+        .into_iter()
+        .filter(|key| !same(parallel.get(key), baseline.stat.get(key)))
+        .collect();
+    if differing.is_empty() {
+        eprintln!("provenance: results match a sequential run");
+        return Ok(());
+    }
+
+    eprintln!(
+        "provenance: {} stations differ from a sequential run",
+        differing.len()
+    );
+    for key in &differing {
+        let count = |stat: Option<&Stat>| stat.map_or(0, |stat| stat.count);
+        eprintln!(
+            "  {}: {} readings in parallel, {} sequentially",
+            describe(key),
+            count(parallel.get(key)),
+            count(baseline.stat.get(key))
+        );
+        // By offset, so that a chunk counted twice shows up twice in a row
+        let mut chunks: Vec<(u64, usize, u64)> = thread_datas
+            .iter()
+            .filter_map(|d| d.provenance.as_ref())
+            .flat_map(|p| {
+                let chunks = p.chunks.get(key).map_or(&[][..], Vec::as_slice);
+                chunks
+                    .iter()
+                    .map(|&(offset, count)| (offset, p.worker, count))
+            })
+            .collect();
+        chunks.sort_unstable();
+        let mut contributions = String::new();
+        for (offset, worker, count) in chunks {
+            let _ = write!(
+                contributions,
+                " worker {} chunk at byte {} ({}),",
+                worker, offset, count
+            );
+        }
+        eprintln!("    parallel:{}", contributions.trim_end_matches(','));
+        let mut contributions = String::new();
+        let sequential = baseline.provenance.as_ref().and_then(|p| p.chunks.get(key));
+        for (offset, count) in sequential.into_iter().flatten() {
+            let _ = write!(contributions, " chunk at byte {} ({}),", offset, count);
+        }
+        eprintln!("    sequential:{}", contributions.trim_end_matches(','));
+    }
+    anyhow::bail!("parallel results differ from a sequential run");
+}
+
+/// Whether two aggregates agree, allowing for the sums having been added
+/// up in a different order.
+fn same(a: Option<&Stat>, b: Option<&Stat>) -> bool {
+    match (a, b) {
+        (Some(a), Some(b)) => {
+            a.count == b.count
+                && a.min == b.min
+                && a.max == b.max
+                && (a.sum - b.sum).abs() <= 1e-9 * a.sum.abs().max(1.0) * a.count as f64
+        }
+        (None, None) => true,
+        _ => false,
+    }
+}
+
+fn describe(key: &Key) -> String {
+    let mut out = key.name.to_string();
+    if let Some(window) = key.window {
+        out.push_str(&format!(" (window {})", window));
+    }
+    if let Some(column) = key.column {
+        out.push_str(&format!(" (column {})", column));
+    }
+    out
+}
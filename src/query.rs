@@ -0,0 +1,727 @@
+//! `--query`: a small SQL dialect over the aggregated results, for ad hoc
+//! questions that would otherwise mean exporting them somewhere else. There
+//! is one table, `results`, with the columns of `--format table`:
+//!
+//! ```text
+//! SELECT station, max FROM results WHERE max > 40 ORDER BY max DESC LIMIT 10
+//! ```
+//!
+//! `WHERE` takes comparisons of columns with numbers or `'strings'`, `LIKE`
+//! patterns, `AND`, `OR`, `NOT` and parentheses. Numbers are compared as
+//! shown, rounded to `--precision` decimals.
+
+use crate::output::{self, Table};
+use anyhow::{anyhow, bail};
+use std::cmp::Ordering;
+
+#[derive(Clone, Debug)]
+pub struct Query {
+    /// `None` for `*`
+    columns: Option<Vec<String>>,
+    filter: Option<Expr>,
+    /// Columns and whether they are descending
+    order: Vec<(String, bool)>,
+    limit: Option<usize>,
+}
+
+#[derive(Clone, Debug)]
+enum Expr {
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+    Compare(Operand, Op, Operand),
+    Like(Operand, String),
+}
+
+#[derive(Clone, Debug)]
+enum Operand {
+    Column(String),
+    Number(f64),
+    Text(String),
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Op {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Word(String),
+    Number(f64),
+    Text(String),
+    Symbol(&'static str),
+}
+
+/// A cell of a row, `Null` for one left empty such as a station missing
+/// from the metadata.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Value<'a> {
+    Null,
+    Number(f64),
+    Text(&'a str),
+}
+
+impl Query {
+    pub fn parse(sql: &str) -> anyhow::Result<Self> {
+        let mut parser = Parser {
+            tokens: tokenize(sql)?,
+            pos: 0,
+        };
+        let query = parser.query()?;
+        if let Some(token) = parser.peek() {
+            bail!("unexpected {} after the query", describe(token));
+        }
+        Ok(query)
+    }
+
+    /// Fails on a column `header` does not have, or on a comparison of
+    /// values that cannot be compared, so that a query can be checked
+    /// before there are results to run it on.
+    pub fn check(&self, header: &[String]) -> anyhow::Result<()> {
+        let column = |name: &str| match header.iter().any(|h| h == name) {
+            true => Ok(()),
+            false => Err(anyhow!(
+                "unknown column `{}`, the results have {}",
+                name,
+                header.join(", ")
+            )),
+        };
+        let ordered = self.order.iter().map(|(name, _)| name);
+        for name in self.columns.iter().flatten().chain(ordered) {
+            column(name)?;
+        }
+        match &self.filter {
+            Some(filter) => filter.check(&column),
+            None => Ok(()),
+        }
+    }
+
+    /// Runs the query over `table`, whose rows are in the default order
+    /// until `ORDER BY` says otherwise.
+    pub fn run(&self, table: &Table) -> anyhow::Result<Table> {
+        self.check(&table.header)?;
+        let column = |name: &str| {
+            table
+                .header
+                .iter()
+                .position(|h| h == name)
+                .expect("checked")
+        };
+        let selected: Vec<usize> = match &self.columns {
+            Some(columns) => columns.iter().map(|name| column(name)).collect(),
+            None => (0..table.header.len()).collect(),
+        };
+        let order: Vec<(usize, bool)> = self
+            .order
+            .iter()
+            .map(|(name, descending)| (column(name), *descending))
+            .collect();
+
+        let mut rows = vec![];
+        for row in &table.rows {
+            let keep = match &self.filter {
+                Some(filter) => filter.eval(table, row)?,
+                None => true,
+            };
+            if keep {
+                rows.push(row);
+            }
+        }
+        // Stable, so ties keep the default order; empty cells go last
+        rows.sort_by(|a, b| {
+            for &(i, descending) in &order {
+                let ordering = match (value(table, a, i), value(table, b, i)) {
+                    (Value::Null, Value::Null) => Ordering::Equal,
+                    (Value::Null, _) => return Ordering::Greater,
+                    (_, Value::Null) => return Ordering::Less,
+                    (Value::Number(a), Value::Number(b)) => a.total_cmp(&b),
+                    (a, b) => text(a).cmp(text(b)),
+                };
+                let ordering = if descending {
+                    ordering.reverse()
+                } else {
+                    ordering
+                };
+                if ordering != Ordering::Equal {
+                    return ordering;
+                }
+            }
+            Ordering::Equal
+        });
+        rows.truncate(self.limit.unwrap_or(usize::MAX));
+
+        Ok(Table {
+            header: selected.iter().map(|&i| table.header[i].clone()).collect(),
+            rows: rows
+                .into_iter()
+                .map(|row| selected.iter().map(|&i| row[i].clone()).collect())
+                .collect(),
+        })
+    }
+}
+
+impl Expr {
+    /// Fails on a column the table does not have, or on operands of
+    /// different types, before any row is looked at.
+    fn check(&self, column: &dyn Fn(&str) -> anyhow::Result<()>) -> anyhow::Result<()> {
+        match self {
+            Expr::And(a, b) | Expr::Or(a, b) => {
+                a.check(column)?;
+                b.check(column)
+            }
+            Expr::Not(a) => a.check(column),
+            Expr::Compare(a, _, b) => {
+                for operand in [a, b] {
+                    if let Operand::Column(name) = operand {
+                        column(name)?;
+                    }
+                }
+                if a.is_text() != b.is_text() {
+                    bail!("cannot compare {} with {}", a.describe(), b.describe());
+                }
+                Ok(())
+            }
+            Expr::Like(a, _) => {
+                if let Operand::Column(name) = a {
+                    column(name)?;
+                }
+                match a.is_text() {
+                    true => Ok(()),
+                    false => bail!("LIKE only applies to text, not {}", a.describe()),
+                }
+            }
+        }
+    }
+
+    fn eval(&self, table: &Table, row: &[String]) -> anyhow::Result<bool> {
+        let operand = |operand| resolve(operand, table, row);
+        Ok(match self {
+            Expr::And(a, b) => a.eval(table, row)? && b.eval(table, row)?,
+            Expr::Or(a, b) => a.eval(table, row)? || b.eval(table, row)?,
+            Expr::Not(a) => !a.eval(table, row)?,
+            Expr::Compare(a, op, b) => {
+                let ordering = match (operand(a), operand(b)) {
+                    (Value::Null, _) | (_, Value::Null) => return Ok(false),
+                    (Value::Number(a), Value::Number(b)) => a.total_cmp(&b),
+                    (Value::Text(a), Value::Text(b)) => a.cmp(b),
+                    (a, b) => bail!(
+                        "cannot compare {} with {}",
+                        describe_value(a),
+                        describe_value(b)
+                    ),
+                };
+                match op {
+                    Op::Eq => ordering == Ordering::Equal,
+                    Op::Ne => ordering != Ordering::Equal,
+                    Op::Lt => ordering == Ordering::Less,
+                    Op::Le => ordering != Ordering::Greater,
+                    Op::Gt => ordering == Ordering::Greater,
+                    Op::Ge => ordering != Ordering::Less,
+                }
+            }
+            Expr::Like(a, pattern) => match operand(a) {
+                Value::Null => false,
+                Value::Text(s) => like(s, pattern),
+                Value::Number(_) => bail!("LIKE only applies to text"),
+            },
+        })
+    }
+}
+
+impl Operand {
+    fn is_text(&self) -> bool {
+        match self {
+            Operand::Column(name) => output::is_text(name),
+            Operand::Number(_) => false,
+            Operand::Text(_) => true,
+        }
+    }
+
+    fn describe(&self) -> String {
+        match self {
+            Operand::Column(name) if output::is_text(name) => format!("the text column `{}`", name),
+            Operand::Column(name) => format!("the number column `{}`", name),
+            Operand::Number(n) => format!("the number {}", n),
+            Operand::Text(s) => format!("the text '{}'", s),
+        }
+    }
+}
+
+fn resolve<'a>(operand: &'a Operand, table: &Table, row: &'a [String]) -> Value<'a> {
+    match operand {
+        Operand::Column(name) => {
+            let i = table
+                .header
+                .iter()
+                .position(|h| h == name)
+                .expect("checked");
+            value(table, row, i)
+        }
+        Operand::Number(n) => Value::Number(*n),
+        Operand::Text(s) => Value::Text(s),
+    }
+}
+
+fn value<'a>(table: &Table, row: &'a [String], column: usize) -> Value<'a> {
+    let cell = &row[column];
+    if cell.is_empty() {
+        Value::Null
+    } else if table.is_text(column) {
+        Value::Text(cell)
+    } else {
+        cell.parse().map_or(Value::Text(cell), Value::Number)
+    }
+}
+
+fn text<'a>(value: Value<'a>) -> &'a str {
+    match value {
+        Value::Text(s) => s,
+        _ => "",
+    }
+}
+
+fn describe_value(value: Value) -> String {
+    match value {
+        Value::Null => "an empty value".to_string(),
+        Value::Number(n) => format!("the number {}", n),
+        Value::Text(s) => format!("the text '{}'", s),
+    }
+}
+
+/// SQL `LIKE`: `%` for any run of characters and `_` for any one.
+fn like(s: &str, pattern: &str) -> bool {
+    let s: Vec<char> = s.chars().collect();
+    let pattern: Vec<char> = pattern.chars().collect();
+    // Positions in `s` the pattern so far can end at
+    let mut ends = vec![false; s.len() + 1];
+    ends[0] = true;
+    for &p in &pattern {
+        let mut next = vec![false; s.len() + 1];
+        for i in 0..=s.len() {
+            if !ends[i] {
+                continue;
+            }
+            match p {
+                '%' => next[i..].iter_mut().for_each(|end| *end = true),
+                '_' if i < s.len() => next[i + 1] = true,
+                c if i < s.len() && s[i] == c => next[i + 1] = true,
+                _ => {}
+            }
+        }
+        ends = next;
+    }
+    ends[s.len()]
+}
+
+fn tokenize(sql: &str) -> anyhow::Result<Vec<Token>> {
+    const SYMBOLS: &[&str] = &["<=", ">=", "<>", "!=", "=", "<", ">", ",", "*", "(", ")"];
+    let mut tokens = vec![];
+    let mut rest = sql.trim_start();
+    while !rest.is_empty() {
+        let c = rest.chars().next().unwrap();
+        let len = if let Some(symbol) = SYMBOLS.iter().find(|s| rest.starts_with(**s)) {
+            tokens.push(Token::Symbol(symbol));
+            symbol.len()
+        } else if c == '\'' {
+            // Quotes inside strings are doubled
+            let mut text = String::new();
+            let mut chars = rest.char_indices().skip(1).peekable();
+            let end = loop {
+                match chars.next() {
+                    Some((i, '\'')) => {
+                        if chars.next_if(|&(_, c)| c == '\'').is_none() {
+                            break i + 1;
+                        }
+                        text.push('\'');
+                    }
+                    Some((_, c)) => text.push(c),
+                    None => bail!("unterminated string"),
+                }
+            };
+            tokens.push(Token::Text(text));
+            end
+        } else if c.is_ascii_digit() || c == '-' || c == '.' {
+            let len = rest[1..]
+                .find(|c: char| !c.is_ascii_digit() && c != '.')
+                .map_or(rest.len(), |i| i + 1);
+            let number = rest[..len]
+                .parse()
+                .map_err(|_| anyhow!("`{}` is not a number", &rest[..len]))?;
+            tokens.push(Token::Number(number));
+            len
+        } else if c.is_alphabetic() || c == '_' {
+            let len = rest
+                .find(|c: char| !c.is_alphanumeric() && c != '_')
+                .unwrap_or(rest.len());
+            tokens.push(Token::Word(rest[..len].to_lowercase()));
+            len
+        } else {
+            bail!("unexpected `{}`", c);
+        };
+        rest = rest[len..].trim_start();
+    }
+    Ok(tokens)
+}
+
+fn describe(token: &Token) -> String {
+    match token {
+        Token::Word(word) => format!("`{}`", word),
+        Token::Number(n) => format!("`{}`", n),
+        Token::Text(s) => format!("'{}'", s),
+        Token::Symbol(s) => format!("`{}`", s),
+    }
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> anyhow::Result<Token> {
+        let token = self
+            .tokens
+            .get(self.pos)
+            .cloned()
+            .ok_or_else(|| anyhow!("unexpected end of the query"))?;
+        self.pos += 1;
+        Ok(token)
+    }
+
+    /// Consumes the keyword or symbol `s` if it is next.
+    fn eat(&mut self, s: &str) -> bool {
+        let found = match self.peek() {
+            Some(Token::Word(word)) => word == s,
+            Some(Token::Symbol(symbol)) => *symbol == s,
+            _ => false,
+        };
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+
+    fn expect(&mut self, s: &str) -> anyhow::Result<()> {
+        if !self.eat(s) {
+            match self.peek() {
+                Some(token) => bail!("expected `{}`, got {}", s, describe(token)),
+                None => bail!("expected `{}` at the end of the query", s),
+            }
+        }
+        Ok(())
+    }
+
+    fn column(&mut self) -> anyhow::Result<String> {
+        match self.next()? {
+            Token::Word(word) if !is_keyword(&word) => Ok(word),
+            token => bail!("expected a column, got {}", describe(&token)),
+        }
+    }
+
+    fn query(&mut self) -> anyhow::Result<Query> {
+        self.expect("select")?;
+        let columns = if self.eat("*") {
+            None
+        } else {
+            let mut columns = vec![self.column()?];
+            while self.eat(",") {
+                columns.push(self.column()?);
+            }
+            Some(columns)
+        };
+        self.expect("from")?;
+        match self.next()? {
+            Token::Word(table) if table == "results" => {}
+            token => bail!("the only table is `results`, got {}", describe(&token)),
+        }
+        let filter = if self.eat("where") {
+            Some(self.or()?)
+        } else {
+            None
+        };
+        let mut order = vec![];
+        if self.eat("order") {
+            self.expect("by")?;
+            loop {
+                let column = self.column()?;
+                let descending = self.eat("desc");
+                if !descending {
+                    self.eat("asc");
+                }
+                order.push((column, descending));
+                if !self.eat(",") {
+                    break;
+                }
+            }
+        }
+        let limit = if self.eat("limit") {
+            match self.next()? {
+                Token::Number(n) if n >= 0.0 && n.fract() == 0.0 => Some(n as usize),
+                token => bail!("expected a row count after LIMIT, got {}", describe(&token)),
+            }
+        } else {
+            None
+        };
+        Ok(Query {
+            columns,
+            filter,
+            order,
+            limit,
+        })
+    }
+
+    fn or(&mut self) -> anyhow::Result<Expr> {
+        let mut expr = self.and()?;
+        while self.eat("or") {
+            expr = Expr::Or(Box::new(expr), Box::new(self.and()?));
+        }
+        Ok(expr)
+    }
+
+    fn and(&mut self) -> anyhow::Result<Expr> {
+        let mut expr = self.not()?;
+        while self.eat("and") {
+            expr = Expr::And(Box::new(expr), Box::new(self.not()?));
+        }
+        Ok(expr)
+    }
+
+    fn not(&mut self) -> anyhow::Result<Expr> {
+        if self.eat("not") {
+            return Ok(Expr::Not(Box::new(self.not()?)));
+        }
+        if self.eat("(") {
+            let expr = self.or()?;
+            self.expect(")")?;
+            return Ok(expr);
+        }
+        let left = self.operand()?;
+        let negated = self.eat("not");
+        if self.eat("like") {
+            let like = match self.next()? {
+                Token::Text(pattern) => Expr::Like(left, pattern),
+                token => bail!("expected a pattern after LIKE, got {}", describe(&token)),
+            };
+            return Ok(if negated {
+                Expr::Not(Box::new(like))
+            } else {
+                like
+            });
+        }
+        if negated {
+            bail!("expected LIKE after NOT");
+        }
+        let op = match self.next()? {
+            Token::Symbol("=") => Op::Eq,
+            Token::Symbol("!=" | "<>") => Op::Ne,
+            Token::Symbol("<") => Op::Lt,
+            Token::Symbol("<=") => Op::Le,
+            Token::Symbol(">") => Op::Gt,
+            Token::Symbol(">=") => Op::Ge,
+            token => bail!("expected a comparison, got {}", describe(&token)),
+        };
+        Ok(Expr::Compare(left, op, self.operand()?))
+    }
+
+    fn operand(&mut self) -> anyhow::Result<Operand> {
+        match self.next()? {
+            Token::Number(n) => Ok(Operand::Number(n)),
+            Token::Text(s) => Ok(Operand::Text(s)),
+            Token::Word(word) if !is_keyword(&word) => Ok(Operand::Column(word)),
+            token => bail!("expected a column or a value, got {}", describe(&token)),
+        }
+    }
+}
+
+fn is_keyword(word: &str) -> bool {
+    matches!(
+        word,
+        "select"
+            | "from"
+            | "where"
+            | "and"
+            | "or"
+            | "not"
+            | "like"
+            | "order"
+            | "by"
+            | "asc"
+            | "desc"
+            | "limit"
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn table() -> Table {
+        let rows = [
+            ["Hamburg", "-3.4", "9.7", "38.4"],
+            ["Bulawayo", "8.9", "18.9", "43.1"],
+            ["O'Higgins", "", "", ""],
+            ["Palembang", "15.4", "27.3", "39.0"],
+            ["Halifax", "-18.1", "7.5", "31.2"],
+        ];
+        Table {
+            header: ["station", "min", "mean", "max"].map(String::from).to_vec(),
+            rows: rows
+                .iter()
+                .map(|row| row.map(String::from).to_vec())
+                .collect(),
+        }
+    }
+
+    fn run(sql: &str) -> Vec<Vec<String>> {
+        Query::parse(sql).unwrap().run(&table()).unwrap().rows
+    }
+
+    fn stations(sql: &str) -> Vec<String> {
+        run(sql).into_iter().map(|row| row[0].clone()).collect()
+    }
+
+    fn error(sql: &str) -> String {
+        match Query::parse(sql).and_then(|query| query.check(&table().header)) {
+            Ok(()) => panic!("{} is valid", sql),
+            Err(e) => e.to_string(),
+        }
+    }
+
+    #[test]
+    fn select_keeps_the_listed_columns_in_their_order() {
+        let table = Query::parse("SELECT max, station FROM results LIMIT 1")
+            .unwrap()
+            .run(&table())
+            .unwrap();
+        assert_eq!(table.header, ["max", "station"]);
+        assert_eq!(table.rows, [["38.4", "Hamburg"]]);
+        assert_eq!(run("select * from results").len(), 5);
+    }
+
+    #[test]
+    fn and_binds_tighter_than_or() {
+        let sql = "SELECT station FROM results WHERE min < 0 AND max > 35 OR station = 'Bulawayo'";
+        assert_eq!(stations(sql), ["Hamburg", "Bulawayo"]);
+        let sql =
+            "SELECT station FROM results WHERE min < 0 AND (max > 35 OR station = 'Bulawayo')";
+        assert_eq!(stations(sql), ["Hamburg"]);
+        let sql = "SELECT station FROM results WHERE NOT station LIKE 'H%' AND max >= 39";
+        assert_eq!(stations(sql), ["Bulawayo", "Palembang"]);
+    }
+
+    #[test]
+    fn empty_cells_match_no_comparison() {
+        assert_eq!(
+            stations("SELECT station FROM results WHERE max <> 1"),
+            ["Hamburg", "Bulawayo", "Palembang", "Halifax"]
+        );
+    }
+
+    #[test]
+    fn order_by_sorts_ascending_or_descending_with_empty_cells_last() {
+        assert_eq!(
+            stations("SELECT station FROM results ORDER BY max DESC"),
+            ["Bulawayo", "Palembang", "Hamburg", "Halifax", "O'Higgins"]
+        );
+        assert_eq!(
+            stations("SELECT station FROM results ORDER BY max ASC"),
+            ["Halifax", "Hamburg", "Palembang", "Bulawayo", "O'Higgins"]
+        );
+        assert_eq!(
+            stations("SELECT station FROM results ORDER BY station LIMIT 2"),
+            ["Bulawayo", "Halifax"]
+        );
+        assert!(run("SELECT * FROM results LIMIT 0").is_empty());
+    }
+
+    #[test]
+    fn quotes_in_strings_are_doubled() {
+        assert_eq!(
+            stations("SELECT station FROM results WHERE station = 'O''Higgins'"),
+            ["O'Higgins"]
+        );
+        assert_eq!(
+            stations("SELECT station FROM results WHERE station LIKE 'Ha_i%'"),
+            ["Halifax"]
+        );
+    }
+
+    #[test]
+    fn malformed_queries_are_rejected() {
+        let cases = [
+            ("SELECT FROM results", "expected a column, got `from`"),
+            (
+                "SELECT * FROM stations",
+                "the only table is `results`, got `stations`",
+            ),
+            ("SELECT * FROM results WHERE", "unexpected end of the query"),
+            (
+                "SELECT * FROM results WHERE station = 'Ham",
+                "unterminated string",
+            ),
+            (
+                "SELECT * FROM results LIMIT 1.5",
+                "expected a row count after LIMIT, got `1.5`",
+            ),
+            (
+                "SELECT * FROM results LIMIT 1 2",
+                "unexpected `2` after the query",
+            ),
+            (
+                "SELECT * FROM results WHERE (max > 1",
+                "expected `)` at the end of the query",
+            ),
+            (
+                "SELECT * FROM results WHERE max NOT > 1",
+                "expected LIKE after NOT",
+            ),
+            ("SELECT * FROM results WHERE max ~ 1", "unexpected `~`"),
+        ];
+        for (sql, expected) in cases {
+            assert_eq!(error(sql), expected, "{}", sql);
+        }
+    }
+
+    #[test]
+    fn unknown_columns_and_mismatched_types_fail_the_check() {
+        let cases = [
+            (
+                "SELECT station, p99 FROM results",
+                "unknown column `p99`, the results have station, min, mean, max",
+            ),
+            (
+                "SELECT * FROM results ORDER BY country",
+                "unknown column `country`, the results have station, min, mean, max",
+            ),
+            (
+                "SELECT * FROM results WHERE max > 1 OR lat > 1",
+                "unknown column `lat`, the results have station, min, mean, max",
+            ),
+            (
+                "SELECT * FROM results WHERE station > 3",
+                "cannot compare the text column `station` with the number 3",
+            ),
+            (
+                "SELECT * FROM results WHERE max = 'hot'",
+                "cannot compare the number column `max` with the text 'hot'",
+            ),
+            (
+                "SELECT * FROM results WHERE max LIKE '4%'",
+                "LIKE only applies to text, not the number column `max`",
+            ),
+        ];
+        for (sql, expected) in cases {
+            assert_eq!(error(sql), expected, "{}", sql);
+        }
+    }
+}
@@ -0,0 +1,96 @@
+//! Read-ahead for the `read` backend. A background thread keeps reading the
+//! next blocks of the file into a small ring of preallocated buffers while
+//! the lines of the current one are split and dispatched, so that disk and
+//! CPU work overlap.
+
+use std::fs::File;
+use std::io::{self, BufRead, Read};
+use std::sync::mpsc::{Receiver, Sender, channel};
+use std::thread;
+
+pub struct ReadAhead {
+    /// Filled buffers and how many bytes of each are valid
+    full: Receiver<io::Result<(Vec<u8>, usize)>>,
+    /// Buffers handed back for reuse
+    empty: Sender<Vec<u8>>,
+    current: Vec<u8>,
+    len: usize,
+    pos: usize,
+    done: bool,
+}
+
+impl ReadAhead {
+    /// Starts reading `file` from its current position, with `buffers`
+    /// blocks of `block_size` bytes in the ring.
+    pub fn new(mut file: File, buffers: usize, block_size: usize) -> io::Result<ReadAhead> {
+        let (full_tx, full) = channel();
+        let (empty, empty_rx) = channel::<Vec<u8>>();
+        for _ in 0..buffers {
+            let _ = empty.send(vec![0; block_size]);
+        }
+        thread::Builder::new()
+            .name("read-ahead".to_string())
+            .spawn(move || {
+                // Stops when the reader is dropped and no buffer comes back
+                while let Ok(mut buf) = empty_rx.recv() {
+                    let result = loop {
+                        match file.read(&mut buf) {
+                            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                            result => break result,
+                        }
+                    };
+                    let result = result.map(|n| (buf, n));
+                    let last = !matches!(result, Ok((_, n)) if n > 0);
+                    if full_tx.send(result).is_err() || last {
+                        return;
+                    }
+                }
+            })?;
+        Ok(ReadAhead {
+            full,
+            empty,
+            current: Vec::new(),
+            len: 0,
+            pos: 0,
+            done: false,
+        })
+    }
+}
+
+impl Read for ReadAhead {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        let available = self.fill_buf()?;
+        let n = available.len().min(out.len());
+        out[..n].copy_from_slice(&available[..n]);
+        self.consume(n);
+        Ok(n)
+    }
+}
+
+impl BufRead for ReadAhead {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        if self.pos == self.len && !self.done {
+            if !self.current.is_empty() {
+                let _ = self.empty.send(std::mem::take(&mut self.current));
+            }
+            match self.full.recv() {
+                Ok(Ok((buf, n))) => {
+                    self.current = buf;
+                    self.len = n;
+                    self.pos = 0;
+                    self.done = n == 0;
+                }
+                Ok(Err(e)) => {
+                    self.done = true;
+                    return Err(e);
+                }
+                Err(_) => self.done = true,
+            }
+        }
+        Ok(&self.current[self.pos..self.len])
+    }
+
+    fn consume(&mut self, n: usize) {
+        self.pos = (self.pos + n).min(self.len);
+    }
+}
@@ -0,0 +1,141 @@
+//! `1brc repl`: aggregates the input once, then answers queries over the
+//! results at a prompt, without reading the file again.
+
+use crate::arena::Arena;
+use crate::cli::{Config, OutputFormat, SortField};
+use crate::metadata::Metadata;
+use crate::query::Query;
+use crate::{Summary, output, summarize};
+use anyhow::{Context as _, anyhow, bail};
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::sync::Arc;
+
+const HELP: &str = "\
+Commands:
+  SELECT ... FROM results ...  run a query, as with --query
+  show                         the results in the current format and order
+  top N [FIELD]                the N stations with the highest FIELD (default: max)
+  sort FIELD [desc]            order `show` by name, min, mean, max or count
+  format NAME                  text, csv, json, table or markdown
+  export FILE                  write the last output to FILE
+  help                         this list
+  quit                         leave, as does end of input";
+
+pub fn run(mut config: Config, num_threads: usize) -> anyhow::Result<()> {
+    let metadata = match &config.metadata {
+        Some(path) => Some(Metadata::load(path, config.normalize_keys)?),
+        None => None,
+    };
+    // Keys point into these, so they are dropped only after the results
+    let mut arenas = vec![];
+    let mut merged = Arena::default();
+    let mut totals = Summary::default();
+    for input in &config.inputs {
+        let config = Arc::new(Config {
+            input: input.clone(),
+            ..config.clone()
+        });
+        let (summary, _) = summarize(
+            &config,
+            metadata.as_ref(),
+            num_threads,
+            &Arc::default(),
+            &mut arenas,
+        )?;
+        totals.merge(&summary, &mut merged);
+    }
+    let results = totals.results();
+    println!(
+        "{} stations from {} lines, type `help` for the commands",
+        results.len(),
+        totals.lines
+    );
+
+    let mut last = None;
+    let stdin = io::stdin();
+    let mut lines = stdin.lock().lines();
+    loop {
+        print!("1brc> ");
+        io::stdout().flush()?;
+        let Some(line) = lines.next().transpose()? else {
+            println!();
+            break;
+        };
+        let line = line.trim();
+        let (command, rest) = line.split_once(' ').unwrap_or((line, ""));
+        let command = command.to_lowercase();
+        let rest = rest.trim();
+        let output = match command.as_str() {
+            "" => continue,
+            "quit" | "exit" => break,
+            "help" => Ok(HELP.to_string()),
+            "show" => Ok(output::format_results(&config, &results, metadata.as_ref())),
+            "select" => Query::parse(line).and_then(|query| {
+                let table = output::results_table(&config, &results, metadata.as_ref());
+                Ok(output::format_query(&config, &query.run(&table)?))
+            }),
+            "top" => top(rest).and_then(|query| {
+                let table = output::results_table(&config, &results, metadata.as_ref());
+                Ok(output::format_query(&config, &query.run(&table)?))
+            }),
+            "sort" => sort(&mut config, rest).map(|()| String::new()),
+            "format" => format(&mut config, rest).map(|()| String::new()),
+            "export" => match &last {
+                _ if rest.is_empty() => Err(anyhow!("export needs a file name")),
+                Some(text) => fs::write(rest, format!("{}\n", text))
+                    .with_context(|| format!("failed to write {}", rest))
+                    .map(|()| format!("Wrote {}", rest)),
+                None => Err(anyhow!("nothing to export yet, run a query first")),
+            },
+            _ => Err(anyhow!(
+                "unknown command `{}`, type `help` for the commands",
+                command
+            )),
+        };
+        match output {
+            Ok(text) if text.is_empty() => {}
+            Ok(text) => {
+                println!("{}", text);
+                if !matches!(command.as_str(), "export" | "help") {
+                    last = Some(text);
+                }
+            }
+            Err(e) => eprintln!("error: {:#}", e),
+        }
+    }
+    Ok(())
+}
+
+/// `top N [FIELD]` as the query it stands for.
+fn top(args: &str) -> anyhow::Result<Query> {
+    let mut args = args.split_whitespace();
+    let Some(n) = args.next().and_then(|n| n.parse::<usize>().ok()) else {
+        bail!("expected `top N [FIELD]`");
+    };
+    let field = args.next().unwrap_or("max");
+    Query::parse(&format!(
+        "SELECT * FROM results ORDER BY {} DESC LIMIT {}",
+        field, n
+    ))
+}
+
+fn sort(config: &mut Config, args: &str) -> anyhow::Result<()> {
+    let (field, desc) = match args.split_once(' ') {
+        Some((field, "desc")) => (field, true),
+        Some((_, other)) => bail!("expected `desc` after the field, got `{}`", other),
+        None => (args, false),
+    };
+    config.sort.field = SortField::parse(field)?;
+    config.sort.descending = desc;
+    Ok(())
+}
+
+fn format(config: &mut Config, args: &str) -> anyhow::Result<()> {
+    let format = OutputFormat::parse(args)?;
+    if format == OutputFormat::Prom {
+        bail!("prom is not available here");
+    }
+    config.format = format;
+    Ok(())
+}
@@ -0,0 +1,127 @@
+//! `--report`: a self-contained HTML page with the results table, how the
+//! run was configured and how fast it went, for sharing benchmark results.
+
+use crate::cli::Config;
+use crate::output::Table;
+use crate::{alloc, memory, simd, version};
+use anyhow::Context;
+use std::fmt::Write;
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+
+/// What is known about a finished run besides its results.
+pub struct Run<'a> {
+    pub config: &'a Config,
+    pub lines: u64,
+    /// Size of the input
+    pub bytes: u64,
+    pub elapsed: Duration,
+    pub workers: usize,
+    /// Whether the results came from `--cache-dir` rather than a scan
+    pub cached: bool,
+}
+
+pub fn write(path: &Path, run: &Run, table: &Table) -> anyhow::Result<()> {
+    fs::write(path, render(run, table))
+        .with_context(|| format!("failed to write report {}", path.display()))
+}
+
+fn render(run: &Run, table: &Table) -> String {
+    let config = run.config;
+    let command: Vec<String> = std::env::args().collect();
+    let mut settings = vec![
+        ("Command", command.join(" ")),
+        (
+            "Input",
+            format!(
+                "{} ({})",
+                config.inputs.join(", "),
+                memory::format_bytes(run.bytes as usize)
+            ),
+        ),
+        (
+            "Build",
+            format!(
+                "1brc {} ({}), {}, {} allocator",
+                version::VERSION,
+                version::COMMIT,
+                version::features().join(", "),
+                alloc::NAME
+            ),
+        ),
+        (
+            "Execution",
+            format!(
+                "{} workers, {} executor, {} I/O, {} scanner",
+                run.workers,
+                format!("{:?}", config.executor).to_lowercase(),
+                format!("{:?}", config.io).to_lowercase(),
+                simd::name()
+            ),
+        ),
+        ("Lines", run.lines.to_string()),
+    ];
+    let seconds = run.elapsed.as_secs_f64();
+    if run.cached {
+        settings.push(("Elapsed", format!("{:.3} s, from the cache", seconds)));
+    } else {
+        settings.push(("Elapsed", format!("{:.3} s", seconds)));
+        if seconds > 0.0 {
+            settings.push((
+                "Throughput",
+                format!(
+                    "{:.1} M lines/s, {}/s",
+                    run.lines as f64 / seconds / 1e6,
+                    memory::format_bytes((run.bytes as f64 / seconds) as usize)
+                ),
+            ));
+        }
+    }
+
+    let mut out = String::from(
+        "<!DOCTYPE html>\n<html lang=\"en\">\n<head>\n<meta charset=\"utf-8\">\n\
+         <title>1brc results</title>\n<style>\n\
+         body { font-family: sans-serif; margin: 2em; }\n\
+         table { border-collapse: collapse; }\n\
+         th, td { padding: 0.2em 0.8em; border-bottom: 1px solid #ddd; }\n\
+         th { text-align: left; }\n\
+         td.number { text-align: right; font-variant-numeric: tabular-nums; }\n\
+         </style>\n</head>\n<body>\n<h1>1brc results</h1>\n<h2>Run</h2>\n<table>\n",
+    );
+    for (name, value) in &settings {
+        let _ = writeln!(out, "<tr><th>{}</th><td>{}</td></tr>", name, escape(value));
+    }
+    out.push_str("</table>\n<h2>Results</h2>\n<table>\n<tr>");
+    for name in &table.header {
+        let _ = write!(out, "<th>{}</th>", escape(name));
+    }
+    out.push_str("</tr>\n");
+    for row in &table.rows {
+        out.push_str("<tr>");
+        for (i, cell) in row.iter().enumerate() {
+            if table.is_text(i) {
+                let _ = write!(out, "<td>{}</td>", escape(cell));
+            } else {
+                let _ = write!(out, "<td class=\"number\">{}</td>", escape(cell));
+            }
+        }
+        out.push_str("</tr>\n");
+    }
+    out.push_str("</table>\n</body>\n</html>\n");
+    out
+}
+
+fn escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            c => out.push(c),
+        }
+    }
+    out
+}
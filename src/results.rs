@@ -0,0 +1,199 @@
+//! The merged results of a run, with a compact binary snapshot format so
+//! that a long-lived process embedding the aggregator can persist them and
+//! pick up where it left off.
+
+use crate::stat::Stat;
+use std::collections::BTreeMap;
+use std::io::{self, Read, Write};
+
+const MAGIC: &[u8] = b"1brc-results 1\n";
+
+/// A station key plus, for timestamped input, the start of its time
+/// window.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Key {
+    pub name: String,
+    pub window: Option<i64>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Results {
+    /// Lines read from the input
+    pub lines: u64,
+    /// Rows outside the value range
+    pub excluded: u64,
+    /// Rows without a reading, per station
+    pub missing: BTreeMap<String, u64>,
+    pub stats: BTreeMap<Key, Stat>,
+}
+
+impl Results {
+    /// Adds `other` into these results, as if both inputs had been read in
+    /// one run.
+    pub fn merge(&mut self, other: Results) {
+        self.lines += other.lines;
+        self.excluded += other.excluded;
+        for (station, count) in other.missing {
+            *self.missing.entry(station).or_default() += count;
+        }
+        for (key, stat) in other.stats {
+            match self.stats.get_mut(&key) {
+                Some(total) => total.merge(&stat),
+                None => {
+                    self.stats.insert(key, stat);
+                }
+            }
+        }
+    }
+
+    /// Writes a snapshot that [`Results::deserialize`] reads back exactly.
+    /// Numbers are little-endian and strings length-prefixed.
+    pub fn serialize(&self, out: &mut impl Write) -> io::Result<()> {
+        out.write_all(MAGIC)?;
+        out.write_all(&self.lines.to_le_bytes())?;
+        out.write_all(&self.excluded.to_le_bytes())?;
+        out.write_all(&(self.missing.len() as u64).to_le_bytes())?;
+        for (station, count) in &self.missing {
+            write_str(out, station)?;
+            out.write_all(&count.to_le_bytes())?;
+        }
+        out.write_all(&(self.stats.len() as u64).to_le_bytes())?;
+        for (key, stat) in &self.stats {
+            write_str(out, &key.name)?;
+            match key.window {
+                Some(window) => {
+                    out.write_all(&[1])?;
+                    out.write_all(&window.to_le_bytes())?;
+                }
+                None => out.write_all(&[0])?,
+            }
+            out.write_all(&stat.min.to_le_bytes())?;
+            out.write_all(&stat.max.to_le_bytes())?;
+            out.write_all(&stat.sum.to_le_bytes())?;
+            out.write_all(&stat.count.to_le_bytes())?;
+        }
+        Ok(())
+    }
+
+    /// Reads a snapshot written by [`Results::serialize`], leaving `input`
+    /// just past its end.
+    pub fn deserialize(input: &mut impl Read) -> io::Result<Results> {
+        let mut magic = [0; MAGIC.len()];
+        input.read_exact(&mut magic)?;
+        if magic != MAGIC {
+            return Err(invalid("not a results snapshot"));
+        }
+        let mut results = Results {
+            lines: u64::from_le_bytes(read_array(input)?),
+            excluded: u64::from_le_bytes(read_array(input)?),
+            ..Default::default()
+        };
+        for _ in 0..u64::from_le_bytes(read_array(input)?) {
+            let station = read_str(input)?;
+            results
+                .missing
+                .insert(station, u64::from_le_bytes(read_array(input)?));
+        }
+        for _ in 0..u64::from_le_bytes(read_array(input)?) {
+            let name = read_str(input)?;
+            let window = match read_array::<1>(input)? {
+                [0] => None,
+                [1] => Some(i64::from_le_bytes(read_array(input)?)),
+                _ => return Err(invalid("bad window flag")),
+            };
+            let stat = Stat {
+                min: f64::from_le_bytes(read_array(input)?),
+                max: f64::from_le_bytes(read_array(input)?),
+                sum: f64::from_le_bytes(read_array(input)?),
+                count: i64::from_le_bytes(read_array(input)?),
+            };
+            results.stats.insert(Key { name, window }, stat);
+        }
+        Ok(results)
+    }
+}
+
+pub(crate) fn write_str(out: &mut impl Write, s: &str) -> io::Result<()> {
+    out.write_all(&(s.len() as u32).to_le_bytes())?;
+    out.write_all(s.as_bytes())
+}
+
+pub(crate) fn read_str(input: &mut impl Read) -> io::Result<String> {
+    let len = u32::from_le_bytes(read_array(input)?);
+    // Read through `take` so that a corrupt length cannot allocate more
+    // than the input holds
+    let mut bytes = Vec::new();
+    input.take(len as u64).read_to_end(&mut bytes)?;
+    if bytes.len() != len as usize {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    String::from_utf8(bytes).map_err(invalid)
+}
+
+fn read_array<const N: usize>(input: &mut impl Read) -> io::Result<[u8; N]> {
+    let mut buf = [0; N];
+    input.read_exact(&mut buf)?;
+    Ok(buf)
+}
+
+fn invalid(e: impl Into<Box<dyn std::error::Error + Send + Sync>>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(name: &str, window: Option<i64>) -> Key {
+        Key {
+            name: name.to_string(),
+            window,
+        }
+    }
+
+    #[test]
+    fn snapshot_round_trips() {
+        let mut results = Results {
+            lines: 7,
+            excluded: 1,
+            ..Default::default()
+        };
+        results.missing.insert("Zürich".to_string(), 2);
+        let mut stat = Stat::new(-0.0);
+        stat.add(12.3);
+        results.stats.insert(key("東京", None), stat);
+        results
+            .stats
+            .insert(key("Abha", Some(-3600)), Stat::new(f64::MIN));
+
+        let mut bytes = Vec::new();
+        results.serialize(&mut bytes).unwrap();
+        // Followed by something else, which must be left alone
+        bytes.extend_from_slice(b"rest");
+        let mut input = &bytes[..];
+        let read = Results::deserialize(&mut input).unwrap();
+        assert_eq!(input, b"rest");
+
+        assert_eq!((read.lines, read.excluded), (7, 1));
+        assert_eq!(read.missing, results.missing);
+        assert_eq!(read.stats.len(), 2);
+        for (key, stat) in &results.stats {
+            let got = &read.stats[key];
+            assert_eq!(got.min.to_bits(), stat.min.to_bits(), "{:?}", key);
+            assert_eq!(got.max.to_bits(), stat.max.to_bits(), "{:?}", key);
+            assert_eq!(got.sum.to_bits(), stat.sum.to_bits(), "{:?}", key);
+            assert_eq!(got.count, stat.count, "{:?}", key);
+        }
+    }
+
+    #[test]
+    fn truncated_snapshots_are_rejected() {
+        let mut results = Results::default();
+        results.stats.insert(key("Abha", None), Stat::new(1.0));
+        let mut bytes = Vec::new();
+        results.serialize(&mut bytes).unwrap();
+        for len in 0..bytes.len() {
+            assert!(Results::deserialize(&mut &bytes[..len]).is_err(), "{}", len);
+        }
+    }
+}
@@ -0,0 +1,196 @@
+//! `--daemon`: `--watch-dir` as a long-lived systemd service. What would
+//! be printed for a person becomes log records, sent to the journal with
+//! their fields when stdout is connected to it and otherwise written to
+//! stdout as JSON lines. The service manager is told through
+//! `NOTIFY_SOCKET` once the watch is ready and how far it has got, and
+//! SIGHUP reloads the files the options name.
+
+use crate::time::format_timestamp;
+use std::io::{self, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[cfg(unix)]
+use std::os::unix::net::UnixDatagram;
+
+/// Where the journal takes native entries
+#[cfg(unix)]
+const JOURNAL_SOCKET: &str = "/run/systemd/journal/socket";
+
+/// Set by the SIGHUP handler, cleared by [`reload_requested`]
+static RELOAD: AtomicBool = AtomicBool::new(false);
+
+/// Syslog priorities, as the journal takes them.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Level {
+    Error = 3,
+    Warning = 4,
+    Info = 6,
+}
+
+impl Level {
+    fn name(self) -> &'static str {
+        match self {
+            Level::Error => "error",
+            Level::Warning => "warning",
+            Level::Info => "info",
+        }
+    }
+}
+
+pub struct Service {
+    #[cfg(unix)]
+    journal: Option<UnixDatagram>,
+    #[cfg(unix)]
+    notify: Option<(UnixDatagram, std::os::unix::net::SocketAddr)>,
+}
+
+impl Service {
+    /// Connects to the journal and the service manager where systemd
+    /// says they are, and starts taking SIGHUP as a reload.
+    pub fn new() -> Self {
+        install_sighup();
+        Service {
+            #[cfg(unix)]
+            journal: std::env::var_os("JOURNAL_STREAM").and_then(|stream| {
+                if !is_stdout(&stream.to_string_lossy()) {
+                    return None;
+                }
+                let socket = UnixDatagram::unbound().ok()?;
+                socket.connect(JOURNAL_SOCKET).ok()?;
+                Some(socket)
+            }),
+            #[cfg(unix)]
+            notify: std::env::var_os("NOTIFY_SOCKET").and_then(|path| {
+                let addr = notify_addr(&path.to_string_lossy()).ok()?;
+                Some((UnixDatagram::unbound().ok()?, addr))
+            }),
+        }
+    }
+
+    /// Logs `message` with `fields`, whose names are as the journal wants
+    /// them: upper case letters, digits and underscores.
+    pub fn log(&self, level: Level, message: &str, fields: &[(&str, String)]) {
+        #[cfg(unix)]
+        if let Some(journal) = &self.journal {
+            let mut entry = Vec::new();
+            journal_field(&mut entry, "MESSAGE", message);
+            journal_field(&mut entry, "PRIORITY", &(level as u8).to_string());
+            journal_field(&mut entry, "SYSLOG_IDENTIFIER", "1brc");
+            for (name, value) in fields {
+                journal_field(&mut entry, name, value);
+            }
+            // An entry too large for a datagram is written out instead
+            if journal.send(&entry).is_ok() {
+                return;
+            }
+        }
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs() as i64);
+        let mut record = format!(
+            "{{\"timestamp\": \"{}\", \"level\": \"{}\", \"message\": {}",
+            format_timestamp(now),
+            level.name(),
+            crate::format::json_string(message)
+        );
+        for (name, value) in fields {
+            record.push_str(&format!(
+                ", \"{}\": {}",
+                name.to_ascii_lowercase(),
+                crate::format::json_string(value)
+            ));
+        }
+        record.push('}');
+        let mut out = io::stdout().lock();
+        let _ = writeln!(out, "{}", record);
+        let _ = out.flush();
+    }
+
+    /// Sends `state`, such as `READY=1`, to the service manager, if it
+    /// asked for it.
+    pub fn notify(&self, state: &str) {
+        #[cfg(unix)]
+        if let Some((socket, addr)) = &self.notify {
+            let _ = socket.send_to_addr(state.as_bytes(), addr);
+        }
+        #[cfg(not(unix))]
+        let _ = state;
+    }
+}
+
+/// Whether a SIGHUP came since the last call.
+pub fn reload_requested() -> bool {
+    RELOAD.swap(false, Ordering::Relaxed)
+}
+
+/// A field of a native journal entry, in the binary form when the value
+/// spans lines.
+#[cfg(unix)]
+fn journal_field(entry: &mut Vec<u8>, name: &str, value: &str) {
+    entry.extend_from_slice(name.as_bytes());
+    if value.contains('\n') {
+        entry.push(b'\n');
+        entry.extend_from_slice(&(value.len() as u64).to_le_bytes());
+    } else {
+        entry.push(b'=');
+    }
+    entry.extend_from_slice(value.as_bytes());
+    entry.push(b'\n');
+}
+
+/// Whether stdout is the stream `JOURNAL_STREAM` names by its device and
+/// inode numbers, as `dev:ino`. The variable is inherited, so it is set as
+/// well in what a service starts with its stdout set elsewhere.
+#[cfg(unix)]
+fn is_stdout(stream: &str) -> bool {
+    use std::os::fd::AsFd;
+    use std::os::unix::fs::MetadataExt;
+
+    let Some((dev, ino)) = stream.split_once(':') else {
+        return false;
+    };
+    let stdout = io::stdout()
+        .as_fd()
+        .try_clone_to_owned()
+        .and_then(|fd| std::fs::File::from(fd).metadata());
+    match (dev.parse::<u64>(), ino.parse::<u64>(), stdout) {
+        (Ok(dev), Ok(ino), Ok(stdout)) => stdout.dev() == dev && stdout.ino() == ino,
+        _ => false,
+    }
+}
+
+/// `NOTIFY_SOCKET` is a path, or with a leading `@` an abstract name.
+#[cfg(unix)]
+fn notify_addr(socket: &str) -> io::Result<std::os::unix::net::SocketAddr> {
+    #[cfg(target_os = "linux")]
+    if let Some(name) = socket.strip_prefix('@') {
+        use std::os::linux::net::SocketAddrExt;
+        return std::os::unix::net::SocketAddr::from_abstract_name(name);
+    }
+    std::os::unix::net::SocketAddr::from_pathname(socket)
+}
+
+#[cfg(unix)]
+fn install_sighup() {
+    use std::ffi::c_int;
+
+    const SIGHUP: c_int = 1;
+
+    unsafe extern "C" {
+        fn signal(signum: c_int, handler: extern "C" fn(c_int)) -> usize;
+    }
+
+    extern "C" fn on_sighup(_: c_int) {
+        RELOAD.store(true, Ordering::Relaxed);
+    }
+
+    // SAFETY: the handler only stores to an atomic, which is async-signal
+    // safe
+    unsafe {
+        signal(SIGHUP, on_sighup);
+    }
+}
+
+#[cfg(not(unix))]
+fn install_sighup() {}
@@ -0,0 +1,296 @@
+//! SHA-256 (FIPS 180-4), for checking an input against the digest it was
+//! published with before trusting a run over it. Blocks are compressed
+//! with the SHA extensions where the CPU has them, several times faster
+//! than the portable rounds.
+
+const K: [u32; 64] = [
+    0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5,
+    0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174,
+    0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+    0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967,
+    0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85,
+    0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+    0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+    0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2,
+];
+
+const INITIAL: [u32; 8] = [
+    0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab, 0x5be0cd19,
+];
+
+pub type Digest = [u8; 32];
+
+#[derive(Clone)]
+pub struct Sha256 {
+    state: [u32; 8],
+    /// The start of a block not yet compressed
+    buf: [u8; 64],
+    buffered: usize,
+    /// Bytes hashed so far
+    len: u64,
+}
+
+impl Default for Sha256 {
+    fn default() -> Self {
+        Sha256 {
+            state: INITIAL,
+            buf: [0; 64],
+            buffered: 0,
+            len: 0,
+        }
+    }
+}
+
+impl Sha256 {
+    pub fn update(&mut self, mut bytes: &[u8]) {
+        self.len += bytes.len() as u64;
+        if self.buffered > 0 {
+            let n = bytes.len().min(64 - self.buffered);
+            self.buf[self.buffered..self.buffered + n].copy_from_slice(&bytes[..n]);
+            self.buffered += n;
+            bytes = &bytes[n..];
+            if self.buffered < 64 {
+                return;
+            }
+            compress(&mut self.state, &self.buf);
+            self.buffered = 0;
+        }
+        let whole = bytes.len() / 64 * 64;
+        compress(&mut self.state, &bytes[..whole]);
+        let rest = &bytes[whole..];
+        self.buf[..rest.len()].copy_from_slice(rest);
+        self.buffered = rest.len();
+    }
+
+    pub fn finish(mut self) -> Digest {
+        let bits = self.len.wrapping_mul(8);
+        // A 1 bit, zeros up to 8 bytes short of a block, then the length
+        let padding = 1 + (64 + 55 - self.buffered) % 64;
+        let mut tail = [0; 72];
+        tail[0] = 0x80;
+        tail[padding..padding + 8].copy_from_slice(&bits.to_be_bytes());
+        self.update(&tail[..padding + 8]);
+        debug_assert_eq!(self.buffered, 0);
+
+        let mut digest = [0; 32];
+        for (out, word) in digest.chunks_exact_mut(4).zip(self.state) {
+            out.copy_from_slice(&word.to_be_bytes());
+        }
+        digest
+    }
+}
+
+/// Which implementation of the compression function this CPU runs.
+pub fn implementation() -> &'static str {
+    #[cfg(target_arch = "x86_64")]
+    if is_x86_feature_detected!("sha") && is_x86_feature_detected!("sse4.1") {
+        return "sha-ni";
+    }
+    "scalar"
+}
+
+/// Compresses `blocks`, a whole number of 64-byte blocks, into `state`.
+fn compress(state: &mut [u32; 8], blocks: &[u8]) {
+    #[cfg(target_arch = "x86_64")]
+    if is_x86_feature_detected!("sha") && is_x86_feature_detected!("sse4.1") {
+        // SAFETY: both features were just detected
+        unsafe { sha_ni::compress(state, blocks) };
+        return;
+    }
+    for block in blocks.chunks_exact(64) {
+        compress_scalar(state, block);
+    }
+}
+
+fn compress_scalar(state: &mut [u32; 8], block: &[u8]) {
+    let mut w = [0u32; 64];
+    for (i, word) in block.chunks_exact(4).enumerate() {
+        w[i] = u32::from_be_bytes(word.try_into().expect("4 bytes"));
+    }
+    for i in 16..64 {
+        let s0 = w[i - 15].rotate_right(7) ^ w[i - 15].rotate_right(18) ^ (w[i - 15] >> 3);
+        let s1 = w[i - 2].rotate_right(17) ^ w[i - 2].rotate_right(19) ^ (w[i - 2] >> 10);
+        w[i] = w[i - 16]
+            .wrapping_add(s0)
+            .wrapping_add(w[i - 7])
+            .wrapping_add(s1);
+    }
+
+    let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut h] = *state;
+    for i in 0..64 {
+        let s1 = e.rotate_right(6) ^ e.rotate_right(11) ^ e.rotate_right(25);
+        let ch = (e & f) ^ (!e & g);
+        let t1 = h
+            .wrapping_add(s1)
+            .wrapping_add(ch)
+            .wrapping_add(K[i])
+            .wrapping_add(w[i]);
+        let s0 = a.rotate_right(2) ^ a.rotate_right(13) ^ a.rotate_right(22);
+        let maj = (a & b) ^ (a & c) ^ (b & c);
+        let t2 = s0.wrapping_add(maj);
+        h = g;
+        g = f;
+        f = e;
+        e = d.wrapping_add(t1);
+        d = c;
+        c = b;
+        b = a;
+        a = t1.wrapping_add(t2);
+    }
+    for (word, add) in state.iter_mut().zip([a, b, c, d, e, f, g, h]) {
+        *word = word.wrapping_add(add);
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+mod sha_ni {
+    use super::K;
+    use std::arch::x86_64::*;
+
+    /// Four rounds with the message words `w`.
+    macro_rules! rounds {
+        ($abef:ident, $cdgh:ident, $w:expr, $i:expr) => {{
+            // SAFETY: `K` has 64 words
+            let k = unsafe { _mm_loadu_si128(K.as_ptr().add($i * 4).cast()) };
+            let wk = _mm_add_epi32($w, k);
+            $cdgh = _mm_sha256rnds2_epu32($cdgh, $abef, wk);
+            $abef = _mm_sha256rnds2_epu32($abef, $cdgh, _mm_shuffle_epi32(wk, 0x0e));
+        }};
+    }
+
+    /// The next four message words, from the last sixteen.
+    #[target_feature(enable = "sha,sse4.1")]
+    fn schedule(w0: __m128i, w1: __m128i, w2: __m128i, w3: __m128i) -> __m128i {
+        let w = _mm_add_epi32(_mm_sha256msg1_epu32(w0, w1), _mm_alignr_epi8(w3, w2, 4));
+        _mm_sha256msg2_epu32(w, w3)
+    }
+
+    #[target_feature(enable = "sha,sse4.1")]
+    pub unsafe fn compress(state: &mut [u32; 8], blocks: &[u8]) {
+        // Big-endian words
+        let mask = _mm_set_epi64x(0x0c0d_0e0f_0809_0a0b, 0x0405_0607_0001_0203);
+        // SAFETY: `state` holds two vectors of words
+        let (dcba, hgfe) = unsafe {
+            (
+                _mm_loadu_si128(state.as_ptr().cast()),
+                _mm_loadu_si128(state.as_ptr().add(4).cast()),
+            )
+        };
+        // The instructions want the state as ABEF and CDGH
+        let cdab = _mm_shuffle_epi32(dcba, 0xb1);
+        let efgh = _mm_shuffle_epi32(hgfe, 0x1b);
+        let mut abef = _mm_alignr_epi8(cdab, efgh, 8);
+        let mut cdgh = _mm_blend_epi16(efgh, cdab, 0xf0);
+
+        for block in blocks.chunks_exact(64) {
+            let (abef_start, cdgh_start) = (abef, cdgh);
+            let load = |i: usize| {
+                // SAFETY: the 16 bytes from `i` are within the block
+                let words = unsafe { _mm_loadu_si128(block.as_ptr().add(i * 16).cast()) };
+                _mm_shuffle_epi8(words, mask)
+            };
+            let mut w = [load(0), load(1), load(2), load(3)];
+            for i in 0..16 {
+                if i >= 4 {
+                    let next = schedule(w[i % 4], w[(i + 1) % 4], w[(i + 2) % 4], w[(i + 3) % 4]);
+                    w[i % 4] = next;
+                }
+                rounds!(abef, cdgh, w[i % 4], i);
+            }
+            abef = _mm_add_epi32(abef, abef_start);
+            cdgh = _mm_add_epi32(cdgh, cdgh_start);
+        }
+
+        let feba = _mm_shuffle_epi32(abef, 0x1b);
+        let dchg = _mm_shuffle_epi32(cdgh, 0xb1);
+        let dcba = _mm_blend_epi16(feba, dchg, 0xf0);
+        let hgef = _mm_alignr_epi8(dchg, feba, 8);
+        // SAFETY: as for the loads
+        unsafe {
+            _mm_storeu_si128(state.as_mut_ptr().cast(), dcba);
+            _mm_storeu_si128(state.as_mut_ptr().add(4).cast(), hgef);
+        }
+    }
+}
+
+/// The digest as 64 lowercase hex digits.
+pub fn to_hex(digest: &Digest) -> String {
+    digest.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// A digest written as 64 hex digits of either case.
+pub fn from_hex(s: &str) -> Option<Digest> {
+    if s.len() != 64 || !s.is_ascii() {
+        return None;
+    }
+    let mut digest = [0; 32];
+    for (i, byte) in digest.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&s[i * 2..i * 2 + 2], 16).ok()?;
+    }
+    Some(digest)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex(bytes: &[u8]) -> String {
+        let mut hasher = Sha256::default();
+        hasher.update(bytes);
+        to_hex(&hasher.finish())
+    }
+
+    #[test]
+    fn known_digests() {
+        assert_eq!(
+            hex(b""),
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+        assert_eq!(
+            hex(b"abc"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        // Two blocks once padded
+        assert_eq!(
+            hex(b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq"),
+            "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1"
+        );
+        assert_eq!(
+            hex(&[b'a'; 1_000_000]),
+            "cdc76e5c9914fb9281a1c7e284d73e67f1809a48a497200e046d39ccc7112cd0"
+        );
+    }
+
+    #[test]
+    fn updates_split_anywhere() {
+        let data: Vec<u8> = (0..300u32).map(|i| (i * 7) as u8).collect();
+        let whole = hex(&data);
+        for split in [0, 1, 55, 56, 63, 64, 65, 128, 299] {
+            let mut hasher = Sha256::default();
+            hasher.update(&data[..split]);
+            hasher.update(&data[split..]);
+            assert_eq!(to_hex(&hasher.finish()), whole, "split at {}", split);
+        }
+    }
+
+    #[test]
+    fn every_compression_agrees() {
+        let data: Vec<u8> = (0..64 * 40u32).map(|i| ((i * 131) >> 3) as u8).collect();
+        let mut expected = INITIAL;
+        for block in data.chunks_exact(64) {
+            compress_scalar(&mut expected, block);
+        }
+        let mut actual = INITIAL;
+        compress(&mut actual, &data);
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn hex_round_trips() {
+        let digest = Sha256::default().finish();
+        assert_eq!(from_hex(&to_hex(&digest)), Some(digest));
+        assert_eq!(from_hex(&to_hex(&digest).to_uppercase()), Some(digest));
+        assert_eq!(from_hex("e3b0"), None);
+        assert_eq!(from_hex(&"g".repeat(64)), None);
+    }
+}
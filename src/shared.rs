@@ -0,0 +1,77 @@
+//! `--strategy shared-map`: one table for all workers, split into shards
+//! each behind its own lock, instead of a map per worker and a merge at
+//! the end. With few stations the shards stay hot in cache, and there is
+//! nothing left to reduce; with many, the locking costs more than it saves.
+//!
+//! The names of the keys are copied into an arena of each shard's own
+//! rather than the workers', so that they outlive whichever worker added
+//! them.
+
+use crate::arena::Arena;
+use crate::{Key, Stat};
+use std::collections::HashMap;
+use std::hash::{BuildHasher, RandomState};
+use std::sync::Mutex;
+
+/// More than there are workers, so that two rarely want the same one
+const SHARDS: usize = 64;
+
+#[derive(Default)]
+struct Shard {
+    map: HashMap<Key, Stat>,
+    /// The names of the keys in `map`
+    arena: Arena,
+}
+
+pub struct SharedMap {
+    shards: Vec<Mutex<Shard>>,
+    hasher: RandomState,
+}
+
+impl SharedMap {
+    /// Shards sized for `capacity` keys between them.
+    pub fn new(capacity: usize) -> Self {
+        let shard = capacity.div_ceil(SHARDS);
+        SharedMap {
+            shards: (0..SHARDS)
+                .map(|_| {
+                    Mutex::new(Shard {
+                        map: HashMap::with_capacity(shard),
+                        arena: Arena::default(),
+                    })
+                })
+                .collect(),
+            hasher: RandomState::new(),
+        }
+    }
+
+    /// Adds `value` to the stat of `lookup`, storing a copy of the key if
+    /// it is new. Returns the bytes a new key adds to the map.
+    pub fn add(&self, lookup: Key, value: f64) -> Option<usize> {
+        let shard = self.hasher.hash_one(lookup) as usize % SHARDS;
+        let mut shard = self.shards[shard].lock().unwrap();
+        if let Some(stat) = shard.map.get_mut(&lookup) {
+            stat.add(value);
+            return None;
+        }
+        let key = lookup.stored(&mut shard.arena);
+        shard.map.insert(key, Stat::new(value));
+        Some(crate::spill::entry_size(&key))
+    }
+
+    /// Everything added so far, leaving the map empty. The names of the
+    /// keys are kept alive by the arenas added to `arenas`.
+    pub fn take(&self, arenas: &mut Vec<Arena>) -> HashMap<Key, Stat> {
+        let mut shards: Vec<Shard> = self
+            .shards
+            .iter()
+            .map(|shard| std::mem::take(&mut *shard.lock().unwrap()))
+            .collect();
+        let mut all = HashMap::with_capacity(shards.iter().map(|shard| shard.map.len()).sum());
+        for shard in shards.drain(..) {
+            all.extend(shard.map);
+            arenas.push(shard.arena);
+        }
+        all
+    }
+}
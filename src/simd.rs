@@ -0,0 +1,202 @@
+//! Byte scanning for the field and line delimiters, with AVX2 and NEON
+//! versions picked at startup from what the CPU supports.
+
+use std::sync::OnceLock;
+
+type FindFn = fn(&[u8], u8) -> Option<usize>;
+
+struct Scanner {
+    name: &'static str,
+    find: FindFn,
+}
+
+static SCANNER: OnceLock<Scanner> = OnceLock::new();
+
+/// Picks the implementation [`find`] uses. Without a call, the best one
+/// available is picked on first use.
+pub fn init(force_scalar: bool) {
+    let _ = SCANNER.set(select(force_scalar));
+}
+
+fn select(force_scalar: bool) -> Scanner {
+    if !force_scalar {
+        #[cfg(target_arch = "x86_64")]
+        if is_x86_feature_detected!("avx2") {
+            return Scanner {
+                name: "avx2",
+                find: avx2::find,
+            };
+        }
+        #[cfg(target_arch = "aarch64")]
+        if std::arch::is_aarch64_feature_detected!("neon") {
+            return Scanner {
+                name: "neon",
+                find: neon::find,
+            };
+        }
+    }
+    Scanner {
+        name: "scalar",
+        find: find_scalar,
+    }
+}
+
+fn scanner() -> &'static Scanner {
+    SCANNER.get_or_init(|| select(false))
+}
+
+/// Name of the implementation in use.
+pub fn name() -> &'static str {
+    scanner().name
+}
+
+/// Position of the first `needle` in `haystack`.
+#[inline]
+pub fn find(haystack: &[u8], needle: u8) -> Option<usize> {
+    (scanner().find)(haystack, needle)
+}
+
+/// Splits `s` at every `delimiter`, which must be ASCII, like `str::split`.
+pub fn split(s: &str, delimiter: u8) -> Split<'_> {
+    debug_assert!(delimiter.is_ascii());
+    Split {
+        rest: Some(s),
+        delimiter,
+    }
+}
+
+pub struct Split<'a> {
+    rest: Option<&'a str>,
+    delimiter: u8,
+}
+
+impl<'a> Iterator for Split<'a> {
+    type Item = &'a str;
+
+    fn next(&mut self) -> Option<&'a str> {
+        let rest = self.rest?;
+        match find(rest.as_bytes(), self.delimiter) {
+            Some(i) => {
+                // Splitting at an ASCII byte keeps both halves valid UTF-8
+                self.rest = Some(&rest[i + 1..]);
+                Some(&rest[..i])
+            }
+            None => {
+                self.rest = None;
+                Some(rest)
+            }
+        }
+    }
+}
+
+fn find_scalar(haystack: &[u8], needle: u8) -> Option<usize> {
+    haystack.iter().position(|&b| b == needle)
+}
+
+#[cfg(target_arch = "x86_64")]
+mod avx2 {
+    use std::arch::x86_64::*;
+
+    pub fn find(haystack: &[u8], needle: u8) -> Option<usize> {
+        // SAFETY: only selected once AVX2 has been detected
+        unsafe { find_avx2(haystack, needle) }
+    }
+
+    #[target_feature(enable = "avx2")]
+    unsafe fn find_avx2(haystack: &[u8], needle: u8) -> Option<usize> {
+        let pattern = _mm256_set1_epi8(needle as i8);
+        let mut i = 0;
+        while i + 32 <= haystack.len() {
+            // SAFETY: the 32 bytes from `i` are within `haystack`
+            let block = unsafe { _mm256_loadu_si256(haystack.as_ptr().add(i).cast()) };
+            let mask = _mm256_movemask_epi8(_mm256_cmpeq_epi8(block, pattern)) as u32;
+            if mask != 0 {
+                return Some(i + mask.trailing_zeros() as usize);
+            }
+            i += 32;
+        }
+        super::find_scalar(&haystack[i..], needle).map(|p| i + p)
+    }
+}
+
+#[cfg(target_arch = "aarch64")]
+mod neon {
+    use std::arch::aarch64::*;
+
+    pub fn find(haystack: &[u8], needle: u8) -> Option<usize> {
+        // SAFETY: only selected once NEON has been detected
+        unsafe { find_neon(haystack, needle) }
+    }
+
+    #[target_feature(enable = "neon")]
+    unsafe fn find_neon(haystack: &[u8], needle: u8) -> Option<usize> {
+        let pattern = vdupq_n_u8(needle);
+        let mut i = 0;
+        while i + 16 <= haystack.len() {
+            // SAFETY: the 16 bytes from `i` are within `haystack`
+            let block = unsafe { vld1q_u8(haystack.as_ptr().add(i)) };
+            let eq = vceqq_u8(block, pattern);
+            if vmaxvq_u8(eq) != 0 {
+                // Narrow each byte of the comparison to 4 bits of a u64
+                let nibbles = vshrn_n_u16::<4>(vreinterpretq_u16_u8(eq));
+                let mask = vget_lane_u64::<0>(vreinterpret_u64_u8(nibbles));
+                return Some(i + (mask.trailing_zeros() / 4) as usize);
+            }
+            i += 16;
+        }
+        super::find_scalar(&haystack[i..], needle).map(|p| i + p)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Every implementation this CPU can run, the scalar one first.
+    fn scanners() -> Vec<(&'static str, FindFn)> {
+        let mut scanners = vec![("scalar", find_scalar as FindFn)];
+        let best = select(false);
+        if best.name != "scalar" {
+            scanners.push((best.name, best.find));
+        }
+        scanners
+    }
+
+    #[test]
+    fn every_scanner_finds_the_first_match_at_every_offset() {
+        // Past a few vector widths, with the needle at every position and
+        // in every tail
+        let haystack: Vec<u8> = (0..100u8).map(|i| b'a' + i % 26).collect();
+        for (name, find) in scanners() {
+            for len in 0..haystack.len() {
+                for at in 0..len {
+                    let mut bytes = haystack[..len].to_vec();
+                    bytes[at] = b';';
+                    // A second match after it must not be found instead
+                    if at + 1 < len {
+                        bytes[len - 1] = b';';
+                    }
+                    assert_eq!(
+                        find(&bytes, b';'),
+                        Some(at),
+                        "{} at {} of {}",
+                        name,
+                        at,
+                        len
+                    );
+                }
+                assert_eq!(find(&haystack[..len], b';'), None, "{} in {}", name, len);
+            }
+        }
+    }
+
+    #[test]
+    fn split_matches_str_split() {
+        for s in ["", ";", "a;b", ";a;;b;", "Zürich;12.3;x"] {
+            assert_eq!(
+                split(s, b';').collect::<Vec<_>>(),
+                s.split(';').collect::<Vec<_>>()
+            );
+        }
+    }
+}
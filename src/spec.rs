@@ -0,0 +1,142 @@
+//! The rules of the original challenge's input, as `1brc validate` checks
+//! them: every line is `name;reading` ending in `\n`, the name is 1 to 100
+//! bytes of UTF-8 without a `;`, and the reading is -99.9 to 99.9 with one
+//! fractional digit. There are at most 10,000 distinct names.
+
+pub const MAX_NAME_BYTES: usize = 100;
+pub const MAX_STATIONS: usize = 10_000;
+
+/// How a line breaks the rules.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Violation {
+    NotUtf8,
+    /// Ends in `\r\n` rather than `\n`
+    CarriageReturn,
+    EmptyLine,
+    NoDelimiter,
+    EmptyName,
+    LongName,
+    /// A `;` in the name, or more than one field after it
+    ExtraDelimiter,
+    /// Not `-?\d?\d\.\d`
+    BadReading,
+    /// Well formed, but past ±99.9
+    OutOfRange,
+}
+
+impl Violation {
+    pub const ALL: [Violation; 9] = [
+        Violation::NotUtf8,
+        Violation::CarriageReturn,
+        Violation::EmptyLine,
+        Violation::NoDelimiter,
+        Violation::EmptyName,
+        Violation::LongName,
+        Violation::ExtraDelimiter,
+        Violation::BadReading,
+        Violation::OutOfRange,
+    ];
+
+    pub fn describe(self) -> &'static str {
+        match self {
+            Violation::NotUtf8 => "not UTF-8",
+            Violation::CarriageReturn => "\\r\\n line ending",
+            Violation::EmptyLine => "empty line",
+            Violation::NoDelimiter => "no `;`",
+            Violation::EmptyName => "empty name",
+            Violation::LongName => "name over 100 bytes",
+            Violation::ExtraDelimiter => "more than one `;`",
+            Violation::BadReading => "reading not like -12.3",
+            Violation::OutOfRange => "reading out of -99.9..99.9",
+        }
+    }
+}
+
+/// The name on `line`, which is without its `\n`, or the first rule in
+/// [`Violation::ALL`] it breaks.
+pub fn check(line: &[u8]) -> Result<&str, Violation> {
+    let line = std::str::from_utf8(line).map_err(|_| Violation::NotUtf8)?;
+    if line.ends_with('\r') {
+        return Err(Violation::CarriageReturn);
+    }
+    if line.is_empty() {
+        return Err(Violation::EmptyLine);
+    }
+    let (name, reading) = line.split_once(';').ok_or(Violation::NoDelimiter)?;
+    if name.is_empty() {
+        return Err(Violation::EmptyName);
+    }
+    if name.len() > MAX_NAME_BYTES {
+        return Err(Violation::LongName);
+    }
+    if reading.contains(';') {
+        return Err(Violation::ExtraDelimiter);
+    }
+    check_reading(reading)?;
+    Ok(name)
+}
+
+fn check_reading(reading: &str) -> Result<(), Violation> {
+    let digits = reading.strip_prefix('-').unwrap_or(reading);
+    let (int, frac) = digits.split_once('.').ok_or(Violation::BadReading)?;
+    let is_digits = |s: &str| !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit());
+    if !is_digits(int) || !is_digits(frac) || frac.len() != 1 {
+        return Err(Violation::BadReading);
+    }
+    match int.len() {
+        ..=2 => Ok(()),
+        _ if int.trim_start_matches('0').len() > 2 => Err(Violation::OutOfRange),
+        // Leading zeros, as in 007.5
+        _ => Err(Violation::BadReading),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn challenge_lines_are_valid() {
+        for (line, name) in [
+            ("Hamburg;12.0", "Hamburg"),
+            ("Bulawayo;8.9", "Bulawayo"),
+            ("St. John's;-0.0", "St. John's"),
+            ("Zürich;-99.9", "Zürich"),
+            ("東京;99.9", "東京"),
+        ] {
+            assert_eq!(check(line.as_bytes()), Ok(name));
+        }
+        let longest = format!("{};1.0", "é".repeat(MAX_NAME_BYTES / 2));
+        assert!(check(longest.as_bytes()).is_ok());
+    }
+
+    #[test]
+    fn each_rule_is_told_apart() {
+        let long = format!("{};1.0", "x".repeat(MAX_NAME_BYTES + 1));
+        for (line, violation) in [
+            (&b"Ab\xFFha;1.0"[..], Violation::NotUtf8),
+            (b"Abha;1.0\r", Violation::CarriageReturn),
+            (b"", Violation::EmptyLine),
+            (b"Abha 1.0", Violation::NoDelimiter),
+            (b";1.0", Violation::EmptyName),
+            (long.as_bytes(), Violation::LongName),
+            (b"Abha;1.0;2.0", Violation::ExtraDelimiter),
+            (b"Abha;1", Violation::BadReading),
+            (b"Abha;1.25", Violation::BadReading),
+            (b"Abha;.5", Violation::BadReading),
+            (b"Abha;--1.0", Violation::BadReading),
+            (b"Abha;1e1", Violation::BadReading),
+            (b"Abha;007.5", Violation::BadReading),
+            (b"Abha;", Violation::BadReading),
+            (b"Abha;100.0", Violation::OutOfRange),
+            (b"Abha;-123.4", Violation::OutOfRange),
+        ] {
+            assert_eq!(
+                check(line),
+                Err(violation),
+                "{}",
+                String::from_utf8_lossy(line)
+            );
+        }
+    }
+}
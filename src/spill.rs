@@ -0,0 +1,221 @@
+//! Spilling of partial aggregates to disk, for inputs with more distinct
+//! keys than fit in memory. A worker whose map grows past its budget writes
+//! the map out as a run sorted by key and starts over; the reduce phase then
+//! merges all runs in key order.
+
+use crate::arena::Arena;
+use crate::name::Name;
+use crate::{Key, Stat};
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::mem;
+use std::path::PathBuf;
+
+/// Rough memory cost of one map entry: the key and value, a long name's
+/// copy in the arena, and hash table overhead.
+pub fn entry_size(key: &Key) -> usize {
+    mem::size_of::<Key>() + mem::size_of::<Stat>() + key.name.heap_len() + 16
+}
+
+#[derive(Debug)]
+pub struct Spiller {
+    dir: PathBuf,
+    worker: usize,
+    limit: usize,
+    pub files: Vec<SpillFile>,
+}
+
+impl Spiller {
+    pub fn new(dir: PathBuf, worker: usize, limit: usize) -> Self {
+        Spiller {
+            dir,
+            worker,
+            limit,
+            files: Vec::new(),
+        }
+    }
+
+    /// Writes `map` out and clears it once its estimated size in `bytes`
+    /// has grown past the budget. Returns whether it did.
+    pub fn maybe_spill(&mut self, map: &mut HashMap<Key, Stat>, bytes: usize) -> io::Result<bool> {
+        if bytes <= self.limit {
+            return Ok(false);
+        }
+        let path = self.dir.join(format!(
+            "1brc-spill-{}-{}-{}.bin",
+            std::process::id(),
+            self.worker,
+            self.files.len()
+        ));
+        let file = SpillFile { path };
+        let mut entries: Vec<(Key, Stat)> = map.drain().collect();
+        entries.sort_unstable_by_key(|entry| entry.0);
+
+        let mut out = BufWriter::new(File::create(&file.path)?);
+        for (key, stat) in &entries {
+            write_entry(&mut out, key, stat)?;
+        }
+        out.flush()?;
+
+        self.files.push(file);
+        Ok(true)
+    }
+}
+
+/// A run on disk, removed again when dropped.
+#[derive(Debug)]
+pub struct SpillFile {
+    path: PathBuf,
+}
+
+impl Drop for SpillFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+fn write_entry(out: &mut impl Write, key: &Key, stat: &Stat) -> io::Result<()> {
+    out.write_all(&(key.name.len() as u32).to_le_bytes())?;
+    out.write_all(key.name.as_bytes())?;
+    match key.window {
+        Some(window) => {
+            out.write_all(&[1])?;
+            out.write_all(&window.to_le_bytes())?;
+        }
+        None => out.write_all(&[0])?,
+    }
+    match key.column {
+        Some(column) => {
+            out.write_all(&[1])?;
+            out.write_all(&column.to_le_bytes())?;
+        }
+        None => out.write_all(&[0])?,
+    }
+    out.write_all(&stat.min.to_le_bytes())?;
+    out.write_all(&stat.max.to_le_bytes())?;
+    out.write_all(&stat.sum.to_le_bytes())?;
+    out.write_all(&stat.count.to_le_bytes())
+}
+
+fn read_entry(input: &mut impl Read, arena: &mut Arena) -> io::Result<Option<(Key, Stat)>> {
+    let mut len = [0; 4];
+    match input.read_exact(&mut len) {
+        Ok(()) => {}
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    }
+    let mut name = vec![0; u32::from_le_bytes(len) as usize];
+    input.read_exact(&mut name)?;
+    let name =
+        std::str::from_utf8(&name).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    let name = Name::new(name, arena);
+
+    let mut flag = [0; 1];
+    input.read_exact(&mut flag)?;
+    let window = if flag[0] == 1 {
+        Some(i64::from_le_bytes(read_array(input)?))
+    } else {
+        None
+    };
+    input.read_exact(&mut flag)?;
+    let column = if flag[0] == 1 {
+        Some(u16::from_le_bytes(read_array(input)?))
+    } else {
+        None
+    };
+    let stat = Stat {
+        min: f64::from_le_bytes(read_array(input)?),
+        max: f64::from_le_bytes(read_array(input)?),
+        sum: f64::from_le_bytes(read_array(input)?),
+        count: i64::from_le_bytes(read_array(input)?),
+    };
+    Ok(Some((
+        Key {
+            name,
+            window,
+            column,
+        },
+        stat,
+    )))
+}
+
+fn read_array<const N: usize>(input: &mut impl Read) -> io::Result<[u8; N]> {
+    let mut buf = [0; N];
+    input.read_exact(&mut buf)?;
+    Ok(buf)
+}
+
+/// A sorted stream of entries, either read back from disk or left in
+/// memory at the end of the scan.
+enum Run {
+    Disk(BufReader<File>),
+    Memory(std::vec::IntoIter<(Key, Stat)>),
+}
+
+impl Run {
+    fn next(&mut self, arena: &mut Arena) -> io::Result<Option<(Key, Stat)>> {
+        match self {
+            Run::Disk(reader) => read_entry(reader, arena),
+            Run::Memory(entries) => Ok(entries.next()),
+        }
+    }
+}
+
+/// Merges the spilled runs and the final in-memory maps in key order,
+/// calling `emit` once per key with its combined stats, and stopping at
+/// the first error it returns. Names read back from disk are allocated in
+/// `arena`.
+pub fn merge(
+    files: &[SpillFile],
+    maps: Vec<HashMap<Key, Stat>>,
+    arena: &mut Arena,
+    mut emit: impl FnMut(Key, Stat) -> io::Result<()>,
+) -> io::Result<()> {
+    let mut runs = Vec::new();
+    for file in files {
+        runs.push(Run::Disk(BufReader::new(File::open(&file.path)?)));
+    }
+    for map in maps {
+        let mut entries: Vec<(Key, Stat)> = map.into_iter().collect();
+        entries.sort_unstable_by_key(|entry| entry.0);
+        runs.push(Run::Memory(entries.into_iter()));
+    }
+
+    // Min-heap of each run's next key, with the run index to refill from
+    let mut heads: Vec<Option<Stat>> = Vec::with_capacity(runs.len());
+    let mut heap = BinaryHeap::new();
+    for (i, run) in runs.iter_mut().enumerate() {
+        match run.next(arena)? {
+            Some((key, stat)) => {
+                heap.push(Reverse((key, i)));
+                heads.push(Some(stat));
+            }
+            None => heads.push(None),
+        }
+    }
+
+    let mut current: Option<(Key, Stat)> = None;
+    while let Some(Reverse((key, i))) = heap.pop() {
+        let stat = heads[i].take().expect("heap entry without a pending stat");
+        if let Some((next_key, next_stat)) = runs[i].next(arena)? {
+            heap.push(Reverse((next_key, i)));
+            heads[i] = Some(next_stat);
+        }
+        match &mut current {
+            Some((current_key, current_stat)) if *current_key == key => {
+                current_stat.merge(&stat);
+            }
+            _ => {
+                if let Some((done_key, done_stat)) = current.replace((key, stat)) {
+                    emit(done_key, done_stat)?;
+                }
+            }
+        }
+    }
+    if let Some((key, stat)) = current {
+        emit(key, stat)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,42 @@
+//! The running aggregate kept per key.
+
+#[derive(Clone, Debug, Default)]
+pub struct Stat {
+    pub min: f64,
+    pub max: f64,
+    pub sum: f64,
+    pub count: i64,
+}
+
+impl Stat {
+    pub fn new(value: f64) -> Self {
+        Stat {
+            min: value,
+            max: value,
+            sum: value,
+            count: 1,
+        }
+    }
+
+    pub fn add(&mut self, value: f64) {
+        if value < self.min {
+            self.min = value;
+        }
+        if value > self.max {
+            self.max = value;
+        }
+        self.sum += value;
+        self.count += 1;
+    }
+
+    pub fn merge(&mut self, other: &Stat) {
+        if other.min < self.min {
+            self.min = other.min;
+        }
+        if other.max > self.max {
+            self.max = other.max;
+        }
+        self.sum += other.sum;
+        self.count += other.count;
+    }
+}
@@ -0,0 +1,67 @@
+//! `--max-rows-per-sec` and `--max-bytes-per-sec`: caps on how fast the
+//! workers get through the input, so that a run can share a machine with
+//! services that need its CPU and disk more. A worker that has got ahead
+//! of the rate sleeps once it finishes a block, and the readers, whose
+//! queues are bounded, wait on the workers in turn. The rate therefore
+//! holds on average, in steps of one block, which `--chunk-bytes` shrinks.
+
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// The longest a worker sleeps before it looks whether the scan is over
+const SLICE: Duration = Duration::from_millis(10);
+
+/// Units over the whole run, shared by every worker.
+#[derive(Debug)]
+struct Limit {
+    per_sec: u64,
+    used: AtomicU64,
+}
+
+impl Limit {
+    /// Counts `n` more units, and how long after `start` they are due.
+    fn take(&self, n: u64) -> Duration {
+        let used = self.used.fetch_add(n, Ordering::Relaxed) + n;
+        Duration::from_secs_f64(used as f64 / self.per_sec as f64)
+    }
+}
+
+#[derive(Debug)]
+pub struct Throttle {
+    start: Instant,
+    rows: Option<Limit>,
+    bytes: Option<Limit>,
+}
+
+impl Throttle {
+    /// `None` without a limit to enforce.
+    pub fn new(rows_per_sec: Option<u64>, bytes_per_sec: Option<u64>) -> Option<Self> {
+        let limit = |per_sec| Limit {
+            per_sec,
+            used: AtomicU64::new(0),
+        };
+        (rows_per_sec.is_some() || bytes_per_sec.is_some()).then(|| Throttle {
+            start: Instant::now(),
+            rows: rows_per_sec.map(limit),
+            bytes: bytes_per_sec.map(limit),
+        })
+    }
+
+    /// Counts a finished block of `rows` rows and `bytes` bytes, sleeping
+    /// until the run is no longer ahead of either rate, or is `cancelled`.
+    pub fn wait(&self, rows: u64, bytes: u64, cancelled: &AtomicBool) {
+        let due = [(&self.rows, rows), (&self.bytes, bytes)]
+            .into_iter()
+            .filter_map(|(limit, n)| limit.as_ref().map(|limit| limit.take(n)))
+            .max()
+            .unwrap_or_default();
+        loop {
+            let elapsed = self.start.elapsed();
+            if due <= elapsed || cancelled.load(Ordering::Relaxed) {
+                break;
+            }
+            thread::sleep((due - elapsed).min(SLICE));
+        }
+    }
+}
@@ -0,0 +1,122 @@
+//! Just enough calendar arithmetic for timestamped input: parsing epoch
+//! seconds and RFC 3339 timestamps, and formatting window starts in UTC.
+
+use anyhow::{anyhow, bail};
+
+/// Parses a duration such as `90s`, `15m`, `1h` or `1d` into seconds.
+pub fn parse_duration(v: &str) -> anyhow::Result<i64> {
+    let split = v.find(|c: char| !c.is_ascii_digit()).unwrap_or(v.len());
+    let (digits, unit) = v.split_at(split);
+    let n: i64 = digits
+        .parse()
+        .map_err(|_| anyhow!("`{}` is not a duration", v))?;
+    let scale = match unit {
+        "" | "s" => 1,
+        "m" => 60,
+        "h" => 3600,
+        "d" => 86400,
+        _ => bail!("unknown duration unit `{}`, expected s, m, h or d", unit),
+    };
+    if n == 0 {
+        bail!("duration must be positive");
+    }
+    Ok(n * scale)
+}
+
+/// Parses either integer seconds since the Unix epoch or an RFC 3339
+/// timestamp (`2024-01-31T12:00:00Z`, with an optional fraction and
+/// offset; a space may separate date and time).
+pub fn parse_timestamp(v: &str) -> anyhow::Result<i64> {
+    if let Ok(secs) = v.parse::<i64>() {
+        return Ok(secs);
+    }
+    let b = v.as_bytes();
+    let invalid = || anyhow!("`{}` is not a timestamp", v);
+    if b.len() < 19 || b[4] != b'-' || b[7] != b'-' || (b[10] != b'T' && b[10] != b' ') {
+        return Err(invalid());
+    }
+    if b[13] != b':' || b[16] != b':' || !v.is_char_boundary(19) {
+        return Err(invalid());
+    }
+    let num = |range: std::ops::Range<usize>| -> anyhow::Result<i64> {
+        let s = &v[range];
+        if !s.bytes().all(|c| c.is_ascii_digit()) {
+            return Err(invalid());
+        }
+        s.parse().map_err(|_| invalid())
+    };
+    let (year, month, day) = (num(0..4)?, num(5..7)?, num(8..10)?);
+    let (hour, minute, second) = (num(11..13)?, num(14..16)?, num(17..19)?);
+    let valid_date = (1..=12).contains(&month) && (1..=31).contains(&day);
+    if !valid_date || hour > 23 || minute > 59 || second > 60 {
+        return Err(invalid());
+    }
+
+    let mut rest = &v[19..];
+    if let Some(fraction) = rest.strip_prefix('.') {
+        let digits = fraction.bytes().take_while(u8::is_ascii_digit).count();
+        rest = &fraction[digits..];
+    }
+    let offset = match rest {
+        "" | "Z" | "z" => 0,
+        _ => {
+            let sign = match rest.as_bytes()[0] {
+                b'+' => 1,
+                b'-' => -1,
+                _ => return Err(invalid()),
+            };
+            let (h, m) = rest[1..].split_once(':').ok_or_else(invalid)?;
+            let (h, m): (i64, i64) = (
+                h.parse().map_err(|_| invalid())?,
+                m.parse().map_err(|_| invalid())?,
+            );
+            if !(0..=23).contains(&h) || !(0..=59).contains(&m) {
+                return Err(invalid());
+            }
+            sign * (h * 3600 + m * 60)
+        }
+    };
+
+    let days = days_from_civil(year, month, day);
+    Ok(days * 86400 + hour * 3600 + minute * 60 + second - offset)
+}
+
+/// Formats seconds since the epoch as `YYYY-MM-DDTHH:MM:SSZ`.
+pub fn format_timestamp(secs: i64) -> String {
+    let days = secs.div_euclid(86400);
+    let rem = secs.rem_euclid(86400);
+    let (year, month, day) = civil_from_days(days);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        rem / 3600,
+        rem % 3600 / 60,
+        rem % 60
+    )
+}
+
+// Howard Hinnant's days_from_civil and civil_from_days algorithms.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let mp = (month + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
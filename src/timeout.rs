@@ -0,0 +1,100 @@
+//! `--timeout`: a deadline for the whole run, counted from its start, past
+//! which the scan is cancelled as it is when a worker fails. The workers
+//! stop at their next block, or sooner if the throttle has them sleeping,
+//! and what they had aggregated is either kept as partial results, under
+//! `--partial-on-timeout`, or given up for a [`TimedOut`] error, which
+//! exits with 124 as `timeout(1)` does. A deadline that passes after the
+//! scan but before the output fails the run the same way.
+
+use crate::cli::Config;
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::{Arc, OnceLock};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// The exit status of a run that timed out.
+pub const EXIT_STATUS: i32 = 124;
+
+/// When the run is up, and how long it was given
+static DEADLINE: OnceLock<(Instant, Duration)> = OnceLock::new();
+
+/// Starts the clock on a run that may take `timeout`.
+pub fn start(timeout: Duration) {
+    DEADLINE.get_or_init(|| (Instant::now() + timeout, timeout));
+}
+
+/// Fails once the deadline has passed, unless `--partial-on-timeout` lets
+/// the run go on to print what it has.
+pub fn check(config: &Config) -> Result<(), TimedOut> {
+    match DEADLINE.get() {
+        Some(&(deadline, timeout)) if !config.partial_on_timeout && Instant::now() >= deadline => {
+            Err(TimedOut(timeout))
+        }
+        _ => Ok(()),
+    }
+}
+
+#[derive(Debug)]
+pub struct TimedOut(pub Duration);
+
+impl fmt::Display for TimedOut {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "timed out after {}s, before the run was done (--partial-on-timeout keeps \
+             the results so far)",
+            self.0.as_secs()
+        )
+    }
+}
+
+impl std::error::Error for TimedOut {}
+
+/// Sets a flag once the deadline passes, unless stopped first, for a scan
+/// to take as its cancellation.
+pub struct Alarm {
+    cancelled: Arc<AtomicBool>,
+    stop: mpsc::Sender<()>,
+    /// Whether it went off
+    thread: JoinHandle<bool>,
+}
+
+impl Alarm {
+    /// `None` without a deadline. One already past goes off at once.
+    pub fn set() -> Option<Self> {
+        let (deadline, _) = *DEADLINE.get()?;
+        let cancelled = Arc::new(AtomicBool::new(false));
+        let flag = cancelled.clone();
+        let (stop, stopped) = mpsc::channel();
+        let thread = thread::spawn(move || {
+            let left = deadline.saturating_duration_since(Instant::now());
+            match stopped.recv_timeout(left) {
+                Err(RecvTimeoutError::Timeout) => {
+                    flag.store(true, Ordering::Relaxed);
+                    true
+                }
+                _ => false,
+            }
+        });
+        Some(Alarm {
+            cancelled,
+            stop,
+            thread,
+        })
+    }
+
+    /// The flag it sets, which others may set too.
+    pub fn cancelled(&self) -> Arc<AtomicBool> {
+        self.cancelled.clone()
+    }
+
+    /// Stops the alarm once the scan is over, returning whether it cut the
+    /// scan short.
+    pub fn stop(self) -> bool {
+        // Gone if the alarm went off
+        let _ = self.stop.send(());
+        self.thread.join().unwrap()
+    }
+}
@@ -0,0 +1,142 @@
+//! `1brc validate`: checks every line of the inputs against the original
+//! challenge's rules without aggregating them, and reports how many lines
+//! break each rule and the bytes the first few of them start at.
+
+use crate::cli::Config;
+use crate::input;
+use crate::output;
+use crate::spec::{self, Violation};
+use anyhow::Context as _;
+use std::collections::{BTreeMap, HashSet};
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read, Write};
+
+/// Offsets kept of the lines breaking each rule
+const SAMPLES: usize = 5;
+
+#[derive(Default)]
+struct Report {
+    lines: u64,
+    /// The lines breaking each rule, and where the first of them start
+    violations: BTreeMap<Violation, (u64, Vec<u64>)>,
+    /// Up to one more than the most there may be
+    stations: HashSet<String>,
+    /// The input starts with a UTF-8 byte order mark
+    bom: bool,
+}
+
+impl Report {
+    fn add(&mut self, line: &[u8], at: u64) {
+        self.lines += 1;
+        match spec::check(line) {
+            Ok(name) => {
+                if self.stations.len() <= spec::MAX_STATIONS && !self.stations.contains(name) {
+                    self.stations.insert(name.to_string());
+                }
+            }
+            Err(violation) => {
+                let (count, samples) = self.violations.entry(violation).or_default();
+                *count += 1;
+                if samples.len() < SAMPLES {
+                    samples.push(at);
+                }
+            }
+        }
+    }
+
+    fn is_valid(&self) -> bool {
+        self.violations.is_empty() && self.stations.len() <= spec::MAX_STATIONS && !self.bom
+    }
+
+    fn print(&self, path: &str, out: &mut impl Write) -> io::Result<()> {
+        let stations = match self.stations.len() {
+            n if n > spec::MAX_STATIONS => format!("over {}", spec::MAX_STATIONS),
+            n => n.to_string(),
+        };
+        let invalid: u64 = self.violations.values().map(|(count, _)| count).sum();
+        writeln!(
+            out,
+            "{}: {} line{}, {} station{}, {}",
+            path,
+            self.lines,
+            if self.lines == 1 { "" } else { "s" },
+            stations,
+            if self.stations.len() == 1 { "" } else { "s" },
+            match self.is_valid() {
+                true => "valid".to_string(),
+                false => format!("{} invalid", invalid),
+            }
+        )?;
+        if self.bom {
+            writeln!(out, "  starts with a byte order mark")?;
+        }
+        if self.stations.len() > spec::MAX_STATIONS {
+            writeln!(out, "  more than {} distinct stations", spec::MAX_STATIONS)?;
+        }
+        for (violation, (count, samples)) in &self.violations {
+            let samples: Vec<String> = samples.iter().map(u64::to_string).collect();
+            writeln!(
+                out,
+                "  {}: {} ({} {}{})",
+                violation.describe(),
+                count,
+                if *count == 1 { "byte" } else { "bytes" },
+                samples.join(", "),
+                if *count > samples.len() as u64 {
+                    ", ..."
+                } else {
+                    ""
+                }
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Prints what breaks the rules in each input, and returns whether
+/// anything does.
+pub fn run(config: &Config) -> anyhow::Result<bool> {
+    let mut invalid = false;
+    let mut out = output::stdout();
+    for path in &config.inputs {
+        let report = check(path, config.max_line_bytes)
+            .with_context(|| format!("cannot validate {}", path))?;
+        report.print(path, &mut out)?;
+        // Each report is out before the next input is read
+        out.flush()?;
+        invalid |= !report.is_valid();
+    }
+    Ok(invalid)
+}
+
+/// Checks the lines of the file at `path`, failing on one longer than
+/// `max_line` rather than reading on for its end.
+fn check(path: &str, max_line: usize) -> io::Result<Report> {
+    let mut reader = BufReader::with_capacity(1 << 20, File::open(path)?);
+    let mut report = Report::default();
+    let mut line = Vec::new();
+    let mut at = 0;
+    loop {
+        line.clear();
+        let n = (&mut reader)
+            .take(max_line as u64 + 1)
+            .read_until(b'\n', &mut line)?;
+        if n == 0 {
+            return Ok(report);
+        }
+        let row = match line.strip_suffix(b"\n") {
+            Some(row) => row,
+            None if n > max_line => return Err(input::too_long(at, max_line)),
+            None => &line,
+        };
+        let row = match row.strip_prefix(b"\xEF\xBB\xBF") {
+            Some(rest) if at == 0 => {
+                report.bom = true;
+                rest
+            }
+            _ => row,
+        };
+        report.add(row, at);
+        at += n as u64;
+    }
+}
@@ -0,0 +1,41 @@
+//! What `--version` prints: enough about the binary for a benchmark report
+//! to say exactly what produced its numbers.
+
+use crate::alloc;
+use crate::{sha256, simd};
+
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+pub const COMMIT: &str = env!("ONEBRC_COMMIT");
+
+/// The optional parts built in. `mmap` and `simd` are always there.
+pub fn features() -> Vec<&'static str> {
+    let mut features = vec!["mmap", "simd"];
+    if cfg!(feature = "runtime-tokio") {
+        features.push("tokio");
+    }
+    if cfg!(feature = "alloc-stats") {
+        features.push("alloc-stats");
+    }
+    if cfg!(feature = "perf-counters") {
+        features.push("perf-counters");
+    }
+    features
+}
+
+pub fn lines() -> Vec<String> {
+    let target_features = env!("ONEBRC_TARGET_FEATURES").replace(',', ", ");
+    vec![
+        format!("1brc {} ({})", VERSION, COMMIT),
+        format!("profile: {}", env!("ONEBRC_PROFILE")),
+        format!("target: {}", env!("ONEBRC_TARGET")),
+        format!("features: {}", features().join(", ")),
+        format!("target features: {}", target_features),
+        // Detected when the binary is run, whatever it was built for
+        format!(
+            "this CPU: {} scanner, {} SHA-256",
+            simd::name(),
+            sha256::implementation()
+        ),
+        format!("allocator: {}", alloc::NAME),
+    ]
+}
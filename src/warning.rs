@@ -0,0 +1,155 @@
+//! Problems that do not stop a run, handed to whoever embeds the
+//! aggregation instead of being printed, so that it can log, count or
+//! surface them as it sees fit. The `1brc` binary prints each one on
+//! stderr as it comes.
+//!
+//! Rows left out of the results are counted in [`Results`](crate::results::Results)
+//! rather than warned about one by one.
+
+use std::fmt;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Warning {
+    /// A thread could not be pinned to `cores` and runs wherever the
+    /// scheduler puts it
+    Unpinned {
+        thread: String,
+        cores: Vec<usize>,
+        reason: String,
+    },
+    /// Cached results that could not be read or were not valid, and are
+    /// computed again
+    CacheIgnored { path: PathBuf, reason: String },
+    /// Results that could not be cached for the next run
+    CacheNotWritten { path: PathBuf, reason: String },
+    /// A worker that failed, whose share of the input is left out of the
+    /// results
+    WorkerLeftOut { reason: String },
+    /// An input that could not be aggregated and was set aside
+    InputSetAside { path: PathBuf, reason: String },
+    /// A run cut short by `--timeout`, whose results are of the input read
+    /// by then
+    TimedOut { after: Duration },
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Warning::Unpinned {
+                thread,
+                cores,
+                reason,
+            } => write!(
+                f,
+                "could not pin {} thread to cores {:?}: {}",
+                thread, cores, reason
+            ),
+            Warning::CacheIgnored { path, reason } => {
+                write!(f, "ignoring {}: {}", path.display(), reason)
+            }
+            Warning::CacheNotWritten { path, reason } => {
+                write!(f, "cannot write {}: {}", path.display(), reason)
+            }
+            Warning::WorkerLeftOut { reason } => {
+                write!(f, "{}; its results are left out", reason)
+            }
+            Warning::InputSetAside { path, reason } => {
+                write!(f, "{}: {}; moving it to failed/", path.display(), reason)
+            }
+            Warning::TimedOut { after } => write!(
+                f,
+                "timed out after {}s; the results are of the input read so far",
+                after.as_secs()
+            ),
+        }
+    }
+}
+
+type Callback = Box<dyn Fn(&Warning) + Send + Sync>;
+
+/// Where warnings go: to a callback as they happen, or, by default,
+/// collected until [`take`](Warnings::take)n. Either way they are counted.
+#[derive(Default)]
+pub struct Warnings {
+    callback: Option<Callback>,
+    collected: Mutex<Vec<Warning>>,
+    count: AtomicU64,
+}
+
+impl Warnings {
+    /// Warnings handed to `callback`, from whichever thread runs into them.
+    pub fn new(callback: impl Fn(&Warning) + Send + Sync + 'static) -> Self {
+        Warnings {
+            callback: Some(Box::new(callback)),
+            ..Default::default()
+        }
+    }
+
+    pub fn warn(&self, warning: Warning) {
+        self.count.fetch_add(1, Ordering::Relaxed);
+        match &self.callback {
+            Some(callback) => callback(&warning),
+            None => self.collected.lock().unwrap().push(warning),
+        }
+    }
+
+    /// The warnings collected so far, in the order they came, which leaves
+    /// none.
+    pub fn take(&self) -> Vec<Warning> {
+        std::mem::take(&mut self.collected.lock().unwrap())
+    }
+
+    /// How many warnings there have been in all.
+    pub fn count(&self) -> u64 {
+        self.count.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::sync::atomic::AtomicUsize;
+
+    fn left_out(reason: &str) -> Warning {
+        Warning::WorkerLeftOut {
+            reason: reason.to_string(),
+        }
+    }
+
+    #[test]
+    fn warnings_are_collected_in_order_by_default() {
+        let warnings = Warnings::default();
+        warnings.warn(left_out("worker 1 failed"));
+        warnings.warn(left_out("worker 3 failed"));
+        assert_eq!(
+            warnings.take(),
+            [left_out("worker 1 failed"), left_out("worker 3 failed")]
+        );
+        assert_eq!(warnings.take(), []);
+        assert_eq!(warnings.count(), 2);
+    }
+
+    #[test]
+    fn a_callback_gets_each_warning_instead() {
+        let seen = Arc::new(AtomicUsize::new(0));
+        let warnings = Warnings::new({
+            let seen = seen.clone();
+            move |warning| {
+                assert_eq!(
+                    warning.to_string(),
+                    "worker 2 failed; its results are left out"
+                );
+                seen.fetch_add(1, Ordering::Relaxed);
+            }
+        });
+        warnings.warn(left_out("worker 2 failed"));
+        assert_eq!(seen.load(Ordering::Relaxed), 1);
+        assert_eq!(warnings.take(), []);
+        assert_eq!(warnings.count(), 1);
+    }
+}
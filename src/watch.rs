@@ -0,0 +1,400 @@
+//! `--watch-dir`: new files in a directory are aggregated one at a time
+//! into running totals, which are printed after each file. The totals are
+//! saved to a state file in the directory, along with the file they were
+//! last extended by, before that file is moved to `done/`. After a crash
+//! in between, the file is recognised and only moved, so every file is
+//! counted exactly once. The state also records the options the totals
+//! were aggregated with, and a run with others refuses to add to them.
+
+use crate::arena::Arena;
+use crate::cache;
+use crate::cli::Config;
+use crate::metadata::Metadata;
+use crate::results::{self, Results};
+use crate::service::{self, Level, Service};
+use crate::warning::Warning;
+use crate::{Summary, aggregate, output, print_summary};
+use anyhow::Context;
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, UNIX_EPOCH};
+
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+const STATE_FILE: &str = ".1brc-state";
+const MAGIC: &[u8] = b"1brc-watch 2\n";
+
+/// A file as of some moment: its name, size and modification time in
+/// nanoseconds. A file still being written differs between two polls.
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Version {
+    name: String,
+    len: u64,
+    modified: u64,
+}
+
+impl Version {
+    fn of(path: &Path) -> io::Result<Version> {
+        let metadata = fs::metadata(path)?;
+        let modified = metadata.modified()?.duration_since(UNIX_EPOCH);
+        Ok(Version {
+            name: path
+                .file_name()
+                .map_or_else(String::new, |name| name.to_string_lossy().into_owned()),
+            len: metadata.len(),
+            modified: modified.map_or(0, |d| d.as_nanos() as u64),
+        })
+    }
+}
+
+/// Watches `dir` until the process is stopped.
+pub fn run(mut config: Config, dir: &Path, num_threads: usize) -> anyhow::Result<()> {
+    let service = config.daemon.then(Service::new);
+    let mut metadata = load_metadata(&config)?;
+    let done = dir.join("done");
+    let failed = dir.join("failed");
+    fs::create_dir_all(&done).with_context(|| format!("failed to create {}", done.display()))?;
+    fs::create_dir_all(&failed)
+        .with_context(|| format!("failed to create {}", failed.display()))?;
+
+    // Names in the totals live here for the whole run
+    let mut arena = Arena::default();
+    let state = dir.join(STATE_FILE);
+    let options = cache::aggregation_options(&config);
+    let mut totals = resume(dir, &options, &mut arena)?;
+    match &service {
+        Some(service) => {
+            if totals.lines > 0 {
+                service.log(
+                    Level::Info,
+                    &format!("resumed totals of {} lines", totals.lines),
+                    &[("TOTAL_LINES", totals.lines.to_string())],
+                );
+            }
+            service.log(
+                Level::Info,
+                &format!("watching {}", dir.display()),
+                &[("DIRECTORY", dir.display().to_string())],
+            );
+            service.notify("READY=1\nSTATUS=Watching for files");
+        }
+        None if totals.lines > 0 => note!(config, "Resumed totals of {} lines", totals.lines),
+        None => {}
+    }
+
+    // Files as of the previous poll; only those that have not changed
+    // since are picked up
+    let mut seen: HashMap<PathBuf, Version> = HashMap::new();
+    loop {
+        if let Some(service) = &service
+            && service::reload_requested()
+        {
+            service.notify("RELOADING=1");
+            match load_metadata(&config) {
+                Ok(loaded) => {
+                    metadata = loaded;
+                    service.log(Level::Info, "reloaded", &[]);
+                }
+                Err(e) => service.log(
+                    Level::Error,
+                    &format!("reload failed, keeping the previous metadata: {:#}", e),
+                    &[],
+                ),
+            }
+            service.notify("READY=1");
+        }
+        let mut ready = vec![];
+        let mut current = HashMap::new();
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            let hidden = path
+                .file_name()
+                .is_some_and(|name| name.to_string_lossy().starts_with('.'));
+            if hidden || !path.is_file() {
+                continue;
+            }
+            let Ok(version) = Version::of(&path) else {
+                // Removed since it was listed
+                continue;
+            };
+            if seen.get(&path) == Some(&version) {
+                ready.push((path.clone(), version.clone()));
+            }
+            current.insert(path, version);
+        }
+        ready.sort_by(|a, b| a.0.cmp(&b.0));
+        for (path, version) in ready {
+            current.remove(&path);
+            config.input = path.to_string_lossy().into_owned();
+            let mut arenas = vec![];
+            let summary = match aggregate(
+                &Arc::new(config.clone()),
+                metadata.as_ref(),
+                num_threads,
+                &Arc::default(),
+                &mut arenas,
+            ) {
+                Ok(summary) => summary,
+                Err(e) => {
+                    let warning = Warning::InputSetAside {
+                        path: path.clone(),
+                        reason: e.to_string(),
+                    };
+                    match &service {
+                        Some(service) => service.log(
+                            Level::Warning,
+                            &warning.to_string(),
+                            &[
+                                ("FILE", path.display().to_string()),
+                                ("ERROR", e.to_string()),
+                            ],
+                        ),
+                        None => crate::warn(warning),
+                    }
+                    move_into(&path, &failed)?;
+                    continue;
+                }
+            };
+            totals.merge(&summary, &mut arena);
+            save_state(&state, &totals, &version, &options)
+                .with_context(|| format!("failed to write {}", state.display()))?;
+            move_into(&path, &done)?;
+            match &service {
+                Some(service) => {
+                    let results =
+                        output::format_results(&config, &totals.results(), metadata.as_ref());
+                    service.log(
+                        Level::Info,
+                        &format!("added {} ({} lines)", version.name, summary.lines),
+                        &[
+                            ("FILE", version.name.clone()),
+                            ("LINES", summary.lines.to_string()),
+                            ("TOTAL_LINES", totals.lines.to_string()),
+                            ("TOTALS", results),
+                        ],
+                    );
+                    service.notify(&format!(
+                        "STATUS=Totals of {} lines, last from {}",
+                        totals.lines, version.name
+                    ));
+                }
+                None => {
+                    note!(
+                        config,
+                        "Totals after {} ({} lines):",
+                        version.name,
+                        totals.lines
+                    );
+                    print_summary(&config, &totals, metadata.as_ref())?;
+                }
+            }
+        }
+        seen = current;
+        thread::sleep(POLL_INTERVAL);
+    }
+}
+
+fn load_metadata(config: &Config) -> anyhow::Result<Option<Metadata>> {
+    config
+        .metadata
+        .as_ref()
+        .map(|path| Metadata::load(path, config.normalize_keys))
+        .transpose()
+}
+
+/// Moves `path` into `dir`, under a numbered name if one like it is
+/// already there.
+fn move_into(path: &Path, dir: &Path) -> anyhow::Result<()> {
+    let name = path.file_name().expect("a file in the watched directory");
+    let mut target = dir.join(name);
+    let mut n = 1;
+    while target.exists() {
+        target = dir.join(format!("{}.{}", name.to_string_lossy(), n));
+        n += 1;
+    }
+    fs::rename(path, &target)
+        .with_context(|| format!("failed to move {} to {}", path.display(), target.display()))
+}
+
+/// The totals saved in `dir`, after moving the file they were last
+/// extended by to `done/` if a crash left it behind. Totals aggregated
+/// with other `options` are not resumed.
+fn resume(dir: &Path, options: &[(&str, String)], arena: &mut Arena) -> anyhow::Result<Summary> {
+    let state = dir.join(STATE_FILE);
+    let (totals, last) = load_state(&state, options, arena)
+        .with_context(|| format!("failed to read {}", state.display()))?;
+    if let Some(last) = last {
+        let path = dir.join(&last.name);
+        if Version::of(&path).is_ok_and(|version| version == last) {
+            move_into(&path, &dir.join("done"))?;
+        }
+    }
+    Ok(totals)
+}
+
+fn load_state(
+    path: &Path,
+    options: &[(&str, String)],
+    arena: &mut Arena,
+) -> io::Result<(Summary, Option<Version>)> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok((Summary::default(), None)),
+        Err(e) => return Err(e),
+    };
+    let mut input = BufReader::new(file);
+    let mut magic = [0; MAGIC.len()];
+    input.read_exact(&mut magic)?;
+    if magic != MAGIC {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "not a state file of this version",
+        ));
+    }
+    let mut buf = [0; 4];
+    input.read_exact(&mut buf)?;
+    for _ in 0..u32::from_le_bytes(buf) {
+        let (option, then) = (
+            results::read_str(&mut input)?,
+            results::read_str(&mut input)?,
+        );
+        let now = options
+            .iter()
+            .find(|(name, _)| *name == option)
+            .map(|(_, value)| value.as_str());
+        if now != Some(then.as_str()) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "the totals were aggregated with {} {}, not {}; move it away to start over",
+                    option,
+                    then,
+                    now.unwrap_or("unset")
+                ),
+            ));
+        }
+    }
+    let name = results::read_str(&mut input)?;
+    let mut buf = [0; 8];
+    input.read_exact(&mut buf)?;
+    let len = u64::from_le_bytes(buf);
+    input.read_exact(&mut buf)?;
+    let modified = u64::from_le_bytes(buf);
+    let summary = Summary::from_results(Results::deserialize(&mut input)?, arena);
+    Ok((
+        summary,
+        Some(Version {
+            name,
+            len,
+            modified,
+        }),
+    ))
+}
+
+/// Replaces the state file through a temporary one, so that it always
+/// holds either the old or the new totals.
+fn save_state(
+    path: &Path,
+    totals: &Summary,
+    last: &Version,
+    options: &[(&str, String)],
+) -> io::Result<()> {
+    let tmp = path.with_extension("tmp");
+    let mut out = BufWriter::new(File::create(&tmp)?);
+    out.write_all(MAGIC)?;
+    out.write_all(&(options.len() as u32).to_le_bytes())?;
+    for (option, value) in options {
+        results::write_str(&mut out, option)?;
+        results::write_str(&mut out, value)?;
+    }
+    results::write_str(&mut out, &last.name)?;
+    out.write_all(&last.len.to_le_bytes())?;
+    out.write_all(&last.modified.to_le_bytes())?;
+    totals.to_results().serialize(&mut out)?;
+    out.into_inner()?.sync_all()?;
+    fs::rename(&tmp, path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::Unit;
+
+    /// A watched directory with `done/`, removed when dropped.
+    struct Watched(PathBuf);
+
+    impl Watched {
+        fn new(name: &str) -> Self {
+            let dir =
+                std::env::temp_dir().join(format!("1brc-watch-{}-{}", std::process::id(), name));
+            fs::create_dir_all(dir.join("done")).unwrap();
+            Watched(dir)
+        }
+    }
+
+    impl Drop for Watched {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn totals(lines: u64) -> Summary {
+        Summary {
+            lines,
+            ..Summary::default()
+        }
+    }
+
+    #[test]
+    fn a_crash_before_the_move_only_moves_the_file() {
+        let dir = Watched::new("crash");
+        let options = cache::aggregation_options(&Config::default());
+        let file = dir.0.join("a.csv");
+        fs::write(&file, "Hamburg;12.0\n").unwrap();
+        // The state was saved, the file not moved yet
+        let state = dir.0.join(STATE_FILE);
+        save_state(&state, &totals(1), &Version::of(&file).unwrap(), &options).unwrap();
+        let resumed = resume(&dir.0, &options, &mut Arena::default()).unwrap();
+        assert_eq!(resumed.lines, 1);
+        assert!(!file.exists());
+        assert!(dir.0.join("done/a.csv").exists());
+
+        // A file of that name written since is another one
+        fs::write(&file, "Hamburg;12.0\nOslo;1.0\n").unwrap();
+        let resumed = resume(&dir.0, &options, &mut Arena::default()).unwrap();
+        assert_eq!(resumed.lines, 1);
+        assert!(file.exists());
+    }
+
+    #[test]
+    fn totals_of_other_options_are_not_resumed() {
+        let dir = Watched::new("options");
+        let file = dir.0.join("a.csv");
+        fs::write(&file, "Hamburg;12.0\n").unwrap();
+        let before = cache::aggregation_options(&Config::default());
+        let state = dir.0.join(STATE_FILE);
+        save_state(&state, &totals(1), &Version::of(&file).unwrap(), &before).unwrap();
+        let config = Config {
+            unit: Some(Unit::Fahrenheit),
+            ..Config::default()
+        };
+        let error = resume(
+            &dir.0,
+            &cache::aggregation_options(&config),
+            &mut Arena::default(),
+        )
+        .unwrap_err();
+        assert_eq!(
+            format!("{:#}", error),
+            format!(
+                "failed to read {}: the totals were aggregated with unit \"Celsius\", not \"Fahrenheit\"; move it away to start over",
+                state.display()
+            )
+        );
+        // Nothing is moved for totals that are not resumed
+        assert!(file.exists());
+    }
+}
@@ -0,0 +1,191 @@
+//! Failures of individual workers. A worker's error or panic is caught
+//! along with the chunk it was on, so that the run can stop cleanly, or
+//! under `--best-effort` go on without that worker. A record that cannot
+//! be parsed fails its worker with where it is in the input, while one
+//! that cannot be read fails the run as a [`ReadError`].
+
+use crate::ThreadData;
+use crate::cli::Config;
+use crate::warning::Warning;
+use std::any::Any;
+use std::fmt;
+use std::io;
+use std::panic::{self, AssertUnwindSafe};
+
+#[derive(Debug)]
+pub struct WorkerError {
+    pub worker: usize,
+    /// Byte offset of the chunk the worker was processing
+    pub offset: u64,
+    pub cause: Failure,
+}
+
+#[derive(Debug)]
+pub enum Failure {
+    Error(anyhow::Error),
+    Panic(String),
+}
+
+impl fmt::Display for WorkerError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.cause {
+            Failure::Error(e) => write!(
+                f,
+                "worker {} failed on the chunk at byte offset {}: {}",
+                self.worker, self.offset, e
+            ),
+            Failure::Panic(message) => write!(
+                f,
+                "worker {} panicked on the chunk at byte offset {}: {}",
+                self.worker, self.offset, message
+            ),
+        }
+    }
+}
+
+impl std::error::Error for WorkerError {}
+
+impl WorkerError {
+    fn is_read(&self) -> bool {
+        matches!(&self.cause, Failure::Error(e) if e.is::<ReadError>())
+    }
+}
+
+/// The input failing to read, rather than a worker failing on what was
+/// read, whichever thread ran into it. With several inputs it names the
+/// one that failed.
+#[derive(Debug)]
+pub struct ReadError {
+    input: Option<String>,
+    cause: io::Error,
+}
+
+impl ReadError {
+    pub fn new(config: &Config, cause: io::Error) -> Self {
+        ReadError {
+            input: (config.inputs.len() > 1).then(|| config.input.clone()),
+            cause,
+        }
+    }
+}
+
+impl fmt::Display for ReadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.input {
+            Some(input) => write!(f, "failed to read {}: {}", input, self.cause),
+            None => write!(f, "{}", self.cause),
+        }
+    }
+}
+
+impl std::error::Error for ReadError {}
+
+/// A record a worker could not take, with where it is in the input: the
+/// byte it starts at, the line that is, if it could be counted, and how
+/// it starts.
+#[derive(Debug)]
+pub struct RowError {
+    pub at: u64,
+    pub line: Option<u64>,
+    pub snippet: String,
+    pub cause: anyhow::Error,
+}
+
+/// Characters of a record shown with its error
+const SNIPPET: usize = 60;
+
+impl RowError {
+    pub fn new(at: u64, line: Option<u64>, record: &str, cause: anyhow::Error) -> Self {
+        let mut snippet: String = record.chars().take(SNIPPET).collect();
+        if snippet.len() < record.len() {
+            snippet.push_str("...");
+        }
+        RowError {
+            at,
+            line,
+            snippet,
+            cause,
+        }
+    }
+}
+
+impl fmt::Display for RowError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} on ", self.cause)?;
+        match self.line {
+            Some(line) => write!(f, "line {} (byte {})", line, self.at)?,
+            None => write!(f, "the line at byte {}", self.at)?,
+        }
+        write!(f, ": {:?}", self.snippet)
+    }
+}
+
+impl std::error::Error for RowError {}
+
+/// Runs `f` for `worker`, turning an error or panic into a [`WorkerError`]
+/// at the offset `f` last stored.
+pub fn guard<T>(
+    worker: usize,
+    f: impl FnOnce(&mut u64) -> anyhow::Result<T>,
+) -> Result<T, WorkerError> {
+    let mut offset = 0;
+    let result = panic::catch_unwind(AssertUnwindSafe(|| f(&mut offset)));
+    let cause = match result {
+        Ok(Ok(value)) => return Ok(value),
+        Ok(Err(e)) => Failure::Error(e),
+        Err(payload) => Failure::Panic(panic_message(payload)),
+    };
+    Err(WorkerError {
+        worker,
+        offset,
+        cause,
+    })
+}
+
+fn panic_message(payload: Box<dyn Any + Send>) -> String {
+    match payload.downcast::<String>() {
+        Ok(message) => *message,
+        Err(payload) => match payload.downcast::<&str>() {
+            Ok(message) => message.to_string(),
+            Err(_) => "unknown panic".to_string(),
+        },
+    }
+}
+
+/// The data of the workers that finished. A failure is an error naming
+/// every failed worker, unless `best_effort` lets the others' results
+/// stand, which still needs at least one of them.
+pub fn collect(
+    results: Vec<Result<ThreadData, WorkerError>>,
+    best_effort: bool,
+) -> anyhow::Result<Vec<ThreadData>> {
+    let (finished, failed): (Vec<_>, Vec<_>) = results.into_iter().partition(Result::is_ok);
+    let finished: Vec<ThreadData> = finished.into_iter().flat_map(Result::ok).collect();
+    let mut failed: Vec<WorkerError> = failed.into_iter().flat_map(Result::err).collect();
+    if failed.is_empty() {
+        return Ok(finished);
+    }
+    // Not being able to read the input is not a worker's to leave out
+    if let Some(i) = failed.iter().position(WorkerError::is_read)
+        && let Failure::Error(e) = failed.swap_remove(i).cause
+    {
+        return Err(e);
+    }
+    if best_effort && !finished.is_empty() {
+        for e in &failed {
+            crate::warn(Warning::WorkerLeftOut {
+                reason: e.to_string(),
+            });
+        }
+        return Ok(finished);
+    }
+    if failed.len() == 1 {
+        return Err(failed.remove(0).into());
+    }
+    let mut message = format!("{} workers failed:", failed.len());
+    for e in &failed {
+        message.push_str("\n  ");
+        message.push_str(&e.to_string());
+    }
+    Err(anyhow::anyhow!(message))
+}
@@ -0,0 +1,3 @@
+//! Helpers shared by the integration tests.
+
+pub use onebrc::rng::Rng;
@@ -0,0 +1,797 @@
+//! The binary's full output for small generated datasets, in every output
+//! format, compared with the files under `tests/golden`. After an intended
+//! change to the output, run with `UPDATE_GOLDEN=1` to rewrite them.
+
+mod common;
+
+use common::Rng;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+const STATIONS: &[&str] = &[
+    "Abha",
+    "Abéché",
+    "Zürich",
+    "東京",
+    "St. John's",
+    "Las Palmas de Gran Canaria",
+];
+
+/// Readings whose means sit on a rounding boundary, followed by seeded
+/// ones.
+fn readings() -> String {
+    let mut out = String::new();
+    for (station, values) in [
+        ("Half up", &["0.1", "0.0"][..]),
+        ("Half down", &["-0.1", "0.0"]),
+        ("Negative zero", &["-0.0"]),
+        ("Cancelling", &["0.1", "-0.1"]),
+        ("Extremes", &["-99.9", "99.9", "-99.9"]),
+    ] {
+        for value in values {
+            out.push_str(&format!("{};{}\n", station, value));
+        }
+    }
+    let mut rng = Rng::new(129);
+    for _ in 0..300 {
+        let station = STATIONS[rng.below(STATIONS.len() as u64) as usize];
+        let tenths = rng.below(1999) as i64 - 999;
+        out.push_str(&format!("{};{:.1}\n", station, tenths as f64 / 10.0));
+    }
+    out
+}
+
+/// Seeded readings over four hours, prefixed with epoch seconds.
+fn timed_readings() -> String {
+    let mut out = String::new();
+    let mut rng = Rng::new(1291);
+    for _ in 0..200 {
+        let timestamp = 1_700_000_000 + rng.below(4 * 3600);
+        let station = STATIONS[rng.below(3) as usize];
+        let tenths = rng.below(1999) as i64 - 999;
+        out.push_str(&format!(
+            "{};{};{:.1}\n",
+            timestamp,
+            station,
+            tenths as f64 / 10.0
+        ));
+    }
+    out
+}
+
+/// Seeded rows of three readings each, the last sometimes missing.
+fn multi_readings() -> String {
+    let mut out = String::new();
+    let mut rng = Rng::new(1611);
+    for _ in 0..200 {
+        let station = STATIONS[rng.below(STATIONS.len() as u64) as usize];
+        let mut row = station.to_string();
+        for _ in 0..3 {
+            let tenths = rng.below(1999) as i64 - 999;
+            row.push_str(&format!(";{:.1}", tenths as f64 / 10.0));
+        }
+        if rng.below(5) == 0 {
+            row.truncate(row.rfind(';').unwrap() + 1);
+            row.push_str("NA");
+        }
+        out.push_str(&row);
+        out.push('\n');
+    }
+    out
+}
+
+/// Runs `args` over `input` as is and with every key spilled, when the
+/// results are written as the spilled runs are merged, and compares each
+/// output with the golden file `name`.
+fn check(name: &str, input: &str, args: &[&str]) {
+    check_bytes(name, input.as_bytes(), args);
+}
+
+/// [`check`] for input that is not UTF-8.
+fn check_bytes(name: &str, input: &[u8], args: &[&str]) {
+    // Tests checking the same golden file run at once, with inputs of their
+    // own
+    static CHECKS: AtomicUsize = AtomicUsize::new(0);
+    let path = std::env::temp_dir().join(format!(
+        "1brc-golden-{}-{}-{}.csv",
+        name,
+        std::process::id(),
+        CHECKS.fetch_add(1, Ordering::Relaxed)
+    ));
+    fs::write(&path, input).unwrap();
+    for spill in [&[][..], &["--spill-budget", "1"]] {
+        let output = Command::new(env!("CARGO_BIN_EXE_1brc"))
+            .args(["-t", "3"])
+            .args(args)
+            .args(spill)
+            .arg(&path)
+            .output()
+            .expect("run 1brc");
+        assert!(
+            output.status.success(),
+            "1brc {:?} {:?} failed: {}",
+            args,
+            spill,
+            String::from_utf8_lossy(&output.stderr)
+        );
+        let actual = String::from_utf8(output.stdout).expect("UTF-8 output");
+        if !compare(name, &actual, &format!("output {:?}", spill)) {
+            break;
+        }
+    }
+    fs::remove_file(&path).unwrap();
+}
+
+/// Compares `actual`, described as `what`, with the golden file `name`,
+/// or rewrites the file under `UPDATE_GOLDEN`, returning whether it
+/// compared.
+fn compare(name: &str, actual: &str, what: &str) -> bool {
+    let golden = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/golden")
+        .join(format!("{}.txt", name));
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        fs::write(&golden, actual).unwrap();
+        return false;
+    }
+    let expected = fs::read_to_string(&golden)
+        .unwrap_or_else(|e| panic!("{}: {} (run with UPDATE_GOLDEN=1)", golden.display(), e));
+    assert!(
+        actual == expected,
+        "{} differs from {} (run with UPDATE_GOLDEN=1 to accept it)\n--- expected\n{}\n--- actual\n{}",
+        what,
+        golden.display(),
+        expected,
+        actual
+    );
+    true
+}
+
+#[test]
+fn text() {
+    check("text", &readings(), &[]);
+}
+
+#[test]
+fn csv() {
+    check("csv", &readings(), &["--format", "csv"]);
+}
+
+/// Runs `args` over `input` with `--cache-dir dir`, returning the output
+/// and whether it came from the cache.
+fn cached_run(dir: &Path, input: &Path, args: &[&str]) -> (String, bool) {
+    let output = Command::new(env!("CARGO_BIN_EXE_1brc"))
+        .args(["-t", "3", "--stats", "--cache-dir"])
+        .arg(dir)
+        .args(args)
+        .arg(input)
+        .output()
+        .expect("run 1brc");
+    assert!(
+        output.status.success(),
+        "1brc {:?} failed: {}",
+        args,
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    (
+        String::from_utf8(output.stdout).unwrap(),
+        stderr.contains("cached results:"),
+    )
+}
+
+/// A run with an option that changes the results scans the input again,
+/// and gives what it would without the cache.
+#[test]
+fn options_that_change_the_results_are_not_answered_from_the_cache() {
+    let dir = std::env::temp_dir().join(format!("1brc-golden-cache-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let input = dir.join("input.csv");
+    let mut text = readings();
+    // Rows that only merge into Abha's once lowered
+    text.push_str("ABHA;12.0\nabha;-3.0\n");
+    fs::write(&input, &text).unwrap();
+    // With whether the results differ, as the longer lines here fail
+    // neither limit
+    for (first, second, differs) in [
+        (&[][..], &["--unit", "f"][..], true),
+        (&[], &["--min-value", "0"], true),
+        (
+            &["--min-value", "0"],
+            &["--min-value", "0", "--max-value", "50"],
+            true,
+        ),
+        (
+            &["--max-line-bytes", "4K"],
+            &["--max-line-bytes", "64"],
+            false,
+        ),
+        (&[], &["--normalize-keys", "lower"], true),
+    ] {
+        let cache = dir.join(format!("cache-{}", second.join("-")));
+        let (before, cached) = cached_run(&cache, &input, first);
+        assert!(!cached, "{:?}", first);
+        // The same options are answered from the cache
+        assert_eq!(
+            cached_run(&cache, &input, first),
+            (before.clone(), true),
+            "{:?}",
+            first
+        );
+
+        let (output, cached) = cached_run(&cache, &input, second);
+        assert!(!cached, "{:?} after {:?} used the cache", second, first);
+        assert_eq!(output != before, differs, "{:?}", second);
+        let uncached = Command::new(env!("CARGO_BIN_EXE_1brc"))
+            .args(["-t", "3"])
+            .args(second)
+            .arg(&input)
+            .output()
+            .expect("run 1brc");
+        assert_eq!(
+            output,
+            String::from_utf8(uncached.stdout).unwrap(),
+            "{:?}",
+            second
+        );
+        assert_eq!(fs::read_dir(&cache).unwrap().count(), 2, "{:?}", second);
+    }
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn profiles() {
+    let config =
+        std::env::temp_dir().join(format!("1brc-golden-profiles-{}.toml", std::process::id()));
+    fs::write(
+        &config,
+        "# For a spreadsheet\n[profile.csv]\nformat = \"csv\"\nread-ahead = 0\n\n\
+         [profile.json]\nformat = 'json'  # overridden below\nstats = true\n",
+    )
+    .unwrap();
+    let config = config.to_str().unwrap();
+    check(
+        "csv",
+        &readings(),
+        &["--config", config, "--profile", "csv"],
+    );
+    // The command line overrides the profile
+    check(
+        "csv",
+        &readings(),
+        &["--profile=json", "--format", "csv", "--config", config],
+    );
+    fs::remove_file(config).unwrap();
+}
+
+#[test]
+fn aliases() {
+    let aliases =
+        std::env::temp_dir().join(format!("1brc-golden-aliases-{}.csv", std::process::id()));
+    // Zürich was Zuerich, and before that Zurich
+    fs::write(
+        &aliases,
+        "# old;current\nZurich;Zuerich\nZuerich;Zürich\n\nOld Abha;Abha\n",
+    )
+    .unwrap();
+    let mut renamed = String::new();
+    for (i, line) in readings().lines().enumerate() {
+        let line = match (line.split_once(';'), i % 3) {
+            (Some(("Zürich", value)), 0) => format!("Zurich;{}", value),
+            (Some(("Zürich", value)), 1) => format!("Zuerich;{}", value),
+            (Some(("Abha", value)), 0) => format!("Old Abha;{}", value),
+            _ => line.to_string(),
+        };
+        renamed.push_str(&line);
+        renamed.push('\n');
+    }
+    assert_ne!(renamed, readings());
+    check("text", &renamed, &["--aliases", aliases.to_str().unwrap()]);
+    fs::remove_file(aliases).unwrap();
+}
+
+#[test]
+fn byte_order_marks() {
+    // As Windows tools export text
+    let utf16: Vec<u8> = readings()
+        .encode_utf16()
+        .flat_map(|unit| unit.to_le_bytes())
+        .collect();
+    check_bytes(
+        "text",
+        &[b"\xEF\xBB\xBF", readings().as_bytes()].concat(),
+        &[],
+    );
+    check_bytes(
+        "text",
+        &[b"\xFF\xFE", &utf16[..]].concat(),
+        &["--encoding", "utf16le"],
+    );
+    check_bytes(
+        "text",
+        &utf16,
+        &["--encoding", "utf16le", "--chunk-bytes", "64"],
+    );
+}
+
+/// Names whose order depends on the collation: accents, case and the
+/// letters Swedish and Spanish sort on their own.
+#[test]
+fn collation() {
+    let mut input = String::new();
+    for (i, station) in [
+        "Zürich",
+        "Zagreb",
+        "Åre",
+        "Aarhus",
+        "aberdeen",
+        "Örebro",
+        "Oslo",
+        "École",
+        "Edinburgh",
+        "Ñandú",
+        "Nuuk",
+        "Öl",
+        "oakland",
+    ]
+    .iter()
+    .enumerate()
+    {
+        input.push_str(&format!("{};{}.0\n", station, i));
+    }
+    for (name, collation) in [
+        ("collation-bytes", "bytes"),
+        ("collation-unicode", "unicode"),
+        ("collation-sv", "locale:sv"),
+        ("collation-es", "locale:es"),
+    ] {
+        check(
+            name,
+            &input,
+            &["--format", "csv", "--agg", "mean", "--collation", collation],
+        );
+    }
+}
+
+/// The files `--partition-output-by` writes, each after a line with its
+/// name, with the rows `--partition-rows` adds. One worker keeps the rows
+/// in input order.
+#[test]
+fn partition_output_files() {
+    let dir = std::env::temp_dir().join(format!("1brc-golden-partitions-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let input = dir.join("input.csv");
+    fs::write(&input, readings()).unwrap();
+    for (name, args) in [
+        (
+            "partitions-first-letter",
+            &["--partition-output-by", "first-letter", "--partition-rows"][..],
+        ),
+        (
+            "partitions-station-hash",
+            &["--partition-output-by", "station-hash:3", "--format", "csv"],
+        ),
+    ] {
+        let out = dir.join(name);
+        let output = Command::new(env!("CARGO_BIN_EXE_1brc"))
+            .args(["-t", "1"])
+            .args(args)
+            .arg("--partition-dir")
+            .arg(&out)
+            .arg(&input)
+            .output()
+            .expect("run 1brc");
+        assert!(
+            output.status.success(),
+            "1brc {:?} failed: {}",
+            args,
+            String::from_utf8_lossy(&output.stderr)
+        );
+        assert!(output.stdout.is_empty(), "{:?} wrote to stdout", args);
+        let mut files: Vec<_> = fs::read_dir(&out)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect();
+        files.sort();
+        let mut actual = String::new();
+        for file in files {
+            let name = file.file_name().unwrap().to_string_lossy().into_owned();
+            actual.push_str(&format!(
+                "== {}\n{}",
+                name,
+                fs::read_to_string(&file).unwrap()
+            ));
+        }
+        compare(name, &actual, &format!("{:?}", args));
+    }
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn stations() {
+    check(
+        "stations",
+        &readings(),
+        &["--station", "Abha", "--station", "東京"],
+    );
+}
+
+/// The rows `--export-rows --preserve-order` copies, dealt out to three
+/// workers in small chunks, are the matching input lines in input order.
+#[test]
+fn exported_rows_keep_the_input_order() {
+    let dir = std::env::temp_dir().join(format!("1brc-golden-export-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let input = dir.join("input.csv");
+    let exported = dir.join("rows.csv");
+    let readings = readings();
+    fs::write(&input, &readings).unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_1brc"))
+        .args(["-t", "3", "--chunk-bytes", "256", "--preserve-order"])
+        .args(["--station", "Abha", "--station", "東京", "--export-rows"])
+        .arg(&exported)
+        .arg(&input)
+        .output()
+        .expect("run 1brc");
+    assert!(
+        output.status.success(),
+        "1brc failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let actual = fs::read_to_string(&exported).unwrap();
+    let expected: String = readings
+        .lines()
+        .filter(|line| line.starts_with("Abha;") || line.starts_with("東京;"))
+        .map(|line| format!("{}\n", line))
+        .collect();
+    assert_eq!(actual, expected);
+    compare("export-rows", &actual, "the exported rows");
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn precision() {
+    // The means on a rounding boundary at one decimal are exact at two
+    check(
+        "precision",
+        &readings(),
+        &["--precision", "2", "--format", "table"],
+    );
+    check(
+        "precision-json",
+        &readings(),
+        &[
+            "--precision=3",
+            "--format",
+            "json",
+            "--agg",
+            "mean,stddev,p50",
+        ],
+    );
+    // Percentiles of readings between the tenths, to the same decimals as
+    // the extremes around them
+    check(
+        "precision-percentiles",
+        "a;1.05\na;1.15\na;1.12\nb;-0.15\nb;-0.05\nb;-0.12\n",
+        &["--precision", "2", "--agg", "min,p50,max,p100"],
+    );
+}
+
+#[test]
+fn json() {
+    check("json", &readings(), &["--format", "json"]);
+}
+
+#[test]
+fn aggregates() {
+    check(
+        "aggregates",
+        &readings(),
+        &[
+            "--agg",
+            "count,sum,stddev,p0,p50,p90,p100",
+            "--format",
+            "csv",
+        ],
+    );
+}
+
+#[test]
+fn extreme_percentiles_are_the_minimum_and_maximum() {
+    // Readings between the tenths, which a histogram of tenths rounded
+    // past the extremes
+    let mut input = String::from("a;1.05\na;1.15\nb;-0.15\nb;-0.05\n");
+    let mut rng = Rng::new(160);
+    for _ in 0..300 {
+        let hundredths = rng.below(19999) as i64 - 9999;
+        input.push_str(&format!(
+            "{};{:.2}\n",
+            STATIONS[rng.below(STATIONS.len() as u64) as usize],
+            hundredths as f64 / 100.0
+        ));
+    }
+    let dir = std::env::temp_dir().join(format!("1brc-golden-percentiles-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("input.csv");
+    fs::write(&path, &input).unwrap();
+    for precision in ["1", "2", "3"] {
+        let output = Command::new(env!("CARGO_BIN_EXE_1brc"))
+            .args(["--agg", "min,p0,p100,max", "--format", "csv"])
+            .args(["--precision", precision])
+            .arg(&path)
+            .output()
+            .expect("run 1brc");
+        let stdout = String::from_utf8(output.stdout).expect("UTF-8 output");
+        let mut lines = stdout.lines();
+        assert_eq!(lines.next(), Some("station,min,p0,p100,max"));
+        for line in lines {
+            let fields: Vec<&str> = line.rsplitn(5, ',').collect();
+            let [max, p100, p0, min, _] = fields[..] else {
+                panic!("{}", line);
+            };
+            assert_eq!((p0, p100), (min, max), "{}", line);
+        }
+    }
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn value_columns() {
+    check(
+        "value_columns",
+        &multi_readings(),
+        &[
+            "--value-columns",
+            "2,4",
+            "--null-values",
+            "NA",
+            "--format",
+            "csv",
+        ],
+    );
+}
+
+#[test]
+fn windows() {
+    check(
+        "windows",
+        &timed_readings(),
+        &["--window", "1h", "--format", "csv"],
+    );
+}
+
+/// Results with no keys in them: brackets, or a header, and no rows, and
+/// nothing at all for a list of stations.
+#[test]
+fn nothing_to_report() {
+    let formats: [(&str, &[&str]); 8] = [
+        ("text", &[]),
+        ("csv", &["--format", "csv"]),
+        ("json", &["--format", "json"]),
+        ("table", &["--format", "table"]),
+        ("markdown", &["--format", "markdown"]),
+        ("prom", &["--format", "prom"]),
+        ("count-only", &["--count-only", "--format", "csv"]),
+        ("list-stations", &["--list-stations"]),
+    ];
+    // An empty file, one of blank lines, and one whose every row is left
+    // out by the filters
+    let inputs: [(&str, &[&str]); 3] = [
+        ("", &[]),
+        ("\n\r\n\n", &[]),
+        ("Abha;1.0\nZürich;-2.0\n", &["--station", "Nowhere"]),
+    ];
+    for (format, args) in formats {
+        for (input, filters) in inputs {
+            let name = format!("empty-{}", format);
+            check(&name, input, &[args, filters].concat());
+        }
+    }
+
+    let dir = std::env::temp_dir().join(format!("1brc-golden-empty-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let (path, manifest) = (dir.join("empty.csv"), dir.join("manifest.json"));
+    fs::write(&path, "").unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_1brc"))
+        .arg("--manifest")
+        .args([&manifest, &path])
+        .output()
+        .expect("run 1brc");
+    assert!(output.status.success());
+    let manifest = fs::read_to_string(manifest).unwrap();
+    assert!(
+        manifest.contains(
+            r#""counts": {"lines": 0, "readings": 0, "excluded": 0, "missing": 0, "keys": 0}"#
+        ),
+        "{}",
+        manifest
+    );
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+/// Names that would break a row of the Markdown table, read back the same
+/// by `diff`.
+#[test]
+fn markdown() {
+    let input = concat!(
+        r#"{"station": "Pipe|Town", "temp": 1.0}"#,
+        "\n",
+        r#"{"station": "Back\\slash", "temp": 2.0}"#,
+        "\n",
+        r#"{"station": "Two\nLines\r", "temp": 3.0}"#,
+        "\n",
+    );
+    let args = ["--input-format", "jsonl", "--format", "markdown"];
+    check("markdown", input, &args);
+
+    let dir = std::env::temp_dir().join(format!("1brc-golden-markdown-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let (path, json) = (dir.join("input.jsonl"), dir.join("results.json"));
+    fs::write(&path, input).unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_1brc"))
+        .args(["--input-format", "jsonl", "--format", "json"])
+        .arg(&path)
+        .output()
+        .expect("run 1brc");
+    fs::write(&json, output.stdout).unwrap();
+    let golden = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/golden");
+    let output = Command::new(env!("CARGO_BIN_EXE_1brc"))
+        .arg("diff")
+        .arg(golden.join("markdown.txt"))
+        .arg(&json)
+        .output()
+        .expect("run 1brc diff");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.starts_with("3 keys in both, 0 differing"),
+        "{}",
+        stdout
+    );
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn diff_reads_back_every_format() {
+    let golden = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/golden");
+    for other in ["csv.txt", "json.txt"] {
+        let output = Command::new(env!("CARGO_BIN_EXE_1brc"))
+            .arg("diff")
+            .arg(golden.join("text.txt"))
+            .arg(golden.join(other))
+            .output()
+            .expect("run 1brc diff");
+        assert!(
+            output.status.success(),
+            "text.txt and {} differ: {}{}",
+            other,
+            String::from_utf8_lossy(&output.stdout),
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+}
+
+#[test]
+fn diagnostics_go_to_stderr_as_the_level_asks() {
+    let path = std::env::temp_dir().join(format!("1brc-golden-levels-{}.csv", std::process::id()));
+    fs::write(&path, readings()).unwrap();
+    let golden = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/golden/text.txt");
+    let expected = fs::read_to_string(golden).unwrap();
+    let levels: [(&[&str], &[&str]); 4] = [
+        (&["-q"], &[]),
+        (&[], &["Processed 310 lines total", "File processed"]),
+        (&["-v"], &["Options:", "Phases: setup"]),
+        (&["-vv"], &["Options:", "Worker 2: "]),
+    ];
+    for (level, said) in levels {
+        let output = Command::new(env!("CARGO_BIN_EXE_1brc"))
+            .args(["-t", "3"])
+            .args(level)
+            .arg(&path)
+            .output()
+            .expect("run 1brc");
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(output.status.success(), "{:?}: {}", level, stderr);
+        assert_eq!(
+            String::from_utf8_lossy(&output.stdout),
+            expected,
+            "{:?}",
+            level
+        );
+        for said in said {
+            assert!(
+                stderr.contains(said),
+                "{:?} does not say {:?}: {}",
+                level,
+                said,
+                stderr
+            );
+        }
+        if said.is_empty() {
+            assert_eq!(stderr, "", "{:?}", level);
+        }
+    }
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn completions_and_man_page_cover_every_option_in_the_help() {
+    let run = |args: &[&str]| {
+        let output = Command::new(env!("CARGO_BIN_EXE_1brc"))
+            .args(args)
+            .output()
+            .expect("run 1brc");
+        assert!(output.status.success(), "{:?}", args);
+        String::from_utf8(output.stdout).unwrap()
+    };
+    let help = run(&["--help"]);
+    let options: Vec<&str> = help
+        .split_whitespace()
+        .filter_map(|word| word.strip_prefix("--"))
+        .filter(|name| name.chars().all(|c| c.is_ascii_lowercase() || c == '-'))
+        .collect();
+    assert!(options.contains(&"format"), "{}", help);
+    for shell in ["bash", "zsh", "fish"] {
+        let script = run(&["completions", shell]);
+        for option in &options {
+            let written = match shell {
+                "fish" => format!("-l {}", option),
+                _ => format!("--{}", option),
+            };
+            assert!(script.contains(&written), "{} lacks --{}", shell, option);
+        }
+    }
+    let man = run(&["man"]);
+    for option in &options {
+        let written = format!("\\-\\-{}", option.replace('-', "\\-"));
+        assert!(man.contains(&written), "man page lacks --{}", option);
+    }
+}
+
+#[test]
+fn validate() {
+    let dir = std::env::temp_dir().join(format!("1brc-golden-validate-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let run = |name: &str, input: &[u8]| {
+        let path = dir.join(name);
+        fs::write(&path, input).unwrap();
+        let output = Command::new(env!("CARGO_BIN_EXE_1brc"))
+            .current_dir(&dir)
+            .args(["validate", name])
+            .output()
+            .expect("run 1brc");
+        let stdout = String::from_utf8(output.stdout).expect("UTF-8 output");
+        (output.status.code(), stdout)
+    };
+    let stations = readings()
+        .lines()
+        .map(|line| line.split_once(';').unwrap().0)
+        .collect::<std::collections::HashSet<_>>()
+        .len();
+    assert_eq!(
+        run("valid.csv", readings().as_bytes()),
+        (
+            Some(0),
+            format!(
+                "valid.csv: {} lines, {} stations, valid\n",
+                readings().lines().count(),
+                stations
+            )
+        )
+    );
+    let (code, report) = run(
+        "invalid.csv",
+        "Abha;1.0\r\nAbha;12.34\n;1.0\nAbha;1\nZürich;-100.0\nAbha;2.0\nAbha 3.0".as_bytes(),
+    );
+    assert_eq!(code, Some(1));
+    assert_eq!(
+        report,
+        "invalid.csv: 7 lines, 1 station, 6 invalid\n  \
+         \\r\\n line ending: 1 (byte 0)\n  \
+         no `;`: 1 (byte 57)\n  \
+         empty name: 1 (byte 21)\n  \
+         reading not like -12.3: 2 (bytes 10, 26)\n  \
+         reading out of -99.9..99.9: 1 (byte 33)\n"
+    );
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,1135 @@
+//! Random datasets through the parallel executors, checked against a naive
+//! single-threaded fold. The datasets come from a seeded generator, so a
+//! failure names the seed that reproduces it.
+
+mod common;
+
+use common::Rng;
+use onebrc::sha256::{self, Sha256};
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::path::PathBuf;
+use std::process::Command;
+
+const CASES: u64 = 24;
+
+/// The executors and settings each dataset is run with.
+const CONFIGURATIONS: &[&[&str]] = &[
+    &["-t", "1"],
+    &["-t", "4"],
+    &["-t", "7", "--executor", "threads"],
+    // Enough maps to be merged as a tree
+    &["-t", "37", "--executor", "threads"],
+    &["-t", "4", "--executor", "pipeline"],
+    &["-t", "5", "--strategy", "shared-map"],
+    &[
+        "-t",
+        "5",
+        "--strategy",
+        "shared-map",
+        "--expected-stations",
+        "500",
+    ],
+    &["-t", "4", "--expected-stations", "3"],
+    &["-t", "5", "--strategy", "vectorized"],
+    &["-t", "5", "--strategy", "prefix-table"],
+    &["-t", "3", "--io", "mmap"],
+    &["-t", "5", "--read-ahead", "0"],
+    &["-t", "2", "--spill-budget", "4K"],
+    // Small blocks, whose reads end inside multi-byte names, and blocks
+    // shorter than a line
+    &["-t", "3", "--chunk-bytes", "1K"],
+    &["-t", "4", "--io", "mmap", "--chunk-bytes", "5"],
+    &["-t", "3", "--readers", "2", "--chunk-bytes", "4K"],
+    &[
+        "-t",
+        "3",
+        "--max-rows-per-sec",
+        "1000000",
+        "--chunk-bytes",
+        "1K",
+    ],
+];
+
+/// Names mixing ASCII, accents, other scripts and emoji, some sharing
+/// prefixes.
+const NAMES: &[&str] = &[
+    "Abha",
+    "Abidjan",
+    "Abéché",
+    "Zürich",
+    "São Paulo",
+    "Łódź",
+    "東京",
+    "Москва",
+    "القاهرة",
+    "Reykjavík",
+    "St. John's",
+    "🌧 Station",
+    "A",
+    "AA",
+    "Las Palmas de Gran Canaria",
+];
+
+struct Dataset {
+    text: String,
+    /// Per station: min, max and sum in tenths, and the count
+    expected: BTreeMap<String, (i64, i64, i64, i64)>,
+}
+
+fn generate(seed: u64) -> Dataset {
+    let mut rng = Rng::new(seed);
+    let stations: Vec<String> = (0..1 + rng.below(60))
+        .map(|i| {
+            let name = NAMES[rng.below(NAMES.len() as u64) as usize];
+            // Some existing names again, some new ones
+            if rng.below(3) == 0 {
+                name.to_string()
+            } else {
+                format!("{} {}", name, i)
+            }
+        })
+        .collect();
+    let newline = if rng.below(4) == 0 { "\r\n" } else { "\n" };
+    let rows = rng.below(5000);
+
+    let mut text = String::new();
+    let mut expected: BTreeMap<String, (i64, i64, i64, i64)> = BTreeMap::new();
+    for row in 0..rows {
+        let station = &stations[rng.below(stations.len() as u64) as usize];
+        let tenths = match rng.below(20) {
+            0 => -999,
+            1 => 999,
+            2 => 0,
+            _ => rng.below(1999) as i64 - 999,
+        };
+        text.push_str(station);
+        text.push(';');
+        text.push_str(&format!("{:.1}", tenths as f64 / 10.0));
+        if row + 1 < rows || rng.below(2) == 0 {
+            text.push_str(newline);
+        }
+        let entry = expected
+            .entry(station.clone())
+            .or_insert((tenths, tenths, 0, 0));
+        entry.0 = entry.0.min(tenths);
+        entry.1 = entry.1.max(tenths);
+        entry.2 += tenths;
+        entry.3 += 1;
+    }
+    Dataset { text, expected }
+}
+
+/// Runs the binary over `path` and parses its CSV output into rows of
+/// station, min, mean and max.
+fn run(path: &PathBuf, args: &[&str]) -> Vec<(String, f64, f64, f64)> {
+    let output = Command::new(env!("CARGO_BIN_EXE_1brc"))
+        .args(args)
+        .args(["--format", "csv"])
+        .arg(path)
+        .output()
+        .expect("run 1brc");
+    assert!(
+        output.status.success(),
+        "1brc {:?} failed: {}",
+        args,
+        String::from_utf8_lossy(&output.stderr)
+    );
+    // Nothing but the results, which are CSV
+    let stdout = String::from_utf8(output.stdout).expect("UTF-8 output");
+    let mut lines = stdout.lines();
+    assert_eq!(lines.next(), Some("station,min,mean,max"), "{}", stdout);
+    lines
+        .map(|line| {
+            let fields: Vec<&str> = line.rsplitn(4, ',').collect();
+            let number = |i: usize| fields[i].parse::<f64>().expect("a number");
+            (fields[3].to_string(), number(2), number(1), number(0))
+        })
+        .collect()
+}
+
+#[test]
+fn parallel_aggregation_matches_a_sequential_fold() {
+    let dir = std::env::temp_dir().join(format!("1brc-parallel-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    for seed in 0..CASES {
+        let dataset = generate(seed);
+        let path = dir.join(format!("{}.csv", seed));
+        fs::write(&path, &dataset.text).unwrap();
+
+        for args in CONFIGURATIONS {
+            let rows = run(&path, args);
+            let names: Vec<&String> = rows.iter().map(|row| &row.0).collect();
+            let expected_names: Vec<&String> = dataset.expected.keys().collect();
+            assert_eq!(names, expected_names, "seed {} with {:?}", seed, args);
+            for ((name, min, mean, max), (_, &(lo, hi, sum, count))) in
+                rows.iter().zip(&dataset.expected)
+            {
+                let context = format!("{} for seed {} with {:?}", name, seed, args);
+                assert_eq!(*min, lo as f64 / 10.0, "min of {}", context);
+                assert_eq!(*max, hi as f64 / 10.0, "max of {}", context);
+                // The mean is rounded to one decimal, from a sum that may
+                // have been added up in another order
+                let exact = sum as f64 / count as f64 / 10.0;
+                assert!(
+                    (mean - exact).abs() <= 0.05 + 1e-9,
+                    "mean of {}: {} vs {}",
+                    context,
+                    mean,
+                    exact
+                );
+            }
+        }
+    }
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn track_extremes_finds_the_first_row_of_each_extreme() {
+    let dir = std::env::temp_dir().join(format!("1brc-extremes-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    for seed in 0..4 {
+        let dataset = generate(seed);
+        let path = dir.join(format!("{}.csv", seed));
+        fs::write(&path, &dataset.text).unwrap();
+        // Per station, the first row of its min and of its max, as the
+        // reading in tenths, the byte offset and the line
+        let mut expected: BTreeMap<&str, [(i64, usize, usize); 2]> = BTreeMap::new();
+        let mut at = 0;
+        for (i, line) in dataset.text.split_inclusive('\n').enumerate() {
+            let row = line.trim_end_matches(['\r', '\n']);
+            let (station, value) = row.rsplit_once(';').unwrap();
+            let tenths = (value.parse::<f64>().unwrap() * 10.0).round() as i64;
+            let row = (tenths, at, i + 1);
+            let entry = expected.entry(station).or_insert([row, row]);
+            if tenths < entry[0].0 {
+                entry[0] = row;
+            }
+            if tenths > entry[1].0 {
+                entry[1] = row;
+            }
+            at += line.len();
+        }
+        let expected: Vec<String> = expected
+            .iter()
+            .map(|(station, [min, max])| {
+                format!(
+                    "  {}: min {:.1} at byte {}, line {}; max {:.1} at byte {}, line {}",
+                    station,
+                    min.0 as f64 / 10.0,
+                    min.1,
+                    min.2,
+                    max.0 as f64 / 10.0,
+                    max.1,
+                    max.2
+                )
+            })
+            .collect();
+
+        for args in CONFIGURATIONS {
+            let output = Command::new(env!("CARGO_BIN_EXE_1brc"))
+                .args(*args)
+                .args(["--track-extremes", "-vv"])
+                .arg(&path)
+                .output()
+                .expect("run 1brc");
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            assert!(output.status.success(), "{:?}: {}", args, stderr);
+            let extremes: Vec<&str> = stderr
+                .lines()
+                .skip_while(|line| *line != "Extremes:")
+                .skip(1)
+                .take_while(|line| line.starts_with("  "))
+                .collect();
+            assert_eq!(extremes, expected, "seed {} with {:?}", seed, args);
+        }
+    }
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+/// `text` with its first half again at the end, so that most copies are
+/// in another worker's share than the rows they repeat.
+fn with_repeats(text: &str) -> String {
+    let lines: Vec<&str> = text.split_inclusive('\n').collect();
+    let mut doubled = lines.concat();
+    if !doubled.ends_with('\n') {
+        doubled.push('\n');
+    }
+    doubled.push_str(&lines[..lines.len() / 2].concat());
+    doubled
+}
+
+#[test]
+fn detect_duplicates_counts_repeated_lines_across_workers() {
+    let dir = std::env::temp_dir().join(format!("1brc-duplicates-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    for seed in 0..4 {
+        let doubled = with_repeats(&generate(seed).text);
+        let path = dir.join(format!("{}.csv", seed));
+        fs::write(&path, &doubled).unwrap();
+        let rows: Vec<&str> = doubled
+            .lines()
+            .map(|line| line.trim_end_matches('\r'))
+            .filter(|line| !line.is_empty())
+            .collect();
+        let distinct = rows.iter().collect::<HashSet<_>>().len();
+        let expected = format!(
+            "Found {} duplicate lines of {} ({} distinct)",
+            rows.len() - distinct,
+            rows.len(),
+            distinct
+        );
+
+        for args in CONFIGURATIONS {
+            let output = Command::new(env!("CARGO_BIN_EXE_1brc"))
+                .args(*args)
+                .arg("--detect-duplicates")
+                .arg(&path)
+                .output()
+                .expect("run 1brc");
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            assert!(output.status.success(), "{:?}: {}", args, stderr);
+            assert!(
+                stderr.lines().any(|line| line == expected),
+                "seed {} with {:?}: expected {}, got {}",
+                seed,
+                args,
+                expected,
+                stderr
+            );
+        }
+    }
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn dedupe_aggregates_each_distinct_row_once() {
+    let dir = std::env::temp_dir().join(format!("1brc-dedupe-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    for seed in 0..4 {
+        let doubled = with_repeats(&generate(seed).text);
+        let mut seen = HashSet::new();
+        let mut distinct = String::new();
+        for line in doubled.split_inclusive('\n') {
+            if seen.insert(line.trim_end_matches(['\r', '\n'])) {
+                distinct.push_str(line);
+            }
+        }
+        let distinct_path = dir.join(format!("{}-distinct.csv", seed));
+        fs::write(&distinct_path, &distinct).unwrap();
+        let expected = run(&distinct_path, &["-t", "1"]);
+        let path = dir.join(format!("{}.csv", seed));
+        fs::write(&path, &doubled).unwrap();
+        let repeats = doubled.lines().count() - distinct.lines().count();
+
+        for args in CONFIGURATIONS {
+            // A rate low enough for no row of these seeds to be taken for
+            // a repeat, which the count of skipped rows would show
+            let args = [args, &["--dedupe", "--dedupe-fp", "0.00001"][..]].concat();
+            let rows = run(&path, &args);
+            assert_eq!(rows.len(), expected.len(), "seed {} with {:?}", seed, args);
+            for (row, expected) in rows.iter().zip(&expected) {
+                let context = format!("{} for seed {} with {:?}", row.0, seed, args);
+                assert_eq!(
+                    (&row.0, row.1, row.3),
+                    (&expected.0, expected.1, expected.3),
+                    "{}",
+                    context
+                );
+                // Both means are rounded, from sums added up in different
+                // orders
+                assert!(
+                    (row.2 - expected.2).abs() <= 0.1 + 1e-9,
+                    "mean of {}",
+                    context
+                );
+            }
+            let output = Command::new(env!("CARGO_BIN_EXE_1brc"))
+                .args(&args)
+                .arg(&path)
+                .output()
+                .expect("run 1brc");
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            assert!(
+                stderr.contains(&format!("Skipped {} duplicate rows", repeats)),
+                "seed {} with {:?}: {}",
+                seed,
+                args,
+                stderr
+            );
+        }
+    }
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn an_over_long_line_fails_every_configuration() {
+    let dir = std::env::temp_dir().join(format!("1brc-long-lines-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let text = generate(1).text;
+    let half = text[..text.len() / 2].rfind('\n').map_or(0, |i| i + 1);
+    let long = format!("{};1.0\n", "x".repeat(20_000));
+    // In the middle of the input, and at its end without a newline, as in
+    // a file cut short
+    let inputs = [
+        format!("{}{}{}", &text[..half], long, &text[half..]),
+        format!("{}\n{}", text, "x".repeat(100_000)),
+    ];
+    let good = dir.join("good.csv");
+    fs::write(&good, &text).unwrap();
+    for (i, input) in inputs.iter().enumerate() {
+        let path = dir.join(format!("{}.csv", i));
+        fs::write(&path, input).unwrap();
+        for args in CONFIGURATIONS {
+            let output = Command::new(env!("CARGO_BIN_EXE_1brc"))
+                .args(*args)
+                .arg(&path)
+                .output()
+                .expect("run 1brc");
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            // No results, even those of the rows before it, and a failed
+            // run's status
+            assert!(output.stdout.is_empty(), "input {} with {:?}", i, args);
+            assert_eq!(output.status.code(), Some(1), "input {} with {:?}", i, args);
+            assert!(
+                stderr.contains("is longer than 8.0 KiB (--max-line-bytes)"),
+                "input {} with {:?}: {}",
+                i,
+                args,
+                stderr
+            );
+            // Among several inputs, as a read error on the one it is in
+            // rather than a failure of whichever worker came across it
+            let output = Command::new(env!("CARGO_BIN_EXE_1brc"))
+                .args(*args)
+                .arg(&good)
+                .arg(&path)
+                .output()
+                .expect("run 1brc");
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            assert_eq!(output.status.code(), Some(1), "input {} with {:?}", i, args);
+            assert!(
+                stderr.contains(&format!(
+                    "failed to read {}: the line at byte",
+                    path.display()
+                )) && !stderr.contains("worker"),
+                "input {} with {:?}: {}",
+                i,
+                args,
+                stderr
+            );
+        }
+        let cache = dir.join(format!("cache-{}", i));
+        let allowed = Command::new(env!("CARGO_BIN_EXE_1brc"))
+            .args(["-t", "4", "--max-line-bytes", "128K", "--cache-dir"])
+            .arg(&cache)
+            .arg(&path)
+            .output()
+            .expect("run 1brc");
+        assert!(
+            String::from_utf8_lossy(&allowed.stdout).starts_with('{'),
+            "input {}: {}",
+            i,
+            String::from_utf8_lossy(&allowed.stderr)
+        );
+        // Not from the results cached under the larger limit
+        let cached = Command::new(env!("CARGO_BIN_EXE_1brc"))
+            .args(["-t", "4", "--cache-dir"])
+            .arg(&cache)
+            .arg(&path)
+            .output()
+            .expect("run 1brc");
+        assert_eq!(cached.status.code(), Some(1), "input {}", i);
+    }
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn a_bad_reading_names_its_line_in_every_configuration() {
+    let dir = std::env::temp_dir().join(format!("1brc-bad-rows-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let text = generate(2).text;
+    let half = text[..text.len() / 2].rfind('\n').map_or(0, |i| i + 1);
+    let path = dir.join("bad.csv");
+    fs::write(
+        &path,
+        format!("{}Zürich;12,5\n{}", &text[..half], &text[half..]),
+    )
+    .unwrap();
+    let line = text[..half].lines().count() + 1;
+    let expected = format!("on line {} (byte {}): \"Zürich;12,5\"", line, half);
+    for args in CONFIGURATIONS {
+        let output = Command::new(env!("CARGO_BIN_EXE_1brc"))
+            .args(*args)
+            .arg(&path)
+            .output()
+            .expect("run 1brc");
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(output.stdout.is_empty(), "{:?}", args);
+        assert_eq!(output.status.code(), Some(1), "{:?}", args);
+        assert!(
+            stderr.contains("invalid reading `12,5`") && stderr.contains(&expected),
+            "{:?}: {}",
+            args,
+            stderr
+        );
+    }
+    // In UTF-16 the offset is in the file, after the byte order mark, not
+    // in the transcoded text, and the names have bytes that look like
+    // newlines to UTF-8
+    let text = "Ċapa;1.0\nĊapa;2.0\nZürich;12,5\nĊapa;3.0\n";
+    let mut utf16 = b"\xFF\xFE".to_vec();
+    utf16.extend(text.encode_utf16().flat_map(u16::to_le_bytes));
+    fs::write(&path, utf16).unwrap();
+    for args in [
+        &["-t", "1"][..],
+        &["-t", "3", "--io", "mmap", "--chunk-bytes", "16"],
+    ] {
+        let output = Command::new(env!("CARGO_BIN_EXE_1brc"))
+            .args(args)
+            .args(["--encoding", "utf16le"])
+            .arg(&path)
+            .output()
+            .expect("run 1brc");
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(
+            stderr.contains("on line 3 (byte 38): \"Zürich;12,5\""),
+            "{:?}: {}",
+            args,
+            stderr
+        );
+    }
+    // Whatever failed, in whichever mode
+    let missing = dir.join("missing.csv");
+    for args in [&[][..], &["--estimate-rows"], &["validate"]] {
+        let output = Command::new(env!("CARGO_BIN_EXE_1brc"))
+            .args(args)
+            .arg(&missing)
+            .output()
+            .expect("run 1brc");
+        assert!(!output.status.success(), "{:?}", args);
+    }
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn best_effort_leaves_a_failed_worker_out_of_separate_maps_only() {
+    let dir = std::env::temp_dir().join(format!("1brc-best-effort-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("input.csv");
+    let lines: String = (0..2000)
+        .map(|i| format!("Las Palmas de Gran Canaria {};{}.0\n", i % 7, i % 10))
+        .collect();
+    fs::write(
+        &path,
+        format!("{}Las Palmas de Gran Canaria 7;bad\n", lines),
+    )
+    .unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_1brc"))
+        .args(["-t", "2", "--chunk-bytes", "1K", "--best-effort"])
+        .arg(&path)
+        .output()
+        .expect("run 1brc");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.starts_with('{'),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert!(!stdout.contains("Canaria 7="), "{}", stdout);
+    // A shared map cannot take a worker's readings back out
+    let output = Command::new(env!("CARGO_BIN_EXE_1brc"))
+        .args(["-t", "2", "--strategy", "shared-map", "--best-effort"])
+        .arg(&path)
+        .output()
+        .expect("run 1brc");
+    assert!(output.stdout.is_empty());
+    assert!(
+        String::from_utf8_lossy(&output.stderr)
+            .contains("--strategy shared-map cannot be combined with --best-effort")
+    );
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn estimated_rows_bracket_the_true_count() {
+    let dir = std::env::temp_dir().join(format!("1brc-estimate-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let estimate = |text: &str, args: &[&str]| {
+        let path = dir.join("input.csv");
+        fs::write(&path, text).unwrap();
+        let output = Command::new(env!("CARGO_BIN_EXE_1brc"))
+            .args(args)
+            .arg("--estimate-rows")
+            .arg(&path)
+            .output()
+            .expect("run 1brc");
+        let stdout = String::from_utf8(output.stdout).expect("UTF-8 output");
+        // After the path, which has the process ID in it
+        let numbers: Vec<u64> = stdout
+            .split_once(": ")
+            .map_or("", |(_, estimate)| estimate)
+            .split(|c: char| !c.is_ascii_digit())
+            .filter_map(|n| n.parse().ok())
+            .collect();
+        (stdout, numbers)
+    };
+    // Small enough to be counted
+    let small = generate(3).text;
+    let (stdout, numbers) = estimate(&small, &[]);
+    assert_eq!(numbers, [small.lines().count() as u64], "{}", stdout);
+    // Datasets of different line lengths one after another
+    let mut large = String::new();
+    for seed in 0.. {
+        if large.len() > 12 << 20 {
+            break;
+        }
+        large.push_str(&generate(seed).text);
+        large.push('\n');
+    }
+    let lines = large.lines().count() as u64;
+    for args in [&[][..], &["--io", "mmap"]] {
+        let (stdout, numbers) = estimate(&large, args);
+        // With the 95 of the confidence in between
+        let [estimate, _, low, high] = numbers[..] else {
+            panic!("{}", stdout);
+        };
+        assert!(stdout.contains("about"), "{}", stdout);
+        assert!(low <= lines && lines <= high, "{} lines: {}", lines, stdout);
+        assert!(low <= estimate && estimate <= high, "{}", stdout);
+    }
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn a_timeout_stops_the_run_with_or_without_its_results() {
+    let dir = std::env::temp_dir().join(format!("1brc-timeout-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let text: String = (10..20).map(|seed| generate(seed).text + "\n").collect();
+    let path = dir.join("input.csv");
+    fs::write(&path, &text).unwrap();
+    // Some seconds of input at the rate it is held to
+    assert!(text.lines().count() > 10_000);
+    let throttled = [
+        "--timeout",
+        "1",
+        "--max-rows-per-sec",
+        "2000",
+        "--chunk-bytes",
+        "1K",
+    ];
+    for args in [
+        &["-t", "4", "--executor", "threads"][..],
+        &["-t", "4", "--executor", "pipeline"],
+    ] {
+        let output = Command::new(env!("CARGO_BIN_EXE_1brc"))
+            .args(args)
+            .args(throttled)
+            .arg(&path)
+            .output()
+            .expect("run 1brc");
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert_eq!(output.status.code(), Some(124), "{:?}: {}", args, stderr);
+        assert!(output.stdout.is_empty(), "{:?}", args);
+        assert!(
+            stderr.contains("timed out after 1s"),
+            "{:?}: {}",
+            args,
+            stderr
+        );
+
+        let output = Command::new(env!("CARGO_BIN_EXE_1brc"))
+            .args(args)
+            .args(throttled)
+            .arg("--partial-on-timeout")
+            .arg(&path)
+            .output()
+            .expect("run 1brc");
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(output.status.success(), "{:?}: {}", args, stderr);
+        assert!(
+            String::from_utf8_lossy(&output.stdout).starts_with('{'),
+            "{:?}: {}",
+            args,
+            stderr
+        );
+        assert!(
+            stderr.contains("the results are of the input read so far"),
+            "{:?}: {}",
+            args,
+            stderr
+        );
+    }
+    // A worker sleeping off a whole block's worth of the rate wakes up for
+    // the deadline, whichever executor it is in
+    for executor in ["threads", "pipeline"] {
+        let start = std::time::Instant::now();
+        let output = Command::new(env!("CARGO_BIN_EXE_1brc"))
+            .args([
+                "--executor",
+                executor,
+                "--timeout",
+                "1",
+                "--max-rows-per-sec",
+                "100",
+            ])
+            .arg(&path)
+            .output()
+            .expect("run 1brc");
+        assert_eq!(output.status.code(), Some(124), "{}", executor);
+        assert!(
+            start.elapsed().as_secs_f64() < 2.5,
+            "{}: {:?}",
+            executor,
+            start.elapsed()
+        );
+    }
+    // A deadline the run makes is no different from none
+    let output = Command::new(env!("CARGO_BIN_EXE_1brc"))
+        .args(["--timeout", "5m"])
+        .arg(&path)
+        .output()
+        .expect("run 1brc");
+    assert!(output.status.success());
+    assert!(!String::from_utf8_lossy(&output.stderr).contains("timed out"));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn prefix_table_collisions_never_merge_names_sharing_a_prefix() {
+    let dir = std::env::temp_dir().join(format!("1brc-prefix-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("input.csv");
+    // Names of one length whose first 8 bytes are the same, so that each
+    // takes the slot of the first or goes on to the map
+    let text: String = (0..3000)
+        .map(|i| format!("Stationname{:04};{}.0\n", i % 500, i % 500 % 90))
+        .collect();
+    fs::write(&path, text).unwrap();
+    let expected = run(&path, &["-t", "1"]);
+    assert_eq!(expected.len(), 500);
+    let args = ["-t", "2", "--strategy", "prefix-table"];
+    assert_eq!(run(&path, &args), expected);
+    let output = Command::new(env!("CARGO_BIN_EXE_1brc"))
+        .args(["-t", "1", "--strategy", "prefix-table", "--stats"])
+        .arg(&path)
+        .output()
+        .expect("run 1brc");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    // All but the rows of the first name
+    assert!(
+        stderr.contains("prefix table collisions: 2994 (2994 with the same prefix"),
+        "{}",
+        stderr
+    );
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn lowered_priorities_leave_the_results_alone() {
+    let dir = std::env::temp_dir().join(format!("1brc-priority-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("input.csv");
+    fs::write(&path, generate(4).text).unwrap();
+    let expected = run(&path, &["-t", "1"]);
+    for args in [
+        &["-t", "4", "--nice", "10", "--idle-io"][..],
+        &[
+            "-t",
+            "4",
+            "--executor",
+            "pipeline",
+            "--nice",
+            "19",
+            "--idle-io",
+        ],
+    ] {
+        assert_eq!(run(&path, args), expected, "{:?}", args);
+    }
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+/// Runs `1brc generate` with `args` into `dir`, returning what it wrote.
+fn generated(dir: &std::path::Path, args: &[&str]) -> Vec<u8> {
+    let path = dir.join("measurements.txt");
+    let output = Command::new(env!("CARGO_BIN_EXE_1brc"))
+        .arg("generate")
+        .args(args)
+        .arg("--output")
+        .arg(&path)
+        .output()
+        .expect("run 1brc");
+    assert!(
+        output.status.success(),
+        "1brc generate {:?} failed: {}",
+        args,
+        String::from_utf8_lossy(&output.stderr)
+    );
+    fs::read(&path).unwrap()
+}
+
+#[test]
+fn generated_rows_follow_the_seed_and_the_rules() {
+    let dir = std::env::temp_dir().join(format!("1brc-generate-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let rows = generated(&dir, &["--rows", "200000", "--seed", "7"]);
+    assert_eq!(rows.iter().filter(|&&b| b == b'\n').count(), 200_000);
+    assert_eq!(rows, generated(&dir, &["--rows", "200000", "--seed", "7"]));
+    assert_ne!(rows, generated(&dir, &["--rows", "200000", "--seed", "8"]));
+    // More rows only add to the end
+    assert!(generated(&dir, &["--rows", "300000", "--seed", "7"]).starts_with(&rows));
+    // As many as make up the bytes, the same rows as by count
+    let sized = generated(&dir, &["--bytes", "1M", "--seed", "7"]);
+    assert!(
+        (1 << 20..(1 << 20) + 107).contains(&sized.len()),
+        "{}",
+        sized.len()
+    );
+    assert!(rows.starts_with(&sized) && sized.ends_with(b"\n"));
+    let output = Command::new(env!("CARGO_BIN_EXE_1brc"))
+        .args(["generate", "--rows", "1000", "--progress", "--output", "-"])
+        .output()
+        .expect("run 1brc");
+    assert_eq!(output.stdout.iter().filter(|&&b| b == b'\n').count(), 1000);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.starts_with("Generated 1000 rows, "), "{}", stderr);
+    assert!(stderr.contains(" M rows/s"), "{}", stderr);
+
+    let path = dir.join("input.txt");
+    fs::write(&path, &rows).unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_1brc"))
+        .arg("validate")
+        .arg(&path)
+        .output()
+        .expect("run 1brc");
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stdout)
+    );
+    // Every station of the original challenge
+    assert_eq!(run(&path, &["-t", "4"]).len(), 413);
+
+    let stations = dir.join("stations.txt");
+    fs::write(&stations, "# Two of them\nHere;10.0;2\n\nThere;-20;0\n").unwrap();
+    let stations = stations.to_str().unwrap();
+    for distribution in ["gaussian", "uniform", "bimodal"] {
+        let rows = generated(
+            &dir,
+            &[
+                "--rows",
+                "10000",
+                "--stations-file",
+                stations,
+                "--distribution",
+                distribution,
+            ],
+        );
+        fs::write(&path, &rows).unwrap();
+        let results = run(&path, &[]);
+        let [(here, min, mean, max), there] = &results[..] else {
+            panic!("{:?}", results);
+        };
+        assert_eq!(here, "Here");
+        assert!((mean - 10.0).abs() < 0.2, "{} {}", distribution, mean);
+        assert!(
+            *min < 7.0 && *max > 13.0,
+            "{} {} {}",
+            distribution,
+            min,
+            max
+        );
+        if distribution == "uniform" {
+            // Within the square root of three spreads of the mean
+            assert!(*min >= 6.5 && *max <= 13.5, "{} {}", min, max);
+        }
+        assert_eq!(there, &("There".to_string(), -20.0, -20.0, -20.0));
+    }
+
+    let many = dir.join("many.txt");
+    let names: String = (0..100).map(|i| format!("S{};0\n", i)).collect();
+    fs::write(&many, names).unwrap();
+    let many = many.to_str().unwrap();
+    // How often each of the stations comes up, in the order of the file
+    let counts = |skew: &str| -> Vec<usize> {
+        let rows = generated(
+            &dir,
+            &["--rows", "100000", "--stations-file", many, "--skew", skew],
+        );
+        let mut counts = vec![0; 100];
+        for line in String::from_utf8(rows).unwrap().lines() {
+            let (name, _) = line.split_once(';').unwrap();
+            counts[name[1..].parse::<usize>().unwrap()] += 1;
+        }
+        counts
+    };
+    let even = counts("none");
+    assert!(even.iter().all(|&n| (700..1300).contains(&n)), "{:?}", even);
+    let skewed = counts("zipf:1.2");
+    // The first is 2^1.2 as common as the second, and 10^1.2 as the tenth
+    assert!(skewed[0] > 25_000, "{:?}", skewed);
+    let ratio = |a: usize, b: usize| skewed[a] as f64 / skewed[b] as f64;
+    assert!((ratio(0, 1) - 2f64.powf(1.2)).abs() < 0.2, "{:?}", skewed);
+    assert!((ratio(0, 9) - 10f64.powf(1.2)).abs() < 2.0, "{:?}", skewed);
+
+    fs::write(&path, "Here;10.0\nThere\n").unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_1brc"))
+        .args(["generate", "--rows", "1", "--stations-file"])
+        .arg(&path)
+        .output()
+        .expect("run 1brc");
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("line 2: expected `name;mean`"),
+        "{}",
+        stderr
+    );
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn generated_shards_split_the_rows_between_files() {
+    let dir = std::env::temp_dir().join(format!("1brc-shards-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let generate = |args: &[&str]| {
+        let output = Command::new(env!("CARGO_BIN_EXE_1brc"))
+            .arg("generate")
+            .args(args)
+            .output()
+            .expect("run 1brc");
+        assert!(
+            output.status.success(),
+            "{}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+    };
+    let output = dir.join("rows.txt");
+    let output = output.to_str().unwrap();
+    let shards = |suffix: &str| -> Vec<Vec<u8>> {
+        (0..3)
+            .map(|i| fs::read(dir.join(format!("rows-{}.txt{}", i, suffix))).unwrap())
+            .collect()
+    };
+    generate(&["--rows", "100001", "--shards", "3", "--output", output]);
+    let first = shards("");
+    let lines: Vec<usize> = first
+        .iter()
+        .map(|shard| shard.iter().filter(|&&b| b == b'\n').count())
+        .collect();
+    assert_eq!(lines, [33333, 33334, 33334]);
+    // Each of a seed of its own
+    assert_ne!(first[0][..100], first[1][..100]);
+    generate(&["--rows", "100001", "--shards", "3", "--output", output]);
+    assert_eq!(shards(""), first);
+    for i in 0..3 {
+        let output = Command::new(env!("CARGO_BIN_EXE_1brc"))
+            .arg("validate")
+            .arg(dir.join(format!("rows-{}.txt", i)))
+            .output()
+            .expect("run 1brc");
+        assert!(output.status.success());
+    }
+
+    // Named .gz whether or not the output was; the tests of GzipWriter
+    // inflate what it writes
+    for output in [output, &format!("{}.gz", output)] {
+        generate(&[
+            "--rows",
+            "100001",
+            "--shards",
+            "3",
+            "--output",
+            output,
+            "--compress",
+            "gzip",
+        ]);
+        for (shard, plain) in shards(".gz").iter().zip(&first) {
+            assert_eq!(shard[..2], [0x1F, 0x8B]);
+            assert!(shard.len() * 2 < plain.len());
+        }
+    }
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn generated_edge_cases_are_valid_and_read_alike_by_every_configuration() {
+    let dir = std::env::temp_dir().join(format!("1brc-edge-cases-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let rows = generated(&dir, &["--rows", "100000", "--seed", "3", "--edge-cases"]);
+    let text = String::from_utf8(rows.clone()).expect("UTF-8 rows");
+    assert!(text.contains(";-0.0\n") && text.contains(";-99.9\n"));
+    assert!(text.lines().any(|line| line.find(';') == Some(100)));
+    // A row across every 4 KiB boundary, many of them cut in a character
+    let boundaries: Vec<usize> = (4096..rows.len()).step_by(4096).collect();
+    assert!(boundaries.iter().all(|&at| rows[at - 1] != b'\n'));
+    let inside = boundaries
+        .iter()
+        .filter(|&&at| !text.is_char_boundary(at))
+        .count();
+    assert!(
+        inside * 4 > boundaries.len(),
+        "{} of {}",
+        inside,
+        boundaries.len()
+    );
+
+    let path = dir.join("input.txt");
+    fs::write(&path, &rows).unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_1brc"))
+        .arg("validate")
+        .arg(&path)
+        .output()
+        .expect("run 1brc");
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stdout)
+    );
+    let mut expected: BTreeMap<&str, (i64, i64, i64, i64)> = BTreeMap::new();
+    for line in text.lines() {
+        let (station, value) = line.split_once(';').unwrap();
+        let tenths = (value.parse::<f64>().unwrap() * 10.0).round() as i64;
+        let entry = expected.entry(station).or_insert((tenths, tenths, 0, 0));
+        entry.0 = entry.0.min(tenths);
+        entry.1 = entry.1.max(tenths);
+        entry.2 += tenths;
+        entry.3 += 1;
+    }
+    for args in CONFIGURATIONS {
+        let rows = run(&path, args);
+        assert_eq!(rows.len(), expected.len(), "{:?}", args);
+        for ((name, min, mean, max), (station, &(lo, hi, sum, count))) in rows.iter().zip(&expected)
+        {
+            // Quoted by the CSV where they have a comma
+            assert_eq!(name.trim_matches('"'), *station, "{:?}", args);
+            assert_eq!(
+                (*min, *max),
+                (lo as f64 / 10.0, hi as f64 / 10.0),
+                "{} {:?}",
+                name,
+                args
+            );
+            // Rounded from a sum that may have been added up in another order
+            let exact = sum as f64 / count as f64 / 10.0;
+            assert!((mean - exact).abs() <= 0.05 + 1e-9, "{} {:?}", name, args);
+        }
+    }
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+/// Runs the binary over `path` with `--verify-manifest manifest`.
+fn verify(path: &PathBuf, manifest: &PathBuf, args: &[&str]) -> std::process::Output {
+    Command::new(env!("CARGO_BIN_EXE_1brc"))
+        .args(args)
+        .arg("--verify-manifest")
+        .arg(manifest)
+        .arg(path)
+        .output()
+        .expect("run 1brc")
+}
+
+#[test]
+fn every_configuration_matches_the_manifest_of_a_sequential_run() {
+    let dir = std::env::temp_dir().join(format!("1brc-manifest-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let manifest = dir.join("manifest.json");
+    for seed in 0..4 {
+        let path = dir.join(format!("{}.csv", seed));
+        fs::write(&path, generate(seed).text).unwrap();
+        run(
+            &path,
+            &["-t", "1", "--manifest", manifest.to_str().unwrap()],
+        );
+        for args in CONFIGURATIONS {
+            let output = verify(&path, &manifest, args);
+            assert!(
+                output.status.success(),
+                "seed {} with {:?}: {}",
+                seed,
+                args,
+                String::from_utf8_lossy(&output.stderr)
+            );
+        }
+
+        // Another input does not match
+        fs::write(&path, generate(seed + CASES).text).unwrap();
+        let output = verify(&path, &manifest, &[]);
+        assert_eq!(output.status.code(), Some(1), "seed {}", seed);
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(stderr.contains("input_hash: expected"), "{}", stderr);
+
+        // Each option is compared on its own
+        let output = verify(&path, &manifest, &["--unit", "f"]);
+        assert_eq!(output.status.code(), Some(1), "seed {}", seed);
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(
+            stderr.contains(r#"options.unit: expected "Celsius", got "Fahrenheit""#),
+            "{}",
+            stderr
+        );
+        assert!(!stderr.contains("options.input_unit"), "{}", stderr);
+    }
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn expect_sha256_checks_the_input_alongside_every_configuration() {
+    let dir = std::env::temp_dir().join(format!("1brc-sha256-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("0.csv");
+    let dataset = generate(0);
+    fs::write(&path, &dataset.text).unwrap();
+    let mut hasher = Sha256::default();
+    hasher.update(dataset.text.as_bytes());
+    let digest = sha256::to_hex(&hasher.finish());
+    for args in CONFIGURATIONS {
+        let args = [args, &["--expect-sha256", &digest][..]].concat();
+        assert_eq!(
+            run(&path, &args).len(),
+            dataset.expected.len(),
+            "{:?}",
+            args
+        );
+    }
+
+    let first = if digest.starts_with('0') { '1' } else { '0' };
+    let wrong = format!("{}{}", first, &digest[1..]);
+    let output = Command::new(env!("CARGO_BIN_EXE_1brc"))
+        .args(["--expect-sha256", &wrong])
+        .arg(&path)
+        .output()
+        .expect("run 1brc");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains(&format!("has SHA-256 {}", digest)),
+        "{}",
+        stderr
+    );
+    assert!(!String::from_utf8_lossy(&output.stdout).contains("Abha"));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+/// Windows takes forward slashes and verbatim `\\?\` paths as well as its
+/// own, whichever backend opens them.
+#[cfg(windows)]
+#[test]
+fn windows_path_spellings_are_read_by_both_backends() {
+    let dir = std::env::temp_dir().join(format!("1brc-paths-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("0.csv");
+    let dataset = generate(0);
+    fs::write(&path, &dataset.text).unwrap();
+    let verbatim = fs::canonicalize(&path).unwrap();
+    let forward = PathBuf::from(path.to_str().unwrap().replace('\\', "/"));
+    let expected: Vec<&String> = dataset.expected.keys().collect();
+    for spelling in [&path, &verbatim, &forward] {
+        for io in ["read", "mmap"] {
+            let rows = run(spelling, &["-t", "3", "--io", io]);
+            let names: Vec<&String> = rows.iter().map(|row| &row.0).collect();
+            assert_eq!(names, expected, "{} with --io {}", spelling.display(), io);
+        }
+    }
+    fs::remove_dir_all(&dir).unwrap();
+}